@@ -1,65 +1,71 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::error::{error_from_body, parse_response, ErrorBody};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::Error;
+use golem_llm::golem::llm::llm::{Error, Kv};
+use golem_llm::trace_id::TRACE_ID;
+use golem_llm::transport::{transport_for, HttpRequest, HttpTransport, RateLimitStatus};
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response};
-use serde::de::DeserializeOwned;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::fmt::Debug;
 
 const BASE_URL: &str = "https://api.anthropic.com";
 
+/// Beta feature flag required by the built-in code execution tool
+const CODE_EXECUTION_BETA: &str = "code-execution-2025-05-22";
+
+/// Beta feature flag required by cache breakpoints with a TTL of one hour
+const EXTENDED_CACHE_TTL_BETA: &str = "extended-cache-ttl-2025-04-11";
+
 /// The Anthropic API client for creating model responses.
 pub struct MessagesApi {
     api_key: String,
-    client: Client,
+    transport: Box<dyn HttpTransport>,
 }
 
 impl MessagesApi {
-    pub fn new(api_key: String) -> Self {
-        let client = Client::builder()
-            .build()
-            .expect("Failed to initialize HTTP client");
-        Self { api_key, client }
+    pub fn new(api_key: String, provider_options: &[Kv]) -> Self {
+        Self::with_transport(api_key, transport_for(provider_options))
+    }
+
+    pub fn with_transport(api_key: String, transport: Box<dyn HttpTransport>) -> Self {
+        Self { api_key, transport }
     }
 
     pub fn send_messages(&self, request: MessagesRequest) -> Result<MessagesResponse, Error> {
-        trace!("Sending request to Anthropic API: {request:?}");
-
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/messages"))
-            .header("anthropic-version", "2023-06-01")
-            .header("x-api-key", &self.api_key)
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
-
-        parse_response(response)
+        trace!("{TRACE_ID}Sending request to Anthropic API: {request:?}");
+
+        let response = self.transport.send(
+            with_beta_header(
+                HttpRequest::post(format!("{BASE_URL}/v1/messages"))
+                    .header("anthropic-version", "2023-06-01")
+                    .header("x-api-key", &self.api_key),
+                &request,
+            )
+            .json(&request)?,
+        )?;
+
+        let rate_limit = RateLimitStatus::from_headers(&response);
+        let mut body: MessagesResponse =
+            parse_response::<_, ErrorResponse>("Anthropic API", response)?;
+        body.rate_limit = rate_limit;
+        Ok(body)
     }
 
     pub fn stream_send_messages(&self, request: MessagesRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to Anthropic API: {request:?}");
-
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/messages"))
-            .header("anthropic-version", "2023-06-01")
-            .header("x-api-key", &self.api_key)
-            .header(
-                reqwest::header::ACCEPT,
-                HeaderValue::from_static("text/event-stream"),
-            )
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
+        trace!("{TRACE_ID}Sending request to Anthropic API: {request:?}");
 
-        trace!("Initializing SSE stream");
+        let request = with_beta_header(
+            HttpRequest::post(format!("{BASE_URL}/v1/messages"))
+                .header("anthropic-version", "2023-06-01")
+                .header("x-api-key", &self.api_key)
+                .header(reqwest::header::ACCEPT.as_str(), "text/event-stream"),
+            &request,
+        )
+        .json(&request)?;
 
-        EventSource::new(response)
-            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+        trace!("{TRACE_ID}Initializing SSE stream");
+
+        self.transport.stream(request)
     }
 }
 
@@ -84,6 +90,15 @@ pub struct MessagesRequest {
     pub top_k: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<ServiceTier>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceTier {
+    Auto,
+    StandardOnly,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -104,7 +119,11 @@ pub enum Content {
     Text {
         text: String,
         #[serde(skip_serializing_if = "Option::is_none")]
-        cache_control: Option<CacheControl>, // citations
+        cache_control: Option<CacheControl>,
+        /// Sources of the text, returned for documents with citations enabled (character ranges,
+        /// pages or content blocks of the cited document) and by the built-in web search tool
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        citations: Option<Vec<Value>>,
     },
     #[serde(rename = "image")]
     Image {
@@ -128,15 +147,30 @@ pub enum Content {
         content: Vec<Content>, // can only be Text or Image
         is_error: bool,
     },
+    #[serde(rename = "server_tool_use")]
+    ServerToolUse {
+        id: String,
+        input: Value,
+        name: String,
+    },
+    #[serde(rename = "web_search_tool_result")]
+    WebSearchToolResult { tool_use_id: String, content: Value },
+    #[serde(rename = "code_execution_tool_result")]
+    CodeExecutionToolResult { tool_use_id: String, content: Value },
     // Document
     // Thinking
     // RedactedThinking
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum CacheControl {
     #[serde(rename = "ephemeral")]
-    Ephemeral,
+    Ephemeral {
+        /// How long the cache entry lives, `5m` (the default) or `1h`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -154,12 +188,27 @@ pub enum MediaType {
     Jpeg,
     #[serde(rename = "image/png")]
     Png,
-    #[serde(rename = "image/svg+xml")]
+    #[serde(rename = "image/gif")]
     Gif,
     #[serde(rename = "image/webp")]
     Webp,
 }
 
+impl MediaType {
+    /// The image media types accepted by Anthropic
+    pub const SUPPORTED: [&'static str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type.to_ascii_lowercase().as_str() {
+            "image/jpeg" | "image/jpg" => Some(MediaType::Jpeg),
+            "image/png" => Some(MediaType::Png),
+            "image/gif" => Some(MediaType::Gif),
+            "image/webp" => Some(MediaType::Webp),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ToolChoice {
@@ -195,6 +244,10 @@ pub enum Tool {
         #[serde(skip_serializing_if = "Option::is_none")]
         description: Option<String>,
     },
+    #[serde(rename = "web_search_20250305")]
+    WebSearch { name: String },
+    #[serde(rename = "code_execution_20250522")]
+    CodeExecution { name: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -205,6 +258,9 @@ pub struct MessagesResponse {
     pub role: Role,
     pub stop_reason: Option<StopReason>,
     pub usage: Usage,
+    /// Read from the response headers
+    #[serde(skip)]
+    pub rate_limit: Option<RateLimitStatus>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -217,6 +273,13 @@ pub enum StopReason {
     StopSequence,
     #[serde(rename = "tool_use")]
     ToolUse,
+    #[serde(rename = "refusal")]
+    Refusal,
+    #[serde(rename = "pause_turn")]
+    PauseTurn,
+    /// Stop reasons introduced after this client was written
+    #[serde(other, rename = "unknown")]
+    Unknown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -229,7 +292,19 @@ pub struct Usage {
     pub output_tokens: u32,
 }
 
+/// Cumulative token counts reported by the `message_delta` stream event
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageDeltaUsage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u32>,
+    pub output_tokens: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Role {
     #[serde(rename = "user")]
     User,
@@ -249,6 +324,16 @@ pub struct ErrorResponseDetails {
     pub typ: String,
 }
 
+impl ErrorBody for ErrorResponse {
+    fn into_error(self, status: StatusCode) -> Error {
+        error_from_body(status, &self.error.message, &self)
+    }
+
+    fn is_transient(&self) -> bool {
+        matches!(self.error.typ.as_str(), "api_error" | "overloaded_error")
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ContentBlockDelta {
@@ -256,29 +341,173 @@ pub enum ContentBlockDelta {
     TextDelta { text: String },
     #[serde(rename = "input_json_delta")]
     InputJsonDelta { partial_json: String },
+    #[serde(rename = "citations_delta")]
+    CitationsDelta { citation: Value },
 }
 
-fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
-    let status = response.status();
-    if status.is_success() {
-        let body = response
-            .json::<T>()
-            .map_err(|err| from_reqwest_error("Failed to decode response body", err))?;
-
-        trace!("Received response from Anthropic API: {body:?}");
+fn with_beta_header(http_request: HttpRequest, request: &MessagesRequest) -> HttpRequest {
+    let tools = request.tools.iter().flatten();
+    let mut betas = Vec::new();
+    if tools
+        .clone()
+        .any(|tool| matches!(tool, Tool::CodeExecution { .. }))
+    {
+        betas.push(CODE_EXECUTION_BETA);
+    }
+    if tools.clone().any(|tool| {
+        matches!(
+            tool,
+            Tool::CustomTool {
+                cache_control: Some(CacheControl::Ephemeral { ttl: Some(ttl) }),
+                ..
+            } if ttl == "1h"
+        )
+    }) {
+        betas.push(EXTENDED_CACHE_TTL_BETA);
+    }
 
-        Ok(body)
+    if betas.is_empty() {
+        http_request
     } else {
-        let error_body = response
-            .json::<ErrorResponse>()
-            .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
+        http_request.header("anthropic-beta", betas.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{CacheControl, MessagesApi, MessagesRequest, Tool};
+    use crate::conversions::process_response;
+    use golem_llm::error::is_transient;
+    use golem_llm::golem::llm::llm::{ChatEvent, ErrorCode};
+    use golem_llm::transport::{CannedTransport, HttpResponse};
+    use reqwest::StatusCode;
+    use std::rc::Rc;
+
+    const BAD_GATEWAY_PAGE: &str = "<html><head><title>502 Bad Gateway</title></head><body><h1>502 Bad Gateway</h1></body></html>";
+
+    fn request() -> MessagesRequest {
+        MessagesRequest {
+            max_tokens: 100,
+            messages: vec![],
+            model: "claude-3-5-sonnet-latest".to_string(),
+            metadata: None,
+            stop_sequences: None,
+            stream: false,
+            system: vec![],
+            temperature: None,
+            tool_choice: None,
+            tools: None,
+            top_k: None,
+            top_p: None,
+            service_tier: None,
+        }
+    }
+
+    #[test]
+    fn one_hour_cache_ttl_sends_the_beta_header() {
+        let transport = Rc::new(CannedTransport::json(
+            StatusCode::OK,
+            r#"{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","content":[{"type":"text","text":"Hi"}],"stop_reason":"end_turn","usage":{"input_tokens":5,"output_tokens":1}}"#,
+        ));
+        let api = MessagesApi::with_transport("key".to_string(), Box::new(transport.clone()));
+        let request = MessagesRequest {
+            tools: Some(vec![Tool::CustomTool {
+                input_schema: serde_json::json!({"type": "object"}),
+                name: "get_weather".to_string(),
+                cache_control: Some(CacheControl::Ephemeral {
+                    ttl: Some("1h".to_string()),
+                }),
+                description: None,
+            }]),
+            ..request()
+        };
+
+        api.send_messages(request).unwrap();
+
+        assert!(transport.requests()[0].headers.contains(&(
+            "anthropic-beta".to_string(),
+            "extended-cache-ttl-2025-04-11".to_string()
+        )));
+    }
+
+    #[test]
+    fn html_error_body_is_passed_through() {
+        let transport = CannedTransport::new([Ok(HttpResponse::new(
+            StatusCode::BAD_GATEWAY,
+            BAD_GATEWAY_PAGE,
+        )
+        .with_header("content-type", "text/html"))]);
+        let api = MessagesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let error = api.send_messages(request()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert_eq!(error.message, "Request failed with 502 Bad Gateway");
+        assert_eq!(
+            error.provider_error_json,
+            Some(serde_json::json!({ "body": BAD_GATEWAY_PAGE, "transient": true }).to_string())
+        );
+    }
+
+    #[test]
+    fn json_error_body_is_decoded() {
+        let transport = CannedTransport::json(
+            StatusCode::TOO_MANY_REQUESTS,
+            r#"{"type":"error","error":{"type":"rate_limit_error","message":"Slow down"}}"#,
+        );
+        let api = MessagesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let error = api.send_messages(request()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::RateLimitExceeded);
+        assert_eq!(
+            error.message,
+            "Request failed with 429 Too Many Requests: Slow down"
+        );
+    }
+
+    #[test]
+    fn overloaded_error_in_successful_response_is_transient() {
+        let transport = CannedTransport::json(
+            StatusCode::OK,
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+        );
+        let api = MessagesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let error = api.send_messages(request()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert_eq!(
+            error.message,
+            "Provider returned an error in a successful response: Overloaded"
+        );
+        assert!(is_transient(&error));
+    }
+
+    #[test]
+    fn rate_limit_headers_are_reported_in_metadata() {
+        let transport = CannedTransport::new([Ok(HttpResponse::new(
+            StatusCode::OK,
+            r#"{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","content":[{"type":"text","text":"Hi"}],"stop_reason":"end_turn","usage":{"input_tokens":5,"output_tokens":1}}"#,
+        )
+        .with_header("content-type", "application/json")
+        .with_header("anthropic-ratelimit-requests-remaining", "49")
+        .with_header("anthropic-ratelimit-tokens-remaining", "39000"))]);
+        let api = MessagesApi::with_transport("key".to_string(), Box::new(transport));
 
-        trace!("Received {status} response from Anthropic API: {error_body:?}");
+        let response = api.send_messages(request()).unwrap();
 
-        Err(Error {
-            code: error_code_from_status(status),
-            message: format!("Request failed with {status}: {}", error_body.error.message),
-            provider_error_json: Some(serde_json::to_string(&error_body).unwrap()),
-        })
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                let metadata: serde_json::Value =
+                    serde_json::from_str(&message.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(
+                    metadata,
+                    serde_json::json!({"role": "assistant", "ratelimit_remaining_requests": 49, "ratelimit_remaining_tokens": 39000})
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
     }
 }
@@ -1,17 +1,41 @@
 use crate::client::{
-    Content, ImageSource, MessagesRequest, MessagesRequestMetadata, MessagesResponse, StopReason,
-    Tool, ToolChoice,
+    CacheControl, Content, ImageSource, MediaType, MessageDeltaUsage, MessagesRequest,
+    MessagesRequestMetadata, MessagesResponse, ServiceTier, StopReason, Tool, ToolChoice,
+};
+use golem_llm::config::{
+    apply_sampling_preset, bool_option, builtin_tools, max_tokens, output_constraint,
+    request_metadata, resolve_model,
+};
+use golem_llm::error::{
+    check_tool_result_id, check_tool_result_json, parse_tool_call_arguments, unsupported,
 };
 use golem_llm::golem::llm::llm::{
     ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageUrl,
     Message, ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
 };
+use golem_llm::response_id::response_id;
+use golem_llm::tool_call_only::forced_tool_choice;
+use golem_llm::transport::{mime_type_from_url_extension, RateLimitStatus};
+use serde_json::Value;
 use std::collections::HashMap;
 
 pub fn messages_to_request(
     messages: Vec<Message>,
     config: Config,
 ) -> Result<MessagesRequest, Error> {
+    if output_constraint(&config)?.is_some() {
+        return Err(unsupported("The grammar and regex options"));
+    }
+
+    let builtin_tools = builtin_tools(&config);
+    let config = apply_sampling_preset(config, "anthropic")?;
+    let model = resolve_model(&config, "anthropic")?;
+    // Anthropic requires max_tokens, so the default is sent when it is not set
+    let max_tokens = max_tokens(&config)?.unwrap_or(4096);
+    let tool_choice = forced_tool_choice(&config, "any")?;
+    // Anthropic only accepts the user_id tag in the request metadata
+    let metadata_user_id = request_metadata(&config)?.and_then(|mut tags| tags.remove("user_id"));
+    let cache_tools = bool_option(&config, "cache_tools");
     let options = config
         .provider_options
         .into_iter()
@@ -21,44 +45,70 @@ pub fn messages_to_request(
     let mut anthropic_messages = Vec::new();
     for message in &messages {
         if message.role != Role::System {
-            anthropic_messages.push(crate::client::Message {
-                role: match &message.role {
-                    Role::User => crate::client::Role::User,
-                    Role::Assistant => crate::client::Role::Assistant,
-                    Role::Tool => crate::client::Role::User,
-                    Role::System => unreachable!(),
+            let role = match &message.role {
+                Role::User => crate::client::Role::User,
+                Role::Assistant => crate::client::Role::Assistant,
+                Role::Tool => crate::client::Role::User,
+                Role::System => unreachable!(),
+            };
+            push_message(
+                &mut anthropic_messages,
+                crate::client::Message {
+                    role,
+                    content: message_to_content(message)?,
                 },
-                content: message_to_content(message),
-            })
+            );
         }
     }
 
     let mut system_messages = Vec::new();
     for message in &messages {
         if message.role == Role::System {
-            system_messages.extend(message_to_content(message))
+            system_messages.extend(message_to_content(message)?)
         }
     }
 
-    let tool_choice = config.tool_choice.map(convert_tool_choice);
-    let tools = if config.tools.is_empty() {
+    let tool_choice = tool_choice.map(convert_tool_choice);
+    let tools = if config.tools.is_empty() && builtin_tools.is_empty() {
         None
     } else {
         let mut tools = Vec::new();
         for tool in &config.tools {
             tools.push(tool_definition_to_tool(tool)?)
         }
+        // A cache breakpoint on the last tool caches the definitions of all the tools before it
+        if cache_tools {
+            if let Some(Tool::CustomTool { cache_control, .. }) = tools.last_mut() {
+                *cache_control = Some(CacheControl::Ephemeral {
+                    ttl: cache_ttl(&options)?,
+                });
+            }
+        }
+        for name in &builtin_tools {
+            tools.push(builtin_tool(name)?)
+        }
         Some(tools)
     };
 
+    let service_tier = options
+        .get("service_tier")
+        .map(|tier| convert_service_tier(tier))
+        .transpose()?;
+    let top_k = options
+        .get("top_k")
+        .map(|top_k| parse_top_k(top_k))
+        .transpose()?;
+
     Ok(MessagesRequest {
-        max_tokens: config.max_tokens.unwrap_or(4096),
+        max_tokens,
         messages: anthropic_messages,
-        model: config.model,
+        model,
         metadata: options
             .get("user_id")
+            .cloned()
+            .or(metadata_user_id)
             .map(|user_id| MessagesRequestMetadata {
-                user_id: Some(user_id.to_string()),
+                user_id: Some(user_id),
             }),
         stop_sequences: config.stop_sequences,
         stream: false,
@@ -66,15 +116,70 @@ pub fn messages_to_request(
         temperature: config.temperature,
         tool_choice,
         tools,
-        top_k: options
-            .get("top_k")
-            .and_then(|top_k_s| top_k_s.parse::<u32>().ok()),
+        top_k,
         top_p: options
             .get("top_p")
             .and_then(|top_p_s| top_p_s.parse::<f32>().ok()),
+        service_tier,
     })
 }
 
+/// Appends a message to the conversation. Anthropic requires the roles to alternate, so a message
+/// of the same role as the previous one is merged into it.
+pub fn push_message(messages: &mut Vec<crate::client::Message>, message: crate::client::Message) {
+    match messages.last_mut() {
+        Some(previous) if previous.role == message.role => previous.content.extend(message.content),
+        _ => messages.push(message),
+    }
+}
+
+fn parse_top_k(top_k: &str) -> Result<u32, Error> {
+    match top_k.trim().parse::<u32>() {
+        Ok(top_k) if top_k > 0 => Ok(top_k),
+        _ => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("top_k must be a positive integer, got '{top_k}'"),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// Gets the lifetime of the cache breakpoints from the `cache_ttl` provider option, `5m` or `1h`.
+/// Without it Anthropic's default of five minutes is used.
+fn cache_ttl(options: &HashMap<String, String>) -> Result<Option<String>, Error> {
+    match options.get("cache_ttl").map(String::as_str) {
+        None => Ok(None),
+        Some(ttl @ ("5m" | "1h")) => Ok(Some(ttl.to_string())),
+        Some(ttl) => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("cache_ttl must be 5m or 1h, got '{ttl}'"),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// Maps the OpenAI style `service_tier` values to Anthropic's. Anthropic has no flex or priority
+/// tier that can be requested per request.
+fn convert_service_tier(tier: &str) -> Result<ServiceTier, Error> {
+    match tier {
+        "auto" => Ok(ServiceTier::Auto),
+        "default" | "standard_only" => Ok(ServiceTier::StandardOnly),
+        _ => Err(unsupported(format!("Service tier {tier}"))),
+    }
+}
+
+fn builtin_tool(name: &str) -> Result<Tool, Error> {
+    match name {
+        "web_search" => Ok(Tool::WebSearch {
+            name: "web_search".to_string(),
+        }),
+        "code_interpreter" => Ok(Tool::CodeExecution {
+            name: "code_execution".to_string(),
+        }),
+        _ => Err(unsupported(format!("Built-in tool {name}"))),
+    }
+}
+
 fn convert_tool_choice(tool_name: String) -> ToolChoice {
     if &tool_name == "auto" {
         ToolChoice::Auto {
@@ -95,12 +200,27 @@ fn convert_tool_choice(tool_name: String) -> ToolChoice {
 }
 
 pub fn process_response(response: MessagesResponse) -> ChatEvent {
+    let id = response_id(&response.id, &response);
     let mut contents = Vec::new();
     let mut tool_calls = Vec::new();
+    let mut citations = Vec::new();
+    let mut builtin_tool_results = Vec::new();
 
     for content in response.content {
         match content {
-            Content::Text { text, .. } => contents.push(ContentPart::Text(text)),
+            Content::Text {
+                text,
+                citations: text_citations,
+                ..
+            } => {
+                citations.extend(
+                    text_citations
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|citation| with_content_index(citation, contents.len())),
+                );
+                contents.push(ContentPart::Text(text))
+            }
             Content::Image { source, .. } => match source {
                 ImageSource::Url { url } => {
                     contents.push(ContentPart::Image(ImageUrl { url, detail: None }))
@@ -121,10 +241,20 @@ pub fn process_response(response: MessagesResponse) -> ChatEvent {
                 arguments_json: serde_json::to_string(&input).unwrap(),
             }),
             Content::ToolResult { .. } => {}
+            // Built-in tools are executed by Anthropic, their results are reported in the metadata
+            Content::ServerToolUse { .. } => {}
+            result @ (Content::WebSearchToolResult { .. }
+            | Content::CodeExecutionToolResult { .. }) => {
+                if let Ok(value) = serde_json::to_value(result) {
+                    builtin_tool_results.push(value);
+                }
+            }
         }
     }
 
-    if contents.is_empty() {
+    // Like the other providers, a response consisting of tool calls only is a tool request, while
+    // text accompanying the tool calls is preserved by returning both in a message
+    if contents.is_empty() && !tool_calls.is_empty() {
         ChatEvent::ToolRequest(tool_calls)
     } else {
         let metadata = ResponseMetadata {
@@ -132,11 +262,17 @@ pub fn process_response(response: MessagesResponse) -> ChatEvent {
             usage: Some(convert_usage(response.usage)),
             provider_id: None,
             timestamp: None,
-            provider_metadata_json: None,
+            provider_metadata_json: Some(provider_metadata(
+                &response.role,
+                citations,
+                builtin_tool_results,
+                response.rate_limit,
+            )),
+            raw_json: None,
         };
 
         ChatEvent::Message(CompleteResponse {
-            id: response.id,
+            id,
             content: contents,
             tool_calls,
             metadata,
@@ -144,37 +280,79 @@ pub fn process_response(response: MessagesResponse) -> ChatEvent {
     }
 }
 
+/// Adds the index of the content part containing the cited text to a citation, under the
+/// `content_index` key
+fn with_content_index(mut citation: Value, content_index: usize) -> Value {
+    if let Value::Object(fields) = &mut citation {
+        fields.insert("content_index".to_string(), Value::from(content_index));
+    }
+    citation
+}
+
+/// Collects the citations, built-in tool results and rate limit status of a response into provider
+/// metadata
+fn provider_metadata(
+    role: &crate::client::Role,
+    citations: Vec<Value>,
+    builtin_tool_results: Vec<Value>,
+    rate_limit: Option<RateLimitStatus>,
+) -> String {
+    let mut provider_metadata = serde_json::Map::new();
+    if let Ok(role) = serde_json::to_value(role) {
+        provider_metadata.insert("role".to_string(), role);
+    }
+    if !citations.is_empty() {
+        provider_metadata.insert("citations".to_string(), Value::Array(citations));
+    }
+    if !builtin_tool_results.is_empty() {
+        provider_metadata.insert(
+            "builtin_tool_results".to_string(),
+            Value::Array(builtin_tool_results),
+        );
+    }
+    if let Some(rate_limit) = rate_limit {
+        rate_limit.add_to_metadata(&mut provider_metadata);
+    }
+    Value::Object(provider_metadata).to_string()
+}
+
 pub fn tool_results_to_messages(
     tool_results: Vec<(ToolCall, ToolResult)>,
-) -> Vec<crate::client::Message> {
+) -> Result<Vec<crate::client::Message>, Error> {
     let mut messages = Vec::new();
 
     for (tool_call, tool_result) in tool_results {
+        check_tool_result_id(&tool_call, &tool_result)?;
         messages.push(crate::client::Message {
             content: vec![Content::ToolUse {
                 id: tool_call.id.clone(),
-                input: serde_json::from_str(&tool_call.arguments_json).unwrap(),
+                input: parse_tool_call_arguments(&tool_call)?,
                 name: tool_call.name,
                 cache_control: None,
             }],
             role: crate::client::Role::Assistant,
         });
         let content = match tool_result {
-            ToolResult::Success(success) => Content::ToolResult {
-                tool_use_id: tool_call.id,
-                cache_control: None,
-                content: vec![Content::Text {
-                    text: success.result_json,
+            ToolResult::Success(success) => {
+                check_tool_result_json(&success)?;
+                Content::ToolResult {
+                    tool_use_id: tool_call.id,
                     cache_control: None,
-                }],
-                is_error: false,
-            },
+                    content: vec![Content::Text {
+                        text: success.result_json,
+                        cache_control: None,
+                        citations: None,
+                    }],
+                    is_error: false,
+                }
+            }
             ToolResult::Error(error) => Content::ToolResult {
                 tool_use_id: tool_call.id,
                 cache_control: None,
                 content: vec![Content::Text {
                     text: error.error_message,
                     cache_control: None,
+                    citations: None,
                 }],
                 is_error: true,
             },
@@ -185,7 +363,7 @@ pub fn tool_results_to_messages(
         });
     }
 
-    messages
+    Ok(messages)
 }
 
 pub fn stop_reason_to_finish_reason(stop_reason: StopReason) -> FinishReason {
@@ -194,6 +372,8 @@ pub fn stop_reason_to_finish_reason(stop_reason: StopReason) -> FinishReason {
         StopReason::MaxTokens => FinishReason::Length,
         StopReason::StopSequence => FinishReason::Stop,
         StopReason::ToolUse => FinishReason::ToolCalls,
+        StopReason::Refusal => FinishReason::ContentFilter,
+        StopReason::PauseTurn | StopReason::Unknown => FinishReason::Other,
     }
 }
 
@@ -202,10 +382,32 @@ pub fn convert_usage(usage: crate::client::Usage) -> Usage {
         input_tokens: Some(usage.input_tokens),
         output_tokens: Some(usage.output_tokens),
         total_tokens: None,
+        reasoning_tokens: None,
+        cached_tokens: usage.cache_read_input_tokens,
+        audio_tokens: None,
     }
 }
 
-fn message_to_content(message: &Message) -> Vec<Content> {
+/// Applies the cumulative token counts of a streamed `message_delta` event to the usage reported
+/// so far
+pub fn apply_usage_delta(usage: Option<Usage>, delta: MessageDeltaUsage) -> Usage {
+    let usage = usage.unwrap_or(Usage {
+        input_tokens: None,
+        output_tokens: None,
+        total_tokens: None,
+        reasoning_tokens: None,
+        cached_tokens: None,
+        audio_tokens: None,
+    });
+    Usage {
+        input_tokens: delta.input_tokens.or(usage.input_tokens),
+        output_tokens: Some(delta.output_tokens),
+        cached_tokens: delta.cache_read_input_tokens.or(usage.cached_tokens),
+        ..usage
+    }
+}
+
+fn message_to_content(message: &Message) -> Result<Vec<Content>, Error> {
     let mut result = Vec::new();
 
     for content_part in &message.content {
@@ -213,17 +415,51 @@ fn message_to_content(message: &Message) -> Vec<Content> {
             ContentPart::Text(text) => result.push(Content::Text {
                 text: text.clone(),
                 cache_control: None,
+                citations: None,
             }),
             ContentPart::Image(image_url) => result.push(Content::Image {
-                source: ImageSource::Url {
-                    url: image_url.url.clone(),
-                },
+                source: image_source(&image_url.url)?,
                 cache_control: None,
             }),
         }
     }
 
-    result
+    Ok(result)
+}
+
+/// Converts an image URL to an image source, checking that its media type is supported.
+///
+/// Base64 data URLs are sent inline with their media type. The media type of other URLs is
+/// detected from their file extension, URLs without a known image extension are passed through
+/// for Anthropic to check.
+fn image_source(url: &str) -> Result<ImageSource, Error> {
+    if let Some((mime_type, data)) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        return Ok(ImageSource::Base64 {
+            data: data.to_string(),
+            media_type: supported_media_type(mime_type)?,
+        });
+    }
+
+    if let Some(mime_type) = mime_type_from_url_extension(url) {
+        supported_media_type(mime_type)?;
+    }
+    Ok(ImageSource::Url {
+        url: url.to_string(),
+    })
+}
+
+fn supported_media_type(mime_type: &str) -> Result<MediaType, Error> {
+    MediaType::from_mime_type(mime_type).ok_or_else(|| Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!(
+            "Image media type {mime_type} is not supported, expected one of {}",
+            MediaType::SUPPORTED.join(", ")
+        ),
+        provider_error_json: None,
+    })
 }
 
 fn tool_definition_to_tool(tool: &ToolDefinition) -> Result<Tool, Error> {
@@ -241,3 +477,583 @@ fn tool_definition_to_tool(tool: &ToolDefinition) -> Result<Tool, Error> {
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::client::MediaType;
+    use crate::conversions::{
+        convert_usage, messages_to_request, process_response, tool_results_to_messages,
+    };
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Config, ContentPart, ErrorCode, FinishReason, ImageUrl, Message, Role, ToolCall,
+        ToolDefinition, ToolResult, ToolSuccess,
+    };
+    use golem_llm::test_config;
+    use golem_llm::tool_examples::with_tool_examples;
+
+    fn config(provider_options: &[(&str, &str)]) -> Config {
+        Config {
+            model: "claude-3-5-sonnet-latest".to_string(),
+            ..test_config::config(provider_options)
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("What is Golem?".to_string())],
+        }]
+    }
+
+    fn image_message(url: &str) -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Image(ImageUrl {
+                url: url.to_string(),
+                detail: None,
+            })],
+        }]
+    }
+
+    #[test]
+    fn consecutive_messages_of_the_same_role_are_merged() {
+        let mut messages = messages();
+        messages.push(Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("And what is Rust?".to_string())],
+        });
+        messages.push(Message {
+            role: Role::Assistant,
+            name: None,
+            content: vec![ContentPart::Text("Golem is a platform".to_string())],
+        });
+
+        let request = messages_to_request(messages, config(&[])).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(json["messages"][0]["role"], "user");
+        assert_eq!(
+            json["messages"][0]["content"],
+            serde_json::json!([
+                {"type": "text", "text": "What is Golem?"},
+                {"type": "text", "text": "And what is Rust?"}
+            ])
+        );
+        assert_eq!(json["messages"][1]["role"], "assistant");
+    }
+
+    #[test]
+    fn supported_image_media_types_are_sent_inline() {
+        for media_type in ["image/jpeg", "image/png", "image/gif", "image/webp"] {
+            let request = messages_to_request(
+                image_message(&format!("data:{media_type};base64,aW1hZ2U=")),
+                config(&[]),
+            )
+            .unwrap();
+            let json = serde_json::to_value(&request).unwrap();
+
+            assert_eq!(
+                json["messages"][0]["content"][0]["source"],
+                serde_json::json!({"type": "base64", "data": "aW1hZ2U=", "media_type": media_type})
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_image_media_type_is_rejected() {
+        for url in [
+            "data:image/bmp;base64,aW1hZ2U=",
+            "https://example.com/diagram.svg",
+        ] {
+            let error = messages_to_request(image_message(url), config(&[])).unwrap_err();
+
+            assert_eq!(error.code, ErrorCode::InvalidRequest);
+            assert!(error.message.starts_with("Image media type image/"));
+        }
+    }
+
+    #[test]
+    fn image_urls_are_passed_through() {
+        for url in [
+            "https://example.com/photo.JPG",
+            "https://example.com/image?id=1",
+        ] {
+            let request = messages_to_request(image_message(url), config(&[])).unwrap();
+            let json = serde_json::to_value(&request).unwrap();
+
+            assert_eq!(
+                json["messages"][0]["content"][0]["source"],
+                serde_json::json!({"type": "url", "url": url})
+            );
+        }
+    }
+
+    #[test]
+    fn gif_media_type_is_not_mislabeled_as_svg() {
+        assert_eq!(
+            serde_json::to_value(MediaType::Gif).unwrap(),
+            serde_json::json!("image/gif")
+        );
+        assert!(matches!(
+            MediaType::from_mime_type("image/gif"),
+            Some(MediaType::Gif)
+        ));
+        assert!(MediaType::from_mime_type("image/svg+xml").is_none());
+    }
+
+    #[test]
+    fn usage_preserves_cached_tokens() {
+        let usage: crate::client::Usage = serde_json::from_str(
+            r#"{"cache_creation_input_tokens":10,"cache_read_input_tokens":60,"input_tokens":100,"output_tokens":40}"#,
+        )
+        .unwrap();
+
+        let usage = convert_usage(usage);
+
+        assert_eq!(usage.input_tokens, Some(100));
+        assert_eq!(usage.cached_tokens, Some(60));
+        assert_eq!(usage.reasoning_tokens, None);
+    }
+
+    #[test]
+    fn builtin_tools_are_added_to_the_request() {
+        let request = messages_to_request(
+            messages(),
+            config(&[("builtin_tools", "web_search,code_interpreter")]),
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(
+            json["tools"],
+            serde_json::json!([
+                {"type": "web_search_20250305", "name": "web_search"},
+                {"type": "code_execution_20250522", "name": "code_execution"}
+            ])
+        );
+    }
+
+    #[test]
+    fn user_id_is_taken_from_metadata() {
+        let request = messages_to_request(
+            messages(),
+            config(&[("metadata", r#"{"user_id": "user-42", "tenant": "acme"}"#)]),
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["metadata"], serde_json::json!({"user_id": "user-42"}));
+    }
+
+    #[test]
+    fn model_alias_is_resolved() {
+        let mut config = config(&[(
+            "model_alias",
+            r#"{"fast": {"openai": "gpt-4o-mini", "anthropic": "claude-3-5-haiku-latest"}}"#,
+        )]);
+        config.model = "fast".to_string();
+
+        let request = messages_to_request(messages(), config).unwrap();
+
+        assert_eq!(request.model, "claude-3-5-haiku-latest");
+    }
+
+    #[test]
+    fn top_k_option_is_serialized() {
+        let request = messages_to_request(messages(), config(&[("top_k", "40")])).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["top_k"], 40);
+    }
+
+    #[test]
+    fn all_sampling_params_are_serialized() {
+        let config = Config {
+            temperature: Some(0.5),
+            max_tokens: Some(256),
+            stop_sequences: Some(vec!["END".to_string()]),
+            ..config(&[("top_p", "0.25"), ("top_k", "40")])
+        };
+
+        let request = messages_to_request(messages(), config).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["temperature"], 0.5);
+        assert_eq!(json["max_tokens"], 256);
+        assert_eq!(json["stop_sequences"], serde_json::json!(["END"]));
+        assert_eq!(json["top_p"], 0.25);
+        assert_eq!(json["top_k"], 40);
+    }
+
+    #[test]
+    fn sampling_preset_only_sets_the_temperature() {
+        let request = messages_to_request(messages(), config(&[("preset", "creative")])).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["temperature"], 1.0);
+        assert!(json.get("top_p").is_none());
+    }
+
+    #[test]
+    fn max_tokens_defaults_unless_positive() {
+        let mut config = config(&[]);
+
+        let request = messages_to_request(messages(), config.clone()).unwrap();
+        assert_eq!(request.max_tokens, 4096);
+
+        config.max_tokens = Some(0);
+        let error = messages_to_request(messages(), config).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn top_k_is_omitted_by_default() {
+        let request = messages_to_request(messages(), config(&[])).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert!(json.get("top_k").is_none());
+    }
+
+    #[test]
+    fn invalid_top_k_is_rejected() {
+        for top_k in ["0", "-1", "many"] {
+            let error = messages_to_request(messages(), config(&[("top_k", top_k)])).unwrap_err();
+
+            assert_eq!(error.code, ErrorCode::InvalidRequest);
+        }
+    }
+
+    #[test]
+    fn service_tier_is_mapped() {
+        let request =
+            messages_to_request(messages(), config(&[("service_tier", "default")])).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["service_tier"], "standard_only");
+    }
+
+    #[test]
+    fn flex_service_tier_is_unsupported() {
+        let error =
+            messages_to_request(messages(), config(&[("service_tier", "flex")])).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::Unsupported);
+    }
+
+    #[test]
+    fn grammar_is_unsupported() {
+        let error = messages_to_request(
+            messages(),
+            config(&[("grammar", r#"root ::= "yes" | "no""#)]),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::Unsupported);
+    }
+
+    #[test]
+    fn unknown_builtin_tool_is_unsupported() {
+        let error = messages_to_request(messages(), config(&[("builtin_tools", "time_travel")]))
+            .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::Unsupported);
+    }
+
+    #[test]
+    fn web_search_citations_are_reported_in_metadata() {
+        let response = serde_json::from_str(
+            r#"{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","stop_reason":"end_turn","usage":{"input_tokens":10,"output_tokens":5},"content":[{"type":"server_tool_use","id":"srvtoolu_1","name":"web_search","input":{"query":"golem"}},{"type":"web_search_tool_result","tool_use_id":"srvtoolu_1","content":[{"type":"web_search_result","url":"https://golem.cloud","title":"Golem"}]},{"type":"text","text":"Golem is a durable computing platform","citations":[{"type":"web_search_result_location","url":"https://golem.cloud","title":"Golem","cited_text":"Golem"}]}]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                assert_eq!(
+                    message.content,
+                    vec![ContentPart::Text(
+                        "Golem is a durable computing platform".to_string()
+                    )]
+                );
+                let provider_metadata: serde_json::Value =
+                    serde_json::from_str(&message.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(
+                    provider_metadata["citations"][0]["url"],
+                    "https://golem.cloud"
+                );
+                assert_eq!(
+                    provider_metadata["builtin_tool_results"][0]["tool_use_id"],
+                    "srvtoolu_1"
+                );
+                assert_eq!(provider_metadata["role"], "assistant");
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn text_accompanying_tool_use_is_preserved() {
+        let response = serde_json::from_str(
+            r#"{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","stop_reason":"tool_use","usage":{"input_tokens":10,"output_tokens":5},"content":[{"type":"text","text":"Let me check the weather"},{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{"city":"Berlin"}}]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                assert_eq!(
+                    message.content,
+                    vec![ContentPart::Text("Let me check the weather".to_string())]
+                );
+                assert_eq!(
+                    message.tool_calls,
+                    vec![ToolCall {
+                        id: "toolu_1".to_string(),
+                        name: "get_weather".to_string(),
+                        arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+                    }]
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn tool_use_response_is_a_tool_request() {
+        let response = serde_json::from_str(
+            r#"{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","stop_reason":"tool_use","usage":{"input_tokens":10,"output_tokens":5},"content":[{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{"city":"Berlin"}},{"type":"tool_use","id":"toolu_2","name":"get_time","input":{}}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process_response(response),
+            ChatEvent::ToolRequest(vec![
+                ToolCall {
+                    id: "toolu_1".to_string(),
+                    name: "get_weather".to_string(),
+                    arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+                },
+                ToolCall {
+                    id: "toolu_2".to_string(),
+                    name: "get_time".to_string(),
+                    arguments_json: "{}".to_string(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_response_is_a_message() {
+        let response = serde_json::from_str(
+            r#"{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","stop_reason":"end_turn","usage":{"input_tokens":10,"output_tokens":0},"content":[]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                assert!(message.content.is_empty());
+                assert_eq!(message.metadata.finish_reason, Some(FinishReason::Other));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    /// Tool arguments with integers beyond the precision of an f64, up to the 64-bit limits
+    const LARGE_INTEGER_ARGUMENTS: &str = r#"{"order_id":18446744073709551615,"timestamp":9007199254740993,"user_id":-9223372036854775808}"#;
+
+    #[test]
+    fn large_integer_tool_arguments_are_preserved() {
+        let response = serde_json::from_str(&format!(
+            r#"{{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","stop_reason":"tool_use","usage":{{"input_tokens":10,"output_tokens":5}},"content":[{{"type":"tool_use","id":"toolu_1","name":"get_order","input":{LARGE_INTEGER_ARGUMENTS}}}]}}"#
+        ))
+        .unwrap();
+        let tool_call = match process_response(response) {
+            ChatEvent::ToolRequest(mut tool_calls) => tool_calls.remove(0),
+            other => panic!("Unexpected event: {other:?}"),
+        };
+        assert_eq!(tool_call.arguments_json, LARGE_INTEGER_ARGUMENTS);
+
+        let result = ToolResult::Success(ToolSuccess {
+            id: "toolu_1".to_string(),
+            name: "get_order".to_string(),
+            result_json: "{}".to_string(),
+            execution_time_ms: None,
+        });
+        let messages = tool_results_to_messages(vec![(tool_call, result)]).unwrap();
+        let json = serde_json::to_value(&messages).unwrap();
+
+        assert_eq!(
+            json[0]["content"][0]["input"].to_string(),
+            LARGE_INTEGER_ARGUMENTS
+        );
+    }
+
+    #[test]
+    fn truncated_tool_arguments_are_an_invalid_request() {
+        let tool_call = ToolCall {
+            id: "toolu_1".to_string(),
+            name: "get_order".to_string(),
+            arguments_json: r#"{"order_id":18"#.to_string(),
+        };
+        let result = ToolResult::Success(ToolSuccess {
+            id: "toolu_1".to_string(),
+            name: "get_order".to_string(),
+            result_json: "{}".to_string(),
+            execution_time_ms: None,
+        });
+
+        let error = tool_results_to_messages(vec![(tool_call, result)]).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error
+            .message
+            .starts_with("Arguments of tool call toolu_1 (get_order) are not valid JSON"));
+    }
+
+    #[test]
+    fn cache_tools_marks_the_last_tool_as_cache_breakpoint() {
+        let tool = |name: &str| ToolDefinition {
+            name: name.to_string(),
+            description: None,
+            parameters_schema: r#"{"type":"object"}"#.to_string(),
+        };
+        let mut config = config(&[("cache_tools", "true")]);
+        config.tools = vec![tool("get_weather"), tool("get_time")];
+
+        let request = messages_to_request(messages(), config.clone()).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert!(json["tools"][0].get("cache_control").is_none());
+        assert_eq!(
+            json["tools"][1]["cache_control"],
+            serde_json::json!({"type": "ephemeral"})
+        );
+
+        config.provider_options.clear();
+        let request = messages_to_request(messages(), config).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert!(json["tools"][1].get("cache_control").is_none());
+    }
+
+    #[test]
+    fn cache_ttl_is_serialized_on_the_cache_breakpoint() {
+        let mut config = config(&[("cache_tools", "true"), ("cache_ttl", "1h")]);
+        config.tools = vec![ToolDefinition {
+            name: "get_weather".to_string(),
+            description: None,
+            parameters_schema: r#"{"type":"object"}"#.to_string(),
+        }];
+
+        let request = messages_to_request(messages(), config.clone()).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(
+            json["tools"][0]["cache_control"],
+            serde_json::json!({"type": "ephemeral", "ttl": "1h"})
+        );
+
+        config.provider_options[1].value = "1d".to_string();
+        let error = messages_to_request(messages(), config).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(error.message, "cache_ttl must be 5m or 1h, got '1d'");
+    }
+
+    #[test]
+    fn tool_examples_are_serialized_in_the_input_schema() {
+        let tool = with_tool_examples(
+            ToolDefinition {
+                name: "get_weather".to_string(),
+                description: None,
+                parameters_schema: r#"{"type":"object","properties":{"city":{"type":"string"}}}"#
+                    .to_string(),
+            },
+            &[serde_json::json!({"city": "Berlin"})],
+        )
+        .unwrap();
+        let mut config = config(&[]);
+        config.tools = vec![tool];
+
+        let request = messages_to_request(messages(), config).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(
+            json["tools"][0]["input_schema"]["examples"],
+            serde_json::json!([{"city": "Berlin"}])
+        );
+    }
+
+    #[test]
+    fn markdown_option_is_ignored() {
+        let mut messages = messages();
+        messages.insert(
+            0,
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text("Answer in one sentence.".to_string())],
+            },
+        );
+        let markdown = config(&[("markdown", "true")]);
+
+        let with_hint = messages_to_request(messages.clone(), markdown).unwrap();
+        let without_hint = messages_to_request(messages, config(&[])).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&with_hint).unwrap(),
+            serde_json::to_value(&without_hint).unwrap()
+        );
+    }
+
+    #[test]
+    fn response_id_is_never_empty() {
+        for (id, expected) in [("msg_1", Some("msg_1")), ("", None)] {
+            let response = serde_json::from_str(&format!(
+                r#"{{"id":"{id}","model":"claude-3-5-sonnet-latest","role":"assistant","stop_reason":"end_turn","usage":{{"input_tokens":10,"output_tokens":5}},"content":[{{"type":"text","text":"Hello"}}]}}"#
+            ))
+            .unwrap();
+
+            match process_response(response) {
+                ChatEvent::Message(message) => {
+                    assert!(!message.id.is_empty());
+                    if let Some(expected) = expected {
+                        assert_eq!(message.id, expected);
+                    }
+                }
+                other => panic!("Unexpected event: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn document_citations_are_reported_in_metadata() {
+        let response = serde_json::from_str(
+            r#"{"id":"msg_1","model":"claude-3-5-sonnet-latest","role":"assistant","stop_reason":"end_turn","usage":{"input_tokens":10,"output_tokens":5},"content":[{"type":"text","text":"According to the document, "},{"type":"text","text":"the grass is green","citations":[{"type":"char_location","cited_text":"The grass is green.","document_index":0,"document_title":"Example Document","start_char_index":0,"end_char_index":20}]}]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                let provider_metadata: serde_json::Value =
+                    serde_json::from_str(&message.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(
+                    provider_metadata["citations"],
+                    serde_json::json!([{
+                        "type": "char_location",
+                        "cited_text": "The grass is green.",
+                        "document_index": 0,
+                        "document_title": "Example Document",
+                        "start_char_index": 0,
+                        "end_char_index": 20,
+                        "content_index": 1
+                    }])
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+}
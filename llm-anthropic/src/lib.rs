@@ -2,20 +2,29 @@ mod client;
 mod conversions;
 
 use crate::client::{
-    Content, ContentBlockDelta, ErrorResponse, MessagesApi, MessagesRequest, StopReason, Usage,
+    Content, ContentBlockDelta, ErrorResponse, MessageDeltaUsage, MessagesApi, MessagesRequest,
+    StopReason, Usage,
 };
 use crate::conversions::{
-    convert_usage, messages_to_request, process_response, stop_reason_to_finish_reason,
-    tool_results_to_messages,
+    apply_usage_delta, convert_usage, messages_to_request, process_response, push_message,
+    stop_reason_to_finish_reason, tool_results_to_messages,
+};
+use golem_llm::chat_stream::{
+    attach_raw_enabled, emit_heartbeat_enabled, emit_tool_start_enabled, heartbeat_delta,
+    sentence_flush_timeout, started_tool_calls_metadata, stream_usage_updates_enabled,
+    timing_enabled, unknown_event_type, usage_delta, with_block_index, LlmChatStream,
+    LlmChatStreamState,
 };
-use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
 use golem_llm::config::with_config_key;
 use golem_llm::durability::{DurableLLM, ExtendedGuest};
+use golem_llm::error::unsupported;
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, ErrorCode, Guest, Message, ResponseMetadata,
-    Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    ChatEvent, ChatStream, Config, ContentPart, Error, ErrorCode, Guest, ImageGenConfig,
+    ImageResult, Message, ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    Usage as GolemUsage,
 };
+use golem_llm::trace_id::TRACE_ID;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
@@ -35,10 +44,16 @@ struct AnthropicChatStream {
     finished: RefCell<bool>,
     json_fragments: RefCell<HashMap<u64, JsonFragment>>,
     response_metadata: RefCell<ResponseMetadata>,
+    stream_usage_updates: bool,
+    emit_tool_start: bool,
 }
 
 impl AnthropicChatStream {
-    pub fn new(stream: EventSource) -> LlmChatStream<Self> {
+    pub fn new(
+        stream: EventSource,
+        stream_usage_updates: bool,
+        emit_tool_start: bool,
+    ) -> LlmChatStream<Self> {
         LlmChatStream::new(AnthropicChatStream {
             stream: RefCell::new(Some(stream)),
             failure: None,
@@ -50,7 +65,10 @@ impl AnthropicChatStream {
                 provider_id: None,
                 timestamp: None,
                 provider_metadata_json: None,
+                raw_json: None,
             }),
+            stream_usage_updates,
+            emit_tool_start,
         })
     }
 
@@ -66,9 +84,24 @@ impl AnthropicChatStream {
                 provider_id: None,
                 timestamp: None,
                 provider_metadata_json: None,
+                raw_json: None,
             }),
+            stream_usage_updates: false,
+            emit_tool_start: false,
         })
     }
+
+    /// Stores the latest usage reported by the stream, returning a delta carrying it if it changed
+    /// and usage updates were requested
+    fn update_usage(&self, usage: GolemUsage) -> Option<StreamEvent> {
+        let mut response_metadata = self.response_metadata.borrow_mut();
+        if response_metadata.usage.as_ref() == Some(&usage) {
+            return None;
+        }
+        response_metadata.usage = Some(usage.clone());
+        self.stream_usage_updates
+            .then(|| StreamEvent::Delta(usage_delta(usage)))
+    }
 }
 
 impl LlmChatStreamState for AnthropicChatStream {
@@ -93,7 +126,7 @@ impl LlmChatStreamState for AnthropicChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
+        trace!("{TRACE_ID}Received raw stream event: {raw}");
         let json: serde_json::Value = serde_json::from_str(raw)
             .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
 
@@ -132,6 +165,20 @@ impl LlmChatStreamState for AnthropicChatStream {
                     .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
 
                 if let Content::ToolUse { id, name, .. } = content_block {
+                    let started = self.emit_tool_start.then(|| {
+                        StreamEvent::Delta(with_block_index(
+                            StreamDelta {
+                                content: None,
+                                tool_calls: None,
+                                provider_metadata_json: Some(started_tool_calls_metadata(vec![(
+                                    id.clone(),
+                                    name.clone(),
+                                )])),
+                                raw_json: None,
+                            },
+                            index as u32,
+                        ))
+                    });
                     self.json_fragments.borrow_mut().insert(
                         index,
                         JsonFragment {
@@ -140,11 +187,19 @@ impl LlmChatStreamState for AnthropicChatStream {
                             json: String::new(),
                         },
                     );
+                    Ok(started)
+                } else {
+                    Ok(None)
                 }
-
-                Ok(None)
             }
             Some("content_block_delta") => {
+                let index = json
+                    .as_object()
+                    .and_then(|obj| obj.get("index"))
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(|| {
+                        "Unexpected stream event format, does not have 'index' field".to_string()
+                    })?;
                 let raw_delta = json
                     .as_object()
                     .and_then(|obj| obj.get("delta"))
@@ -156,27 +211,38 @@ impl LlmChatStreamState for AnthropicChatStream {
 
                 match delta {
                     ContentBlockDelta::TextDelta { text } => {
-                        Ok(Some(StreamEvent::Delta(StreamDelta {
-                            content: Some(vec![ContentPart::Text(text)]),
-                            tool_calls: None,
-                        })))
+                        Ok(Some(StreamEvent::Delta(with_block_index(
+                            StreamDelta {
+                                content: Some(vec![ContentPart::Text(text)]),
+                                tool_calls: None,
+                                provider_metadata_json: None,
+                                raw_json: None,
+                            },
+                            index as u32,
+                        ))))
                     }
                     ContentBlockDelta::InputJsonDelta { partial_json } => {
-                        let index = json
-                            .as_object()
-                            .and_then(|obj| obj.get("index"))
-                            .and_then(|v| v.as_u64())
-                            .ok_or_else(|| {
-                                "Unexpected stream event format, does not have 'index' field"
-                                    .to_string()
-                            })?;
-
-                        let mut json_fragments = self.json_fragments.borrow_mut();
-                        let fragment = json_fragments.entry(index).or_default();
-                        fragment.json.push_str(&partial_json);
+                        // Inputs of built-in server tools have no fragment as they are not
+                        // reported as tool calls
+                        if let Some(fragment) = self.json_fragments.borrow_mut().get_mut(&index) {
+                            fragment.json.push_str(&partial_json);
+                        }
 
                         Ok(None)
                     }
+                    ContentBlockDelta::CitationsDelta { citation } => {
+                        Ok(Some(StreamEvent::Delta(with_block_index(
+                            StreamDelta {
+                                content: None,
+                                tool_calls: None,
+                                provider_metadata_json: Some(
+                                    serde_json::json!({ "citations": [citation] }).to_string(),
+                                ),
+                                raw_json: None,
+                            },
+                            index as u32,
+                        ))))
+                    }
                 }
             }
             Some("content_block_stop") => {
@@ -189,14 +255,19 @@ impl LlmChatStreamState for AnthropicChatStream {
                     })?;
 
                 if let Some(tool_use) = self.json_fragments.borrow_mut().remove(&index) {
-                    Ok(Some(StreamEvent::Delta(StreamDelta {
-                        content: None,
-                        tool_calls: Some(vec![ToolCall {
-                            id: tool_use.id,
-                            name: tool_use.name,
-                            arguments_json: tool_use.json,
-                        }]),
-                    })))
+                    Ok(Some(StreamEvent::Delta(with_block_index(
+                        StreamDelta {
+                            content: None,
+                            tool_calls: Some(vec![ToolCall {
+                                id: tool_use.id,
+                                name: tool_use.name,
+                                arguments_json: tool_use.json,
+                            }]),
+                            provider_metadata_json: None,
+                            raw_json: None,
+                        },
+                        index as u32,
+                    ))))
                 } else {
                     Ok(None)
                 }
@@ -211,22 +282,35 @@ impl LlmChatStreamState for AnthropicChatStream {
                 let usage = json
                     .as_object()
                     .and_then(|obj| obj.get("usage"))
-                    .and_then(|v| serde_json::from_value::<Usage>(v.clone()).ok());
+                    .and_then(|v| serde_json::from_value::<MessageDeltaUsage>(v.clone()).ok());
 
                 if let Some(stop_reason) = stop_reason {
                     self.response_metadata.borrow_mut().finish_reason =
                         Some(stop_reason_to_finish_reason(stop_reason));
                 }
                 if let Some(usage) = usage {
-                    self.response_metadata.borrow_mut().usage = Some(convert_usage(usage));
+                    let previous = self.response_metadata.borrow().usage.clone();
+                    Ok(self.update_usage(apply_usage_delta(previous, usage)))
+                } else {
+                    Ok(None)
                 }
-                Ok(None)
             }
             Some("message_stop") => {
                 let response_metadata = self.response_metadata.borrow().clone();
                 Ok(Some(StreamEvent::Finish(response_metadata)))
             }
-            Some(_) => Ok(None),
+            Some("message_start") => {
+                let usage = json
+                    .as_object()
+                    .and_then(|obj| obj.get("message"))
+                    .and_then(|v| v.as_object())
+                    .and_then(|obj| obj.get("usage"))
+                    .and_then(|v| serde_json::from_value::<Usage>(v.clone()).ok());
+
+                Ok(usage.and_then(|usage| self.update_usage(convert_usage(usage))))
+            }
+            Some("ping") => Ok(Some(StreamEvent::Delta(heartbeat_delta()))),
+            Some(typ) => unknown_event_type(typ),
             None => Err("Unexpected stream event format, does not have 'type' field".to_string()),
         }
     }
@@ -247,10 +331,12 @@ impl AnthropicComponent {
     fn streaming_request(
         client: MessagesApi,
         mut request: MessagesRequest,
+        stream_usage_updates: bool,
+        emit_tool_start: bool,
     ) -> LlmChatStream<AnthropicChatStream> {
         request.stream = true;
         match client.stream_send_messages(request) {
-            Ok(stream) => AnthropicChatStream::new(stream),
+            Ok(stream) => AnthropicChatStream::new(stream, stream_usage_updates, emit_tool_start),
             Err(err) => AnthropicChatStream::failed(err),
         }
     }
@@ -262,7 +348,7 @@ impl Guest for AnthropicComponent {
     fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |anthropic_api_key| {
-            let client = MessagesApi::new(anthropic_api_key);
+            let client = MessagesApi::new(anthropic_api_key, &config.provider_options);
 
             match messages_to_request(messages, config) {
                 Ok(request) => Self::request(client, request),
@@ -279,15 +365,18 @@ impl Guest for AnthropicComponent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |anthropic_api_key| {
-            let client = MessagesApi::new(anthropic_api_key);
+            let client = MessagesApi::new(anthropic_api_key, &config.provider_options);
 
             match messages_to_request(messages, config) {
-                Ok(mut request) => {
-                    request
-                        .messages
-                        .extend(tool_results_to_messages(tool_results));
-                    Self::request(client, request)
-                }
+                Ok(mut request) => match tool_results_to_messages(tool_results) {
+                    Ok(tool_messages) => {
+                        for message in tool_messages {
+                            push_message(&mut request.messages, message);
+                        }
+                        Self::request(client, request)
+                    }
+                    Err(err) => ChatEvent::Error(err),
+                },
                 Err(err) => ChatEvent::Error(err),
             }
         })
@@ -296,6 +385,10 @@ impl Guest for AnthropicComponent {
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn generate_image(_prompt: String, _config: ImageGenConfig) -> Result<Vec<ImageResult>, Error> {
+        Err(unsupported("Image generation"))
+    }
 }
 
 impl ExtendedGuest for AnthropicComponent {
@@ -305,18 +398,33 @@ impl ExtendedGuest for AnthropicComponent {
     ) -> LlmChatStream<AnthropicChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        let attach_raw = attach_raw_enabled(&config);
+        let timing = timing_enabled(&config);
+        let heartbeat = emit_heartbeat_enabled(&config);
+        let sentence_flush = sentence_flush_timeout(&config);
+        let stream_usage_updates = stream_usage_updates_enabled(&config);
+        let emit_tool_start = emit_tool_start_enabled(&config);
         with_config_key(
             Self::ENV_VAR_NAME,
             AnthropicChatStream::failed,
             |anthropic_api_key| {
-                let client = MessagesApi::new(anthropic_api_key);
+                let client = MessagesApi::new(anthropic_api_key, &config.provider_options);
 
                 match messages_to_request(messages, config) {
-                    Ok(request) => Self::streaming_request(client, request),
+                    Ok(request) => Self::streaming_request(
+                        client,
+                        request,
+                        stream_usage_updates,
+                        emit_tool_start,
+                    ),
                     Err(err) => AnthropicChatStream::failed(err),
                 }
             },
         )
+        .with_raw_payloads(attach_raw)
+        .with_timing(timing)
+        .with_sentence_flush(sentence_flush)
+        .with_heartbeat(heartbeat)
     }
 
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
@@ -376,3 +484,295 @@ impl ExtendedGuest for AnthropicComponent {
 type DurableAnthropicComponent = DurableLLM<AnthropicComponent>;
 
 golem_llm::export_llm!(DurableAnthropicComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::conversions::tool_results_to_messages;
+    use crate::AnthropicChatStream;
+    use golem_llm::chat_stream::{
+        collect_events, delta_started_tool_calls, is_heartbeat, usage_delta, LlmChatStreamState,
+    };
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, ContentPart, FinishReason, ResponseMetadata, StreamEvent, ToolCall, ToolResult,
+        ToolSuccess, Usage,
+    };
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    const MESSAGE_START: &str = r#"{"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","content":[],"model":"claude-3-5-sonnet-latest","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":25,"output_tokens":1}}}"#;
+    const FIRST_MESSAGE_DELTA: &str = r#"{"type":"message_delta","delta":{"stop_reason":null,"stop_sequence":null},"usage":{"output_tokens":8}}"#;
+    const SECOND_MESSAGE_DELTA: &str = r#"{"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":15}}"#;
+    const MESSAGE_STOP: &str = r#"{"type":"message_stop"}"#;
+
+    fn stream_state(stream_usage_updates: bool) -> AnthropicChatStream {
+        AnthropicChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            json_fragments: RefCell::new(HashMap::new()),
+            response_metadata: RefCell::new(ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            }),
+            stream_usage_updates,
+            emit_tool_start: false,
+        }
+    }
+
+    fn usage(output_tokens: u32) -> Usage {
+        Usage {
+            input_tokens: Some(25),
+            output_tokens: Some(output_tokens),
+            total_tokens: None,
+            reasoning_tokens: None,
+            cached_tokens: None,
+            audio_tokens: None,
+        }
+    }
+
+    #[test]
+    fn usage_updates_are_emitted_as_usage_grows() {
+        let state = stream_state(true);
+
+        let events = [
+            MESSAGE_START,
+            FIRST_MESSAGE_DELTA,
+            FIRST_MESSAGE_DELTA,
+            SECOND_MESSAGE_DELTA,
+        ]
+        .iter()
+        .filter_map(|raw| state.decode_message(raw).unwrap())
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta(usage_delta(usage(1))),
+                StreamEvent::Delta(usage_delta(usage(8))),
+                StreamEvent::Delta(usage_delta(usage(15))),
+            ]
+        );
+        match state.decode_message(MESSAGE_STOP).unwrap() {
+            Some(StreamEvent::Finish(metadata)) => {
+                assert_eq!(metadata.finish_reason, Some(FinishReason::Other));
+                assert_eq!(metadata.usage, Some(usage(15)));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    fn finish_reason_of(stop_reason: &str) -> Option<FinishReason> {
+        let state = stream_state(false);
+        let message_delta = format!(
+            r#"{{"type":"message_delta","delta":{{"stop_reason":"{stop_reason}","stop_sequence":null}},"usage":{{"output_tokens":15}}}}"#
+        );
+
+        for raw in [MESSAGE_START, &message_delta] {
+            assert_eq!(state.decode_message(raw).unwrap(), None);
+        }
+        match state.decode_message(MESSAGE_STOP).unwrap() {
+            Some(StreamEvent::Finish(metadata)) => metadata.finish_reason,
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn refusal_finishes_with_content_filter() {
+        assert_eq!(
+            finish_reason_of("refusal"),
+            Some(FinishReason::ContentFilter)
+        );
+        assert_eq!(finish_reason_of("pause_turn"), Some(FinishReason::Other));
+    }
+
+    #[test]
+    fn unknown_stop_reason_does_not_break_decoding() {
+        assert_eq!(
+            finish_reason_of("model_context_window_exceeded"),
+            Some(FinishReason::Other)
+        );
+    }
+
+    #[test]
+    fn usage_is_only_reported_on_finish_by_default() {
+        let state = stream_state(false);
+
+        for raw in [MESSAGE_START, FIRST_MESSAGE_DELTA, SECOND_MESSAGE_DELTA] {
+            assert_eq!(state.decode_message(raw).unwrap(), None);
+        }
+        match state.decode_message(MESSAGE_STOP).unwrap() {
+            Some(StreamEvent::Finish(metadata)) => {
+                assert_eq!(metadata.usage, Some(usage(15)));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn ping_frames_are_decoded_as_heartbeats() {
+        let state = stream_state(false);
+
+        let events = [MESSAGE_START, r#"{"type":"ping"}"#, FIRST_MESSAGE_DELTA]
+            .iter()
+            .filter_map(|raw| state.decode_message(raw).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(events.len(), 1);
+        assert!(is_heartbeat(&events[0]));
+        match &events[0] {
+            StreamEvent::Delta(delta) => {
+                assert_eq!(delta.content, None);
+                assert_eq!(delta.tool_calls, None);
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn streamed_tool_call_id_correlates_the_result() {
+        let state = stream_state(false);
+
+        let tool_calls = [
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"city\": \"Berlin\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+        ]
+        .iter()
+        .filter_map(|raw| match state.decode_message(raw).unwrap() {
+            Some(StreamEvent::Delta(delta)) => delta.tool_calls,
+            _ => None,
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+        assert_eq!(tool_calls.len(), 1);
+        let tool_call = tool_calls[0].clone();
+        assert_eq!(tool_call.id, "toolu_1");
+
+        let result = ToolResult::Success(ToolSuccess {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            result_json: r#"{"temperature":21}"#.to_string(),
+            execution_time_ms: None,
+        });
+        let messages =
+            serde_json::to_value(tool_results_to_messages(vec![(tool_call, result)]).unwrap())
+                .unwrap();
+
+        assert_eq!(messages[0]["content"][0]["id"], "toolu_1");
+        assert_eq!(messages[1]["content"][0]["tool_use_id"], "toolu_1");
+    }
+
+    #[test]
+    fn tool_call_start_precedes_the_complete_tool_call() {
+        let mut state = stream_state(false);
+        state.emit_tool_start = true;
+
+        let events = [
+            MESSAGE_START,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"{\"city\": \"Berlin\"}"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            SECOND_MESSAGE_DELTA,
+            MESSAGE_STOP,
+        ]
+        .iter()
+        .filter_map(|raw| state.decode_message(raw).unwrap())
+        .collect::<Vec<_>>();
+
+        let started = ToolCall {
+            id: "toolu_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: String::new(),
+        };
+        match &events[..] {
+            [StreamEvent::Delta(start), StreamEvent::Delta(complete), StreamEvent::Finish(_)] => {
+                assert_eq!(delta_started_tool_calls(start), vec![started]);
+                assert_eq!(start.tool_calls, None);
+                assert_eq!(
+                    complete.tool_calls.as_ref().unwrap()[0].arguments_json,
+                    r#"{"city": "Berlin"}"#
+                );
+                assert!(delta_started_tool_calls(complete).is_empty());
+            }
+            other => panic!("Unexpected events: {other:?}"),
+        }
+        assert!(
+            matches!(collect_events(events), ChatEvent::ToolRequest(calls) if calls.len() == 1)
+        );
+    }
+
+    #[test]
+    fn streamed_citations_are_reported_in_metadata() {
+        let state = stream_state(false);
+
+        let event = state
+            .decode_message(
+                r#"{"type":"content_block_delta","index":0,"delta":{"type":"citations_delta","citation":{"type":"page_location","cited_text":"The grass is green.","document_index":0,"document_title":"Example Document","start_page_number":1,"end_page_number":2}}}"#,
+            )
+            .unwrap();
+
+        match event {
+            Some(StreamEvent::Delta(delta)) => {
+                assert_eq!(delta.content, None);
+                let provider_metadata: serde_json::Value =
+                    serde_json::from_str(&delta.provider_metadata_json.unwrap()).unwrap();
+                assert_eq!(provider_metadata["citations"][0]["start_page_number"], 1);
+                assert_eq!(provider_metadata["citations"][0]["document_index"], 0);
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn interleaved_text_and_tool_calls_are_collected_in_order() {
+        let state = stream_state(false);
+
+        let events = [
+            MESSAGE_START,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Let me check "}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"the weather."}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"city\": \"Berlin\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"content_block_start","index":2,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":2,"delta":{"type":"text_delta","text":"Then the time."}}"#,
+            r#"{"type":"content_block_stop","index":2}"#,
+            SECOND_MESSAGE_DELTA,
+            MESSAGE_STOP,
+        ]
+        .iter()
+        .filter_map(|raw| state.decode_message(raw).unwrap())
+        .collect::<Vec<_>>();
+
+        match collect_events(events) {
+            ChatEvent::Message(response) => {
+                assert_eq!(
+                    response.content,
+                    vec![
+                        ContentPart::Text("Let me check the weather.".to_string()),
+                        ContentPart::Text("Then the time.".to_string()),
+                    ]
+                );
+                assert_eq!(response.tool_calls[0].id, "toolu_1");
+                let provider_metadata: serde_json::Value =
+                    serde_json::from_str(&response.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(
+                    provider_metadata["blocks"],
+                    serde_json::json!([
+                        {"type": "content", "content_index": 0},
+                        {"type": "tool_call", "id": "toolu_1"},
+                        {"type": "content", "content_index": 1},
+                    ])
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+}
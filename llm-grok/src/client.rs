@@ -1,12 +1,10 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::error::{parse_response, OpenAiErrorBody};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::Error;
+use golem_llm::golem::llm::llm::{Error, Kv};
+use golem_llm::trace_id::TRACE_ID;
+use golem_llm::transport::{transport_for, HttpRequest, HttpTransport};
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response};
-use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
 use std::str::FromStr;
 
 const BASE_URL: &str = "https://api.x.ai";
@@ -14,50 +12,41 @@ const BASE_URL: &str = "https://api.x.ai";
 /// The Completions API client for creating model responses.
 pub struct CompletionsApi {
     api_key: String,
-    client: Client,
+    transport: Box<dyn HttpTransport>,
 }
 
 impl CompletionsApi {
-    pub fn new(api_key: String) -> Self {
-        let client = Client::builder()
-            .build()
-            .expect("Failed to initialize HTTP client");
-        Self { api_key, client }
+    pub fn new(api_key: String, provider_options: &[Kv]) -> Self {
+        Self::with_transport(api_key, transport_for(provider_options))
+    }
+
+    pub fn with_transport(api_key: String, transport: Box<dyn HttpTransport>) -> Self {
+        Self { api_key, transport }
     }
 
     pub fn send_messages(&self, request: CompletionsRequest) -> Result<CompletionsResponse, Error> {
-        trace!("Sending request to xAI API: {request:?}");
+        trace!("{TRACE_ID}Sending request to xAI API: {request:?}");
 
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
+        let response = self.transport.send(
+            HttpRequest::post(format!("{BASE_URL}/v1/chat/completions"))
+                .bearer_auth(&self.api_key)
+                .json(&request)?,
+        )?;
 
-        parse_response(response)
+        parse_response::<_, OpenAiErrorBody>("xAI API", response)
     }
 
     pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to xAI API: {request:?}");
-
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
-            .header(
-                reqwest::header::ACCEPT,
-                HeaderValue::from_static("text/event-stream"),
-            )
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
-
-        trace!("Initializing SSE stream");
-
-        EventSource::new(response)
-            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+        trace!("{TRACE_ID}Sending request to xAI API: {request:?}");
+
+        let request = HttpRequest::post(format!("{BASE_URL}/v1/chat/completions"))
+            .bearer_auth(&self.api_key)
+            .header(reqwest::header::ACCEPT.as_str(), "text/event-stream")
+            .json(&request)?;
+
+        trace!("{TRACE_ID}Initializing SSE stream");
+
+        self.transport.stream(request)
     }
 }
 
@@ -67,6 +56,9 @@ pub struct CompletionsRequest {
     pub model: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub frequency_penalty: Option<f32>,
+    /// xAI deprecated `max_tokens` in favor of `max_completion_tokens`, so only the latter is sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_completion_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -255,6 +247,7 @@ pub enum FinishReason {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseMessage {
+    pub role: Option<String>,
     pub content: Option<String>,
     pub reasoning_content: Option<String>,
     pub refusal: Option<String>,
@@ -301,36 +294,76 @@ pub struct ChoiceChunk {
     pub index: u32,
     pub delta: ChoiceDelta,
     pub finish_reason: Option<FinishReason>,
+    #[serde(default)]
+    pub logprobs: Option<LogProbs>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogProbs {
+    pub content: Option<Vec<LogProb>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogProb {
+    pub token: String,
+    pub logprob: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChoiceDelta {
     pub content: Option<String>,
+    #[serde(default)]
+    pub refusal: Option<String>,
     pub tool_calls: Option<Vec<ToolCall>>,
     pub role: String,
 }
 
-fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
-    let status = response.status();
-    if status.is_success() {
-        let body = response
-            .json::<T>()
-            .map_err(|err| from_reqwest_error("Failed to decode response body", err))?;
-
-        trace!("Received response from xAI API: {body:?}");
+#[cfg(test)]
+mod tests {
+    use crate::client::{CompletionsApi, CompletionsRequest, Content, Message};
+    use golem_llm::golem::llm::llm::ErrorCode;
+    use golem_llm::transport::CannedTransport;
+    use reqwest::StatusCode;
+
+    fn request() -> CompletionsRequest {
+        CompletionsRequest {
+            messages: vec![Message::User {
+                content: Content::TextInput("Hello".to_string()),
+                name: None,
+            }],
+            model: "grok-3".to_string(),
+            frequency_penalty: None,
+            logprobs: None,
+            max_completion_tokens: None,
+            n: None,
+            presence_penalty: None,
+            reasoning_effort: None,
+            seed: None,
+            stop: None,
+            stream: None,
+            stream_options: None,
+            temperature: None,
+            tool_choice: None,
+            tools: vec![],
+            top_logprobs: None,
+            top_p: None,
+            user: None,
+        }
+    }
 
-        Ok(body)
-    } else {
-        let error_body = response
-            .text()
-            .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
+    #[test]
+    fn error_in_successful_response_is_classified() {
+        let body = r#"{"error":{"message":"Model not found","type":"invalid_request_error"}}"#;
+        let transport = CannedTransport::json(StatusCode::OK, body);
+        let api = CompletionsApi::with_transport("key".to_string(), Box::new(transport));
 
-        trace!("Received {status} response from xAI API: {error_body:?}");
+        let error = api.send_messages(request()).unwrap_err();
 
-        Err(Error {
-            code: error_code_from_status(status),
-            message: format!("Request failed with {status}"),
-            provider_error_json: Some(serde_json::to_string(&error_body).unwrap()),
-        })
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "Provider returned an error in a successful response: Model not found"
+        );
+        assert_eq!(error.provider_error_json, Some(body.to_string()));
     }
 }
@@ -1,20 +1,43 @@
 use crate::client::{CompletionsRequest, CompletionsResponse, Detail, Effort};
+use golem_llm::chat_stream::TokenLogprob;
+use golem_llm::config::{
+    apply_sampling_preset, builtin_tools, max_tokens, output_constraint, resolve_model,
+};
+use golem_llm::error::{check_tool_result_id, check_tool_result_json, unsupported};
 use golem_llm::golem::llm::llm::{
     ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageDetail,
     Message, ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
 };
+use golem_llm::response_id::response_id;
+use golem_llm::tool_call_only::forced_tool_choice;
 use std::collections::HashMap;
 
 pub fn messages_to_request(
     messages: Vec<Message>,
     config: Config,
 ) -> Result<CompletionsRequest, Error> {
+    if let Some(name) = builtin_tools(&config).first() {
+        return Err(unsupported(format!("Built-in tool {name}")));
+    }
+
+    if output_constraint(&config)?.is_some() {
+        return Err(unsupported("The grammar and regex options"));
+    }
+
+    let config = apply_sampling_preset(config, "grok")?;
+    let model = resolve_model(&config, "grok")?;
+    let max_completion_tokens = max_tokens(&config)?;
+    let tool_choice = forced_tool_choice(&config, "required")?;
     let options = config
         .provider_options
         .into_iter()
         .map(|kv| (kv.key, kv.value))
         .collect::<HashMap<_, _>>();
 
+    if options.contains_key("service_tier") {
+        return Err(unsupported("The service_tier option"));
+    }
+
     let mut completion_messages = Vec::new();
     for message in messages {
         match message.role {
@@ -46,11 +69,14 @@ pub fn messages_to_request(
 
     Ok(CompletionsRequest {
         messages: completion_messages,
-        model: config.model,
+        model,
         frequency_penalty: options
             .get("frequency_penalty")
             .and_then(|fp_s| fp_s.parse::<f32>().ok()),
-        max_completion_tokens: config.max_tokens,
+        logprobs: options
+            .get("logprobs")
+            .and_then(|logprobs_s| logprobs_s.parse::<bool>().ok()),
+        max_completion_tokens,
         n: options.get("n").and_then(|n_s| n_s.parse::<u32>().ok()),
         presence_penalty: options
             .get("presence_penalty")
@@ -65,7 +91,7 @@ pub fn messages_to_request(
         stream: Some(false),
         stream_options: None,
         temperature: config.temperature,
-        tool_choice: config.tool_choice,
+        tool_choice,
         tools,
         top_logprobs: options
             .get("top_logprobs")
@@ -86,6 +112,15 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
         if let Some(content) = &choice.message.content {
             contents.push(ContentPart::Text(content.clone()));
         }
+        // A refusal is returned as content like with OpenAI, finishing with content-filter
+        let refusal = choice
+            .message
+            .refusal
+            .as_ref()
+            .filter(|refusal| !refusal.is_empty());
+        if let Some(refusal) = refusal {
+            contents.push(ContentPart::Text(format!("Refusal: {refusal}")));
+        }
 
         let empty = Vec::new();
         for tool_call in choice.message.tool_calls.as_ref().unwrap_or(&empty) {
@@ -96,15 +131,24 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
             ChatEvent::ToolRequest(tool_calls)
         } else {
             let metadata = ResponseMetadata {
-                finish_reason: choice.finish_reason.as_ref().map(convert_finish_reason),
+                finish_reason: if refusal.is_some() {
+                    Some(FinishReason::ContentFilter)
+                } else {
+                    choice.finish_reason.as_ref().map(convert_finish_reason)
+                },
                 usage: response.usage.as_ref().map(convert_usage),
                 provider_id: None,
                 timestamp: Some(response.created.to_string()),
-                provider_metadata_json: None,
+                provider_metadata_json: choice
+                    .message
+                    .role
+                    .as_ref()
+                    .map(|role| serde_json::json!({ "role": role }).to_string()),
+                raw_json: None,
             };
 
             ChatEvent::Message(CompleteResponse {
-                id: response.id,
+                id: response_id(&response.id, &response),
                 content: contents,
                 tool_calls,
                 metadata,
@@ -121,9 +165,10 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
 
 pub fn tool_results_to_messages(
     tool_results: Vec<(ToolCall, ToolResult)>,
-) -> Vec<crate::client::Message> {
+) -> Result<Vec<crate::client::Message>, Error> {
     let mut messages = Vec::new();
     for (tool_call, tool_result) in tool_results {
+        check_tool_result_id(&tool_call, &tool_result)?;
         messages.push(crate::client::Message::Assistant {
             content: None,
             name: None,
@@ -137,9 +182,12 @@ pub fn tool_results_to_messages(
             }]),
         });
         let content = match tool_result {
-            ToolResult::Success(success) => crate::client::ContentPart::TextInput {
-                text: success.result_json,
-            },
+            ToolResult::Success(success) => {
+                check_tool_result_json(&success)?;
+                crate::client::ContentPart::TextInput {
+                    text: success.result_json,
+                }
+            }
             ToolResult::Error(failure) => crate::client::ContentPart::TextInput {
                 text: failure.error_message,
             },
@@ -150,7 +198,7 @@ pub fn tool_results_to_messages(
             tool_call_id: Some(tool_call.id),
         });
     }
-    messages
+    Ok(messages)
 }
 
 pub fn convert_tool_call(tool_call: &crate::client::ToolCall) -> ToolCall {
@@ -203,9 +251,27 @@ pub fn convert_usage(value: &crate::client::Usage) -> Usage {
         input_tokens: Some(value.prompt_tokens),
         output_tokens: Some(value.completion_tokens),
         total_tokens: Some(value.total_tokens),
+        reasoning_tokens: Some(value.completion_tokens_details.reasoning_tokens),
+        cached_tokens: Some(value.prompt_tokens_details.cached_tokens),
+        audio_tokens: Some(
+            value.prompt_tokens_details.audio_tokens + value.completion_tokens_details.audio_tokens,
+        ),
     }
 }
 
+/// Converts the log probabilities of a stream chunk's tokens to the delta's provider metadata
+pub fn convert_logprobs(logprobs: Option<crate::client::LogProbs>) -> Vec<TokenLogprob> {
+    logprobs
+        .and_then(|logprobs| logprobs.content)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|logprob| TokenLogprob {
+            token: logprob.token,
+            logprob: logprob.logprob,
+        })
+        .collect()
+}
+
 fn tool_definition_to_tool(tool: ToolDefinition) -> Result<crate::client::Tool, Error> {
     match serde_json::from_str(&tool.parameters_schema) {
         Ok(value) => Ok(crate::client::Tool::Function {
@@ -222,3 +288,135 @@ fn tool_definition_to_tool(tool: ToolDefinition) -> Result<crate::client::Tool,
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::conversions::{convert_usage, messages_to_request, process_response};
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Config, ContentPart, ErrorCode, FinishReason, Message, Role,
+    };
+    use golem_llm::test_config;
+
+    fn config(max_tokens: Option<u32>) -> Config {
+        Config {
+            model: "grok-3".to_string(),
+            max_tokens,
+            ..test_config::config(&[])
+        }
+    }
+
+    fn serialized_request(max_tokens: Option<u32>) -> serde_json::Value {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Hello".to_string())],
+        }];
+        let request = messages_to_request(messages, config(max_tokens)).unwrap();
+        serde_json::to_value(request).unwrap()
+    }
+
+    #[test]
+    fn max_tokens_is_sent_as_max_completion_tokens() {
+        let json = serialized_request(Some(128));
+
+        assert_eq!(json["max_completion_tokens"], 128);
+        assert!(json.get("max_tokens").is_none());
+    }
+
+    #[test]
+    fn missing_max_tokens_is_omitted() {
+        let json = serialized_request(None);
+
+        assert!(json.get("max_completion_tokens").is_none());
+        assert!(json.get("max_tokens").is_none());
+    }
+
+    #[test]
+    fn zero_max_tokens_is_rejected() {
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Hello".to_string())],
+        }];
+
+        let error = messages_to_request(messages, config(Some(0))).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn usage_preserves_token_details() {
+        let usage: crate::client::Usage = serde_json::from_str(
+            r#"{"completion_tokens":40,"completion_tokens_details":{"accepted_prediction_tokens":0,"audio_tokens":2,"reasoning_tokens":30,"rejected_prediction_tokens":0},"prompt_tokens":100,"prompt_tokens_details":{"audio_tokens":3,"cached_tokens":60,"image_tokens":0,"text_tokens":100},"total_tokens":140}"#,
+        )
+        .unwrap();
+
+        let usage = convert_usage(&usage);
+
+        assert_eq!(usage.reasoning_tokens, Some(30));
+        assert_eq!(usage.cached_tokens, Some(60));
+        assert_eq!(usage.audio_tokens, Some(5));
+    }
+
+    #[test]
+    fn response_message_role_is_reported_in_metadata() {
+        let response = serde_json::from_str(
+            r#"{"id":"chatcmpl_1","created":1,"model":"grok-3","system_fingerprint":null,"usage":null,"choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"Hello","reasoning_content":null,"refusal":null,"tool_calls":null}}]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                let provider_metadata: serde_json::Value =
+                    serde_json::from_str(&message.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(provider_metadata["role"], "assistant");
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn response_id_is_never_empty() {
+        for (id, expected) in [("chatcmpl_1", Some("chatcmpl_1")), ("", None)] {
+            let response = serde_json::from_str(&format!(
+                r#"{{"id":"{id}","created":1,"model":"grok-3","system_fingerprint":"fp","usage":null,"choices":[{{"index":0,"finish_reason":"stop","message":{{"role":"assistant","content":"Hello","reasoning_content":null,"refusal":null,"tool_calls":null}}}}]}}"#
+            ))
+            .unwrap();
+
+            match process_response(response) {
+                ChatEvent::Message(message) => {
+                    assert!(!message.id.is_empty());
+                    if let Some(expected) = expected {
+                        assert_eq!(message.id, expected);
+                    }
+                }
+                other => panic!("Unexpected event: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn refusal_is_content_finishing_with_content_filter() {
+        let response = serde_json::from_str(
+            r#"{"id":"chatcmpl_1","created":1,"model":"grok-3","system_fingerprint":null,"usage":null,"choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":null,"reasoning_content":null,"refusal":"I can't help with that.","tool_calls":null}}]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                assert_eq!(
+                    message.content,
+                    vec![ContentPart::Text(
+                        "Refusal: I can't help with that.".to_string()
+                    )]
+                );
+                assert_eq!(
+                    message.metadata.finish_reason,
+                    Some(FinishReason::ContentFilter)
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+}
@@ -3,27 +3,51 @@ mod conversions;
 
 use crate::client::{ChatCompletionChunk, CompletionsApi, CompletionsRequest, StreamOptions};
 use crate::conversions::{
-    convert_finish_reason, convert_tool_call, convert_usage, messages_to_request, process_response,
-    tool_results_to_messages,
+    convert_finish_reason, convert_logprobs, convert_tool_call, convert_usage, messages_to_request,
+    process_response, tool_results_to_messages,
+};
+use golem_llm::chat_stream::{
+    attach_raw_enabled, candidate_metadata, emit_heartbeat_enabled, sentence_flush_timeout,
+    timing_enabled, unknown_event_type, LlmChatStream, LlmChatStreamState,
 };
-use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
 use golem_llm::config::with_config_key;
 use golem_llm::durability::{DurableLLM, ExtendedGuest};
+use golem_llm::error::unsupported;
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, Message,
-    ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, ImageGenConfig,
+    ImageResult, Message, ResponseMetadata, StreamDelta, StreamEvent, ToolCall, ToolResult,
 };
+use golem_llm::trace_id::TRACE_ID;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
+use serde::Serialize;
 use std::cell::{Ref, RefCell, RefMut};
+use std::collections::{BTreeMap, BTreeSet};
 
 struct GrokChatStream {
     stream: RefCell<Option<EventSource>>,
     failure: Option<Error>,
     finished: RefCell<bool>,
+    /// The finish reason of the first candidate, reported in the finish event
     finish_reason: RefCell<Option<FinishReason>>,
+    /// The finish reasons of all the candidates seen so far by choice index, when `n` requests
+    /// multiple candidates
+    candidates: RefCell<BTreeMap<u32, Option<crate::client::FinishReason>>>,
+    /// The choice indexes of the candidates that are refusals
+    refusals: RefCell<BTreeSet<u32>>,
+}
+
+#[derive(Serialize)]
+struct CandidateMetadata {
+    index: u32,
+    finish_reason: Option<crate::client::FinishReason>,
+}
+
+#[derive(Serialize)]
+struct FinishMetadata {
+    candidates: Vec<CandidateMetadata>,
 }
 
 impl GrokChatStream {
@@ -33,15 +57,37 @@ impl GrokChatStream {
             failure: None,
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
+            candidates: RefCell::new(BTreeMap::new()),
+            refusals: RefCell::new(BTreeSet::new()),
         })
     }
 
+    /// The `provider_metadata_json` of the finish event, listing the finish reason of each
+    /// candidate if multiple candidates were generated
+    fn finish_metadata(&self) -> Option<String> {
+        let candidates = self.candidates.borrow();
+        if candidates.len() > 1 {
+            let candidates = candidates
+                .iter()
+                .map(|(index, finish_reason)| CandidateMetadata {
+                    index: *index,
+                    finish_reason: finish_reason.clone(),
+                })
+                .collect();
+            serde_json::to_string(&FinishMetadata { candidates }).ok()
+        } else {
+            None
+        }
+    }
+
     pub fn failed(error: Error) -> LlmChatStream<Self> {
         LlmChatStream::new(GrokChatStream {
             stream: RefCell::new(None),
             failure: Some(error),
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
+            candidates: RefCell::new(BTreeMap::new()),
+            refusals: RefCell::new(BTreeSet::new()),
         })
     }
 }
@@ -68,7 +114,12 @@ impl LlmChatStreamState for GrokChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
+        self.decode_events(raw)
+            .map(|stream_events| stream_events.into_iter().next())
+    }
+
+    fn decode_events(&self, raw: &str) -> Result<Vec<StreamEvent>, String> {
+        trace!("{TRACE_ID}Received raw stream event: {raw}");
         let json: serde_json::Value = serde_json::from_str(raw)
             .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
 
@@ -80,38 +131,95 @@ impl LlmChatStreamState for GrokChatStream {
             Some("chat.completion.chunk") => {
                 let message: ChatCompletionChunk = serde_json::from_value(json)
                     .map_err(|err| format!("Failed to parse stream event: {err}"))?;
-                if let Some(choice) = message.choices.into_iter().next() {
-                    if let Some(finish_reason) = choice.finish_reason {
-                        *self.finish_reason.borrow_mut() =
-                            Some(convert_finish_reason(&finish_reason));
-                    }
-                    Ok(Some(StreamEvent::Delta(StreamDelta {
-                        content: choice
-                            .delta
-                            .content
-                            .map(|text| vec![ContentPart::Text(text)]),
-                        tool_calls: choice
-                            .delta
-                            .tool_calls
-                            .map(|calls| calls.iter().map(convert_tool_call).collect()),
-                    })))
+                if !message.choices.is_empty() {
+                    // With `n` above 1 the deltas of the candidates are interleaved, each
+                    // routed by its choice index
+                    Ok(message
+                        .choices
+                        .into_iter()
+                        .map(|choice| {
+                            // A refusal is streamed as content, starting with `Refusal: ` like
+                            // in complete responses, and finishes with content-filter
+                            let refusal = choice
+                                .delta
+                                .refusal
+                                .filter(|refusal| !refusal.is_empty())
+                                .map(|refusal| {
+                                    if self.refusals.borrow_mut().insert(choice.index) {
+                                        format!("Refusal: {refusal}")
+                                    } else {
+                                        refusal
+                                    }
+                                });
+                            let mut candidates = self.candidates.borrow_mut();
+                            let candidate_finish_reason =
+                                candidates.entry(choice.index).or_default();
+                            if let Some(finish_reason) = choice.finish_reason {
+                                if choice.index == 0 {
+                                    *self.finish_reason.borrow_mut() =
+                                        Some(if self.refusals.borrow().contains(&0) {
+                                            FinishReason::ContentFilter
+                                        } else {
+                                            convert_finish_reason(&finish_reason)
+                                        });
+                                }
+                                *candidate_finish_reason = Some(finish_reason);
+                            }
+                            let content = choice
+                                .delta
+                                .content
+                                .into_iter()
+                                .chain(refusal)
+                                .map(ContentPart::Text)
+                                .collect::<Vec<_>>();
+                            StreamEvent::Delta(StreamDelta {
+                                content: (!content.is_empty()).then_some(content),
+                                tool_calls: choice
+                                    .delta
+                                    .tool_calls
+                                    .map(|calls| calls.iter().map(convert_tool_call).collect()),
+                                provider_metadata_json: candidate_metadata(
+                                    choice.index,
+                                    convert_logprobs(choice.logprobs),
+                                ),
+                                raw_json: None,
+                            })
+                        })
+                        .collect())
                 } else if let Some(usage) = message.usage {
                     let finish_reason = self.finish_reason.borrow();
-                    Ok(Some(StreamEvent::Finish(ResponseMetadata {
+                    Ok(vec![StreamEvent::Finish(ResponseMetadata {
                         finish_reason: *finish_reason,
                         usage: Some(convert_usage(&usage)),
                         provider_id: None,
                         timestamp: Some(message.created.to_string()),
-                        provider_metadata_json: None,
-                    })))
+                        provider_metadata_json: self.finish_metadata(),
+                        raw_json: None,
+                    })])
                 } else {
-                    Ok(None)
+                    Ok(vec![])
                 }
             }
-            Some(_) => Ok(None),
+            Some(typ) => {
+                unknown_event_type(typ).map(|stream_event| stream_event.into_iter().collect())
+            }
             None => Err("Unexpected stream event format, does not have 'object' field".to_string()),
         }
     }
+
+    fn end_of_stream(&self) -> Option<StreamEvent> {
+        // Some proxies close the stream without sending the final usage chunk
+        self.finish_reason.borrow().map(|finish_reason| {
+            StreamEvent::Finish(ResponseMetadata {
+                finish_reason: Some(finish_reason),
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: self.finish_metadata(),
+                raw_json: None,
+            })
+        })
+    }
 }
 
 struct GrokComponent;
@@ -148,7 +256,7 @@ impl Guest for GrokComponent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |xai_api_key| {
-            let client = CompletionsApi::new(xai_api_key);
+            let client = CompletionsApi::new(xai_api_key, &config.provider_options);
 
             match messages_to_request(messages, config) {
                 Ok(request) => Self::request(client, request),
@@ -165,15 +273,16 @@ impl Guest for GrokComponent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |xai_api_key| {
-            let client = CompletionsApi::new(xai_api_key);
+            let client = CompletionsApi::new(xai_api_key, &config.provider_options);
 
             match messages_to_request(messages, config) {
-                Ok(mut request) => {
-                    request
-                        .messages
-                        .extend(tool_results_to_messages(tool_results));
-                    Self::request(client, request)
-                }
+                Ok(mut request) => match tool_results_to_messages(tool_results) {
+                    Ok(tool_messages) => {
+                        request.messages.extend(tool_messages);
+                        Self::request(client, request)
+                    }
+                    Err(err) => ChatEvent::Error(err),
+                },
                 Err(err) => ChatEvent::Error(err),
             }
         })
@@ -182,20 +291,32 @@ impl Guest for GrokComponent {
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn generate_image(_prompt: String, _config: ImageGenConfig) -> Result<Vec<ImageResult>, Error> {
+        Err(unsupported("Image generation"))
+    }
 }
 
 impl ExtendedGuest for GrokComponent {
     fn unwrapped_stream(messages: Vec<Message>, config: Config) -> LlmChatStream<GrokChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        let attach_raw = attach_raw_enabled(&config);
+        let timing = timing_enabled(&config);
+        let heartbeat = emit_heartbeat_enabled(&config);
+        let sentence_flush = sentence_flush_timeout(&config);
         with_config_key(Self::ENV_VAR_NAME, GrokChatStream::failed, |xai_api_key| {
-            let client = CompletionsApi::new(xai_api_key);
+            let client = CompletionsApi::new(xai_api_key, &config.provider_options);
 
             match messages_to_request(messages, config) {
                 Ok(request) => Self::streaming_request(client, request),
                 Err(err) => GrokChatStream::failed(err),
             }
         })
+        .with_raw_payloads(attach_raw)
+        .with_timing(timing)
+        .with_sentence_flush(sentence_flush)
+        .with_heartbeat(heartbeat)
     }
 
     fn subscribe(stream: &Self::ChatStream) -> Pollable {
@@ -206,3 +327,194 @@ impl ExtendedGuest for GrokComponent {
 type DurableGrokComponent = DurableLLM<GrokComponent>;
 
 golem_llm::export_llm!(DurableGrokComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::conversions::tool_results_to_messages;
+    use crate::GrokChatStream;
+    use golem_llm::chat_stream::{
+        collect_events, delta_candidate_index, delta_logprobs, LlmChatStreamState,
+    };
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, ContentPart, FinishReason, StreamEvent, ToolResult, ToolSuccess,
+    };
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn stream_state() -> GrokChatStream {
+        GrokChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            finish_reason: RefCell::new(None),
+            candidates: RefCell::new(BTreeMap::new()),
+            refusals: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    #[test]
+    fn finish_is_emitted_at_end_of_stream_without_usage() {
+        let state = stream_state();
+
+        let delta = state
+            .decode_message(
+                r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":0,"delta":{"content":"Hi","role":"assistant"},"finish_reason":"stop"}],"usage":null,"system_fingerprint":"fp"}"#,
+            )
+            .unwrap();
+        assert!(matches!(delta, Some(StreamEvent::Delta(_))));
+
+        match state.end_of_stream() {
+            Some(StreamEvent::Finish(metadata)) => {
+                assert_eq!(metadata.finish_reason, Some(FinishReason::Stop));
+                assert_eq!(metadata.usage, None);
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn nothing_is_emitted_at_end_of_stream_without_finish_reason() {
+        assert_eq!(stream_state().end_of_stream(), None);
+    }
+
+    #[test]
+    fn chunk_logprobs_are_attached_to_the_delta() {
+        let state = stream_state();
+
+        let event = state
+            .decode_message(
+                r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":0,"delta":{"content":"Hi there","role":"assistant"},"finish_reason":null,"logprobs":{"content":[{"token":"Hi","logprob":-0.2,"top_logprobs":[]},{"token":" there","logprob":-1.5,"top_logprobs":[]}]}}],"usage":null,"system_fingerprint":"fp"}"#,
+            )
+            .unwrap();
+
+        match event {
+            Some(StreamEvent::Delta(delta)) => {
+                let logprobs = delta_logprobs(&delta);
+                assert_eq!(logprobs.len(), 2);
+                assert_eq!(logprobs[1].token, " there");
+                assert_eq!(logprobs[1].logprob, -1.5);
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn streamed_tool_call_id_correlates_the_result() {
+        let state = stream_state();
+
+        let event = state
+            .decode_message(
+                r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":0,"delta":{"role":"assistant","tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"Berlin\"}"},"index":0}]},"finish_reason":"tool_calls"}],"usage":null,"system_fingerprint":"fp"}"#,
+            )
+            .unwrap();
+        let tool_call = match event {
+            Some(StreamEvent::Delta(delta)) => delta.tool_calls.unwrap().remove(0),
+            other => panic!("Unexpected event: {other:?}"),
+        };
+        assert_eq!(tool_call.id, "call_1");
+
+        let result = ToolResult::Success(ToolSuccess {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            result_json: r#"{"temperature":21}"#.to_string(),
+            execution_time_ms: None,
+        });
+        let messages =
+            serde_json::to_value(tool_results_to_messages(vec![(tool_call, result)]).unwrap())
+                .unwrap();
+
+        assert_eq!(messages[0]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(messages[1]["tool_call_id"], "call_1");
+    }
+
+    #[test]
+    fn interleaved_candidates_are_routed_by_choice_index() {
+        let state = stream_state();
+
+        let events: Vec<StreamEvent> = [
+            r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":0,"delta":{"content":"Hello","role":"assistant"},"finish_reason":null}],"usage":null,"system_fingerprint":"fp"}"#,
+            r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":1,"delta":{"content":"Hi","role":"assistant"},"finish_reason":null}],"usage":null,"system_fingerprint":"fp"}"#,
+            r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":1,"delta":{"content":" there"},"finish_reason":"length"},{"index":0,"delta":{"content":" world"},"finish_reason":"stop"}],"usage":null,"system_fingerprint":"fp"}"#,
+            r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[],"usage":{"prompt_tokens":5,"completion_tokens":4,"total_tokens":9,"prompt_tokens_details":{"text_tokens":5,"audio_tokens":0,"image_tokens":0,"cached_tokens":0},"completion_tokens_details":{"reasoning_tokens":0,"audio_tokens":0,"accepted_prediction_tokens":0,"rejected_prediction_tokens":0}},"system_fingerprint":"fp"}"#,
+        ]
+        .into_iter()
+        .flat_map(|raw| state.decode_events(raw).unwrap())
+        .collect();
+
+        let candidate_text = |candidate_index: u32| -> String {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    StreamEvent::Delta(delta)
+                        if delta_candidate_index(delta) == candidate_index =>
+                    {
+                        delta.content.clone()
+                    }
+                    _ => None,
+                })
+                .flatten()
+                .map(|part| match part {
+                    ContentPart::Text(text) => text,
+                    ContentPart::Image(_) => panic!("Unexpected image"),
+                })
+                .collect()
+        };
+        assert_eq!(candidate_text(0), "Hello world");
+        assert_eq!(candidate_text(1), "Hi there");
+
+        match events.last() {
+            Some(StreamEvent::Finish(metadata)) => {
+                assert_eq!(metadata.finish_reason, Some(FinishReason::Stop));
+                let candidates: serde_json::Value =
+                    serde_json::from_str(metadata.provider_metadata_json.as_ref().unwrap())
+                        .unwrap();
+                assert_eq!(
+                    candidates,
+                    serde_json::json!({"candidates": [
+                        {"index": 0, "finish_reason": "stop"},
+                        {"index": 1, "finish_reason": "length"}
+                    ]})
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+
+        match collect_events(events) {
+            ChatEvent::Message(response) => assert_eq!(
+                response.content,
+                vec![ContentPart::Text("Hello world".to_string())]
+            ),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn streamed_refusal_is_content_finishing_with_content_filter() {
+        let state = stream_state();
+
+        let contents = [
+            r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":0,"delta":{"refusal":"I can't help","role":"assistant"},"finish_reason":null}],"usage":null,"system_fingerprint":"fp"}"#,
+            r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"grok-3","choices":[{"index":0,"delta":{"refusal":" with that.","role":"assistant"},"finish_reason":"stop"}],"usage":null,"system_fingerprint":"fp"}"#,
+        ]
+        .iter()
+        .map(|raw| match state.decode_message(raw).unwrap() {
+            Some(StreamEvent::Delta(delta)) => delta.content,
+            other => panic!("Unexpected event: {other:?}"),
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            contents,
+            vec![
+                Some(vec![ContentPart::Text("Refusal: I can't help".to_string())]),
+                Some(vec![ContentPart::Text(" with that.".to_string())]),
+            ]
+        );
+        match state.end_of_stream() {
+            Some(StreamEvent::Finish(metadata)) => {
+                assert_eq!(metadata.finish_reason, Some(FinishReason::ContentFilter));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+}
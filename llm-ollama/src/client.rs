@@ -1,116 +1,90 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::error::{error_code_from_status, error_from_body, parse_response, ErrorBody};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::Error;
+use golem_llm::golem::llm::llm::{Error, Kv};
+use golem_llm::trace_id::TRACE_ID;
+use golem_llm::transport::{
+    mime_type_from_url_extension, transport_for, HttpRequest, HttpResponse, HttpTransport,
+};
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response};
-use serde::de::DeserializeOwned;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::fmt::Debug;
 
 const DEFAULT_BASE_URL: &str = "http://localhost:11434";
 
 /// The Ollama API client for creating model responses.
 pub struct OllamaApi {
     base_url: String,
-    client: Client,
+    transport: Box<dyn HttpTransport>,
 }
 
 impl OllamaApi {
-    pub fn new() -> Self {
+    pub fn new(provider_options: &[Kv]) -> Self {
         let base_url =
             std::env::var("OLLAMA_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
-        Self::with_base_url(base_url)
+        Self::with_base_url(base_url, provider_options)
     }
 
-    pub fn with_base_url(base_url: String) -> Self {
-        let client = Client::builder()
-            .build()
-            .expect("Failed to initialize HTTP client");
-        Self { base_url, client }
+    pub fn with_base_url(base_url: String, provider_options: &[Kv]) -> Self {
+        Self::with_transport(base_url, transport_for(provider_options))
     }
 
-    pub fn image_url_to_base64(&self, url: &str) -> Result<String, Error> {
-        use base64::engine::general_purpose;
-        use base64::Engine;
-
-        let response = self
-            .client
-            .get(url)
-            .timeout(std::time::Duration::from_secs(10))
-            .send()
-            .map_err(|err| from_reqwest_error("Failed to download image", err))?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Error {
-                code: error_code_from_status(status),
-                message: format!("Failed to fetch image: {}", status),
-                provider_error_json: None,
-            });
+    pub fn with_transport(base_url: String, transport: Box<dyn HttpTransport>) -> Self {
+        Self {
+            base_url,
+            transport,
         }
+    }
 
-        let mime_type = response
-            .headers()
-            .get(reqwest::header::CONTENT_TYPE)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("image/png")
-            .to_string();
-        let bytes = response
-            .bytes()
-            .map_err(|err| from_reqwest_error("Failed to read image bytes", err))?;
-
-        let encoded = general_purpose::STANDARD.encode(&bytes);
-
-        Ok(format!("data:{};base64,{}", mime_type, encoded))
+    /// Downloads the images at `urls` as a batch and encodes them as base64 data URLs. The
+    /// results are in the order of `urls`.
+    pub fn images_to_base64(&self, urls: &[String]) -> Vec<Result<String, Error>> {
+        let requests = urls
+            .iter()
+            .map(|url| HttpRequest::get(url).timeout(std::time::Duration::from_secs(10)))
+            .collect();
+
+        self.transport
+            .send_all(requests)
+            .into_iter()
+            .zip(urls)
+            .map(|(response, url)| {
+                response.and_then(|response| image_response_to_base64(url, response))
+            })
+            .collect()
     }
 
     pub fn send_messages(&self, request: OllamaChatRequest) -> Result<OllamaChatResponse, Error> {
-        trace!("Sending chat request to Ollama API: {request:?}");
+        trace!("{TRACE_ID}Sending chat request to Ollama API: {request:?}");
 
         let mut stream_request = request;
         stream_request.stream = false;
 
-        let response: Response = self
-            .client
-            .request(
-                Method::POST,
-                format!("{}/v1/chat/completions", self.base_url),
+        let response = self
+            .transport
+            .send(
+                HttpRequest::post(format!("{}/v1/chat/completions", self.base_url))
+                    .json(&stream_request)?,
             )
-            .json(&stream_request)
-            .send()
-            .map_err(|err| {
-                log::error!("Failed to send HTTP request to Ollama: {err:?}");
-                from_reqwest_error("Request failed", err)
+            .inspect_err(|err| {
+                log::error!("{TRACE_ID}Failed to send HTTP request to Ollama: {err:?}");
             })?;
 
-        parse_response(response)
+        parse_response::<_, OllamaErrorResponse>("Ollama API", response)
     }
 
     pub fn stream_send_messages(&self, request: OllamaChatRequest) -> Result<EventSource, Error> {
-        trace!("Sending streaming chat request to Ollama API: {request:?}");
+        trace!("{TRACE_ID}Sending streaming chat request to Ollama API: {request:?}");
         let mut stream_request = request;
         stream_request.stream = true;
 
-        let response: Response = self
-            .client
-            .request(
-                Method::POST,
-                format!("{}/v1/chat/completions", self.base_url),
-            )
-            .header(
-                reqwest::header::ACCEPT,
-                HeaderValue::from_static("text/event-stream"),
-            )
-            .json(&stream_request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
+        let request = HttpRequest::post(format!("{}/v1/chat/completions", self.base_url))
+            .header(reqwest::header::ACCEPT.as_str(), "text/event-stream")
+            .json(&stream_request)?;
 
-        trace!("Initializing SSE stream");
+        trace!("{TRACE_ID}Initializing SSE stream");
 
-        EventSource::new(response)
-            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+        self.transport.stream(request)
     }
 }
 
@@ -140,6 +114,16 @@ pub struct OllamaChatRequest {
     pub max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_alive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub think: Option<bool>,
+    /// GBNF grammar constraining the output, as named by the llama.cpp server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+    /// The same grammar, as named by vLLM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guided_grammar: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guided_regex: Option<String>,
     pub stream: bool,
 }
 
@@ -150,6 +134,9 @@ pub struct OllamaMessage {
     pub content: OllamaMessageContent,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<OllamaToolCall>>,
+    /// The id of the tool call a `tool` message is the result of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -229,6 +216,12 @@ pub struct OllamaError {
     pub code: Option<String>,
 }
 
+impl ErrorBody for OllamaErrorResponse {
+    fn into_error(self, status: StatusCode) -> Error {
+        error_from_body(status, &self.error.message, &self)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OllamaChatResponse {
     pub id: String,
@@ -253,6 +246,9 @@ pub struct OllamaMessageContent {
     pub content: Option<MessageContentPayload>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<OllamaToolCall>>,
+    /// The thinking output of reasoning models, when enabled with `think`
+    #[serde(default, alias = "thinking", skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -286,29 +282,104 @@ pub struct OllamaDeltaMessageContent {
     pub content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<OllamaToolCall>>,
+    #[serde(default, alias = "thinking", skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
 }
 
-fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
-    let status = response.status();
-    if status.is_success() {
-        let body = response
-            .json::<T>()
-            .map_err(|err| from_reqwest_error("Failed to decode response body", err))?;
+fn image_response_to_base64(url: &str, response: HttpResponse) -> Result<String, Error> {
+    use base64::engine::general_purpose;
+    use base64::Engine;
 
-        trace!("Received response from Ollama API: {body:?}");
+    let status = response.status;
+    if !status.is_success() {
+        return Err(Error {
+            code: error_code_from_status(status),
+            message: format!("Failed to fetch image: {}", status),
+            provider_error_json: None,
+        });
+    }
 
-        Ok(body)
-    } else {
-        let error_body = response
-            .json::<OllamaErrorResponse>()
-            .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
+    let mime_type = response
+        .header(reqwest::header::CONTENT_TYPE.as_str())
+        .or_else(|| mime_type_from_url_extension(url))
+        .unwrap_or("image/png")
+        .to_string();
 
-        trace!("Received {status} response from Ollama API: {error_body:?}");
+    let encoded = general_purpose::STANDARD.encode(&response.body);
 
-        Err(Error {
-            code: error_code_from_status(status),
-            message: format!("Request failed with {status}: {}", error_body.error.message),
-            provider_error_json: Some(serde_json::to_string(&error_body).unwrap()),
-        })
+    Ok(format!("data:{};base64,{}", mime_type, encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{OllamaApi, OllamaChatRequest};
+    use golem_llm::golem::llm::llm::ErrorCode;
+    use golem_llm::transport::{CannedTransport, HttpResponse};
+    use reqwest::StatusCode;
+
+    #[test]
+    fn html_error_body_is_passed_through() {
+        let page = "<html><body><h1>502 Bad Gateway</h1></body></html>";
+        let transport =
+            CannedTransport::new([Ok(HttpResponse::new(StatusCode::BAD_GATEWAY, page)
+                .with_header("content-type", "text/html"))]);
+        let api = OllamaApi::with_transport("http://localhost".to_string(), Box::new(transport));
+
+        let error = api
+            .send_messages(OllamaChatRequest {
+                model: "llama3".to_string(),
+                messages: vec![],
+                tools: None,
+                tool_choice: None,
+                response_format: None,
+                temperature: None,
+                top_p: None,
+                stop: None,
+                frequency_penalty: None,
+                presence_penalty: None,
+                seed: None,
+                max_tokens: None,
+                keep_alive: None,
+                think: None,
+                grammar: None,
+                guided_grammar: None,
+                guided_regex: None,
+                stream: false,
+            })
+            .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert_eq!(
+            error.provider_error_json,
+            Some(serde_json::json!({ "body": page, "transient": true }).to_string())
+        );
+    }
+
+    #[test]
+    fn image_mime_type_is_inferred_from_url_without_content_type() {
+        let transport = CannedTransport::new([
+            Ok(HttpResponse::new(StatusCode::OK, "first")),
+            Ok(HttpResponse::new(StatusCode::OK, "second")),
+            Ok(HttpResponse::new(StatusCode::OK, "third")),
+        ]);
+        let api = OllamaApi::with_transport("http://localhost".to_string(), Box::new(transport));
+
+        let images = api.images_to_base64(
+            &[
+                "https://example.com/photo.JPG?size=large".to_string(),
+                "https://example.com/image.webp".to_string(),
+                "https://example.com/image".to_string(),
+            ],
+            1,
+        );
+
+        assert_eq!(
+            images,
+            vec![
+                Ok("data:image/jpeg;base64,Zmlyc3Q=".to_string()),
+                Ok("data:image/webp;base64,c2Vjb25k".to_string()),
+                Ok("data:image/png;base64,dGhpcmQ=".to_string()),
+            ]
+        );
     }
 }
@@ -1,12 +1,22 @@
 use crate::client::{
     ContentPart, MessageContentPayload, OllamaApi, OllamaChatRequest, OllamaChatResponse,
     OllamaFunction, OllamaMessage, OllamaMessageContent, OllamaTool, OllamaToolCall,
-    OllamaToolCallFunction, ToolChoice,
+    OllamaToolCallFunction, OllamaUsage, ToolChoice,
+};
+use golem_llm::config::{
+    apply_sampling_preset, builtin_tools, max_tokens, output_constraint, resolve_model,
+    OutputConstraint,
+};
+use golem_llm::error::{
+    check_tool_result_id, check_tool_result_json, parse_tool_call_arguments, unsupported,
 };
 use golem_llm::golem::llm::llm::{
     ChatEvent, CompleteResponse, Config, ContentPart as GolemContentPart, Error, ErrorCode,
     FinishReason, Message, ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
 };
+use golem_llm::response_id::response_id;
+use golem_llm::tool_call_only::forced_tool_choice;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 
 pub fn messages_to_request(
@@ -14,12 +24,29 @@ pub fn messages_to_request(
     config: Config,
     api: &OllamaApi,
 ) -> Result<OllamaChatRequest, Error> {
+    if let Some(name) = builtin_tools(&config).first() {
+        return Err(unsupported(format!("Built-in tool {name}")));
+    }
+
+    let config = apply_sampling_preset(config, "ollama")?;
+    let model = resolve_model(&config, "ollama")?;
+    let max_tokens = max_tokens(&config)?;
+    let tool_choice = forced_tool_choice(&config, "required")?;
+    let (grammar, regex) = match output_constraint(&config)? {
+        Some(OutputConstraint::Grammar(grammar)) => (Some(grammar), None),
+        Some(OutputConstraint::Regex(regex)) => (None, Some(regex)),
+        None => (None, None),
+    };
     let options = config
         .provider_options
         .iter()
         .map(|kv| (kv.key.clone(), kv.value.clone()))
         .collect::<HashMap<_, _>>();
 
+    if options.contains_key("service_tier") {
+        return Err(unsupported("The service_tier option"));
+    }
+
     let mut ollama_messages = Vec::new();
     for message in messages {
         ollama_messages.push(message_to_ollama_message(message, api)?);
@@ -35,21 +62,19 @@ pub fn messages_to_request(
         Some(tools)
     };
 
-    let tool_choice = if let Some(tc) = config.tool_choice.as_ref() {
-        if tc == "none" || tc == "auto" {
-            Some(ToolChoice::String(tc.clone()))
+    let tool_choice = tool_choice.map(|tc| {
+        if tc == "none" || tc == "auto" || tc == "required" {
+            ToolChoice::String(tc)
         } else {
-            Some(ToolChoice::Object {
+            ToolChoice::Object {
                 typ: "function".to_string(),
-                function: crate::client::OllamaFunctionChoice { name: tc.clone() },
-            })
+                function: crate::client::OllamaFunctionChoice { name: tc },
+            }
         }
-    } else {
-        None
-    };
+    });
 
     Ok(OllamaChatRequest {
-        model: config.model,
+        model,
         messages: ollama_messages,
         tools,
         tool_choice,
@@ -62,8 +87,12 @@ pub fn messages_to_request(
             .and_then(|v| v.parse().ok()),
         presence_penalty: options.get("presence_penalty").and_then(|v| v.parse().ok()),
         seed: options.get("seed").and_then(|v| v.parse().ok()),
-        max_tokens: config.max_tokens,
+        max_tokens,
         keep_alive: options.get("keep_alive").cloned(),
+        think: options.get("think").and_then(|v| v.parse().ok()),
+        guided_grammar: grammar.clone(),
+        grammar,
+        guided_regex: regex,
         stream: false,
     })
 }
@@ -76,6 +105,22 @@ fn message_to_ollama_message(message: Message, api: &OllamaApi) -> Result<Ollama
         Role::Tool => "tool".to_string(),
     };
 
+    // All the images of the message are downloaded in a single batch before assembling the parts
+    let image_urls = message
+        .content
+        .iter()
+        .filter_map(|part| match part {
+            GolemContentPart::Image(image) => Some(image.url.clone()),
+            GolemContentPart::Text(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let mut inlined_images = if image_urls.is_empty() {
+        Vec::new()
+    } else {
+        api.images_to_base64(&image_urls)
+    }
+    .into_iter();
+
     let mut images = false;
     let mut content = String::new();
     let mut parts = Vec::new();
@@ -88,7 +133,7 @@ fn message_to_ollama_message(message: Message, api: &OllamaApi) -> Result<Ollama
                 }
                 content.push_str(&text);
             }
-            GolemContentPart::Image(image) => {
+            GolemContentPart::Image(_) => {
                 images = true;
 
                 if !content.is_empty() {
@@ -97,7 +142,9 @@ fn message_to_ollama_message(message: Message, api: &OllamaApi) -> Result<Ollama
                     });
                 }
 
-                let base64 = api.image_url_to_base64(&image.url)?;
+                let base64 = inlined_images
+                    .next()
+                    .expect("Every image part has a download result")?;
                 parts.push(ContentPart::ImageUrl {
                     image_url: crate::client::ImageUrl {
                         url: base64,
@@ -125,8 +172,10 @@ fn message_to_ollama_message(message: Message, api: &OllamaApi) -> Result<Ollama
             role,
             content: Some(final_content),
             tool_calls: None,
+            reasoning: None,
         },
         tool_calls: None,
+        tool_call_id: None,
     })
 }
 
@@ -164,22 +213,21 @@ pub fn process_response(response: OllamaChatResponse) -> ChatEvent {
         }
     };
 
-    if let Some(tool_calls) = &choice.message.tool_calls {
-        if !tool_calls.is_empty() {
-            let tool_calls = tool_calls
-                .iter()
-                .map(|tc| ToolCall {
-                    id: tc.id.clone(),
-                    name: tc.function.name.clone(),
-                    arguments_json: tc.function.arguments.to_string(),
-                })
-                .collect();
-
-            return ChatEvent::ToolRequest(tool_calls);
-        }
-    }
+    let tool_calls: Vec<ToolCall> = choice
+        .message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|tc| ToolCall {
+            id: tc.id.clone(),
+            name: tc.function.name.clone(),
+            arguments_json: tc.function.arguments.to_string(),
+        })
+        .collect();
 
     let content = match &choice.message.content {
+        // Tool calling responses come with an empty content if there is no accompanying text
+        Some(MessageContentPayload::Text { content }) if content.is_empty() => vec![],
         Some(MessageContentPayload::Text { content }) => {
             vec![GolemContentPart::Text(content.clone())]
         }
@@ -203,6 +251,11 @@ pub fn process_response(response: OllamaChatResponse) -> ChatEvent {
         None => vec![],
     };
 
+    // Text accompanying the tool calls is preserved by returning both in a message
+    if content.is_empty() && !tool_calls.is_empty() {
+        return ChatEvent::ToolRequest(tool_calls);
+    }
+
     let finish_reason = choice.finish_reason.as_deref().map(|reason| match reason {
         "stop" => FinishReason::Stop,
         "length" => FinishReason::Length,
@@ -215,6 +268,9 @@ pub fn process_response(response: OllamaChatResponse) -> ChatEvent {
         input_tokens: Some(u.prompt_tokens),
         output_tokens: Some(u.completion_tokens),
         total_tokens: Some(u.total_tokens),
+        reasoning_tokens: None,
+        cached_tokens: None,
+        audio_tokens: None,
     });
 
     let metadata = ResponseMetadata {
@@ -222,27 +278,58 @@ pub fn process_response(response: OllamaChatResponse) -> ChatEvent {
         usage,
         provider_id: Some(response.id.clone()),
         timestamp: Some(response.created.to_string()),
-        provider_metadata_json: Some(serde_json::to_string(&response.usage).unwrap_or_default()),
+        provider_metadata_json: Some(response_metadata_json(
+            &response.usage,
+            &choice.message.role,
+            choice.message.reasoning.as_deref(),
+        )),
+        raw_json: None,
     };
 
     ChatEvent::Message(CompleteResponse {
-        id: response.id.clone(),
+        id: response_id(&response.id, &response),
         content,
-        tool_calls: Vec::new(),
+        tool_calls,
         metadata,
     })
 }
 
-pub fn tool_results_to_messages(tool_results: Vec<(ToolCall, ToolResult)>) -> Vec<OllamaMessage> {
+/// The provider metadata of a complete response: the usage as reported by Ollama, the role of
+/// the response message under the `role` key, and the thinking output of reasoning models under
+/// the `reasoning` key
+fn response_metadata_json(
+    usage: &Option<OllamaUsage>,
+    role: &str,
+    reasoning: Option<&str>,
+) -> String {
+    let mut fields = match serde_json::to_value(usage) {
+        Ok(Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    fields.insert("role".to_string(), json!(role));
+    if let Some(reasoning) = reasoning.filter(|reasoning| !reasoning.is_empty()) {
+        fields.insert("reasoning".to_string(), json!(reasoning));
+    }
+    Value::Object(fields).to_string()
+}
+
+/// The provider metadata of a stream delta carrying thinking output
+pub fn reasoning_metadata(reasoning: &str) -> String {
+    json!({ "reasoning": reasoning }).to_string()
+}
+
+pub fn tool_results_to_messages(
+    tool_results: Vec<(ToolCall, ToolResult)>,
+) -> Result<Vec<OllamaMessage>, Error> {
     let mut messages = Vec::new();
 
     for (tool_call, tool_result) in tool_results {
+        check_tool_result_id(&tool_call, &tool_result)?;
         let tool_call_obj = OllamaToolCall {
             id: tool_call.id.clone(),
             function: OllamaToolCallFunction {
                 name: tool_call.name.clone(),
-                arguments: serde_json::from_str(&tool_call.arguments_json)
-                    .unwrap_or(serde_json::Value::Null),
+                arguments: parse_tool_call_arguments(&tool_call)?,
             },
         };
 
@@ -255,12 +342,17 @@ pub fn tool_results_to_messages(tool_results: Vec<(ToolCall, ToolResult)>) -> Ve
                     content: "".to_string(),
                 }),
                 tool_calls: Some(vec![tool_call_obj]),
+                reasoning: None,
             },
             tool_calls: None,
+            tool_call_id: None,
         });
 
         let result_content = match tool_result {
-            ToolResult::Success(success) => success.result_json,
+            ToolResult::Success(success) => {
+                check_tool_result_json(&success)?;
+                success.result_json
+            }
             ToolResult::Error(error) => format!("Error: {}", error.error_message),
         };
 
@@ -272,10 +364,317 @@ pub fn tool_results_to_messages(tool_results: Vec<(ToolCall, ToolResult)>) -> Ve
                     content: result_content,
                 }),
                 tool_calls: None,
+                reasoning: None,
             },
             tool_calls: None,
+            tool_call_id: Some(tool_call.id),
         });
     }
 
-    messages
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{ContentPart, MessageContentPayload, OllamaApi};
+    use crate::conversions::{messages_to_request, process_response, tool_results_to_messages};
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Config, ContentPart as GolemContentPart, ErrorCode, ImageUrl, Kv, Message, Role,
+        ToolCall, ToolResult, ToolSuccess,
+    };
+    use golem_llm::test_config;
+    use golem_llm::transport::{CannedTransport, HttpResponse};
+    use reqwest::StatusCode;
+    use std::rc::Rc;
+
+    fn image(url: &str) -> GolemContentPart {
+        GolemContentPart::Image(ImageUrl {
+            url: url.to_string(),
+            detail: None,
+        })
+    }
+
+    fn image_response(mime_type: &str, body: &str) -> HttpResponse {
+        HttpResponse::new(StatusCode::OK, body).with_header("content-type", mime_type)
+    }
+
+    #[test]
+    fn images_are_inlined_in_order() {
+        let transport = Rc::new(CannedTransport::new([
+            Ok(image_response("image/png", "first")),
+            Ok(image_response("image/jpeg", "second")),
+            Ok(image_response("image/webp", "third")),
+        ]));
+        let api =
+            OllamaApi::with_transport("http://localhost".to_string(), Box::new(transport.clone()));
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![
+                GolemContentPart::Text("Compare these".to_string()),
+                image("https://example.com/1.png"),
+                image("https://example.com/2.jpg"),
+                image("https://example.com/3.webp"),
+            ],
+        }];
+        let config = Config {
+            model: "llava".to_string(),
+            ..test_config::config(&[])
+        };
+
+        let request = messages_to_request(messages, config, &api).unwrap();
+
+        let requested_urls = transport
+            .requests()
+            .into_iter()
+            .map(|request| request.url)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requested_urls,
+            vec![
+                "https://example.com/1.png",
+                "https://example.com/2.jpg",
+                "https://example.com/3.webp"
+            ]
+        );
+        let parts = match &request.messages[0].content.content {
+            Some(MessageContentPayload::Array { content }) => content,
+            other => panic!("Unexpected content: {other:?}"),
+        };
+        let parts = parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => text.clone(),
+                ContentPart::ImageUrl { image_url } => image_url.url.clone(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            vec![
+                "Compare these",
+                "data:image/png;base64,Zmlyc3Q=",
+                "data:image/jpeg;base64,c2Vjb25k",
+                "data:image/webp;base64,dGhpcmQ=",
+            ]
+        );
+    }
+
+    fn chat_response(message: &str) -> crate::client::OllamaChatResponse {
+        serde_json::from_str(&format!(
+            r#"{{"id":"chatcmpl-1","object":"chat.completion","created":1,"model":"llama3.2","choices":[{{"index":0,"message":{message},"finish_reason":"tool_calls"}}],"usage":null}}"#
+        ))
+        .unwrap()
+    }
+
+    fn weather_call() -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn text_accompanying_tool_calls_is_preserved() {
+        let event = process_response(chat_response(
+            r#"{"role":"assistant","content":"Let me check the weather","tool_calls":[{"id":"call_1","function":{"name":"get_weather","arguments":{"city":"Berlin"}}}]}"#,
+        ));
+
+        match event {
+            ChatEvent::Message(response) => {
+                assert_eq!(
+                    response.content,
+                    vec![GolemContentPart::Text(
+                        "Let me check the weather".to_string()
+                    )]
+                );
+                assert_eq!(response.tool_calls, vec![weather_call()]);
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn tool_calls_without_text_are_a_tool_request() {
+        let event = process_response(chat_response(
+            r#"{"role":"assistant","content":"","tool_calls":[{"id":"call_1","function":{"name":"get_weather","arguments":{"city":"Berlin"}}}]}"#,
+        ));
+
+        assert_eq!(event, ChatEvent::ToolRequest(vec![weather_call()]));
+    }
+
+    /// Tool arguments with integers beyond the precision of an f64, up to the 64-bit limits
+    const LARGE_INTEGER_ARGUMENTS: &str = r#"{"order_id":18446744073709551615,"timestamp":9007199254740993,"user_id":-9223372036854775808}"#;
+
+    #[test]
+    fn large_integer_tool_arguments_are_preserved() {
+        let event = process_response(chat_response(&format!(
+            r#"{{"role":"assistant","content":"","tool_calls":[{{"id":"call_1","function":{{"name":"get_order","arguments":{LARGE_INTEGER_ARGUMENTS}}}}}]}}"#
+        )));
+        let tool_call = match event {
+            ChatEvent::ToolRequest(mut tool_calls) => tool_calls.remove(0),
+            other => panic!("Unexpected event: {other:?}"),
+        };
+        assert_eq!(tool_call.arguments_json, LARGE_INTEGER_ARGUMENTS);
+
+        let result = ToolResult::Success(ToolSuccess {
+            id: "call_1".to_string(),
+            name: "get_order".to_string(),
+            result_json: "{}".to_string(),
+            execution_time_ms: None,
+        });
+        let messages = tool_results_to_messages(vec![(tool_call, result)]).unwrap();
+        let json = serde_json::to_value(&messages).unwrap();
+
+        assert_eq!(
+            json[0]["tool_calls"][0]["function"]["arguments"].to_string(),
+            LARGE_INTEGER_ARGUMENTS
+        );
+    }
+
+    #[test]
+    fn think_option_is_serialized() {
+        let api = OllamaApi::with_transport(
+            "http://localhost".to_string(),
+            Box::new(CannedTransport::new([])),
+        );
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![GolemContentPart::Text("Why is the sky blue?".to_string())],
+        }];
+        let config = Config {
+            model: "deepseek-r1".to_string(),
+            ..test_config::config(&[("think", "true")])
+        };
+
+        let request = messages_to_request(messages, config, &api).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(request.think, Some(true));
+        assert_eq!(json["think"], serde_json::Value::Bool(true));
+    }
+
+    #[test]
+    fn max_tokens_is_omitted_unless_positive() {
+        let api = OllamaApi::with_transport(
+            "http://localhost".to_string(),
+            Box::new(CannedTransport::new([])),
+        );
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![GolemContentPart::Text("Hello".to_string())],
+        }];
+        let mut config = Config {
+            model: "llama3.2".to_string(),
+            ..test_config::config(&[])
+        };
+
+        let request = messages_to_request(messages.clone(), config.clone(), &api).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("max_tokens").is_none());
+
+        config.max_tokens = Some(0);
+        let error = messages_to_request(messages, config, &api).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn grammar_and_regex_are_serialized_for_constrained_decoding() {
+        let api = OllamaApi::with_transport(
+            "http://localhost".to_string(),
+            Box::new(CannedTransport::new([])),
+        );
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![GolemContentPart::Text("Is the sky blue?".to_string())],
+        }];
+        let mut config = Config {
+            model: "llama3.2".to_string(),
+            ..test_config::config(&[("grammar", r#"root ::= "yes" | "no""#)])
+        };
+
+        let request = messages_to_request(messages.clone(), config.clone(), &api).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["grammar"], r#"root ::= "yes" | "no""#);
+        assert_eq!(json["guided_grammar"], r#"root ::= "yes" | "no""#);
+        assert!(json.get("guided_regex").is_none());
+
+        config.provider_options[0] = Kv {
+            key: "regex".to_string(),
+            value: "(yes|no)".to_string(),
+        };
+        let request = messages_to_request(messages, config, &api).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["guided_regex"], "(yes|no)");
+        assert!(json.get("grammar").is_none());
+    }
+
+    #[test]
+    fn thinking_is_reported_in_metadata_not_content() {
+        let event = process_response(chat_response(
+            r#"{"role":"assistant","content":"Rayleigh scattering.","reasoning":"Short wavelengths scatter more."}"#,
+        ));
+
+        match event {
+            ChatEvent::Message(response) => {
+                assert_eq!(
+                    response.content,
+                    vec![GolemContentPart::Text("Rayleigh scattering.".to_string())]
+                );
+                let metadata: serde_json::Value =
+                    serde_json::from_str(&response.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(metadata["reasoning"], "Short wavelengths scatter more.");
+                assert_eq!(metadata["role"], "assistant");
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn response_id_is_never_empty() {
+        let message = r#"{"role":"assistant","content":"Hello"}"#;
+        let mut without_id = chat_response(message);
+        without_id.id = String::new();
+
+        for (response, expected) in [
+            (chat_response(message), Some("chatcmpl-1")),
+            (without_id, None),
+        ] {
+            match process_response(response) {
+                ChatEvent::Message(message) => {
+                    assert!(!message.id.is_empty());
+                    if let Some(expected) = expected {
+                        assert_eq!(message.id, expected);
+                    }
+                }
+                other => panic!("Unexpected event: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn stop_sequence_included_by_the_model_is_stripped_when_enabled() {
+        let config = Config {
+            model: "llama3.2".to_string(),
+            stop_sequences: Some(vec!["<|end|>".to_string()]),
+            ..test_config::config(&[("strip_stop_sequence", "true")])
+        };
+        let event = process_response(chat_response(
+            r#"{"role":"assistant","content":"Hello<|end|>"}"#,
+        ));
+
+        match golem_llm::stop_sequence::strip_stop_sequence(&config, event) {
+            ChatEvent::Message(message) => {
+                assert_eq!(
+                    message.content,
+                    vec![GolemContentPart::Text("Hello".to_string())]
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
 }
@@ -1,16 +1,24 @@
 use crate::client::{OllamaApi, OllamaChatDeltaResponse, OllamaChatRequest};
-use crate::conversions::{messages_to_request, process_response, tool_results_to_messages};
-use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
+use crate::conversions::{
+    messages_to_request, process_response, reasoning_metadata, tool_results_to_messages,
+};
+use golem_llm::chat_stream::{
+    attach_raw_enabled, emit_heartbeat_enabled, sentence_flush_timeout, timing_enabled,
+    LlmChatStream, LlmChatStreamState,
+};
+use golem_llm::config::bool_option;
 use golem_llm::durability::{DurableLLM, ExtendedGuest};
+use golem_llm::error::unsupported;
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, Message,
-    ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, ImageGenConfig,
+    ImageResult, Message, ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
 };
+use golem_llm::trace_id::TRACE_ID;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
-use std::cell::{Ref, RefCell, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 
 mod client;
 mod conversions;
@@ -19,14 +27,16 @@ struct OllamaChatStream {
     stream: RefCell<Option<EventSource>>,
     failure: Option<Error>,
     finished: RefCell<bool>,
+    reconnection: Option<Reconnection>,
 }
 
 impl OllamaChatStream {
-    pub fn new(stream: EventSource) -> LlmChatStream<Self> {
+    pub fn new(stream: EventSource, reconnection: Option<Reconnection>) -> LlmChatStream<Self> {
         LlmChatStream::new(OllamaChatStream {
             stream: RefCell::new(Some(stream)),
             failure: None,
             finished: RefCell::new(false),
+            reconnection,
         })
     }
 
@@ -35,10 +45,56 @@ impl OllamaChatStream {
             stream: RefCell::new(None),
             failure: Some(error),
             finished: RefCell::new(false),
+            reconnection: None,
         })
     }
 }
 
+/// Returns whether the `reconnect_on_drop=true` provider option is set
+fn reconnect_on_drop(config: &Config) -> bool {
+    bool_option(config, "reconnect_on_drop")
+}
+
+/// What is needed to reconnect a stream once when the connection to the local Ollama server
+/// drops, for example because it was restarted. The generation is continued with the same
+/// configuration, asking the model to carry on from the deltas received so far.
+struct Reconnection {
+    client: OllamaApi,
+    messages: Vec<Message>,
+    config: Config,
+    received: RefCell<Vec<StreamDelta>>,
+    attempted: Cell<bool>,
+}
+
+impl Reconnection {
+    fn new(client: OllamaApi, messages: Vec<Message>, config: Config) -> Self {
+        Self {
+            client,
+            messages,
+            config,
+            received: RefCell::new(Vec::new()),
+            attempted: Cell::new(false),
+        }
+    }
+
+    fn reconnect(&self) -> Option<EventSource> {
+        if self.attempted.replace(true) {
+            return None;
+        }
+
+        let messages = OllamaComponent::retry_prompt(&self.messages, &self.received.borrow());
+        let stream = messages_to_request(messages, self.config.clone(), &self.client)
+            .and_then(|request| self.client.stream_send_messages(request));
+        match stream {
+            Ok(stream) => Some(stream),
+            Err(err) => {
+                trace!("{TRACE_ID}Failed to reconnect Ollama stream: {err:?}");
+                None
+            }
+        }
+    }
+}
+
 impl LlmChatStreamState for OllamaChatStream {
     fn failure(&self) -> &Option<Error> {
         &self.failure
@@ -60,8 +116,25 @@ impl LlmChatStreamState for OllamaChatStream {
         self.stream.borrow_mut()
     }
 
+    fn reconnect(&self) -> Option<EventSource> {
+        self.reconnection
+            .as_ref()
+            .and_then(|reconnection| reconnection.reconnect())
+    }
+
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw Ollama stream event: {raw}");
+        let event = self.decode_chunk(raw)?;
+        if let (Some(reconnection), Some(StreamEvent::Delta(delta))) = (&self.reconnection, &event)
+        {
+            reconnection.received.borrow_mut().push(delta.clone());
+        }
+        Ok(event)
+    }
+}
+
+impl OllamaChatStream {
+    fn decode_chunk(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
+        trace!("{TRACE_ID}Received raw Ollama stream event: {raw}");
 
         let chunk: OllamaChatDeltaResponse = serde_json::from_str(raw).map_err(|err| {
             format!("Failed to deserialize Ollama stream chunk : {err} - raw: {raw}")
@@ -72,11 +145,21 @@ impl LlmChatStreamState for OllamaChatStream {
             None => return Ok(None),
         };
 
+        // Thinking output is kept out of the answer, reported in the delta's metadata instead
+        let provider_metadata_json = choice
+            .delta
+            .reasoning
+            .as_deref()
+            .filter(|reasoning| !reasoning.is_empty())
+            .map(reasoning_metadata);
+
         if let Some(content) = &choice.delta.content {
             if !content.is_empty() {
                 return Ok(Some(StreamEvent::Delta(StreamDelta {
                     content: Some(vec![ContentPart::Text(content.clone())]),
                     tool_calls: None,
+                    provider_metadata_json,
+                    raw_json: None,
                 })));
             }
         }
@@ -95,9 +178,19 @@ impl LlmChatStreamState for OllamaChatStream {
                 return Ok(Some(StreamEvent::Delta(StreamDelta {
                     content: None,
                     tool_calls: Some(golem_tool_calls),
+                    provider_metadata_json: None,
+                    raw_json: None,
                 })));
             }
         }
+        if provider_metadata_json.is_some() {
+            return Ok(Some(StreamEvent::Delta(StreamDelta {
+                content: None,
+                tool_calls: None,
+                provider_metadata_json,
+                raw_json: None,
+            })));
+        }
         if let Some(finish_reason) = &choice.finish_reason {
             let finish_reason_enum = match finish_reason.as_str() {
                 "stop" => FinishReason::Stop,
@@ -116,6 +209,7 @@ impl LlmChatStreamState for OllamaChatStream {
                     r#"{{"id":"{}","created":{}}}"#,
                     chunk.id, chunk.created
                 )),
+                raw_json: None,
             };
 
             return Ok(Some(StreamEvent::Finish(metadata)));
@@ -138,10 +232,11 @@ impl OllamaComponent {
     fn streaming_request(
         client: &OllamaApi,
         mut request: OllamaChatRequest,
+        reconnection: Option<Reconnection>,
     ) -> LlmChatStream<OllamaChatStream> {
         request.stream = true;
         match client.stream_send_messages(request) {
-            Ok(stream) => OllamaChatStream::new(stream),
+            Ok(stream) => OllamaChatStream::new(stream, reconnection),
             Err(err) => OllamaChatStream::failed(err),
         }
     }
@@ -153,7 +248,7 @@ impl Guest for OllamaComponent {
     fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
-        let client = OllamaApi::new();
+        let client = OllamaApi::new(&config.provider_options);
         match messages_to_request(messages, config, &client) {
             Ok(request) => Self::request(&client, request),
             Err(err) => ChatEvent::Error(err),
@@ -167,14 +262,15 @@ impl Guest for OllamaComponent {
     ) -> ChatEvent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
-        let client = OllamaApi::new();
+        let client = OllamaApi::new(&config.provider_options);
         match messages_to_request(messages, config, &client) {
-            Ok(mut request) => {
-                request
-                    .messages
-                    .extend(tool_results_to_messages(tool_results));
-                Self::request(&client, request)
-            }
+            Ok(mut request) => match tool_results_to_messages(tool_results) {
+                Ok(tool_messages) => {
+                    request.messages.extend(tool_messages);
+                    Self::request(&client, request)
+                }
+                Err(err) => ChatEvent::Error(err),
+            },
             Err(err) => ChatEvent::Error(err),
         }
     }
@@ -182,17 +278,37 @@ impl Guest for OllamaComponent {
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn generate_image(_prompt: String, _config: ImageGenConfig) -> Result<Vec<ImageResult>, Error> {
+        Err(unsupported("Image generation"))
+    }
 }
 
 impl ExtendedGuest for OllamaComponent {
     fn unwrapped_stream(messages: Vec<Message>, config: Config) -> LlmChatStream<OllamaChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
-        let client = OllamaApi::new();
-        match messages_to_request(messages, config, &client) {
-            Ok(request) => Self::streaming_request(&client, request),
+        let attach_raw = attach_raw_enabled(&config);
+        let timing = timing_enabled(&config);
+        let heartbeat = emit_heartbeat_enabled(&config);
+        let sentence_flush = sentence_flush_timeout(&config);
+        let reconnection = reconnect_on_drop(&config).then(|| {
+            Reconnection::new(
+                OllamaApi::new(&config.provider_options),
+                messages.clone(),
+                config.clone(),
+            )
+        });
+        let client = OllamaApi::new(&config.provider_options);
+        let stream = match messages_to_request(messages, config, &client) {
+            Ok(request) => Self::streaming_request(&client, request, reconnection),
             Err(err) => OllamaChatStream::failed(err),
-        }
+        };
+        stream
+            .with_raw_payloads(attach_raw)
+            .with_timing(timing)
+            .with_sentence_flush(sentence_flush)
+            .with_heartbeat(heartbeat)
     }
 
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
@@ -256,3 +372,134 @@ impl ExtendedGuest for OllamaComponent {
 type DurableOllamaComponent = DurableLLM<OllamaComponent>;
 
 golem_llm::export_llm!(DurableOllamaComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::client::OllamaApi;
+    use crate::conversions::tool_results_to_messages;
+    use crate::{reconnect_on_drop, OllamaChatStream, Reconnection};
+    use golem_llm::chat_stream::LlmChatStreamState;
+    use golem_llm::event_source::EventSource;
+    use golem_llm::golem::llm::llm::{
+        Config, ContentPart, GuestChatStream, Message, Role, StreamEvent, ToolResult, ToolSuccess,
+    };
+    use golem_llm::test_config;
+    use golem_llm::transport::{CannedStream, CannedTransport};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn config(provider_options: &[(&str, &str)]) -> Config {
+        Config {
+            model: "llama3.2".to_string(),
+            ..test_config::config(provider_options)
+        }
+    }
+
+    fn text_chunk(text: &str) -> String {
+        format!(
+            r#"{{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"llama3.2","choices":[{{"index":0,"delta":{{"role":"assistant","content":"{text}"}},"finish_reason":null}}]}}"#
+        )
+    }
+
+    #[test]
+    fn reconnect_on_drop_is_enabled_by_provider_option() {
+        assert!(!reconnect_on_drop(&config(&[])));
+        assert!(reconnect_on_drop(&config(&[("reconnect_on_drop", "true")])));
+    }
+
+    fn event(data: String) -> String {
+        format!("data: {data}\n\n")
+    }
+
+    #[test]
+    fn dropped_stream_reconnects_once_continuing_the_generation() {
+        let first =
+            CannedStream::new([event(text_chunk("one, ")), event(text_chunk("two, "))]).dropping();
+        let first_released = first.released();
+        let transport = Rc::new(
+            CannedTransport::default()
+                .with_streams([CannedStream::new([event(text_chunk("three, "))]).dropping()]),
+        );
+        let client =
+            OllamaApi::with_transport("http://localhost".to_string(), Box::new(transport.clone()));
+        let messages = vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Count to five".to_string())],
+        }];
+        let stream = OllamaChatStream::new(
+            EventSource::from_stream(first),
+            Some(Reconnection::new(client, messages, config(&[]))),
+        );
+
+        let events = (0..10)
+            .flat_map(|_| stream.get_next().unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        // The generation continues on the second stream after the first one dropped, and the
+        // second drop is reported as an error instead of reconnecting again
+        let texts = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::Delta(delta) => match delta.content.as_deref() {
+                    Some([ContentPart::Text(text)]) => Some(text.as_str()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["one, ", "two, ", "three, "]);
+        match events.last() {
+            Some(StreamEvent::Error(error)) => {
+                assert_eq!(error.message, "Transport stream error: connection reset")
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        assert!(first_released.get());
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(body["model"], "llama3.2");
+        assert_eq!(body["stream"], true);
+        let messages = body["messages"].to_string();
+        assert!(messages.contains("Count to five"));
+        assert!(messages.contains("one, "));
+        assert!(messages.contains("two, "));
+    }
+
+    #[test]
+    fn streamed_tool_call_id_correlates_the_result() {
+        let state = OllamaChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            reconnection: None,
+        };
+
+        let event = state
+            .decode_message(
+                r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"llama3.2","choices":[{"index":0,"delta":{"role":"assistant","content":"","tool_calls":[{"id":"call_1","index":0,"type":"function","function":{"name":"get_weather","arguments":{"city":"Berlin"}}}]},"finish_reason":null}]}"#,
+            )
+            .unwrap();
+        let tool_call = match event {
+            Some(StreamEvent::Delta(delta)) => delta.tool_calls.unwrap().remove(0),
+            other => panic!("Unexpected event: {other:?}"),
+        };
+        assert_eq!(tool_call.id, "call_1");
+
+        let result = ToolResult::Success(ToolSuccess {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            result_json: r#"{"temperature":21}"#.to_string(),
+            execution_time_ms: None,
+        });
+        let messages =
+            serde_json::to_value(tool_results_to_messages(vec![(tool_call, result)]).unwrap())
+                .unwrap();
+
+        assert_eq!(messages[0]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(messages[1]["tool_call_id"], "call_1");
+    }
+}
@@ -1,7 +1,7 @@
 // Generated by `wit-bindgen` 0.36.0. DO NOT EDIT!
 // Options used:
 //   * runtime_path: "wit_bindgen_rt"
-//   * with "golem:llm/llm@1.0.0" = "golem_llm::golem::llm::llm"
+//   * with "golem:llm/llm@2.0.0" = "golem_llm::golem::llm::llm"
 //   * with "wasi:io/poll@0.2.0" = "golem_rust::wasm_rpc::wasi::io::poll"
 //   * generate_unused_types
 use golem_llm::golem::llm::llm as __with_name0;
@@ -9,9 +9,9 @@ use golem_llm::golem::llm::llm as __with_name0;
 #[link_section = "component-type:wit-bindgen:0.36.0:golem:llm-openai@1.0.0:llm-library:encoded world"]
 #[doc(hidden)]
 
-pub static __WIT_BINDGEN_COMPONENT_TYPE: [u8; 1665] = *b"\
-\0asm\x0d\0\x01\0\0\x19\x16wit-component-encoding\x04\0\x07\xff\x0b\x01A\x02\x01\
-A\x02\x01BJ\x01m\x04\x04user\x09assistant\x06system\x04tool\x04\0\x04role\x03\0\0\
+pub static __WIT_BINDGEN_COMPONENT_TYPE: [u8; 2116] = *b"\
+\0asm\x0d\0\x01\0\0\x19\x16wit-component-encoding\x04\0\x07\xc2\x0f\x01A\x02\x01\
+A\x02\x01BZ\x01m\x04\x04user\x09assistant\x06system\x04tool\x04\0\x04role\x03\0\0\
 \x01m\x06\x0finvalid-request\x15authentication-failed\x13rate-limit-exceeded\x0e\
 internal-error\x0bunsupported\x07unknown\x04\0\x0aerror-code\x03\0\x02\x01m\x06\x04\
 stop\x06length\x0atool-calls\x0econtent-filter\x05error\x05other\x04\0\x0dfinish\
@@ -27,23 +27,32 @@ ol-success\x03\0\x16\x01r\x04\x02ids\x04names\x0derror-messages\x0aerror-code\x0
 \x04\0\x0btool-result\x03\0\x1a\x01r\x02\x03keys\x05values\x04\0\x02kv\x03\0\x1c\
 \x01kv\x01ps\x01k\x1f\x01p\x12\x01p\x1d\x01r\x07\x05models\x0btemperature\x1e\x0a\
 max-tokens\x15\x0estop-sequences\x20\x05tools!\x0btool-choice\x0d\x10provider-op\
-tions\"\x04\0\x06config\x03\0#\x01r\x03\x0cinput-tokens\x15\x0doutput-tokens\x15\
-\x0ctotal-tokens\x15\x04\0\x05usage\x03\0%\x01k\x05\x01k&\x01r\x05\x0dfinish-rea\
-son'\x05usage(\x0bprovider-id\x0d\x09timestamp\x0d\x16provider-metadata-json\x0d\
-\x04\0\x11response-metadata\x03\0)\x01p\x14\x01r\x04\x02ids\x07content\x0e\x0ato\
-ol-calls+\x08metadata*\x04\0\x11complete-response\x03\0,\x01r\x03\x04code\x03\x07\
-messages\x13provider-error-json\x0d\x04\0\x05error\x03\0.\x01q\x03\x07message\x01\
--\0\x0ctool-request\x01+\0\x05error\x01/\0\x04\0\x0achat-event\x03\00\x01k\x0e\x01\
-k+\x01r\x02\x07content2\x0atool-calls3\x04\0\x0cstream-delta\x03\04\x01q\x03\x05\
-delta\x015\0\x06finish\x01*\0\x05error\x01/\0\x04\0\x0cstream-event\x03\06\x04\0\
-\x0bchat-stream\x03\x01\x01h8\x01p7\x01k:\x01@\x01\x04self9\0;\x04\0\x1c[method]\
-chat-stream.get-next\x01<\x01@\x01\x04self9\0:\x04\0%[method]chat-stream.blockin\
-g-get-next\x01=\x01p\x10\x01@\x02\x08messages>\x06config$\01\x04\0\x04send\x01?\x01\
-o\x02\x14\x1b\x01p\xc0\0\x01@\x03\x08messages>\x0ctool-results\xc1\0\x06config$\0\
-1\x04\0\x08continue\x01B\x01i8\x01@\x02\x08messages>\x06config$\0\xc3\0\x04\0\x06\
-stream\x01D\x04\0\x13golem:llm/llm@1.0.0\x05\0\x04\0\"golem:llm-openai/llm-libra\
-ry@1.0.0\x04\0\x0b\x11\x01\0\x0bllm-library\x03\0\0\0G\x09producers\x01\x0cproce\
-ssed-by\x02\x0dwit-component\x070.220.0\x10wit-bindgen-rust\x060.36.0";
+tions\"\x04\0\x06config\x03\0#\x01r\x06\x0cinput-tokens\x15\x0doutput-tokens\x15\
+\x0ctotal-tokens\x15\x10reasoning-tokens\x15\x0dcached-tokens\x15\x0caudio-token\
+s\x15\x04\0\x05usage\x03\0%\x01k\x05\x01k&\x01r\x06\x0dfinish-reason'\x05usage(\x0b\
+provider-id\x0d\x09timestamp\x0d\x16provider-metadata-json\x0d\x08raw-json\x0d\x04\
+\0\x11response-metadata\x03\0)\x01p\x14\x01r\x04\x02ids\x07content\x0e\x0atool-c\
+alls+\x08metadata*\x04\0\x11complete-response\x03\0,\x01r\x03\x04code\x03\x07mes\
+sages\x13provider-error-json\x0d\x04\0\x05error\x03\0.\x01q\x03\x07message\x01-\0\
+\x0ctool-request\x01+\0\x05error\x01/\0\x04\0\x0achat-event\x03\00\x01k\x0e\x01k\
++\x01r\x04\x07content2\x0atool-calls3\x16provider-metadata-json\x0d\x08raw-json\x0d\
+\x04\0\x0cstream-delta\x03\04\x01q\x03\x05delta\x015\0\x06finish\x01*\0\x05error\
+\x01/\0\x04\0\x0cstream-event\x03\06\x04\0\x0bchat-stream\x03\x01\x01r\x06\x05mo\
+dels\x01n\x15\x04size\x0d\x07quality\x0d\x05style\x0d\x10provider-options\"\x04\0\
+\x10image-gen-config\x03\09\x01q\x02\x03url\x01s\0\x06base64\x01s\0\x04\0\x0aima\
+ge-data\x03\0;\x01r\x03\x04data<\x04size\x0d\x0erevised-prompt\x0d\x04\0\x0cimag\
+e-result\x03\0=\x01h8\x01p7\x01k\xc0\0\x01@\x01\x04self?\0\xc1\0\x04\0\x1c[metho\
+d]chat-stream.get-next\x01B\x01@\x01\x04self?\0\xc0\0\x04\0%[method]chat-stream.\
+blocking-get-next\x01C\x01@\x01\x04self?\0\x7f\x04\0\x1f[method]chat-stream.is-f\
+inished\x01D\x01@\x01\x04self?\0'\x04\0&[method]chat-stream.last-finish-reason\x01\
+E\x01@\x01\x04self?\x01\0\x04\0\x1a[method]chat-stream.cancel\x01F\x01p\x10\x01@\
+\x02\x08messages\xc7\0\x06config$\01\x04\0\x04send\x01H\x01o\x02\x14\x1b\x01p\xc9\
+\0\x01@\x03\x08messages\xc7\0\x0ctool-results\xca\0\x06config$\01\x04\0\x08conti\
+nue\x01K\x01i8\x01@\x02\x08messages\xc7\0\x06config$\0\xcc\0\x04\0\x06stream\x01\
+M\x01p>\x01j\x01\xce\0\x01/\x01@\x02\x06prompts\x06config:\0\xcf\0\x04\0\x0egene\
+rate-image\x01P\x04\0\x13golem:llm/llm@2.0.0\x05\0\x04\0\"golem:llm-openai/llm-l\
+ibrary@1.0.0\x04\0\x0b\x11\x01\0\x0bllm-library\x03\0\0\0G\x09producers\x01\x0cp\
+rocessed-by\x02\x0dwit-component\x070.221.3\x10wit-bindgen-rust\x060.36.0";
 #[inline(never)]
 #[doc(hidden)]
 pub fn __link_custom_section_describing_imports() {
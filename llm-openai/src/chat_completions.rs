@@ -0,0 +1,763 @@
+//! The classic chat completions API, used instead of the responses API when the `api` provider
+//! option is `chat_completions`, for OpenAI-compatible gateways (such as vLLM, LocalAI or
+//! LM Studio) that do not implement the responses API.
+
+use crate::client::base_url;
+use golem_llm::config::{
+    apply_sampling_preset, builtin_tools, max_tokens, option, output_constraint, resolve_model,
+};
+use golem_llm::error::{
+    check_tool_result_id, check_tool_result_json, parse_response, unsupported, OpenAiErrorBody,
+};
+use golem_llm::event_source::EventSource;
+use golem_llm::golem::llm::llm::{
+    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageDetail,
+    Kv, Message, ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult, Usage,
+};
+use golem_llm::response_id::response_id;
+use golem_llm::tool_call_only::forced_tool_choice;
+use golem_llm::trace_id::TRACE_ID;
+use golem_llm::transport::{transport_for, HttpRequest, HttpTransport};
+use log::trace;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+/// Gets whether the `api` provider option selects the chat completions API. Only
+/// `chat_completions` and the default `responses` are accepted.
+pub fn chat_completions_enabled(config: &Config) -> Result<bool, Error> {
+    match option(config, "api") {
+        None | Some("responses") => Ok(false),
+        Some("chat_completions") => Ok(true),
+        Some(api) => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Unknown api {api}, expected responses or chat_completions"),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// The OpenAI API client for creating chat completions.
+///
+/// Based on https://platform.openai.com/docs/api-reference/chat/create
+pub struct ChatCompletionsApi {
+    openai_api_key: String,
+    base_url: String,
+    transport: Box<dyn HttpTransport>,
+}
+
+impl ChatCompletionsApi {
+    pub fn new(openai_api_key: String, provider_options: &[Kv]) -> Self {
+        Self::with_transport(openai_api_key, transport_for(provider_options))
+    }
+
+    pub fn with_transport(openai_api_key: String, transport: Box<dyn HttpTransport>) -> Self {
+        Self {
+            openai_api_key,
+            base_url: base_url(),
+            transport,
+        }
+    }
+
+    pub fn send_messages(&self, request: CompletionsRequest) -> Result<CompletionsResponse, Error> {
+        trace!("{TRACE_ID}Sending request to OpenAI API: {request:?}");
+
+        let response = self.transport.send(
+            HttpRequest::post(format!("{}/v1/chat/completions", self.base_url))
+                .bearer_auth(&self.openai_api_key)
+                .json(&request)?,
+        )?;
+
+        parse_response::<_, OpenAiErrorBody>("OpenAI API", response)
+    }
+
+    pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
+        trace!("{TRACE_ID}Sending request to OpenAI API: {request:?}");
+
+        let request = HttpRequest::post(format!("{}/v1/chat/completions", self.base_url))
+            .bearer_auth(&self.openai_api_key)
+            .header(reqwest::header::ACCEPT.as_str(), "text/event-stream")
+            .json(&request)?;
+
+        trace!("{TRACE_ID}Initializing SSE stream");
+
+        self.transport.stream(request)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsRequest {
+    pub model: String,
+    pub messages: Vec<CompletionsMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamOptions {
+    pub include_usage: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsMessage {
+    pub role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<Vec<CompletionsContentPart>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<CompletionsToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum CompletionsContentPart {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "image_url")]
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Tool {
+    #[serde(rename = "function")]
+    Function { function: Function },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Function {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsResponse {
+    pub id: String,
+    pub created: u64,
+    pub choices: Vec<Choice>,
+    pub usage: Option<CompletionsUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Choice {
+    pub message: ResponseMessage,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseMessage {
+    pub role: Option<String>,
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<CompletionsToolCall>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionsUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub created: u64,
+    pub choices: Vec<ChoiceChunk>,
+    pub usage: Option<CompletionsUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceChunk {
+    pub delta: ChoiceDelta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceDelta {
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallChunk {
+    pub index: u32,
+    pub id: Option<String>,
+    pub function: Option<FunctionCallChunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCallChunk {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+pub fn convert_messages(messages: Vec<Message>) -> Vec<CompletionsMessage> {
+    messages.into_iter().map(convert_message).collect()
+}
+
+pub fn messages_to_request(
+    messages: Vec<CompletionsMessage>,
+    config: Config,
+) -> Result<CompletionsRequest, Error> {
+    if let Some(name) = builtin_tools(&config).first() {
+        return Err(unsupported(format!(
+            "Built-in tool {name} with the chat completions API"
+        )));
+    }
+
+    if output_constraint(&config)?.is_some() {
+        return Err(unsupported("The grammar and regex options"));
+    }
+
+    let config = apply_sampling_preset(config, "openai")?;
+    let model = resolve_model(&config, "openai")?;
+    let max_tokens = max_tokens(&config)?;
+    let tool_choice = forced_tool_choice(&config, "required")?;
+    let options = config
+        .provider_options
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect::<HashMap<_, _>>();
+
+    let mut tools = Vec::new();
+    for tool in config.tools {
+        let parameters = serde_json::from_str(&tool.parameters_schema).map_err(|error| Error {
+            code: ErrorCode::InternalError,
+            message: format!("Failed to parse tool parameters for {}: {error}", tool.name),
+            provider_error_json: None,
+        })?;
+        tools.push(Tool::Function {
+            function: Function {
+                name: tool.name,
+                description: tool.description,
+                parameters,
+            },
+        });
+    }
+
+    Ok(CompletionsRequest {
+        model,
+        messages,
+        temperature: config.temperature,
+        max_tokens,
+        top_p: options
+            .get("top_p")
+            .and_then(|top_p_s| top_p_s.parse::<f32>().ok()),
+        stop: config.stop_sequences,
+        tools,
+        tool_choice: tool_choice.map(convert_tool_choice),
+        parallel_tool_calls: options
+            .get("parallel_tool_calls")
+            .and_then(|ptc_s| ptc_s.parse::<bool>().ok()),
+        user: options.get("user").cloned(),
+        stream: false,
+        stream_options: None,
+    })
+}
+
+fn convert_message(message: Message) -> CompletionsMessage {
+    let role = match message.role {
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::System => "system",
+        Role::Tool => "tool",
+    };
+    CompletionsMessage {
+        role: role.to_string(),
+        content: Some(
+            message
+                .content
+                .into_iter()
+                .map(|part| match part {
+                    ContentPart::Text(text) => CompletionsContentPart::Text { text },
+                    ContentPart::Image(image_url) => CompletionsContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: image_url.url,
+                            detail: image_url.detail.map(|detail| {
+                                match detail {
+                                    ImageDetail::Auto => "auto",
+                                    ImageDetail::Low => "low",
+                                    ImageDetail::High => "high",
+                                }
+                                .to_string()
+                            }),
+                        },
+                    },
+                })
+                .collect(),
+        ),
+        name: message.name,
+        tool_calls: None,
+        tool_call_id: None,
+    }
+}
+
+/// Converts the tool choice to the chat completions format, where a specific tool is selected
+/// with an object
+fn convert_tool_choice(tool_choice: String) -> serde_json::Value {
+    match tool_choice.as_str() {
+        "auto" | "none" | "required" => serde_json::Value::String(tool_choice),
+        _ => serde_json::json!({"type": "function", "function": {"name": tool_choice}}),
+    }
+}
+
+pub fn tool_results_to_messages(
+    tool_results: Vec<(ToolCall, ToolResult)>,
+) -> Result<Vec<CompletionsMessage>, Error> {
+    let mut messages = Vec::new();
+    for (tool_call, tool_result) in tool_results {
+        check_tool_result_id(&tool_call, &tool_result)?;
+        let content = match tool_result {
+            ToolResult::Success(success) => {
+                check_tool_result_json(&success)?;
+                success.result_json
+            }
+            ToolResult::Error(failure) => failure.error_message,
+        };
+        messages.push(CompletionsMessage {
+            role: "assistant".to_string(),
+            content: None,
+            name: None,
+            tool_calls: Some(vec![CompletionsToolCall {
+                id: tool_call.id.clone(),
+                typ: "function".to_string(),
+                function: FunctionCall {
+                    name: tool_call.name,
+                    arguments: tool_call.arguments_json,
+                },
+            }]),
+            tool_call_id: None,
+        });
+        messages.push(CompletionsMessage {
+            role: "tool".to_string(),
+            content: Some(vec![CompletionsContentPart::Text { text: content }]),
+            name: None,
+            tool_calls: None,
+            tool_call_id: Some(tool_call.id),
+        });
+    }
+    Ok(messages)
+}
+
+fn convert_finish_reason(finish_reason: &str) -> FinishReason {
+    match finish_reason {
+        "stop" => FinishReason::Stop,
+        "length" => FinishReason::Length,
+        "tool_calls" | "function_call" => FinishReason::ToolCalls,
+        "content_filter" => FinishReason::ContentFilter,
+        _ => FinishReason::Other,
+    }
+}
+
+fn convert_usage(usage: &CompletionsUsage) -> Usage {
+    Usage {
+        input_tokens: Some(usage.prompt_tokens),
+        output_tokens: Some(usage.completion_tokens),
+        total_tokens: Some(usage.total_tokens),
+        reasoning_tokens: None,
+        cached_tokens: None,
+        audio_tokens: None,
+    }
+}
+
+fn convert_tool_call(tool_call: CompletionsToolCall) -> ToolCall {
+    ToolCall {
+        id: tool_call.id,
+        name: tool_call.function.name,
+        arguments_json: tool_call.function.arguments,
+    }
+}
+
+pub fn process_response(response: CompletionsResponse) -> ChatEvent {
+    let id = response_id(&response.id, &response);
+    let Some(choice) = response.choices.into_iter().next() else {
+        return ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: "No choices in response".to_string(),
+            provider_error_json: None,
+        });
+    };
+
+    let content = choice
+        .message
+        .content
+        .filter(|text| !text.is_empty())
+        .map(|text| vec![ContentPart::Text(text)])
+        .unwrap_or_default();
+    let tool_calls = choice
+        .message
+        .tool_calls
+        .unwrap_or_default()
+        .into_iter()
+        .map(convert_tool_call)
+        .collect::<Vec<_>>();
+
+    if content.is_empty() && !tool_calls.is_empty() {
+        ChatEvent::ToolRequest(tool_calls)
+    } else {
+        ChatEvent::Message(CompleteResponse {
+            id,
+            content,
+            tool_calls,
+            metadata: ResponseMetadata {
+                finish_reason: choice.finish_reason.as_deref().map(convert_finish_reason),
+                usage: response.usage.as_ref().map(convert_usage),
+                provider_id: Some(response.id),
+                timestamp: Some(response.created.to_string()),
+                provider_metadata_json: choice
+                    .message
+                    .role
+                    .map(|role| serde_json::json!({ "role": role }).to_string()),
+                raw_json: None,
+            },
+        })
+    }
+}
+
+/// A tool call whose arguments are still being streamed
+#[derive(Default)]
+struct ToolCallFragment {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// The decoding state of a chat completions stream. Tool call fragments are collected by their
+/// index and emitted once the choice finishes.
+#[derive(Default)]
+pub struct CompletionsStreamState {
+    finish_reason: RefCell<Option<FinishReason>>,
+    tool_calls: RefCell<BTreeMap<u32, ToolCallFragment>>,
+}
+
+impl CompletionsStreamState {
+    pub fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
+        let chunk: ChatCompletionChunk = serde_json::from_str(raw)
+            .map_err(|err| format!("Failed to parse stream event: {err}"))?;
+
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            let mut tool_calls = self.tool_calls.borrow_mut();
+            for tool_call in choice.delta.tool_calls.unwrap_or_default() {
+                let fragment = tool_calls.entry(tool_call.index).or_default();
+                if let Some(id) = tool_call.id {
+                    fragment.id = id;
+                }
+                if let Some(function) = tool_call.function {
+                    if let Some(name) = function.name {
+                        fragment.name = name;
+                    }
+                    if let Some(arguments) = function.arguments {
+                        fragment.arguments.push_str(&arguments);
+                    }
+                }
+            }
+
+            let finished_tool_calls = if let Some(finish_reason) = &choice.finish_reason {
+                *self.finish_reason.borrow_mut() = Some(convert_finish_reason(finish_reason));
+                std::mem::take(&mut *tool_calls)
+                    .into_values()
+                    .map(|fragment| ToolCall {
+                        id: fragment.id,
+                        name: fragment.name,
+                        arguments_json: fragment.arguments,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            let content = choice
+                .delta
+                .content
+                .filter(|text| !text.is_empty())
+                .map(|text| vec![ContentPart::Text(text)]);
+            if content.is_none() && finished_tool_calls.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(StreamEvent::Delta(StreamDelta {
+                    content,
+                    tool_calls: (!finished_tool_calls.is_empty()).then_some(finished_tool_calls),
+                    provider_metadata_json: None,
+                    raw_json: None,
+                })))
+            }
+        } else if let Some(usage) = chunk.usage {
+            Ok(Some(StreamEvent::Finish(ResponseMetadata {
+                finish_reason: *self.finish_reason.borrow(),
+                usage: Some(convert_usage(&usage)),
+                provider_id: Some(chunk.id),
+                timestamp: Some(chunk.created.to_string()),
+                provider_metadata_json: None,
+                raw_json: None,
+            })))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Gateways ignoring `stream_options` never send the final usage chunk, so the stream is
+    /// finished when it ends after a finish reason
+    pub fn end_of_stream(&self) -> Option<StreamEvent> {
+        self.finish_reason.borrow().map(|finish_reason| {
+            StreamEvent::Finish(ResponseMetadata {
+                finish_reason: Some(finish_reason),
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat_completions::{
+        chat_completions_enabled, convert_messages, messages_to_request, process_response,
+        tool_results_to_messages, CompletionsStreamState,
+    };
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Config, ContentPart, ErrorCode, FinishReason, ImageDetail, ImageUrl, Message,
+        Role, StreamEvent, ToolCall, ToolDefinition, ToolResult, ToolSuccess,
+    };
+    use golem_llm::test_config;
+
+    fn config(provider_options: &[(&str, &str)]) -> Config {
+        Config {
+            model: "llama-3.1-8b".to_string(),
+            temperature: Some(0.5),
+            max_tokens: Some(100),
+            stop_sequences: Some(vec!["END".to_string()]),
+            tools: vec![ToolDefinition {
+                name: "get_weather".to_string(),
+                description: None,
+                parameters_schema: r#"{"type":"object"}"#.to_string(),
+            }],
+            tool_choice: Some("get_weather".to_string()),
+            ..test_config::config(provider_options)
+        }
+    }
+
+    #[test]
+    fn api_option_selects_chat_completions() {
+        assert_eq!(chat_completions_enabled(&config(&[])), Ok(false));
+        assert_eq!(
+            chat_completions_enabled(&config(&[("api", "responses")])),
+            Ok(false)
+        );
+        assert_eq!(
+            chat_completions_enabled(&config(&[("api", "chat_completions")])),
+            Ok(true)
+        );
+        assert_eq!(
+            chat_completions_enabled(&config(&[("api", "completions")]))
+                .unwrap_err()
+                .code,
+            ErrorCode::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn request_uses_chat_completions_shape() {
+        let messages = vec![
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text("Be brief".to_string())],
+            },
+            Message {
+                role: Role::User,
+                name: None,
+                content: vec![
+                    ContentPart::Text("What is the weather here?".to_string()),
+                    ContentPart::Image(ImageUrl {
+                        url: "https://example.com/sky.png".to_string(),
+                        detail: Some(ImageDetail::Low),
+                    }),
+                ],
+            },
+        ];
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+        };
+        let result = ToolResult::Success(ToolSuccess {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            result_json: r#"{"temperature":21}"#.to_string(),
+            execution_time_ms: None,
+        });
+
+        let mut messages = convert_messages(messages);
+        messages.extend(tool_results_to_messages(vec![(tool_call, result)]).unwrap());
+        let request =
+            messages_to_request(messages, config(&[("api", "chat_completions")])).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "model": "llama-3.1-8b",
+                "messages": [
+                    {"role": "system", "content": [{"type": "text", "text": "Be brief"}]},
+                    {"role": "user", "content": [
+                        {"type": "text", "text": "What is the weather here?"},
+                        {"type": "image_url", "image_url": {"url": "https://example.com/sky.png", "detail": "low"}}
+                    ]},
+                    {"role": "assistant", "tool_calls": [
+                        {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Berlin\"}"}}
+                    ]},
+                    {"role": "tool", "content": [{"type": "text", "text": "{\"temperature\":21}"}], "tool_call_id": "call_1"}
+                ],
+                "temperature": 0.5,
+                "max_tokens": 100,
+                "stop": ["END"],
+                "tools": [{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}],
+                "tool_choice": {"type": "function", "function": {"name": "get_weather"}},
+                "stream": false
+            })
+        );
+    }
+
+    #[test]
+    fn response_is_converted() {
+        let response = serde_json::from_str(
+            r#"{"id":"chatcmpl-1","object":"chat.completion","created":1,"model":"llama-3.1-8b","choices":[{"index":0,"message":{"role":"assistant","content":"Sunny","tool_calls":null},"finish_reason":"stop"}],"usage":{"prompt_tokens":10,"completion_tokens":1,"total_tokens":11}}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(response) => {
+                assert_eq!(
+                    response.content,
+                    vec![ContentPart::Text("Sunny".to_string())]
+                );
+                assert_eq!(response.metadata.finish_reason, Some(FinishReason::Stop));
+                assert_eq!(response.metadata.usage.unwrap().total_tokens, Some(11));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stream_is_decoded() {
+        let state = CompletionsStreamState::default();
+
+        let events = [
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"role":"assistant","content":"Let me check"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Berlin\"}"}}]},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[],"usage":{"prompt_tokens":10,"completion_tokens":5,"total_tokens":15}}"#,
+        ]
+        .iter()
+        .filter_map(|raw| state.decode_message(raw).unwrap())
+        .collect::<Vec<_>>();
+
+        match &events[..] {
+            [StreamEvent::Delta(text), StreamEvent::Delta(tool_call), StreamEvent::Finish(finish)] =>
+            {
+                assert_eq!(
+                    text.content,
+                    Some(vec![ContentPart::Text("Let me check".to_string())])
+                );
+                assert_eq!(
+                    tool_call.tool_calls,
+                    Some(vec![ToolCall {
+                        id: "call_1".to_string(),
+                        name: "get_weather".to_string(),
+                        arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+                    }])
+                );
+                assert_eq!(finish.finish_reason, Some(FinishReason::ToolCalls));
+                assert_eq!(finish.usage.as_ref().unwrap().total_tokens, Some(15));
+            }
+            other => panic!("Unexpected events: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stream_without_usage_chunk_finishes_at_the_end() {
+        let state = CompletionsStreamState::default();
+        assert_eq!(state.end_of_stream(), None);
+
+        state
+            .decode_message(
+                r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":"stop"}]}"#,
+            )
+            .unwrap();
+
+        match state.end_of_stream() {
+            Some(StreamEvent::Finish(metadata)) => {
+                assert_eq!(metadata.finish_reason, Some(FinishReason::Stop))
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn response_id_is_never_empty() {
+        for (id, expected) in [("chatcmpl-1", Some("chatcmpl-1")), ("", None)] {
+            let response = serde_json::from_str(&format!(
+                r#"{{"id":"{id}","object":"chat.completion","created":1,"model":"llama-3.1-8b","choices":[{{"index":0,"message":{{"role":"assistant","content":"Sunny","tool_calls":null}},"finish_reason":"stop"}}]}}"#
+            ))
+            .unwrap();
+
+            match process_response(response) {
+                ChatEvent::Message(message) => {
+                    assert!(!message.id.is_empty());
+                    if let Some(expected) = expected {
+                        assert_eq!(message.id, expected);
+                    }
+                }
+                other => panic!("Unexpected event: {other:?}"),
+            }
+        }
+    }
+}
@@ -1,31 +1,43 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::error::{parse_response, OpenAiErrorBody};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::Error;
+use golem_llm::golem::llm::llm::{Error, Kv};
+use golem_llm::trace_id::TRACE_ID;
+use golem_llm::transport::{
+    deprecation_notice, transport_for, HttpRequest, HttpTransport, RateLimitStatus,
+};
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response};
-use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::collections::HashMap;
 
-const BASE_URL: &str = "https://api.openai.com";
+const DEFAULT_BASE_URL: &str = "https://api.openai.com";
+
+/// Gets the base URL of the API, which can be changed with the `OPENAI_BASE_URL` environment
+/// variable to use an OpenAI-compatible gateway
+pub fn base_url() -> String {
+    std::env::var("OPENAI_BASE_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| DEFAULT_BASE_URL.to_string())
+}
 
 /// The OpenAI API client for creating model responses.
 ///
 /// Based on https://platform.openai.com/docs/api-reference/responses/create
 pub struct ResponsesApi {
     openai_api_key: String,
-    client: Client,
+    base_url: String,
+    transport: Box<dyn HttpTransport>,
 }
 
 impl ResponsesApi {
-    pub fn new(openai_api_key: String) -> Self {
-        let client = Client::builder()
-            .build()
-            .expect("Failed to initialize HTTP client");
+    pub fn new(openai_api_key: String, provider_options: &[Kv]) -> Self {
+        Self::with_transport(openai_api_key, transport_for(provider_options))
+    }
+
+    pub fn with_transport(openai_api_key: String, transport: Box<dyn HttpTransport>) -> Self {
         Self {
             openai_api_key,
-            client,
+            base_url: base_url(),
+            transport,
         }
     }
 
@@ -33,41 +45,72 @@ impl ResponsesApi {
         &self,
         request: CreateModelResponseRequest,
     ) -> Result<CreateModelResponseResponse, Error> {
-        trace!("Sending request to OpenAI API: {request:?}");
-
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/responses"))
-            .bearer_auth(&self.openai_api_key)
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
-
-        parse_response(response)
+        trace!("{TRACE_ID}Sending request to OpenAI API: {request:?}");
+
+        let response = self.transport.send(
+            HttpRequest::post(format!("{}/v1/responses", self.base_url))
+                .bearer_auth(&self.openai_api_key)
+                .json(&request)?,
+        )?;
+
+        let rate_limit = RateLimitStatus::from_headers(&response);
+        let deprecation = deprecation_notice(&response);
+        let mut body: CreateModelResponseResponse =
+            parse_response::<_, OpenAiErrorBody>("OpenAI API", response)?;
+        body.rate_limit = rate_limit;
+        body.deprecation = deprecation;
+        Ok(body)
     }
 
     pub fn stream_model_response(
         &self,
         request: CreateModelResponseRequest,
     ) -> Result<EventSource, Error> {
-        trace!("Sending request to OpenAI API: {request:?}");
+        trace!("{TRACE_ID}Sending request to OpenAI API: {request:?}");
 
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/v1/responses"))
+        let request = HttpRequest::post(format!("{}/v1/responses", self.base_url))
             .bearer_auth(&self.openai_api_key)
-            .header(
-                reqwest::header::ACCEPT,
-                HeaderValue::from_static("text/event-stream"),
-            )
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
-
-        trace!("Initializing SSE stream");
-
-        EventSource::new(response)
-            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+            .header(reqwest::header::ACCEPT.as_str(), "text/event-stream")
+            .json(&request)?;
+
+        trace!("{TRACE_ID}Initializing SSE stream");
+
+        self.transport.stream(request)
+    }
+}
+
+/// The OpenAI API client for generating images.
+///
+/// Based on https://platform.openai.com/docs/api-reference/images/create
+pub struct ImagesApi {
+    openai_api_key: String,
+    base_url: String,
+    transport: Box<dyn HttpTransport>,
+}
+
+impl ImagesApi {
+    pub fn new(openai_api_key: String, provider_options: &[Kv]) -> Self {
+        Self::with_transport(openai_api_key, transport_for(provider_options))
+    }
+
+    pub fn with_transport(openai_api_key: String, transport: Box<dyn HttpTransport>) -> Self {
+        Self {
+            openai_api_key,
+            base_url: base_url(),
+            transport,
+        }
+    }
+
+    pub fn create_image(&self, request: CreateImageRequest) -> Result<CreateImageResponse, Error> {
+        trace!("{TRACE_ID}Sending request to OpenAI API: {request:?}");
+
+        let response = self.transport.send(
+            HttpRequest::post(format!("{}/v1/images/generations", self.base_url))
+                .bearer_auth(&self.openai_api_key)
+                .json(&request)?,
+        )?;
+
+        parse_response::<_, OpenAiErrorBody>("OpenAI API", response)
     }
 }
 
@@ -75,6 +118,10 @@ impl ResponsesApi {
 pub struct CreateModelResponseRequest {
     pub input: Input,
     pub model: String,
+    /// Inserted into the model's context as a system (developer) message with higher priority than
+    /// the input messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -88,6 +135,25 @@ pub struct CreateModelResponseRequest {
     pub top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+    /// Configuration of reasoning models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<Reasoning>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reasoning {
+    /// `minimal`, `low`, `medium` or `high`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effort: Option<String>,
+    /// `auto`, `concise` or `detailed`, requests a summary of the reasoning
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +166,12 @@ pub struct CreateModelResponseResponse {
     pub output: Vec<OutputItem>,
     pub usage: Option<Usage>,
     pub metadata: Option<serde_json::Value>,
+    /// Read from the response headers
+    #[serde(skip)]
+    pub rate_limit: Option<RateLimitStatus>,
+    /// Read from the response headers
+    #[serde(skip)]
+    pub deprecation: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,13 +192,49 @@ pub enum OutputItem {
         id: String,
         status: Status,
     },
+    #[serde(rename = "web_search_call")]
+    WebSearchCall { id: String, status: String },
+    #[serde(rename = "code_interpreter_call")]
+    CodeInterpreterCall {
+        id: String,
+        status: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        outputs: Option<serde_json::Value>,
+    },
+    #[serde(rename = "reasoning")]
+    Reasoning {
+        id: String,
+        #[serde(default)]
+        summary: Vec<ReasoningSummary>,
+    },
+    #[serde(rename = "image_generation_call")]
+    ImageGenerationCall {
+        id: String,
+        status: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ReasoningSummary {
+    #[serde(rename = "summary_text")]
+    Text { text: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum OutputMessageContent {
     #[serde(rename = "output_text")]
-    Text { text: String },
+    Text {
+        text: String,
+        /// Citations of the built-in web search and file search tools
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        annotations: Vec<serde_json::Value>,
+    },
     #[serde(rename = "refusal")]
     Refusal { refusal: String },
 }
@@ -137,7 +245,7 @@ pub struct ErrorObject {
     pub message: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Status {
     #[serde(rename = "completed")]
     Completed,
@@ -233,6 +341,22 @@ pub enum Tool {
         parameters: Option<serde_json::Value>,
         strict: bool,
     },
+    #[serde(rename = "web_search_preview")]
+    WebSearchPreview {},
+    #[serde(rename = "code_interpreter")]
+    CodeInterpreter { container: ToolContainer },
+    #[serde(rename = "image_generation")]
+    ImageGeneration {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        partial_images: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ToolContainer {
+    #[serde(rename = "auto")]
+    Auto {},
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -262,33 +386,280 @@ pub struct ResponseOutputTextDelta {
     pub output_index: u32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseReasoningSummaryTextDelta {
+    pub delta: String,
+    pub item_id: String,
+    pub output_index: u32,
+    pub summary_index: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseRefusalDelta {
+    pub content_index: u32,
+    pub delta: String,
+    pub item_id: String,
+    pub output_index: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseRefusalDone {
+    pub content_index: u32,
+    pub item_id: String,
+    pub output_index: u32,
+    #[serde(default)]
+    pub refusal: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseImageGenerationCallPartialImage {
+    pub item_id: String,
+    pub output_index: u32,
+    pub partial_image_index: u32,
+    pub partial_image_b64: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseOutputItemAdded {
+    pub item: OutputItem,
+    pub output_index: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseFunctionCallArgumentsDelta {
+    pub delta: String,
+    pub item_id: String,
+    pub output_index: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseOutputItemDone {
     pub item: OutputItem,
     pub output_index: u32,
 }
 
-fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
-    let status = response.status();
-    if status.is_success() {
-        let body = response
-            .json::<T>()
-            .map_err(|err| from_reqwest_error("Failed to decode response body", err))?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateImageRequest {
+    pub prompt: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ImageResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
 
-        trace!("Received response from OpenAI API: {body:?}");
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageResponseFormat {
+    #[serde(rename = "url")]
+    Url,
+    #[serde(rename = "b64_json")]
+    B64Json,
+}
 
-        Ok(body)
-    } else {
-        let body = response
-            .text()
-            .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
-
-        trace!("Received {status} response from OpenAI API: {body:?}");
-
-        Err(Error {
-            code: error_code_from_status(status),
-            message: format!("Request failed with {status}"),
-            provider_error_json: Some(body),
-        })
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateImageResponse {
+    pub created: u64,
+    pub data: Vec<GeneratedImage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratedImage {
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub b64_json: Option<String>,
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{
+        CreateImageRequest, CreateModelResponseRequest, ImagesApi, Input, ResponsesApi, Status,
+    };
+    use crate::conversions::create_response_metadata;
+    use golem_llm::golem::llm::llm::ErrorCode;
+    use golem_llm::transport::{CannedTransport, HttpResponse};
+    use reqwest::StatusCode;
+    use std::rc::Rc;
+
+    fn request() -> CreateModelResponseRequest {
+        CreateModelResponseRequest {
+            input: Input::TextInput("Hello".to_string()),
+            model: "gpt-4o".to_string(),
+            instructions: None,
+            temperature: None,
+            max_output_tokens: None,
+            tools: vec![],
+            tool_choice: None,
+            stream: false,
+            top_p: None,
+            user: None,
+            parallel_tool_calls: None,
+            service_tier: None,
+            metadata: None,
+            reasoning: None,
+        }
+    }
+
+    #[test]
+    fn create_model_response_decodes_successful_response() {
+        let transport = Rc::new(CannedTransport::json(
+            StatusCode::OK,
+            r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[],"usage":null,"metadata":null}"#,
+        ));
+        let api = ResponsesApi::with_transport("key".to_string(), Box::new(transport.clone()));
+
+        let response = api.create_model_response(request()).unwrap();
+
+        assert_eq!(response.id, "resp_1");
+        assert!(matches!(response.status, Status::Completed));
+        let requests = transport.requests();
+        assert_eq!(requests[0].url, "https://api.openai.com/v1/responses");
+        assert!(requests[0]
+            .headers
+            .contains(&("authorization".to_string(), "Bearer key".to_string())));
+    }
+
+    #[test]
+    fn create_model_response_reads_rate_limit_headers() {
+        let transport = CannedTransport::new([Ok(HttpResponse::new(
+            StatusCode::OK,
+            r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[],"usage":null,"metadata":null}"#,
+        )
+        .with_header("content-type", "application/json")
+        .with_header("x-ratelimit-remaining-requests", "59")
+        .with_header("x-ratelimit-remaining-tokens", "149984"))]);
+        let api = ResponsesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let response = api.create_model_response(request()).unwrap();
+
+        let metadata: serde_json::Value = serde_json::from_str(
+            &create_response_metadata(&response)
+                .provider_metadata_json
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(metadata["ratelimit_remaining_requests"], 59);
+        assert_eq!(metadata["ratelimit_remaining_tokens"], 149984);
+    }
+
+    #[test]
+    fn create_model_response_reports_deprecation_in_metadata() {
+        let transport = CannedTransport::new([Ok(HttpResponse::new(
+            StatusCode::OK,
+            r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[],"usage":null,"metadata":null}"#,
+        )
+        .with_header("content-type", "application/json")
+        .with_header("openai-deprecation", "gpt-4-0613 will be retired")
+        .with_header("sunset", "2025-06-06"))]);
+        let api = ResponsesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let response = api.create_model_response(request()).unwrap();
+
+        let metadata: serde_json::Value = serde_json::from_str(
+            &create_response_metadata(&response)
+                .provider_metadata_json
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            metadata["deprecation"],
+            "gpt-4-0613 will be retired (sunset: 2025-06-06)"
+        );
+    }
+
+    #[test]
+    fn create_model_response_maps_error_status() {
+        let transport = CannedTransport::json(
+            StatusCode::TOO_MANY_REQUESTS,
+            r#"{"error":{"message":"Slow down"}}"#,
+        );
+        let api = ResponsesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let error = api.create_model_response(request()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::RateLimitExceeded);
+        assert_eq!(
+            error.provider_error_json,
+            Some(r#"{"error":{"message":"Slow down"}}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn create_model_response_classifies_error_in_successful_response() {
+        let body = r#"{"error":{"message":"Rate limit reached","type":"requests","code":"rate_limit_exceeded"}}"#;
+        let transport = CannedTransport::json(StatusCode::OK, body);
+        let api = ResponsesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let error = api.create_model_response(request()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::RateLimitExceeded);
+        assert_eq!(
+            error.message,
+            "Provider returned an error in a successful response: Rate limit reached"
+        );
+        assert_eq!(error.provider_error_json, Some(body.to_string()));
+    }
+
+    #[test]
+    fn create_model_response_reports_schema_mismatch() {
+        let body = r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"exploded","output":[],"usage":null,"metadata":null}"#;
+        let transport = CannedTransport::json(StatusCode::OK, body);
+        let api = ResponsesApi::with_transport("key".to_string(), Box::new(transport));
+
+        let error = api.create_model_response(request()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert!(error.message.starts_with(
+            "Response body does not match the expected schema at $.status: unknown variant `exploded`"
+        ));
+        assert_eq!(error.provider_error_json, Some(body.to_string()));
+    }
+
+    #[test]
+    fn create_image_decodes_generated_images() {
+        let transport = Rc::new(CannedTransport::json(
+            StatusCode::OK,
+            r#"{"created":1,"data":[{"url":"https://example.com/1.png","revised_prompt":"A red cat"},{"b64_json":"aGVsbG8="}]}"#,
+        ));
+        let api = ImagesApi::with_transport("key".to_string(), Box::new(transport.clone()));
+
+        let response = api
+            .create_image(CreateImageRequest {
+                prompt: "A cat".to_string(),
+                model: "dall-e-3".to_string(),
+                n: Some(2),
+                size: Some("1024x1024".to_string()),
+                quality: None,
+                style: None,
+                response_format: None,
+                user: None,
+            })
+            .unwrap();
+
+        assert_eq!(response.data.len(), 2);
+        assert_eq!(
+            response.data[0].url.as_deref(),
+            Some("https://example.com/1.png")
+        );
+        assert_eq!(response.data[1].b64_json.as_deref(), Some("aGVsbG8="));
+        let requests = transport.requests();
+        assert_eq!(
+            requests[0].url,
+            "https://api.openai.com/v1/images/generations"
+        );
+        assert_eq!(
+            requests[0].body.as_deref(),
+            Some(r#"{"prompt":"A cat","model":"dall-e-3","n":2,"size":"1024x1024"}"#.as_bytes())
+        );
     }
 }
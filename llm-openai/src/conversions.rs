@@ -1,12 +1,23 @@
 use crate::client::{
-    CreateModelResponseRequest, CreateModelResponseResponse, Detail, InnerInput, InnerInputItem,
-    Input, InputItem, OutputItem, OutputMessageContent, Tool,
+    CreateImageRequest, CreateImageResponse, CreateModelResponseRequest,
+    CreateModelResponseResponse, Detail, ImageResponseFormat, InnerInput, InnerInputItem, Input,
+    InputItem, OutputItem, OutputMessageContent, Reasoning, ReasoningSummary, Status, Tool,
+    ToolContainer,
+};
+use golem_llm::config::{
+    apply_sampling_preset, bool_option, builtin_tools, max_tokens, output_constraint,
+    request_metadata, resolve_model,
+};
+use golem_llm::error::{
+    check_tool_result_id, check_tool_result_json, error_code_from_status, unsupported,
 };
-use golem_llm::error::error_code_from_status;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, ImageDetail, Message,
-    ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
+    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageData,
+    ImageDetail, ImageGenConfig, ImageResult, ImageUrl, Message, ResponseMetadata, Role, ToolCall,
+    ToolDefinition, ToolResult, Usage,
 };
+use golem_llm::response_id::response_id;
+use golem_llm::tool_call_only::forced_tool_choice;
 use reqwest::StatusCode;
 use std::collections::HashMap;
 use std::str::FromStr;
@@ -15,20 +26,37 @@ pub fn create_request(
     items: Vec<InputItem>,
     config: Config,
     tools: Vec<Tool>,
-) -> CreateModelResponseRequest {
+) -> Result<CreateModelResponseRequest, Error> {
+    if output_constraint(&config)?.is_some() {
+        return Err(unsupported("The grammar and regex options"));
+    }
+
+    let config = apply_sampling_preset(config, "openai")?;
+    let model = resolve_model(&config, "openai")?;
+    let max_output_tokens = max_tokens(&config)?;
+    let tool_choice = forced_tool_choice(&config, "required")?;
+    let metadata = request_metadata(&config)?;
+    let markdown = bool_option(&config, "markdown");
     let options = config
         .provider_options
         .into_iter()
         .map(|kv| (kv.key, kv.value))
         .collect::<HashMap<_, _>>();
+    let (instructions, items) = split_instructions(items, options.get("instructions").cloned());
+    let instructions = if markdown {
+        Some(with_markdown_hint(instructions))
+    } else {
+        instructions
+    };
 
-    CreateModelResponseRequest {
+    Ok(CreateModelResponseRequest {
         input: Input::List(items),
-        model: config.model,
+        model,
+        instructions,
         temperature: config.temperature,
-        max_output_tokens: config.max_tokens,
+        max_output_tokens,
         tools,
-        tool_choice: config.tool_choice,
+        tool_choice,
         stream: false,
         top_p: options
             .get("top_p")
@@ -36,9 +64,131 @@ pub fn create_request(
         user: options
             .get("user")
             .and_then(|user_s| user_s.parse::<String>().ok()),
+        parallel_tool_calls: options
+            .get("parallel_tool_calls")
+            .and_then(|ptc_s| ptc_s.parse::<bool>().ok()),
+        service_tier: options.get("service_tier").cloned(),
+        metadata,
+        reasoning: reasoning(&options),
+    })
+}
+
+/// The reasoning configuration set by the `reasoning_effort` and `reasoning_summary` provider
+/// options, for reasoning models such as the o-series
+fn reasoning(options: &HashMap<String, String>) -> Option<Reasoning> {
+    let effort = options.get("reasoning_effort").cloned();
+    let summary = options.get("reasoning_summary").cloned();
+    if effort.is_none() && summary.is_none() {
+        None
+    } else {
+        Some(Reasoning { effort, summary })
+    }
+}
+
+/// Moves the text of the system messages into the `instructions` field, after the `instructions`
+/// provider option if it is set. System messages with images are kept as input messages.
+fn split_instructions(
+    items: Vec<InputItem>,
+    instructions: Option<String>,
+) -> (Option<String>, Vec<InputItem>) {
+    let mut parts = instructions.into_iter().collect::<Vec<_>>();
+    let mut rest = Vec::new();
+    for item in items {
+        match item {
+            InputItem::InputMessage { role, content } if role == "system" => {
+                match system_text(&content) {
+                    Some(text) => parts.push(text),
+                    None => rest.push(InputItem::InputMessage { role, content }),
+                }
+            }
+            item => rest.push(item),
+        }
+    }
+
+    let instructions = if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n\n"))
+    };
+    (instructions, rest)
+}
+
+/// Reasoning models avoid markdown in their responses unless the first line of the instructions
+/// re-enables it, which is a no-op for other models
+fn with_markdown_hint(instructions: Option<String>) -> String {
+    match instructions {
+        Some(instructions) => format!("Formatting re-enabled\n{instructions}"),
+        None => "Formatting re-enabled".to_string(),
+    }
+}
+
+fn system_text(content: &InnerInput) -> Option<String> {
+    match content {
+        InnerInput::TextInput(text) => Some(text.clone()),
+        InnerInput::List(items) => items
+            .iter()
+            .map(|item| match item {
+                InnerInputItem::TextInput { text } => Some(text.as_str()),
+                InnerInputItem::ImageInput { .. } => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|texts| texts.join("\n")),
+    }
+}
+
+pub fn create_image_request(prompt: String, config: ImageGenConfig) -> CreateImageRequest {
+    let options = config
+        .provider_options
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect::<HashMap<_, _>>();
+
+    CreateImageRequest {
+        prompt,
+        model: config.model,
+        n: config.n,
+        size: config.size,
+        quality: config.quality,
+        style: config.style,
+        response_format: options
+            .get("response_format")
+            .and_then(|format| match format.as_str() {
+                "url" => Some(ImageResponseFormat::Url),
+                "b64_json" => Some(ImageResponseFormat::B64Json),
+                _ => None,
+            }),
+        user: options.get("user").cloned(),
     }
 }
 
+pub fn process_image_response(
+    response: CreateImageResponse,
+    size: Option<String>,
+) -> Result<Vec<ImageResult>, Error> {
+    response
+        .data
+        .into_iter()
+        .map(|image| {
+            let data = match (image.url, image.b64_json) {
+                (Some(url), _) => ImageData::Url(url),
+                (None, Some(b64_json)) => ImageData::Base64(b64_json),
+                (None, None) => {
+                    return Err(Error {
+                        code: ErrorCode::InternalError,
+                        message: "Generated image has neither url nor b64_json".to_string(),
+                        provider_error_json: None,
+                    })
+                }
+            };
+            Ok(ImageResult {
+                data,
+                size: size.clone(),
+                revised_prompt: image.revised_prompt,
+            })
+        })
+        .collect()
+}
+
 pub fn messages_to_input_items(messages: Vec<Message>) -> Vec<InputItem> {
     let mut items = Vec::new();
     for message in messages {
@@ -56,19 +206,25 @@ pub fn messages_to_input_items(messages: Vec<Message>) -> Vec<InputItem> {
     items
 }
 
-pub fn tool_results_to_input_items(tool_results: Vec<(ToolCall, ToolResult)>) -> Vec<InputItem> {
+pub fn tool_results_to_input_items(
+    tool_results: Vec<(ToolCall, ToolResult)>,
+) -> Result<Vec<InputItem>, Error> {
     let mut items = Vec::new();
     for (tool_call, tool_result) in tool_results {
+        check_tool_result_id(&tool_call, &tool_result)?;
         let tool_call = InputItem::ToolCall {
             arguments: tool_call.arguments_json,
             call_id: tool_call.id,
             name: tool_call.name,
         };
         let tool_result = match tool_result {
-            ToolResult::Success(success) => InputItem::ToolResult {
-                call_id: success.id,
-                output: format!(r#"{{ "success": {} }}"#, success.result_json),
-            },
+            ToolResult::Success(success) => {
+                check_tool_result_json(&success)?;
+                InputItem::ToolResult {
+                    call_id: success.id,
+                    output: format!(r#"{{ "success": {} }}"#, success.result_json),
+                }
+            }
             ToolResult::Error(error) => InputItem::ToolResult {
                 call_id: error.id,
                 output: format!(
@@ -81,7 +237,7 @@ pub fn tool_results_to_input_items(tool_results: Vec<(ToolCall, ToolResult)>) ->
         items.push(tool_call);
         items.push(tool_result);
     }
-    items
+    Ok(items)
 }
 
 pub fn tool_defs_to_tools(tool_definitions: &[ToolDefinition]) -> Result<Vec<Tool>, Error> {
@@ -112,6 +268,53 @@ pub fn tool_defs_to_tools(tool_definitions: &[ToolDefinition]) -> Result<Vec<Too
     Ok(tools)
 }
 
+/// Converts both the user defined tools and the built-in tools enabled by the `builtin_tools`
+/// provider option
+pub fn config_to_tools(config: &Config) -> Result<Vec<Tool>, Error> {
+    let mut tools = tool_defs_to_tools(&config.tools)?;
+    for name in builtin_tools(config) {
+        tools.push(builtin_tool(&name)?);
+    }
+    Ok(tools)
+}
+
+fn builtin_tool(name: &str) -> Result<Tool, Error> {
+    match name {
+        "web_search" => Ok(Tool::WebSearchPreview {}),
+        "code_interpreter" => Ok(Tool::CodeInterpreter {
+            container: ToolContainer::Auto {},
+        }),
+        "image_generation" => Ok(Tool::ImageGeneration {
+            partial_images: None,
+        }),
+        _ => Err(unsupported(format!("Built-in tool {name}"))),
+    }
+}
+
+/// The number of partial images requested from the `image_generation` built-in tool
+const PARTIAL_IMAGES: u32 = 2;
+
+/// Returns whether the `stream_partial_images=true` provider option is set, streaming the
+/// progressively refined partial images of the `image_generation` built-in tool before the final
+/// one
+pub fn stream_partial_images_enabled(config: &Config) -> bool {
+    bool_option(config, "stream_partial_images")
+}
+
+/// Requests partial images from the image generation tools of a streaming request
+pub fn request_partial_images(tools: &mut [Tool]) {
+    for tool in tools {
+        if let Tool::ImageGeneration { partial_images } = tool {
+            *partial_images = Some(PARTIAL_IMAGES);
+        }
+    }
+}
+
+/// Generated images are returned as base64 encoded PNGs, passed on as data URLs
+pub fn image_data_url(b64: &str) -> String {
+    format!("data:image/png;base64,{b64}")
+}
+
 pub fn to_openai_role_name(role: Role) -> &'static str {
     match role {
         Role::User => "user",
@@ -154,10 +357,22 @@ pub fn process_model_response(response: CreateModelResponseResponse) -> ChatEven
             message: error.message,
             provider_error_json: None,
         })
+    } else if response.status == Status::InProgress || response.status == Status::Failed {
+        // A non-final response must not be mistaken for a complete one
+        ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: format!(
+                "Response {} is not complete, its status is {}",
+                response.id,
+                status_name(response.status)
+            ),
+            provider_error_json: create_response_metadata(&response).provider_metadata_json,
+        })
     } else {
         let mut contents = Vec::new();
         let mut tool_calls = Vec::new();
 
+        let id = response_id(&response.id, &response);
         let metadata = create_response_metadata(&response);
 
         for output_item in response.output {
@@ -187,6 +402,21 @@ pub fn process_model_response(response: CreateModelResponseResponse) -> ChatEven
                     };
                     tool_calls.push(tool_call);
                 }
+                OutputItem::ImageGenerationCall {
+                    result: Some(result),
+                    ..
+                } => {
+                    contents.push(ContentPart::Image(ImageUrl {
+                        url: image_data_url(&result),
+                        detail: None,
+                    }));
+                }
+                // Built-in tool calls are executed by OpenAI and reasoning summaries are not part
+                // of the answer, they are reported in the metadata
+                OutputItem::WebSearchCall { .. }
+                | OutputItem::CodeInterpreterCall { .. }
+                | OutputItem::ImageGenerationCall { .. }
+                | OutputItem::Reasoning { .. } => {}
             }
         }
 
@@ -194,7 +424,7 @@ pub fn process_model_response(response: CreateModelResponseResponse) -> ChatEven
             ChatEvent::ToolRequest(tool_calls)
         } else {
             ChatEvent::Message(CompleteResponse {
-                id: response.id,
+                id,
                 content: contents,
                 tool_calls,
                 metadata,
@@ -204,15 +434,728 @@ pub fn process_model_response(response: CreateModelResponseResponse) -> ChatEven
 }
 
 pub fn create_response_metadata(response: &CreateModelResponseResponse) -> ResponseMetadata {
+    let mut provider_metadata = serde_json::Map::new();
+    provider_metadata.insert(
+        "status".to_string(),
+        serde_json::Value::String(status_name(response.status).to_string()),
+    );
+    if let Some(incomplete_details) = &response.incomplete_details {
+        provider_metadata.insert(
+            "incomplete_reason".to_string(),
+            serde_json::Value::String(incomplete_details.reason.clone()),
+        );
+    }
+    if let Some(metadata) = &response.metadata {
+        provider_metadata.insert("metadata".to_string(), metadata.clone());
+    }
+    if let Some(rate_limit) = &response.rate_limit {
+        rate_limit.add_to_metadata(&mut provider_metadata);
+    }
+    if let Some(deprecation) = &response.deprecation {
+        provider_metadata.insert(
+            "deprecation".to_string(),
+            serde_json::Value::String(deprecation.clone()),
+        );
+    }
+
+    let mut citations = Vec::new();
+    let mut builtin_tool_calls = Vec::new();
+    let mut reasoning = Vec::new();
+    let mut refusals = Vec::new();
+    for output_item in &response.output {
+        match output_item {
+            OutputItem::Message { content, role, .. } => {
+                provider_metadata
+                    .entry("role")
+                    .or_insert_with(|| serde_json::Value::String(role.clone()));
+                for content in content {
+                    match content {
+                        OutputMessageContent::Text { annotations, .. } => {
+                            citations.extend(annotations.iter().cloned());
+                        }
+                        OutputMessageContent::Refusal { refusal } => {
+                            refusals.push(refusal.as_str());
+                        }
+                    }
+                }
+            }
+            OutputItem::WebSearchCall { .. } | OutputItem::CodeInterpreterCall { .. } => {
+                if let Ok(value) = serde_json::to_value(output_item) {
+                    builtin_tool_calls.push(value);
+                }
+            }
+            OutputItem::Reasoning { summary, .. } => {
+                for ReasoningSummary::Text { text } in summary {
+                    reasoning.push(text.as_str());
+                }
+            }
+            // Generated images are returned as content parts
+            OutputItem::ToolCall { .. } | OutputItem::ImageGenerationCall { .. } => {}
+        }
+    }
+    if !citations.is_empty() {
+        provider_metadata.insert("citations".to_string(), serde_json::Value::Array(citations));
+    }
+    if !refusals.is_empty() {
+        provider_metadata.insert(
+            "refusal".to_string(),
+            serde_json::Value::String(refusals.join("\n\n")),
+        );
+    }
+    if !reasoning.is_empty() {
+        provider_metadata.insert(
+            "reasoning".to_string(),
+            serde_json::Value::String(reasoning.join("\n\n")),
+        );
+    }
+    if !builtin_tool_calls.is_empty() {
+        provider_metadata.insert(
+            "builtin_tool_calls".to_string(),
+            serde_json::Value::Array(builtin_tool_calls),
+        );
+    }
+
     ResponseMetadata {
-        finish_reason: None,
-        usage: response.usage.as_ref().map(|usage| Usage {
-            input_tokens: Some(usage.input_tokens),
-            output_tokens: Some(usage.output_tokens),
-            total_tokens: Some(usage.total_tokens),
-        }),
+        finish_reason: response_finish_reason(response),
+        usage: response.usage.as_ref().map(convert_usage),
         provider_id: Some(response.id.clone()),
         timestamp: Some(response.created_at.to_string()),
-        provider_metadata_json: response.metadata.as_ref().map(|m| m.to_string()),
+        provider_metadata_json: Some(serde_json::Value::Object(provider_metadata).to_string()),
+        raw_json: None,
+    }
+}
+
+pub fn convert_usage(usage: &crate::client::Usage) -> Usage {
+    Usage {
+        input_tokens: Some(usage.input_tokens),
+        output_tokens: Some(usage.output_tokens),
+        total_tokens: Some(usage.total_tokens),
+        reasoning_tokens: Some(usage.output_tokens_details.reasoning_tokens),
+        cached_tokens: Some(usage.input_tokens_details.cached_tokens),
+        audio_tokens: None,
+    }
+}
+
+fn response_finish_reason(response: &CreateModelResponseResponse) -> Option<FinishReason> {
+    match response.status {
+        Status::Completed => {
+            if response
+                .output
+                .iter()
+                .any(|item| matches!(item, OutputItem::ToolCall { .. }))
+            {
+                Some(FinishReason::ToolCalls)
+            } else if has_refusal(response) {
+                Some(FinishReason::ContentFilter)
+            } else {
+                Some(FinishReason::Stop)
+            }
+        }
+        Status::Incomplete => match response
+            .incomplete_details
+            .as_ref()
+            .map(|details| details.reason.as_str())
+        {
+            Some("max_output_tokens") => Some(FinishReason::Length),
+            Some("content_filter") => Some(FinishReason::ContentFilter),
+            _ => Some(FinishReason::Other),
+        },
+        Status::Failed => Some(FinishReason::Error),
+        Status::InProgress => None,
+    }
+}
+
+fn has_refusal(response: &CreateModelResponseResponse) -> bool {
+    response.output.iter().any(|item| match item {
+        OutputItem::Message { content, .. } => content
+            .iter()
+            .any(|content| matches!(content, OutputMessageContent::Refusal { .. })),
+        _ => false,
+    })
+}
+
+fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Completed => "completed",
+        Status::Failed => "failed",
+        Status::InProgress => "in_progress",
+        Status::Incomplete => "incomplete",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{
+        CreateImageResponse, CreateModelResponseResponse, GeneratedImage, ImageResponseFormat,
+        IncompleteDetailsObject, OutputItem, OutputMessageContent, Status,
+    };
+    use crate::conversions::{
+        config_to_tools, convert_usage, create_image_request, create_request,
+        create_response_metadata, messages_to_input_items, process_image_response,
+        process_model_response, request_partial_images, stream_partial_images_enabled,
+        tool_results_to_input_items,
+    };
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Config, ContentPart, ErrorCode, FinishReason, ImageData, ImageGenConfig,
+        ImageUrl, Kv, Message, Role, ToolCall, ToolDefinition, ToolResult, ToolSuccess,
+    };
+    use golem_llm::test_config;
+
+    fn response(
+        status: Status,
+        incomplete_details: Option<IncompleteDetailsObject>,
+    ) -> CreateModelResponseResponse {
+        CreateModelResponseResponse {
+            id: "resp_1".to_string(),
+            created_at: 1,
+            error: None,
+            incomplete_details,
+            status,
+            output: vec![OutputItem::Message {
+                id: "msg_1".to_string(),
+                content: vec![OutputMessageContent::Text {
+                    text: "Hello".to_string(),
+                    annotations: vec![],
+                }],
+                role: "assistant".to_string(),
+                status,
+            }],
+            usage: None,
+            metadata: None,
+            rate_limit: None,
+            deprecation: None,
+        }
+    }
+
+    #[test]
+    fn completed_response_is_final() {
+        match process_model_response(response(Status::Completed, None)) {
+            ChatEvent::Message(message) => {
+                assert_eq!(message.metadata.finish_reason, Some(FinishReason::Stop));
+                assert_eq!(
+                    message.metadata.provider_metadata_json,
+                    Some(r#"{"status":"completed"}"#.to_string())
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn incomplete_response_reports_reason() {
+        let event = process_model_response(response(
+            Status::Incomplete,
+            Some(IncompleteDetailsObject {
+                reason: "max_output_tokens".to_string(),
+            }),
+        ));
+        match event {
+            ChatEvent::Message(message) => {
+                assert_eq!(message.metadata.finish_reason, Some(FinishReason::Length));
+                assert_eq!(
+                    message.metadata.provider_metadata_json,
+                    Some(
+                        r#"{"incomplete_reason":"max_output_tokens","status":"incomplete"}"#
+                            .to_string()
+                    )
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn in_progress_response_is_an_error() {
+        match process_model_response(response(Status::InProgress, None)) {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InternalError);
+                assert_eq!(
+                    error.provider_error_json,
+                    Some(r#"{"status":"in_progress"}"#.to_string())
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn failed_response_without_error_object_is_an_error() {
+        match process_model_response(response(Status::Failed, None)) {
+            ChatEvent::Error(error) => {
+                assert_eq!(
+                    error.provider_error_json,
+                    Some(r#"{"status":"failed"}"#.to_string())
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn image_request_uses_config_and_options() {
+        let request = create_image_request(
+            "A cat".to_string(),
+            ImageGenConfig {
+                model: "dall-e-3".to_string(),
+                n: Some(1),
+                size: Some("1792x1024".to_string()),
+                quality: Some("hd".to_string()),
+                style: Some("natural".to_string()),
+                provider_options: vec![Kv {
+                    key: "response_format".to_string(),
+                    value: "b64_json".to_string(),
+                }],
+            },
+        );
+
+        assert_eq!(request.size.as_deref(), Some("1792x1024"));
+        assert_eq!(request.quality.as_deref(), Some("hd"));
+        assert_eq!(request.style.as_deref(), Some("natural"));
+        assert_eq!(request.response_format, Some(ImageResponseFormat::B64Json));
+    }
+
+    #[test]
+    fn image_response_is_converted() {
+        let results = process_image_response(
+            CreateImageResponse {
+                created: 1,
+                data: vec![
+                    GeneratedImage {
+                        url: Some("https://example.com/1.png".to_string()),
+                        b64_json: None,
+                        revised_prompt: Some("A red cat".to_string()),
+                    },
+                    GeneratedImage {
+                        url: None,
+                        b64_json: Some("aGVsbG8=".to_string()),
+                        revised_prompt: None,
+                    },
+                ],
+            },
+            Some("1024x1024".to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            results[0].data,
+            ImageData::Url("https://example.com/1.png".to_string())
+        );
+        assert_eq!(results[0].revised_prompt.as_deref(), Some("A red cat"));
+        assert_eq!(results[1].data, ImageData::Base64("aGVsbG8=".to_string()));
+        assert_eq!(results[1].size.as_deref(), Some("1024x1024"));
+    }
+
+    #[test]
+    fn image_without_data_is_an_error() {
+        let error = process_image_response(
+            CreateImageResponse {
+                created: 1,
+                data: vec![GeneratedImage {
+                    url: None,
+                    b64_json: None,
+                    revised_prompt: None,
+                }],
+            },
+            None,
+        )
+        .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+    }
+
+    fn config(provider_options: &[(&str, &str)]) -> Config {
+        Config {
+            model: "gpt-4o".to_string(),
+            ..test_config::config(provider_options)
+        }
+    }
+
+    fn weather_tool() -> ToolDefinition {
+        ToolDefinition {
+            name: "get_weather".to_string(),
+            description: Some("Gets the current weather of a city".to_string()),
+            parameters_schema: r#"{"type":"object","properties":{"city":{"type":"string"}},"required":["city"],"additionalProperties":false}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn configured_tools_are_serialized() {
+        let mut config = config(&[]);
+        config.tools = vec![weather_tool()];
+
+        let tools = config_to_tools(&config).unwrap();
+        let mut request = create_request(vec![], config, tools).unwrap();
+        request.stream = true;
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(
+            json["tools"],
+            serde_json::json!([{
+                "type": "function",
+                "name": "get_weather",
+                "description": "Gets the current weather of a city",
+                "parameters": {
+                    "type": "object",
+                    "properties": {"city": {"type": "string"}},
+                    "required": ["city"],
+                    "additionalProperties": false
+                },
+                "strict": true
+            }])
+        );
+    }
+
+    #[test]
+    fn max_tokens_is_omitted_unless_positive() {
+        let mut config = config(&[]);
+
+        let json =
+            serde_json::to_value(create_request(vec![], config.clone(), vec![]).unwrap()).unwrap();
+        assert!(json.get("max_output_tokens").is_none());
+
+        config.max_tokens = Some(0);
+        let error = create_request(vec![], config, vec![]).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn tools_are_omitted_when_none_are_configured() {
+        let config = config(&[]);
+
+        let tools = config_to_tools(&config).unwrap();
+        let request = create_request(vec![], config, tools).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert!(json.get("tools").is_none());
+    }
+
+    #[test]
+    fn invalid_tool_parameters_are_rejected() {
+        let mut config = config(&[]);
+        config.tools = vec![ToolDefinition {
+            parameters_schema: "{ type: object }".to_string(),
+            ..weather_tool()
+        }];
+
+        let error = config_to_tools(&config).unwrap_err();
+
+        assert!(error.message.contains("get_weather"));
+    }
+
+    #[test]
+    fn parallel_tool_calls_option_is_serialized() {
+        let request =
+            create_request(vec![], config(&[("parallel_tool_calls", "false")]), vec![]).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["parallel_tool_calls"], false);
+    }
+
+    #[test]
+    fn parallel_tool_calls_is_omitted_by_default() {
+        let request = create_request(vec![], config(&[]), vec![]).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert!(json.get("parallel_tool_calls").is_none());
+    }
+
+    #[test]
+    fn service_tier_option_is_serialized() {
+        let request = create_request(vec![], config(&[("service_tier", "flex")]), vec![]).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["service_tier"], "flex");
+    }
+
+    #[test]
+    fn metadata_option_is_serialized() {
+        let request = create_request(
+            vec![],
+            config(&[("metadata", r#"{"tenant": "acme"}"#)]),
+            vec![],
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(json["metadata"], serde_json::json!({"tenant": "acme"}));
+    }
+
+    #[test]
+    fn reasoning_options_are_serialized_as_reasoning_object() {
+        let request = create_request(
+            vec![],
+            config(&[("reasoning_effort", "high"), ("reasoning_summary", "auto")]),
+            vec![],
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(
+            json["reasoning"],
+            serde_json::json!({"effort": "high", "summary": "auto"})
+        );
+    }
+
+    #[test]
+    fn reasoning_is_omitted_by_default() {
+        let request = create_request(vec![], config(&[]), vec![]).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert!(json.get("reasoning").is_none());
+    }
+
+    #[test]
+    fn reasoning_summary_is_reported_in_metadata() {
+        let response: CreateModelResponseResponse = serde_json::from_str(
+            r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[{"type":"reasoning","id":"rs_1","summary":[{"type":"summary_text","text":"Compared the options."}]},{"type":"message","id":"msg_1","role":"assistant","status":"completed","content":[{"type":"output_text","text":"Take the train","annotations":[]}]}],"usage":null,"metadata":null}"#,
+        )
+        .unwrap();
+
+        let metadata = create_response_metadata(&response);
+
+        let provider_metadata: serde_json::Value =
+            serde_json::from_str(&metadata.provider_metadata_json.unwrap()).unwrap();
+        assert_eq!(provider_metadata["reasoning"], "Compared the options.");
+    }
+
+    #[test]
+    fn model_alias_is_resolved() {
+        let mut config = config(&[("model_alias", "builtin")]);
+        config.model = "fast".to_string();
+
+        let request = create_request(vec![], config, vec![]).unwrap();
+
+        assert_eq!(request.model, "gpt-4o-mini");
+    }
+
+    #[test]
+    fn usage_preserves_token_details() {
+        let usage: crate::client::Usage = serde_json::from_str(
+            r#"{"input_tokens":100,"input_tokens_details":{"cached_tokens":60},"output_tokens":40,"output_tokens_details":{"reasoning_tokens":30},"total_tokens":140}"#,
+        )
+        .unwrap();
+
+        let usage = convert_usage(&usage);
+
+        assert_eq!(usage.total_tokens, Some(140));
+        assert_eq!(usage.reasoning_tokens, Some(30));
+        assert_eq!(usage.cached_tokens, Some(60));
+        assert_eq!(usage.audio_tokens, None);
+    }
+
+    #[test]
+    fn invalid_tool_result_json_is_rejected() {
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            name: "sum".to_string(),
+            arguments_json: r#"{"a":1,"b":5}"#.to_string(),
+        };
+        let result = |result_json: &str| {
+            ToolResult::Success(ToolSuccess {
+                id: "call_1".to_string(),
+                name: "sum".to_string(),
+                result_json: result_json.to_string(),
+                execution_time_ms: None,
+            })
+        };
+
+        assert!(
+            tool_results_to_input_items(vec![(tool_call.clone(), result(r#"{"value":6}"#))])
+                .is_ok()
+        );
+        let error =
+            tool_results_to_input_items(vec![(tool_call, result("{ value: 6 }"))]).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn builtin_tools_are_added_to_the_request() {
+        let config = config(&[("builtin_tools", "web_search,code_interpreter")]);
+
+        let tools = config_to_tools(&config).unwrap();
+        let request = create_request(vec![], config, tools).unwrap();
+
+        let json = serde_json::to_value(request).unwrap();
+        assert_eq!(
+            json["tools"],
+            serde_json::json!([
+                {"type": "web_search_preview"},
+                {"type": "code_interpreter", "container": {"type": "auto"}}
+            ])
+        );
+    }
+
+    #[test]
+    fn partial_images_are_requested_when_streaming_them() {
+        let config = config(&[
+            ("builtin_tools", "image_generation"),
+            ("stream_partial_images", "true"),
+        ]);
+        assert!(stream_partial_images_enabled(&config));
+
+        let mut tools = config_to_tools(&config).unwrap();
+        request_partial_images(&mut tools);
+
+        assert_eq!(
+            serde_json::to_value(tools).unwrap(),
+            serde_json::json!([{"type": "image_generation", "partial_images": 2}])
+        );
+    }
+
+    #[test]
+    fn generated_image_is_returned_as_data_url() {
+        let response: CreateModelResponseResponse = serde_json::from_str(
+            r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[{"type":"image_generation_call","id":"ig_1","status":"completed","result":"aW1hZ2U="}],"usage":null,"metadata":null}"#,
+        )
+        .unwrap();
+
+        match process_model_response(response) {
+            ChatEvent::Message(message) => assert_eq!(
+                message.content,
+                vec![ContentPart::Image(ImageUrl {
+                    url: "data:image/png;base64,aW1hZ2U=".to_string(),
+                    detail: None,
+                })]
+            ),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_builtin_tool_is_unsupported() {
+        let config = config(&[("builtin_tools", "time_travel")]);
+
+        let error = config_to_tools(&config).unwrap_err();
+        assert_eq!(error.code, ErrorCode::Unsupported);
+    }
+
+    #[test]
+    fn citations_and_builtin_tool_calls_are_reported_in_metadata() {
+        let response: CreateModelResponseResponse = serde_json::from_str(
+            r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[{"type":"web_search_call","id":"ws_1","status":"completed"},{"type":"message","id":"msg_1","role":"assistant","status":"completed","content":[{"type":"output_text","text":"Golem is great","annotations":[{"type":"url_citation","start_index":0,"end_index":5,"url":"https://golem.cloud","title":"Golem"}]}]}],"usage":null,"metadata":null}"#,
+        )
+        .unwrap();
+
+        let metadata = create_response_metadata(&response);
+
+        let provider_metadata: serde_json::Value =
+            serde_json::from_str(&metadata.provider_metadata_json.unwrap()).unwrap();
+        assert_eq!(
+            provider_metadata["citations"][0]["url"],
+            "https://golem.cloud"
+        );
+        assert_eq!(
+            provider_metadata["builtin_tool_calls"],
+            serde_json::json!([{"type": "web_search_call", "id": "ws_1", "status": "completed"}])
+        );
+    }
+
+    #[test]
+    fn response_message_role_is_reported_in_metadata() {
+        let response: CreateModelResponseResponse = serde_json::from_str(
+            r#"{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[{"type":"message","id":"msg_1","role":"assistant","status":"completed","content":[{"type":"output_text","text":"Hello","annotations":[]}]}],"usage":null,"metadata":null}"#,
+        )
+        .unwrap();
+
+        let metadata = create_response_metadata(&response);
+
+        let provider_metadata: serde_json::Value =
+            serde_json::from_str(&metadata.provider_metadata_json.unwrap()).unwrap();
+        assert_eq!(provider_metadata["role"], "assistant");
+    }
+
+    fn messages_with_system_prompt() -> Vec<Message> {
+        vec![
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text("Answer in one sentence.".to_string())],
+            },
+            Message {
+                role: Role::User,
+                name: None,
+                content: vec![ContentPart::Text("What is Golem?".to_string())],
+            },
+        ]
+    }
+
+    #[test]
+    fn system_messages_are_sent_as_instructions() {
+        let items = messages_to_input_items(messages_with_system_prompt());
+
+        let request = create_request(items, config(&[]), vec![]).unwrap();
+
+        assert_eq!(
+            request.instructions,
+            Some("Answer in one sentence.".to_string())
+        );
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["instructions"], "Answer in one sentence.");
+        assert_eq!(json["input"].as_array().unwrap().len(), 1);
+        assert_eq!(json["input"][0]["role"], "user");
+    }
+
+    #[test]
+    fn instructions_option_precedes_system_messages() {
+        let items = messages_to_input_items(messages_with_system_prompt());
+
+        let request = create_request(
+            items,
+            config(&[("instructions", "You are a helpful assistant.")]),
+            vec![],
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.instructions,
+            Some("You are a helpful assistant.\n\nAnswer in one sentence.".to_string())
+        );
+    }
+
+    #[test]
+    fn instructions_are_omitted_without_system_prompt() {
+        let request = create_request(vec![], config(&[]), vec![]).unwrap();
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("instructions").is_none());
+    }
+
+    #[test]
+    fn markdown_option_re_enables_formatting() {
+        let markdown = || config(&[("markdown", "true")]);
+
+        let items = messages_to_input_items(messages_with_system_prompt());
+        let request = create_request(items, markdown(), vec![]).unwrap();
+        assert_eq!(
+            request.instructions,
+            Some("Formatting re-enabled\nAnswer in one sentence.".to_string())
+        );
+
+        let request = create_request(vec![], markdown(), vec![]).unwrap();
+        assert_eq!(
+            request.instructions,
+            Some("Formatting re-enabled".to_string())
+        );
+    }
+
+    #[test]
+    fn response_id_is_never_empty() {
+        let mut without_id = response(Status::Completed, None);
+        without_id.id = String::new();
+
+        for (response, expected) in [
+            (response(Status::Completed, None), Some("resp_1")),
+            (without_id, None),
+        ] {
+            match process_model_response(response) {
+                ChatEvent::Message(message) => {
+                    assert!(!message.id.is_empty());
+                    if let Some(expected) = expected {
+                        assert_eq!(message.id, expected);
+                    }
+                }
+                other => panic!("Unexpected event: {other:?}"),
+            }
+        }
     }
 }
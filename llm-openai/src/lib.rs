@@ -1,24 +1,38 @@
+use crate::chat_completions::{
+    chat_completions_enabled, convert_messages, tool_results_to_messages, ChatCompletionsApi,
+    CompletionsMessage, CompletionsStreamState, StreamOptions,
+};
 use crate::client::{
-    CreateModelResponseResponse, InputItem, OutputItem, ResponseOutputItemDone,
-    ResponseOutputTextDelta, ResponsesApi,
+    CreateModelResponseResponse, ImagesApi, InputItem, OutputItem,
+    ResponseFunctionCallArgumentsDelta, ResponseImageGenerationCallPartialImage,
+    ResponseOutputItemAdded, ResponseOutputItemDone, ResponseOutputTextDelta,
+    ResponseReasoningSummaryTextDelta, ResponseRefusalDelta, ResponseRefusalDone, ResponsesApi,
 };
 use crate::conversions::{
-    create_request, create_response_metadata, messages_to_input_items, parse_error_code,
-    process_model_response, tool_defs_to_tools, tool_results_to_input_items,
+    config_to_tools, create_image_request, create_request, create_response_metadata,
+    image_data_url, messages_to_input_items, parse_error_code, process_image_response,
+    process_model_response, request_partial_images, stream_partial_images_enabled,
+    tool_results_to_input_items,
+};
+use golem_llm::chat_stream::{
+    attach_raw_enabled, emit_heartbeat_enabled, sentence_flush_timeout, timing_enabled,
+    unknown_event_type, LlmChatStream, LlmChatStreamState,
 };
-use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
 use golem_llm::config::with_config_key;
 use golem_llm::durability::{DurableLLM, ExtendedGuest};
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, ErrorCode, Guest, Message, StreamDelta,
-    StreamEvent, ToolCall, ToolResult,
+    ChatEvent, ChatStream, Config, ContentPart, Error, ErrorCode, Guest, ImageGenConfig,
+    ImageResult, ImageUrl, Message, StreamDelta, StreamEvent, ToolCall, ToolResult,
 };
+use golem_llm::trace_id::TRACE_ID;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
 use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
 
+mod chat_completions;
 mod client;
 mod conversions;
 
@@ -26,6 +40,12 @@ struct OpenAIChatStream {
     stream: RefCell<Option<EventSource>>,
     failure: Option<Error>,
     finished: RefCell<bool>,
+    /// Set when streaming from the chat completions API instead of the responses API
+    chat_completions: Option<CompletionsStreamState>,
+    /// The function calls being streamed, by output item id, with their arguments so far
+    tool_calls: RefCell<HashMap<String, ToolCall>>,
+    /// The refusal text streamed so far
+    refusal: RefCell<String>,
 }
 
 impl OpenAIChatStream {
@@ -34,6 +54,20 @@ impl OpenAIChatStream {
             stream: RefCell::new(Some(stream)),
             failure: None,
             finished: RefCell::new(false),
+            chat_completions: None,
+            tool_calls: RefCell::new(HashMap::new()),
+            refusal: RefCell::new(String::new()),
+        })
+    }
+
+    pub fn new_chat_completions(stream: EventSource) -> LlmChatStream<Self> {
+        LlmChatStream::new(OpenAIChatStream {
+            stream: RefCell::new(Some(stream)),
+            failure: None,
+            finished: RefCell::new(false),
+            chat_completions: Some(CompletionsStreamState::default()),
+            tool_calls: RefCell::new(HashMap::new()),
+            refusal: RefCell::new(String::new()),
         })
     }
 
@@ -42,6 +76,9 @@ impl OpenAIChatStream {
             stream: RefCell::new(None),
             failure: Some(error),
             finished: RefCell::new(false),
+            chat_completions: None,
+            tool_calls: RefCell::new(HashMap::new()),
+            refusal: RefCell::new(String::new()),
         })
     }
 }
@@ -68,7 +105,11 @@ impl LlmChatStreamState for OpenAIChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
+        trace!("{TRACE_ID}Received raw stream event: {raw}");
+        if let Some(chat_completions) = &self.chat_completions {
+            return chat_completions.decode_message(raw);
+        }
+
         let json: serde_json::Value = serde_json::from_str(raw)
             .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
 
@@ -126,8 +167,93 @@ impl LlmChatStreamState for OpenAIChatStream {
                 Ok(Some(StreamEvent::Delta(StreamDelta {
                     content: Some(vec![ContentPart::Text(decoded.delta)]),
                     tool_calls: None,
+                    provider_metadata_json: None,
+                    raw_json: None,
+                })))
+            }
+            Some("response.reasoning_summary_text.delta") => {
+                let decoded = serde_json::from_value::<ResponseReasoningSummaryTextDelta>(json)
+                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+                Ok(Some(StreamEvent::Delta(StreamDelta {
+                    content: None,
+                    tool_calls: None,
+                    provider_metadata_json: Some(
+                        serde_json::json!({ "reasoning": decoded.delta }).to_string(),
+                    ),
+                    raw_json: None,
                 })))
             }
+            Some("response.refusal.delta") => {
+                let decoded = serde_json::from_value::<ResponseRefusalDelta>(json)
+                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+                self.refusal.borrow_mut().push_str(&decoded.delta);
+                Ok(None)
+            }
+            Some("response.refusal.done") => {
+                let decoded = serde_json::from_value::<ResponseRefusalDone>(json)
+                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+                // The refusal is returned as a whole once complete, the same way as in a
+                // non-streamed response
+                let streamed = self.refusal.take();
+                let refusal = if decoded.refusal.is_empty() {
+                    streamed
+                } else {
+                    decoded.refusal
+                };
+                Ok(Some(StreamEvent::Delta(StreamDelta {
+                    content: Some(vec![ContentPart::Text(format!("Refusal: {refusal}"))]),
+                    tool_calls: None,
+                    provider_metadata_json: None,
+                    raw_json: None,
+                })))
+            }
+            Some("response.image_generation_call.partial_image") => {
+                let decoded =
+                    serde_json::from_value::<ResponseImageGenerationCallPartialImage>(json)
+                        .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+                Ok(Some(StreamEvent::Delta(StreamDelta {
+                    content: Some(vec![ContentPart::Image(ImageUrl {
+                        url: image_data_url(&decoded.partial_image_b64),
+                        detail: None,
+                    })]),
+                    tool_calls: None,
+                    provider_metadata_json: Some(
+                        serde_json::json!({ "partial_image_index": decoded.partial_image_index })
+                            .to_string(),
+                    ),
+                    raw_json: None,
+                })))
+            }
+            Some("response.output_item.added") => {
+                let decoded = serde_json::from_value::<ResponseOutputItemAdded>(json)
+                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+                if let OutputItem::ToolCall {
+                    arguments,
+                    call_id,
+                    name,
+                    id,
+                    ..
+                } = decoded.item
+                {
+                    self.tool_calls.borrow_mut().insert(
+                        id,
+                        ToolCall {
+                            id: call_id,
+                            name,
+                            arguments_json: arguments,
+                        },
+                    );
+                }
+                Ok(None)
+            }
+            Some("response.function_call_arguments.delta") => {
+                let decoded = serde_json::from_value::<ResponseFunctionCallArgumentsDelta>(json)
+                    .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
+                if let Some(tool_call) = self.tool_calls.borrow_mut().get_mut(&decoded.item_id) {
+                    tool_call.arguments_json.push_str(&decoded.delta);
+                }
+                Ok(None)
+            }
             Some("response.output_item.done") => {
                 let decoded = serde_json::from_value::<ResponseOutputItemDone>(json)
                     .map_err(|err| format!("Failed to deserialize stream event: {err}"))?;
@@ -135,25 +261,75 @@ impl LlmChatStreamState for OpenAIChatStream {
                     arguments,
                     call_id,
                     name,
+                    id,
                     ..
                 } = decoded.item
                 {
-                    Ok(Some(StreamEvent::Delta(StreamDelta {
-                        content: None,
-                        tool_calls: Some(vec![ToolCall {
+                    // The final item carries the complete arguments, the assembled ones are
+                    // only used if it does not
+                    let tool_call = match self.tool_calls.borrow_mut().remove(&id) {
+                        Some(assembled) if arguments.is_empty() => assembled,
+                        _ => ToolCall {
                             id: call_id,
                             name,
                             arguments_json: arguments,
-                        }]),
+                        },
+                    };
+                    Ok(Some(StreamEvent::Delta(StreamDelta {
+                        content: None,
+                        tool_calls: Some(vec![tool_call]),
+                        provider_metadata_json: None,
+                        raw_json: None,
+                    })))
+                } else if let OutputItem::ImageGenerationCall {
+                    result: Some(result),
+                    ..
+                } = decoded.item
+                {
+                    Ok(Some(StreamEvent::Delta(StreamDelta {
+                        content: Some(vec![ContentPart::Image(ImageUrl {
+                            url: image_data_url(&result),
+                            detail: None,
+                        })]),
+                        tool_calls: None,
+                        provider_metadata_json: None,
+                        raw_json: None,
                     })))
                 } else {
                     Ok(None)
                 }
             }
-            Some(_) => Ok(None),
+            Some("response.created")
+            | Some("response.in_progress")
+            | Some("response.content_part.added")
+            | Some("response.content_part.done")
+            | Some("response.output_text.annotation.added")
+            | Some("response.output_text.done")
+            | Some("response.function_call_arguments.done")
+            | Some("response.web_search_call.in_progress")
+            | Some("response.web_search_call.searching")
+            | Some("response.web_search_call.completed")
+            | Some("response.code_interpreter_call.in_progress")
+            | Some("response.code_interpreter_call.interpreting")
+            | Some("response.code_interpreter_call.completed")
+            | Some("response.code_interpreter_call_code.delta")
+            | Some("response.code_interpreter_call_code.done")
+            | Some("response.image_generation_call.in_progress")
+            | Some("response.image_generation_call.generating")
+            | Some("response.image_generation_call.completed")
+            | Some("response.reasoning_summary_part.added")
+            | Some("response.reasoning_summary_part.done")
+            | Some("response.reasoning_summary_text.done") => Ok(None),
+            Some(typ) => unknown_event_type(typ),
             None => Err("Unexpected stream event format, does not have 'type' field".to_string()),
         }
     }
+
+    fn end_of_stream(&self) -> Option<StreamEvent> {
+        self.chat_completions
+            .as_ref()
+            .and_then(|chat_completions| chat_completions.end_of_stream())
+    }
 }
 
 struct OpenAIComponent;
@@ -162,14 +338,11 @@ impl OpenAIComponent {
     const ENV_VAR_NAME: &'static str = "OPENAI_API_KEY";
 
     fn request(client: ResponsesApi, items: Vec<InputItem>, config: Config) -> ChatEvent {
-        match tool_defs_to_tools(&config.tools) {
-            Ok(tools) => {
-                let request = create_request(items, config, tools);
-                match client.create_model_response(request) {
-                    Ok(response) => process_model_response(response),
-                    Err(error) => ChatEvent::Error(error),
-                }
-            }
+        match config_to_tools(&config).and_then(|tools| create_request(items, config, tools)) {
+            Ok(request) => match client.create_model_response(request) {
+                Ok(response) => process_model_response(response),
+                Err(error) => ChatEvent::Error(error),
+            },
             Err(error) => ChatEvent::Error(error),
         }
     }
@@ -179,10 +352,13 @@ impl OpenAIComponent {
         items: Vec<InputItem>,
         config: Config,
     ) -> LlmChatStream<OpenAIChatStream> {
-        match tool_defs_to_tools(&config.tools) {
-            Ok(tools) => {
-                let mut request = create_request(items, config, tools);
+        let partial_images = stream_partial_images_enabled(&config);
+        match config_to_tools(&config).and_then(|tools| create_request(items, config, tools)) {
+            Ok(mut request) => {
                 request.stream = true;
+                if partial_images {
+                    request_partial_images(&mut request.tools);
+                }
                 match client.stream_model_response(request) {
                     Ok(stream) => OpenAIChatStream::new(stream),
                     Err(error) => OpenAIChatStream::failed(error),
@@ -191,6 +367,40 @@ impl OpenAIComponent {
             Err(error) => OpenAIChatStream::failed(error),
         }
     }
+
+    fn chat_completions_request(
+        client: ChatCompletionsApi,
+        messages: Vec<CompletionsMessage>,
+        config: Config,
+    ) -> ChatEvent {
+        match chat_completions::messages_to_request(messages, config) {
+            Ok(request) => match client.send_messages(request) {
+                Ok(response) => chat_completions::process_response(response),
+                Err(error) => ChatEvent::Error(error),
+            },
+            Err(error) => ChatEvent::Error(error),
+        }
+    }
+
+    fn chat_completions_streaming_request(
+        client: ChatCompletionsApi,
+        messages: Vec<CompletionsMessage>,
+        config: Config,
+    ) -> LlmChatStream<OpenAIChatStream> {
+        match chat_completions::messages_to_request(messages, config) {
+            Ok(mut request) => {
+                request.stream = true;
+                request.stream_options = Some(StreamOptions {
+                    include_usage: true,
+                });
+                match client.stream_send_messages(request) {
+                    Ok(stream) => OpenAIChatStream::new_chat_completions(stream),
+                    Err(error) => OpenAIChatStream::failed(error),
+                }
+            }
+            Err(error) => OpenAIChatStream::failed(error),
+        }
+    }
 }
 
 impl Guest for OpenAIComponent {
@@ -200,10 +410,21 @@ impl Guest for OpenAIComponent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openai_api_key| {
-            let client = ResponsesApi::new(openai_api_key);
+            match chat_completions_enabled(&config) {
+                Ok(true) => {
+                    let client = ChatCompletionsApi::new(openai_api_key, &config.provider_options);
+
+                    let messages = convert_messages(messages);
+                    Self::chat_completions_request(client, messages, config)
+                }
+                Ok(false) => {
+                    let client = ResponsesApi::new(openai_api_key, &config.provider_options);
 
-            let items = messages_to_input_items(messages);
-            Self::request(client, items, config)
+                    let items = messages_to_input_items(messages);
+                    Self::request(client, items, config)
+                }
+                Err(error) => ChatEvent::Error(error),
+            }
         })
     }
 
@@ -215,33 +436,85 @@ impl Guest for OpenAIComponent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openai_api_key| {
-            let client = ResponsesApi::new(openai_api_key);
+            match chat_completions_enabled(&config) {
+                Ok(true) => {
+                    let client = ChatCompletionsApi::new(openai_api_key, &config.provider_options);
+
+                    let mut messages = convert_messages(messages);
+                    match tool_results_to_messages(tool_results) {
+                        Ok(tool_messages) => {
+                            messages.extend(tool_messages);
+                            Self::chat_completions_request(client, messages, config)
+                        }
+                        Err(error) => ChatEvent::Error(error),
+                    }
+                }
+                Ok(false) => {
+                    let client = ResponsesApi::new(openai_api_key, &config.provider_options);
 
-            let mut items = messages_to_input_items(messages);
-            items.extend(tool_results_to_input_items(tool_results));
-            Self::request(client, items, config)
+                    let mut items = messages_to_input_items(messages);
+                    match tool_results_to_input_items(tool_results) {
+                        Ok(tool_items) => {
+                            items.extend(tool_items);
+                            Self::request(client, items, config)
+                        }
+                        Err(error) => ChatEvent::Error(error),
+                    }
+                }
+                Err(error) => ChatEvent::Error(error),
+            }
         })
     }
 
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn generate_image(prompt: String, config: ImageGenConfig) -> Result<Vec<ImageResult>, Error> {
+        LOGGING_STATE.with_borrow_mut(|state| state.init());
+
+        with_config_key(Self::ENV_VAR_NAME, Err, |openai_api_key| {
+            let client = ImagesApi::new(openai_api_key, &config.provider_options);
+
+            let size = config.size.clone();
+            let request = create_image_request(prompt, config);
+            let response = client.create_image(request)?;
+            process_image_response(response, size)
+        })
+    }
 }
 
 impl ExtendedGuest for OpenAIComponent {
     fn unwrapped_stream(messages: Vec<Message>, config: Config) -> Self::ChatStream {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        let attach_raw = attach_raw_enabled(&config);
+        let timing = timing_enabled(&config);
+        let heartbeat = emit_heartbeat_enabled(&config);
+        let sentence_flush = sentence_flush_timeout(&config);
         with_config_key(
             Self::ENV_VAR_NAME,
             OpenAIChatStream::failed,
-            |openai_api_key| {
-                let client = ResponsesApi::new(openai_api_key);
+            |openai_api_key| match chat_completions_enabled(&config) {
+                Ok(true) => {
+                    let client = ChatCompletionsApi::new(openai_api_key, &config.provider_options);
+
+                    let messages = convert_messages(messages);
+                    Self::chat_completions_streaming_request(client, messages, config)
+                }
+                Ok(false) => {
+                    let client = ResponsesApi::new(openai_api_key, &config.provider_options);
 
-                let items = messages_to_input_items(messages);
-                Self::streaming_request(client, items, config)
+                    let items = messages_to_input_items(messages);
+                    Self::streaming_request(client, items, config)
+                }
+                Err(error) => OpenAIChatStream::failed(error),
             },
         )
+        .with_raw_payloads(attach_raw)
+        .with_timing(timing)
+        .with_sentence_flush(sentence_flush)
+        .with_heartbeat(heartbeat)
     }
 
     fn subscribe(stream: &Self::ChatStream) -> Pollable {
@@ -252,3 +525,203 @@ impl ExtendedGuest for OpenAIComponent {
 type DurableOpenAIComponent = DurableLLM<OpenAIComponent>;
 
 golem_llm::export_llm!(DurableOpenAIComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::conversions::tool_results_to_input_items;
+    use crate::OpenAIChatStream;
+    use golem_llm::chat_stream::LlmChatStreamState;
+    use golem_llm::golem::llm::llm::{
+        ContentPart, FinishReason, ImageUrl, StreamEvent, ToolCall, ToolResult, ToolSuccess,
+    };
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    fn state() -> OpenAIChatStream {
+        OpenAIChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            chat_completions: None,
+            tool_calls: RefCell::new(HashMap::new()),
+            refusal: RefCell::new(String::new()),
+        }
+    }
+
+    #[test]
+    fn streamed_tool_call_id_correlates_the_result() {
+        let state = state();
+
+        let event = state
+            .decode_message(
+                r#"{"type":"response.output_item.done","output_index":0,"item":{"type":"function_call","id":"fc_1","call_id":"call_1","name":"get_weather","arguments":"{\"city\":\"Berlin\"}","status":"completed"}}"#,
+            )
+            .unwrap();
+        let tool_call = match event {
+            Some(StreamEvent::Delta(delta)) => delta.tool_calls.unwrap().remove(0),
+            other => panic!("Unexpected event: {other:?}"),
+        };
+        // The function call item's own id is not the one results are correlated with
+        assert_eq!(tool_call.id, "call_1");
+
+        let result = ToolResult::Success(ToolSuccess {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            result_json: r#"{"temperature":21}"#.to_string(),
+            execution_time_ms: None,
+        });
+        let items =
+            serde_json::to_value(tool_results_to_input_items(vec![(tool_call, result)]).unwrap())
+                .unwrap();
+
+        assert_eq!(items[0]["call_id"], "call_1");
+        assert_eq!(items[1]["call_id"], "call_1");
+    }
+
+    #[test]
+    fn tool_calls_are_assembled_from_added_delta_and_done_events() {
+        let state = state();
+
+        let events = [
+            r#"{"type":"response.output_item.added","output_index":0,"item":{"type":"function_call","id":"fc_1","call_id":"call_1","name":"get_weather","arguments":"","status":"in_progress"}}"#,
+            r#"{"type":"response.function_call_arguments.delta","item_id":"fc_1","output_index":0,"delta":"{\"city\":"}"#,
+            r#"{"type":"response.output_item.added","output_index":1,"item":{"type":"function_call","id":"fc_2","call_id":"call_2","name":"get_time","arguments":"","status":"in_progress"}}"#,
+            r#"{"type":"response.function_call_arguments.delta","item_id":"fc_2","output_index":1,"delta":"{\"zone\":\"CET\"}"}"#,
+            r#"{"type":"response.function_call_arguments.delta","item_id":"fc_1","output_index":0,"delta":"\"Berlin\"}"}"#,
+            r#"{"type":"response.function_call_arguments.done","item_id":"fc_1","output_index":0,"arguments":"{\"city\":\"Berlin\"}"}"#,
+            r#"{"type":"response.output_item.done","output_index":0,"item":{"type":"function_call","id":"fc_1","call_id":"call_1","name":"get_weather","arguments":"{\"city\":\"Berlin\"}","status":"completed"}}"#,
+            r#"{"type":"response.function_call_arguments.done","item_id":"fc_2","output_index":1,"arguments":"{\"zone\":\"CET\"}"}"#,
+            r#"{"type":"response.output_item.done","output_index":1,"item":{"type":"function_call","id":"fc_2","call_id":"call_2","name":"get_time","arguments":"","status":"completed"}}"#,
+        ]
+        .iter()
+        .filter_map(|raw| state.decode_message(raw).unwrap())
+        .collect::<Vec<_>>();
+
+        let tool_calls = events
+            .into_iter()
+            .flat_map(|event| match event {
+                StreamEvent::Delta(delta) => delta.tool_calls.unwrap(),
+                other => panic!("Unexpected event: {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tool_calls,
+            vec![
+                ToolCall {
+                    id: "call_1".to_string(),
+                    name: "get_weather".to_string(),
+                    arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+                },
+                // Assembled from the deltas as the final item carries no arguments
+                ToolCall {
+                    id: "call_2".to_string(),
+                    name: "get_time".to_string(),
+                    arguments_json: r#"{"zone":"CET"}"#.to_string(),
+                },
+            ]
+        );
+        assert!(state.tool_calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn streamed_reasoning_summary_is_reported_in_metadata() {
+        let event = state()
+            .decode_message(
+                r#"{"type":"response.reasoning_summary_text.delta","item_id":"rs_1","output_index":0,"summary_index":0,"delta":"Comparing options"}"#,
+            )
+            .unwrap();
+
+        match event {
+            Some(StreamEvent::Delta(delta)) => {
+                assert_eq!(delta.content, None);
+                assert_eq!(
+                    delta.provider_metadata_json.as_deref(),
+                    Some(r#"{"reasoning":"Comparing options"}"#)
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn partial_images_are_streamed_before_the_final_image() {
+        let state = state();
+
+        let events = [
+            r#"{"type":"response.output_item.added","output_index":0,"item":{"type":"image_generation_call","id":"ig_1","status":"in_progress"}}"#,
+            r#"{"type":"response.image_generation_call.in_progress","item_id":"ig_1","output_index":0}"#,
+            r#"{"type":"response.image_generation_call.generating","item_id":"ig_1","output_index":0}"#,
+            r#"{"type":"response.image_generation_call.partial_image","item_id":"ig_1","output_index":0,"partial_image_index":0,"partial_image_b64":"cGFydGlhbDA="}"#,
+            r#"{"type":"response.image_generation_call.partial_image","item_id":"ig_1","output_index":0,"partial_image_index":1,"partial_image_b64":"cGFydGlhbDE="}"#,
+            r#"{"type":"response.image_generation_call.completed","item_id":"ig_1","output_index":0}"#,
+            r#"{"type":"response.output_item.done","output_index":0,"item":{"type":"image_generation_call","id":"ig_1","status":"completed","result":"ZmluYWw="}}"#,
+        ]
+        .iter()
+        .filter_map(|raw| state.decode_message(raw).unwrap())
+        .map(|event| match event {
+            StreamEvent::Delta(delta) => (delta.content.unwrap(), delta.provider_metadata_json),
+            other => panic!("Unexpected event: {other:?}"),
+        })
+        .collect::<Vec<_>>();
+
+        let image = |url: &str| {
+            vec![ContentPart::Image(ImageUrl {
+                url: url.to_string(),
+                detail: None,
+            })]
+        };
+        assert_eq!(
+            events,
+            vec![
+                (
+                    image("data:image/png;base64,cGFydGlhbDA="),
+                    Some(r#"{"partial_image_index":0}"#.to_string())
+                ),
+                (
+                    image("data:image/png;base64,cGFydGlhbDE="),
+                    Some(r#"{"partial_image_index":1}"#.to_string())
+                ),
+                (image("data:image/png;base64,ZmluYWw="), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn streamed_refusal_is_surfaced_with_content_filter_finish() {
+        let state = state();
+
+        let events = [
+            r#"{"type":"response.output_item.added","output_index":0,"item":{"type":"message","id":"msg_1","role":"assistant","status":"in_progress","content":[]}}"#,
+            r#"{"type":"response.content_part.added","item_id":"msg_1","output_index":0,"content_index":0,"part":{"type":"refusal","refusal":""}}"#,
+            r#"{"type":"response.refusal.delta","item_id":"msg_1","output_index":0,"content_index":0,"delta":"I can't help "}"#,
+            r#"{"type":"response.refusal.delta","item_id":"msg_1","output_index":0,"content_index":0,"delta":"with that."}"#,
+            r#"{"type":"response.refusal.done","item_id":"msg_1","output_index":0,"content_index":0,"refusal":"I can't help with that."}"#,
+            r#"{"type":"response.completed","response":{"id":"resp_1","created_at":1,"error":null,"incomplete_details":null,"status":"completed","output":[{"type":"message","id":"msg_1","role":"assistant","status":"completed","content":[{"type":"refusal","refusal":"I can't help with that."}]}],"usage":null,"metadata":null}}"#,
+        ]
+        .iter()
+        .filter_map(|raw| state.decode_message(raw).unwrap())
+        .collect::<Vec<_>>();
+
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            StreamEvent::Delta(delta) => assert_eq!(
+                delta.content,
+                Some(vec![ContentPart::Text(
+                    "Refusal: I can't help with that.".to_string()
+                )])
+            ),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        match &events[1] {
+            StreamEvent::Finish(metadata) => {
+                assert_eq!(metadata.finish_reason, Some(FinishReason::ContentFilter));
+                let provider_metadata: serde_json::Value =
+                    serde_json::from_str(metadata.provider_metadata_json.as_ref().unwrap())
+                        .unwrap();
+                assert_eq!(provider_metadata["refusal"], "I can't help with that.");
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        assert!(state.refusal.borrow().is_empty());
+    }
+}
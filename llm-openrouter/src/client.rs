@@ -1,62 +1,52 @@
-use golem_llm::error::{error_code_from_status, from_event_source_error, from_reqwest_error};
+use golem_llm::error::{error_code_from_status, is_timeout_status, parse_response, ErrorBody};
 use golem_llm::event_source::EventSource;
-use golem_llm::golem::llm::llm::{Error, ErrorCode};
+use golem_llm::golem::llm::llm::{Error, Kv};
+use golem_llm::trace_id::TRACE_ID;
+use golem_llm::transport::{transport_for, HttpRequest, HttpTransport};
 use log::trace;
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Method, Response, StatusCode};
-use serde::de::DeserializeOwned;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
 
 const BASE_URL: &str = "https://openrouter.ai";
 
 /// The Completions API client for creating model responses.
 pub struct CompletionsApi {
     api_key: String,
-    client: Client,
+    transport: Box<dyn HttpTransport>,
 }
 
 impl CompletionsApi {
-    pub fn new(api_key: String) -> Self {
-        let client = Client::builder()
-            .build()
-            .expect("Failed to initialize HTTP client");
-        Self { api_key, client }
+    pub fn new(api_key: String, provider_options: &[Kv]) -> Self {
+        Self::with_transport(api_key, transport_for(provider_options))
+    }
+
+    pub fn with_transport(api_key: String, transport: Box<dyn HttpTransport>) -> Self {
+        Self { api_key, transport }
     }
 
     pub fn send_messages(&self, request: CompletionsRequest) -> Result<CompletionsResponse, Error> {
-        trace!("Sending request to OpenRouter API: {request:?}");
+        trace!("{TRACE_ID}Sending request to OpenRouter API: {request:?}");
 
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/api/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
+        let response = self.transport.send(
+            HttpRequest::post(format!("{BASE_URL}/api/v1/chat/completions"))
+                .bearer_auth(&self.api_key)
+                .json(&request)?,
+        )?;
 
-        parse_response(response)
+        parse_response::<_, ErrorResponseBody>("OpenRouter API", response)
     }
 
     pub fn stream_send_messages(&self, request: CompletionsRequest) -> Result<EventSource, Error> {
-        trace!("Sending request to OpenRouter API: {request:?}");
-
-        let response: Response = self
-            .client
-            .request(Method::POST, format!("{BASE_URL}/api/v1/chat/completions"))
-            .bearer_auth(self.api_key.clone())
-            .header(
-                reqwest::header::ACCEPT,
-                HeaderValue::from_static("text/event-stream"),
-            )
-            .json(&request)
-            .send()
-            .map_err(|err| from_reqwest_error("Request failed", err))?;
-
-        trace!("Initializing SSE stream");
-
-        EventSource::new(response)
-            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+        trace!("{TRACE_ID}Sending request to OpenRouter API: {request:?}");
+
+        let request = HttpRequest::post(format!("{BASE_URL}/api/v1/chat/completions"))
+            .bearer_auth(&self.api_key)
+            .header(reqwest::header::ACCEPT.as_str(), "text/event-stream")
+            .json(&request)?;
+
+        trace!("{TRACE_ID}Initializing SSE stream");
+
+        self.transport.stream(request)
     }
 }
 
@@ -152,7 +142,7 @@ pub enum Content {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ToolChoice {
-    String(String), // none or auto
+    String(String), // none, auto or required
     Function(ToolChoiceFunction),
 }
 
@@ -222,6 +212,9 @@ pub struct CompletionsResponse {
     pub model: String,
     pub system_fingerprint: Option<String>,
     pub usage: Option<Usage>,
+    /// Content filtering results of the prompt, reported by Azure hosted models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_filter_results: Option<Vec<serde_json::Value>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -230,6 +223,9 @@ pub struct Choice {
     pub native_finish_reason: Option<FinishReason>,
     pub message: ResponseMessage,
     pub error: Option<ErrorResponse>,
+    /// Content filtering results of the completion, reported by Azure hosted models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -265,13 +261,55 @@ pub struct ErrorResponseBody {
     pub error: ErrorResponse,
 }
 
+impl ErrorBody for ErrorResponseBody {
+    /// OpenRouter may report errors in responses with a success status, so the status is taken
+    /// from the error's code when it is a valid one
+    fn into_error(self, status: StatusCode) -> Error {
+        let status = TryInto::<u16>::try_into(self.error.code)
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(status);
+        Error {
+            code: error_code_from_status(status),
+            message: self.error.message,
+            provider_error_json: self
+                .error
+                .metadata
+                .map(|value| serde_json::to_string(&value).unwrap()),
+        }
+    }
+
+    fn is_transient(&self) -> bool {
+        u16::try_from(self.error.code)
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .is_some_and(|status| status.is_server_error() || is_timeout_status(status))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
     pub completion_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens_details: Option<CompletionTokenDetails>,
     pub prompt_tokens: u32,
+    #[serde(default)]
+    pub prompt_tokens_details: Option<PromptTokenDetails>,
     pub total_tokens: u32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionTokenDetails {
+    #[serde(default)]
+    pub reasoning_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptTokenDetails {
+    #[serde(default)]
+    pub cached_tokens: Option<u32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionChunk {
     pub id: String,
@@ -296,59 +334,3 @@ pub struct ChoiceDelta {
     pub tool_calls: Option<Vec<ToolCall>>,
     pub role: Option<String>,
 }
-
-fn parse_response<T: DeserializeOwned + Debug>(response: Response) -> Result<T, Error> {
-    let status = response.status();
-    if status.is_success() {
-        let raw_body = response
-            .text()
-            .map_err(|err| from_reqwest_error("Failed to receive response body", err))?;
-        trace!("Received response from OpenRouter API: {raw_body:?}");
-
-        if let Ok(body) = serde_json::from_str::<T>(&raw_body) {
-            trace!("Received response from OpenRouter API: {body:?}");
-            Ok(body)
-        } else {
-            let error_body: ErrorResponseBody =
-                serde_json::from_str(&raw_body).map_err(|err| Error {
-                    code: ErrorCode::InternalError,
-                    message: format!("Failed to parse response body: {err}"),
-                    provider_error_json: Some(raw_body),
-                })?;
-
-            let status = TryInto::<u16>::try_into(error_body.error.code)
-                .ok()
-                .and_then(|code| StatusCode::from_u16(code).ok())
-                .unwrap_or(status);
-            Err(Error {
-                code: error_code_from_status(status),
-                message: error_body.error.message,
-                provider_error_json: error_body
-                    .error
-                    .metadata
-                    .map(|value| serde_json::to_string(&value).unwrap()),
-            })
-        }
-    } else {
-        let raw_error_body = response
-            .text()
-            .map_err(|err| from_reqwest_error("Failed to receive error response body", err))?;
-        trace!("Received {status} response from OpenRouter API: {raw_error_body:?}");
-
-        let error_body: ErrorResponseBody =
-            serde_json::from_str(&raw_error_body).map_err(|err| Error {
-                code: ErrorCode::InternalError,
-                message: format!("Failed to parse error response body: {err}"),
-                provider_error_json: Some(raw_error_body),
-            })?;
-
-        Err(Error {
-            code: error_code_from_status(status),
-            message: error_body.error.message,
-            provider_error_json: error_body
-                .error
-                .metadata
-                .map(|value| serde_json::to_string(&value).unwrap()),
-        })
-    }
-}
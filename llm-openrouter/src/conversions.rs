@@ -1,22 +1,49 @@
 use crate::client::{
-    CompletionsRequest, CompletionsResponse, Detail, FunctionName, ToolChoiceFunction,
+    Choice, CompletionsRequest, CompletionsResponse, Detail, ErrorResponse, FunctionName,
+    ToolChoiceFunction,
+};
+use golem_llm::config::{
+    apply_sampling_preset, builtin_tools, max_tokens, output_constraint, resolve_model,
+};
+use golem_llm::error::{
+    check_tool_result_id, check_tool_result_json, error_code_from_status, unsupported,
 };
 use golem_llm::golem::llm::llm::{
     ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason, ImageDetail,
     Message, ResponseMetadata, Role, ToolCall, ToolDefinition, ToolResult, Usage,
 };
+use golem_llm::response_id::response_id;
+use golem_llm::tool_call_only::forced_tool_choice;
+use reqwest::StatusCode;
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 
 pub fn messages_to_request(
     messages: Vec<Message>,
     config: Config,
 ) -> Result<CompletionsRequest, Error> {
+    if let Some(name) = builtin_tools(&config).first() {
+        return Err(unsupported(format!("Built-in tool {name}")));
+    }
+
+    if output_constraint(&config)?.is_some() {
+        return Err(unsupported("The grammar and regex options"));
+    }
+
+    let config = apply_sampling_preset(config, "openrouter")?;
+    let model = resolve_model(&config, "openrouter")?;
+    let max_tokens = max_tokens(&config)?;
+    let tool_choice = forced_tool_choice(&config, "required")?;
     let options = config
         .provider_options
         .into_iter()
         .map(|kv| (kv.key, kv.value))
         .collect::<HashMap<_, _>>();
 
+    if options.contains_key("service_tier") {
+        return Err(unsupported("The service_tier option"));
+    }
+
     let mut completion_messages = Vec::new();
     for message in messages {
         match message.role {
@@ -48,24 +75,18 @@ pub fn messages_to_request(
 
     Ok(CompletionsRequest {
         messages: completion_messages,
-        model: config.model,
-        frequency_penalty: options
-            .get("frequency_penalty")
-            .and_then(|fp_s| fp_s.parse::<f32>().ok()),
-        max_tokens: config.max_tokens,
-        presence_penalty: options
-            .get("presence_penalty")
-            .and_then(|pp_s| pp_s.parse::<f32>().ok()),
-        repetition_penalty: options
-            .get("repetition_penalty")
-            .and_then(|rp_s| rp_s.parse::<f32>().ok()),
+        model,
+        frequency_penalty: sampling_option(&options, "frequency_penalty", -2.0..=2.0)?,
+        max_tokens,
+        presence_penalty: sampling_option(&options, "presence_penalty", -2.0..=2.0)?,
+        repetition_penalty: sampling_option(&options, "repetition_penalty", 0.0..=2.0)?,
         seed: options
             .get("seed")
             .and_then(|seed_s| seed_s.parse::<u32>().ok()),
         stop: config.stop_sequences,
         stream: Some(false),
         temperature: config.temperature,
-        tool_choice: config.tool_choice.map(convert_tool_choice),
+        tool_choice: tool_choice.map(convert_tool_choice),
         tools,
         top_p: options
             .get("top_p")
@@ -73,23 +94,50 @@ pub fn messages_to_request(
         top_k: options
             .get("top_k")
             .and_then(|top_k_s| top_k_s.parse::<f32>().ok()),
-        min_p: options
-            .get("min_p")
-            .and_then(|min_p_s| min_p_s.parse::<f32>().ok()),
-        top_a: options
-            .get("top_a")
-            .and_then(|top_a_s| top_a_s.parse::<f32>().ok()),
+        min_p: sampling_option(&options, "min_p", 0.0..=1.0)?,
+        top_a: sampling_option(&options, "top_a", 0.0..=1.0)?,
     })
 }
 
+/// Parses a numeric sampling option, rejecting values outside the range OpenRouter accepts
+fn sampling_option(
+    options: &HashMap<String, String>,
+    key: &str,
+    range: RangeInclusive<f32>,
+) -> Result<Option<f32>, Error> {
+    let Some(value) = options.get(key) else {
+        return Ok(None);
+    };
+    match value.trim().parse::<f32>() {
+        Ok(parsed) if range.contains(&parsed) => Ok(Some(parsed)),
+        _ => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "{key} must be a number between {} and {}, got '{value}'",
+                range.start(),
+                range.end()
+            ),
+            provider_error_json: None,
+        }),
+    }
+}
+
 pub fn process_response(response: CompletionsResponse) -> ChatEvent {
     let choice = response.choices.first();
     if let Some(choice) = choice {
+        // Errors of the generation are reported in the choice, with a successful HTTP status
+        if let Some(error) = &choice.error {
+            return ChatEvent::Error(convert_error(error.clone()));
+        }
+
         let mut contents = Vec::new();
         let mut tool_calls = Vec::new();
 
+        // Tool calling responses come with either a null or an empty content
         if let Some(content) = &choice.message.content {
-            contents.push(ContentPart::Text(content.clone()));
+            if !content.is_empty() {
+                contents.push(ContentPart::Text(content.clone()));
+            }
         }
 
         let empty = Vec::new();
@@ -97,7 +145,7 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
             tool_calls.push(convert_tool_call(tool_call));
         }
 
-        if contents.is_empty() {
+        if contents.is_empty() && !tool_calls.is_empty() {
             ChatEvent::ToolRequest(tool_calls)
         } else {
             let metadata = ResponseMetadata {
@@ -105,11 +153,12 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
                 usage: response.usage.as_ref().map(convert_usage),
                 provider_id: None,
                 timestamp: Some(response.created.to_string()),
-                provider_metadata_json: None,
+                provider_metadata_json: Some(provider_metadata(&response, choice)),
+                raw_json: None,
             };
 
             ChatEvent::Message(CompleteResponse {
-                id: response.id,
+                id: response_id(&response.id, &response),
                 content: contents,
                 tool_calls,
                 metadata,
@@ -124,11 +173,49 @@ pub fn process_response(response: CompletionsResponse) -> ChatEvent {
     }
 }
 
+pub fn convert_error(error: ErrorResponse) -> Error {
+    Error {
+        code: error_code_from_status(
+            u16::try_from(error.code)
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        ),
+        message: error.message,
+        provider_error_json: error
+            .metadata
+            .map(|value| serde_json::to_string(&value).unwrap()),
+    }
+}
+
+/// Collects the role of the response message and the content filter categories and severities
+/// of the prompt and the completion into provider metadata, so the reason of a `content_filter`
+/// finish is not lost
+fn provider_metadata(response: &CompletionsResponse, choice: &Choice) -> String {
+    let mut metadata = serde_json::Map::new();
+    metadata.insert(
+        "role".to_string(),
+        serde_json::Value::String(choice.message.role.clone()),
+    );
+    if let Some(results) = &response.prompt_filter_results {
+        metadata.insert(
+            "prompt_filter_results".to_string(),
+            serde_json::Value::Array(results.clone()),
+        );
+    }
+    if let Some(results) = &choice.content_filter_results {
+        metadata.insert("content_filter_results".to_string(), results.clone());
+    }
+
+    serde_json::Value::Object(metadata).to_string()
+}
+
 pub fn tool_results_to_messages(
     tool_results: Vec<(ToolCall, ToolResult)>,
-) -> Vec<crate::client::Message> {
+) -> Result<Vec<crate::client::Message>, Error> {
     let mut messages = Vec::new();
     for (tool_call, tool_result) in tool_results {
+        check_tool_result_id(&tool_call, &tool_result)?;
         messages.push(crate::client::Message::Assistant {
             content: None,
             name: None,
@@ -142,7 +229,10 @@ pub fn tool_results_to_messages(
             }]),
         });
         let content = match tool_result {
-            ToolResult::Success(success) => success.result_json,
+            ToolResult::Success(success) => {
+                check_tool_result_json(&success)?;
+                success.result_json
+            }
             ToolResult::Error(failure) => failure.error_message,
         };
         messages.push(crate::client::Message::Tool {
@@ -151,7 +241,7 @@ pub fn tool_results_to_messages(
             tool_call_id: tool_call.id,
         });
     }
-    messages
+    Ok(messages)
 }
 
 pub fn convert_tool_call(tool_call: &crate::client::ToolCall) -> ToolCall {
@@ -216,6 +306,15 @@ pub fn convert_usage(value: &crate::client::Usage) -> Usage {
         input_tokens: Some(value.prompt_tokens),
         output_tokens: Some(value.completion_tokens),
         total_tokens: Some(value.total_tokens),
+        reasoning_tokens: value
+            .completion_tokens_details
+            .as_ref()
+            .and_then(|details| details.reasoning_tokens),
+        cached_tokens: value
+            .prompt_tokens_details
+            .as_ref()
+            .and_then(|details| details.cached_tokens),
+        audio_tokens: None,
     }
 }
 
@@ -238,9 +337,306 @@ fn tool_definition_to_tool(tool: ToolDefinition) -> Result<crate::client::Tool,
 
 fn convert_tool_choice(tool_choice: String) -> crate::client::ToolChoice {
     match tool_choice.as_str() {
-        "auto" | "none" => crate::client::ToolChoice::String(tool_choice),
+        "auto" | "none" | "required" => crate::client::ToolChoice::String(tool_choice),
         _ => crate::client::ToolChoice::Function(ToolChoiceFunction::Function {
             function: FunctionName { name: tool_choice },
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::client::CompletionsResponse;
+    use crate::conversions::{convert_usage, messages_to_request, process_response};
+    use golem_llm::golem::llm::llm::{
+        ChatEvent, Config, ContentPart, ErrorCode, FinishReason, Message, Role, ToolCall,
+        ToolDefinition,
+    };
+    use golem_llm::test_config;
+
+    fn config(provider_options: &[(&str, &str)]) -> Config {
+        Config {
+            model: "openai/gpt-4o".to_string(),
+            ..test_config::config(provider_options)
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Hello".to_string())],
+        }]
+    }
+
+    fn response(message: &str) -> CompletionsResponse {
+        serde_json::from_str(&format!(
+            r#"{{"id":"gen-1","created":1,"model":"openai/gpt-4o","system_fingerprint":null,"usage":null,"choices":[{{"finish_reason":"tool_calls","native_finish_reason":"tool_calls","error":null,"message":{message}}}]}}"#
+        ))
+        .unwrap()
+    }
+
+    const TOOL_CALLS: &str = r#"[{"type":"function","id":"call_1","function":{"name":"sum","arguments":"{\"a\":1,\"b\":2}"}}]"#;
+
+    #[test]
+    fn sampling_options_are_serialized() {
+        let request = messages_to_request(
+            messages(),
+            config(&[
+                ("repetition_penalty", "1.1"),
+                ("min_p", "0.05"),
+                ("top_a", "0.5"),
+                ("frequency_penalty", "-0.5"),
+                ("presence_penalty", "0.5"),
+            ]),
+        )
+        .unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(json["repetition_penalty"], serde_json::json!(1.1f32));
+        assert_eq!(json["min_p"], serde_json::json!(0.05f32));
+        assert_eq!(json["top_a"], serde_json::json!(0.5f32));
+        assert_eq!(json["frequency_penalty"], serde_json::json!(-0.5f32));
+        assert_eq!(json["presence_penalty"], serde_json::json!(0.5f32));
+    }
+
+    #[test]
+    fn max_tokens_is_omitted_unless_positive() {
+        let mut config = config(&[]);
+
+        let json =
+            serde_json::to_value(messages_to_request(messages(), config.clone()).unwrap()).unwrap();
+        assert!(json.get("max_tokens").is_none());
+
+        config.max_tokens = Some(0);
+        let error = messages_to_request(messages(), config).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn tool_call_only_forces_a_tool_call() {
+        let mut config = config(&[("tool_call_only", "true")]);
+        config.tools.push(ToolDefinition {
+            name: "get_weather".to_string(),
+            description: None,
+            parameters_schema: r#"{"type":"object"}"#.to_string(),
+        });
+
+        let json = serde_json::to_value(messages_to_request(messages(), config).unwrap()).unwrap();
+
+        assert_eq!(json["tool_choice"], "required");
+    }
+
+    #[test]
+    fn sampling_options_are_omitted_by_default() {
+        let json =
+            serde_json::to_value(messages_to_request(messages(), config(&[])).unwrap()).unwrap();
+
+        for key in ["repetition_penalty", "min_p", "top_a"] {
+            assert!(json.get(key).is_none());
+        }
+    }
+
+    #[test]
+    fn out_of_range_sampling_options_are_rejected() {
+        for (key, value) in [
+            ("repetition_penalty", "2.5"),
+            ("min_p", "-0.1"),
+            ("top_a", "1.5"),
+            ("presence_penalty", "3"),
+            ("min_p", "low"),
+        ] {
+            let error = messages_to_request(messages(), config(&[(key, value)])).unwrap_err();
+
+            assert_eq!(error.code, ErrorCode::InvalidRequest);
+            assert!(error.message.starts_with(key));
+        }
+    }
+
+    #[test]
+    fn usage_preserves_token_details() {
+        let usage: crate::client::Usage = serde_json::from_str(
+            r#"{"completion_tokens":40,"completion_tokens_details":{"reasoning_tokens":30},"prompt_tokens":100,"prompt_tokens_details":{"cached_tokens":60},"total_tokens":140}"#,
+        )
+        .unwrap();
+
+        let usage = convert_usage(&usage);
+
+        assert_eq!(usage.reasoning_tokens, Some(30));
+        assert_eq!(usage.cached_tokens, Some(60));
+    }
+
+    #[test]
+    fn usage_without_details_is_accepted() {
+        let usage: crate::client::Usage = serde_json::from_str(
+            r#"{"completion_tokens":40,"prompt_tokens":100,"total_tokens":140}"#,
+        )
+        .unwrap();
+
+        let usage = convert_usage(&usage);
+
+        assert_eq!(usage.total_tokens, Some(140));
+        assert_eq!(usage.reasoning_tokens, None);
+        assert_eq!(usage.cached_tokens, None);
+    }
+
+    #[test]
+    fn null_content_with_tool_calls_is_a_tool_request() {
+        let event = process_response(response(&format!(
+            r#"{{"role":"assistant","content":null,"tool_calls":{TOOL_CALLS}}}"#
+        )));
+
+        assert_eq!(
+            event,
+            ChatEvent::ToolRequest(vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "sum".to_string(),
+                arguments_json: r#"{"a":1,"b":2}"#.to_string(),
+            }])
+        );
+    }
+
+    #[test]
+    fn empty_content_with_tool_calls_is_a_tool_request() {
+        let event = process_response(response(&format!(
+            r#"{{"role":"assistant","content":"","tool_calls":{TOOL_CALLS}}}"#
+        )));
+
+        assert!(matches!(event, ChatEvent::ToolRequest(calls) if calls.len() == 1));
+    }
+
+    #[test]
+    fn null_content_without_tool_calls_is_an_empty_message() {
+        let event = process_response(response(
+            r#"{"role":"assistant","content":null,"tool_calls":null}"#,
+        ));
+
+        match event {
+            ChatEvent::Message(message) => assert!(message.content.is_empty()),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn content_filter_categories_are_reported_in_metadata() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{"id":"gen-1","created":1,"model":"openai/gpt-4o","system_fingerprint":null,"usage":null,"prompt_filter_results":[{"prompt_index":0,"content_filter_results":{"hate":{"filtered":false,"severity":"safe"}}}],"choices":[{"finish_reason":"content_filter","native_finish_reason":"content_filter","error":null,"message":{"role":"assistant","content":null,"tool_calls":null},"content_filter_results":{"hate":{"filtered":false,"severity":"safe"},"violence":{"filtered":true,"severity":"high"}}}]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Message(message) => {
+                assert_eq!(
+                    message.metadata.finish_reason,
+                    Some(FinishReason::ContentFilter)
+                );
+                let metadata: serde_json::Value =
+                    serde_json::from_str(&message.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(
+                    metadata["content_filter_results"]["violence"],
+                    serde_json::json!({"filtered": true, "severity": "high"})
+                );
+                assert_eq!(
+                    metadata["prompt_filter_results"][0]["content_filter_results"]["hate"]
+                        ["severity"],
+                    "safe"
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn metadata_has_only_the_role_without_content_filter_results() {
+        let event = process_response(response(
+            r#"{"role":"assistant","content":"Hello","tool_calls":null}"#,
+        ));
+
+        match event {
+            ChatEvent::Message(message) => {
+                let metadata: serde_json::Value =
+                    serde_json::from_str(&message.metadata.provider_metadata_json.unwrap())
+                        .unwrap();
+                assert_eq!(metadata, serde_json::json!({ "role": "assistant" }));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn error_in_choice_of_successful_response_is_an_error() {
+        let response: CompletionsResponse = serde_json::from_str(
+            r#"{"id":"gen-1","created":1,"model":"openai/gpt-4o","system_fingerprint":null,"usage":null,"choices":[{"finish_reason":"error","native_finish_reason":null,"error":{"code":429,"message":"Rate limit exceeded upstream","metadata":{"provider_name":"OpenAI"}},"message":{"role":"assistant","content":"Partial","tool_calls":null}}]}"#,
+        )
+        .unwrap();
+
+        match process_response(response) {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::RateLimitExceeded);
+                assert_eq!(error.message, "Rate limit exceeded upstream");
+                assert_eq!(
+                    error.provider_error_json,
+                    Some(r#"{"provider_name":"OpenAI"}"#.to_string())
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn response_id_is_never_empty() {
+        let message = r#"{"role":"assistant","content":"Hello","tool_calls":null}"#;
+        let mut without_id = response(message);
+        without_id.id = String::new();
+
+        for (response, expected) in [(response(message), Some("gen-1")), (without_id, None)] {
+            match process_response(response) {
+                ChatEvent::Message(message) => {
+                    assert!(!message.id.is_empty());
+                    if let Some(expected) = expected {
+                        assert_eq!(message.id, expected);
+                    }
+                }
+                other => panic!("Unexpected event: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn stop_sequence_included_by_the_backend_is_stripped_when_enabled() {
+        let config = Config {
+            stop_sequences: Some(vec!["###".to_string()]),
+            ..config(&[("strip_stop_sequence", "true")])
+        };
+        let event = process_response(response(
+            r#"{"role":"assistant","content":"Hello###","tool_calls":null}"#,
+        ));
+
+        match golem_llm::stop_sequence::strip_stop_sequence(&config, event) {
+            ChatEvent::Message(message) => {
+                assert_eq!(
+                    message.content,
+                    vec![ContentPart::Text("Hello".to_string())]
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_tool_arguments_are_an_error_when_validated() {
+        let config = config(&[("validate_tool_args", "true")]);
+        let event = process_response(response(
+            r#"{"role":"assistant","content":null,"tool_calls":[{"type":"function","id":"call_1","function":{"name":"sum","arguments":"{\"a\":1,\"b\""}}]}"#,
+        ));
+
+        match golem_llm::tool_args::reject_invalid_tool_args(&config, event) {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InternalError);
+                assert_eq!(error.provider_error_json, Some(r#"{"a":1,"b""#.to_string()));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+}
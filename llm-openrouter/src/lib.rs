@@ -3,22 +3,26 @@ mod conversions;
 
 use crate::client::{ChatCompletionChunk, CompletionsApi, CompletionsRequest, FunctionCall};
 use crate::conversions::{
-    convert_finish_reason, convert_usage, messages_to_request, process_response,
+    convert_error, convert_finish_reason, convert_usage, messages_to_request, process_response,
     tool_results_to_messages,
 };
-use golem_llm::chat_stream::{LlmChatStream, LlmChatStreamState};
+use golem_llm::chat_stream::{
+    attach_raw_enabled, emit_heartbeat_enabled, emit_tool_start_enabled, sentence_flush_timeout,
+    started_tool_calls_metadata, timing_enabled, unknown_event_type, LlmChatStream,
+    LlmChatStreamState,
+};
 use golem_llm::config::with_config_key;
 use golem_llm::durability::{DurableLLM, ExtendedGuest};
-use golem_llm::error::error_code_from_status;
+use golem_llm::error::unsupported;
 use golem_llm::event_source::EventSource;
 use golem_llm::golem::llm::llm::{
-    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, Message,
-    ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
+    ChatEvent, ChatStream, Config, ContentPart, Error, FinishReason, Guest, ImageGenConfig,
+    ImageResult, Message, ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall, ToolResult,
 };
+use golem_llm::trace_id::TRACE_ID;
 use golem_llm::LOGGING_STATE;
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
-use reqwest::StatusCode;
 use std::cell::{Ref, RefCell, RefMut};
 use std::collections::{HashMap, HashSet};
 
@@ -35,16 +39,18 @@ struct OpenRouterChatStream {
     finished: RefCell<bool>,
     finish_reason: RefCell<Option<FinishReason>>,
     json_fragments: RefCell<HashMap<u32, JsonFragment>>,
+    emit_tool_start: bool,
 }
 
 impl OpenRouterChatStream {
-    pub fn new(stream: EventSource) -> LlmChatStream<Self> {
+    pub fn new(stream: EventSource, emit_tool_start: bool) -> LlmChatStream<Self> {
         LlmChatStream::new(OpenRouterChatStream {
             stream: RefCell::new(Some(stream)),
             failure: None,
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
             json_fragments: RefCell::new(HashMap::new()),
+            emit_tool_start,
         })
     }
 
@@ -55,6 +61,7 @@ impl OpenRouterChatStream {
             finished: RefCell::new(false),
             finish_reason: RefCell::new(None),
             json_fragments: RefCell::new(HashMap::new()),
+            emit_tool_start: false,
         })
     }
 }
@@ -81,7 +88,7 @@ impl LlmChatStreamState for OpenRouterChatStream {
     }
 
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
-        trace!("Received raw stream event: {raw}");
+        trace!("{TRACE_ID}Received raw stream event: {raw}");
         if raw.starts_with(": ") {
             Ok(None) // comment
         } else {
@@ -104,6 +111,7 @@ impl LlmChatStreamState for OpenRouterChatStream {
                             provider_id: None,
                             timestamp: Some(message.created.to_string()),
                             provider_metadata_json: None,
+                            raw_json: None,
                         })))
                     } else if let Some(choice) = message.choices.into_iter().next() {
                         if let Some(finish_reason) = choice.finish_reason {
@@ -111,18 +119,9 @@ impl LlmChatStreamState for OpenRouterChatStream {
                                 Some(convert_finish_reason(&finish_reason));
                         }
                         if let Some(error) = choice.error {
-                            Ok(Some(StreamEvent::Error(Error {
-                                code: error_code_from_status(
-                                    TryInto::<u16>::try_into(error.code)
-                                        .ok()
-                                        .and_then(|code| StatusCode::from_u16(code).ok())
-                                        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
-                                ),
-                                message: error.message,
-                                provider_error_json: error
-                                    .metadata
-                                    .map(|value| serde_json::to_string(&value).unwrap()),
-                            })))
+                            // Nothing follows an error, so the stream is over
+                            self.set_finished();
+                            Ok(Some(StreamEvent::Error(convert_error(error))))
                         } else {
                             let content = choice
                                 .delta
@@ -131,6 +130,7 @@ impl LlmChatStreamState for OpenRouterChatStream {
 
                             let mut seen_indices = HashSet::new();
                             let mut tool_calls = Vec::new();
+                            let mut started_tool_calls = Vec::new();
                             let mut json_fragments = self.json_fragments.borrow_mut();
 
                             for tool_call in choice.delta.tool_calls.unwrap_or_default() {
@@ -161,6 +161,9 @@ impl LlmChatStreamState for OpenRouterChatStream {
                                         index: Some(index),
                                     } => {
                                         // Beginning of a streamed tool call
+                                        if self.emit_tool_start {
+                                            started_tool_calls.push((id.clone(), name.clone()));
+                                        }
                                         json_fragments.insert(
                                             index,
                                             JsonFragment {
@@ -210,13 +213,19 @@ impl LlmChatStreamState for OpenRouterChatStream {
                                 } else {
                                     Some(tool_calls)
                                 },
+                                provider_metadata_json: if started_tool_calls.is_empty() {
+                                    None
+                                } else {
+                                    Some(started_tool_calls_metadata(started_tool_calls))
+                                },
+                                raw_json: None,
                             })))
                         }
                     } else {
                         Ok(None)
                     }
                 }
-                Some(_) => Ok(None),
+                Some(typ) => unknown_event_type(typ),
                 None => {
                     Err("Unexpected stream event format, does not have 'object' field".to_string())
                 }
@@ -240,10 +249,11 @@ impl OpenRouterComponent {
     fn streaming_request(
         client: CompletionsApi,
         mut request: CompletionsRequest,
+        emit_tool_start: bool,
     ) -> LlmChatStream<OpenRouterChatStream> {
         request.stream = Some(true);
         match client.stream_send_messages(request) {
-            Ok(stream) => OpenRouterChatStream::new(stream),
+            Ok(stream) => OpenRouterChatStream::new(stream, emit_tool_start),
             Err(err) => OpenRouterChatStream::failed(err),
         }
     }
@@ -256,7 +266,7 @@ impl Guest for OpenRouterComponent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openrouter_api_key| {
-            let client = CompletionsApi::new(openrouter_api_key);
+            let client = CompletionsApi::new(openrouter_api_key, &config.provider_options);
 
             match messages_to_request(messages, config) {
                 Ok(request) => Self::request(client, request),
@@ -273,15 +283,16 @@ impl Guest for OpenRouterComponent {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
         with_config_key(Self::ENV_VAR_NAME, ChatEvent::Error, |openrouter_api_key| {
-            let client = CompletionsApi::new(openrouter_api_key);
+            let client = CompletionsApi::new(openrouter_api_key, &config.provider_options);
 
             match messages_to_request(messages, config) {
-                Ok(mut request) => {
-                    request
-                        .messages
-                        .extend(tool_results_to_messages(tool_results));
-                    Self::request(client, request)
-                }
+                Ok(mut request) => match tool_results_to_messages(tool_results) {
+                    Ok(tool_messages) => {
+                        request.messages.extend(tool_messages);
+                        Self::request(client, request)
+                    }
+                    Err(err) => ChatEvent::Error(err),
+                },
                 Err(err) => ChatEvent::Error(err),
             }
         })
@@ -290,6 +301,10 @@ impl Guest for OpenRouterComponent {
     fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
         ChatStream::new(Self::unwrapped_stream(messages, config))
     }
+
+    fn generate_image(_prompt: String, _config: ImageGenConfig) -> Result<Vec<ImageResult>, Error> {
+        Err(unsupported("Image generation"))
+    }
 }
 
 impl ExtendedGuest for OpenRouterComponent {
@@ -299,18 +314,27 @@ impl ExtendedGuest for OpenRouterComponent {
     ) -> LlmChatStream<OpenRouterChatStream> {
         LOGGING_STATE.with_borrow_mut(|state| state.init());
 
+        let attach_raw = attach_raw_enabled(&config);
+        let timing = timing_enabled(&config);
+        let heartbeat = emit_heartbeat_enabled(&config);
+        let sentence_flush = sentence_flush_timeout(&config);
+        let emit_tool_start = emit_tool_start_enabled(&config);
         with_config_key(
             Self::ENV_VAR_NAME,
             OpenRouterChatStream::failed,
             |openrouter_api_key| {
-                let client = CompletionsApi::new(openrouter_api_key);
+                let client = CompletionsApi::new(openrouter_api_key, &config.provider_options);
 
                 match messages_to_request(messages, config) {
-                    Ok(request) => Self::streaming_request(client, request),
+                    Ok(request) => Self::streaming_request(client, request, emit_tool_start),
                     Err(err) => OpenRouterChatStream::failed(err),
                 }
             },
         )
+        .with_raw_payloads(attach_raw)
+        .with_timing(timing)
+        .with_sentence_flush(sentence_flush)
+        .with_heartbeat(heartbeat)
     }
 
     fn retry_prompt(original_messages: &[Message], partial_result: &[StreamDelta]) -> Vec<Message> {
@@ -370,3 +394,119 @@ impl ExtendedGuest for OpenRouterComponent {
 type DurableOpenRouterComponent = DurableLLM<OpenRouterComponent>;
 
 golem_llm::export_llm!(DurableOpenRouterComponent with_types_in golem_llm);
+
+#[cfg(test)]
+mod tests {
+    use crate::conversions::tool_results_to_messages;
+    use crate::OpenRouterChatStream;
+    use golem_llm::chat_stream::{delta_started_tool_calls, LlmChatStream, LlmChatStreamState};
+    use golem_llm::golem::llm::llm::{
+        ErrorCode, GuestChatStream, StreamEvent, ToolCall, ToolResult, ToolSuccess,
+    };
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    const ERROR_CHUNK: &str = r#"{"id":"gen-1","object":"chat.completion.chunk","created":1,"model":"openai/gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":"error","native_finish_reason":null,"error":{"code":502,"message":"Upstream provider failed"}}],"usage":null,"system_fingerprint":null}"#;
+
+    fn stream_state() -> OpenRouterChatStream {
+        OpenRouterChatStream {
+            stream: RefCell::new(None),
+            failure: None,
+            finished: RefCell::new(false),
+            finish_reason: RefCell::new(None),
+            json_fragments: RefCell::new(HashMap::new()),
+            emit_tool_start: false,
+        }
+    }
+
+    #[test]
+    fn error_in_choice_finishes_the_stream() {
+        let state = stream_state();
+
+        match state.decode_message(ERROR_CHUNK).unwrap() {
+            Some(StreamEvent::Error(error)) => {
+                assert_eq!(error.code, ErrorCode::InternalError);
+                assert_eq!(error.message, "Upstream provider failed");
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        assert!(state.is_finished());
+
+        // A finished stream returns no more events instead of waiting for new ones
+        let stream = LlmChatStream::new(state);
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    fn chunk(choice: &str) -> String {
+        format!(
+            r#"{{"id":"gen-1","object":"chat.completion.chunk","created":1,"model":"openai/gpt-4o","choices":[{choice}],"usage":null,"system_fingerprint":null}}"#
+        )
+    }
+
+    #[test]
+    fn streamed_tool_call_id_correlates_the_result() {
+        let state = stream_state();
+
+        let tool_calls = [
+            r#"{"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null,"native_finish_reason":null}"#,
+            r#"{"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"type":"function","function":{"arguments":"{\"city\":\"Berlin\"}"}}]},"finish_reason":null,"native_finish_reason":null}"#,
+            r#"{"delta":{"role":"assistant","content":null},"finish_reason":"tool_calls","native_finish_reason":"tool_calls"}"#,
+        ]
+        .iter()
+        .filter_map(|choice| match state.decode_message(&chunk(choice)).unwrap() {
+            Some(StreamEvent::Delta(delta)) => delta.tool_calls,
+            _ => None,
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+        assert_eq!(tool_calls.len(), 1);
+        let tool_call = tool_calls[0].clone();
+        assert_eq!(tool_call.id, "call_1");
+        assert_eq!(tool_call.arguments_json, r#"{"city":"Berlin"}"#);
+
+        let result = ToolResult::Success(ToolSuccess {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            result_json: r#"{"temperature":21}"#.to_string(),
+            execution_time_ms: None,
+        });
+        let messages =
+            serde_json::to_value(tool_results_to_messages(vec![(tool_call, result)]).unwrap())
+                .unwrap();
+
+        assert_eq!(messages[0]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(messages[1]["tool_call_id"], "call_1");
+    }
+
+    #[test]
+    fn tool_call_start_precedes_the_complete_tool_call() {
+        let mut state = stream_state();
+        state.emit_tool_start = true;
+
+        let deltas = [
+            r#"{"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null,"native_finish_reason":null}"#,
+            r#"{"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"type":"function","function":{"arguments":"{\"city\":\"Berlin\"}"}}]},"finish_reason":null,"native_finish_reason":null}"#,
+            r#"{"delta":{"role":"assistant","content":null},"finish_reason":"tool_calls","native_finish_reason":"tool_calls"}"#,
+        ]
+        .iter()
+        .filter_map(|choice| match state.decode_message(&chunk(choice)).unwrap() {
+            Some(StreamEvent::Delta(delta)) => Some(delta),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            delta_started_tool_calls(&deltas[0]),
+            vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: String::new(),
+            }]
+        );
+        assert_eq!(deltas[0].tool_calls, None);
+        let complete = deltas[2].tool_calls.as_ref().unwrap();
+        assert_eq!(complete[0].id, "call_1");
+        assert_eq!(complete[0].arguments_json, r#"{"city":"Berlin"}"#);
+        assert!(delta_started_tool_calls(&deltas[2]).is_empty());
+    }
+}
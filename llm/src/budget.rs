@@ -0,0 +1,291 @@
+use crate::golem::llm::llm::{
+    ChatEvent, Error, ErrorCode, FinishReason, GuestChatStream, StreamEvent, Usage,
+};
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// A total token budget shared by all the calls of a session, for example to enforce a per-user
+/// quota across the turns of an agent.
+///
+/// Each call made through the budget adds the tokens reported in the `usage` of its response
+/// metadata. Once the budget is used up, new calls are refused with an `invalid-request` error
+/// without being sent, as retrying them cannot succeed before the budget is raised. A call
+/// started within the budget is never cut off, so the last one may overshoot it. Clones share the
+/// same budget.
+///
+/// A `tool-request` event carries no response metadata, so the turns of `call` answered with one
+/// are not counted. Agents relying on tool requests should make their calls through `stream`,
+/// whose `finish` event reports the usage of every turn.
+#[derive(Debug, Clone)]
+pub struct TokenBudget {
+    max_total_tokens: u32,
+    used: Rc<Cell<u32>>,
+}
+
+impl TokenBudget {
+    pub fn new(max_total_tokens: u32) -> Self {
+        Self {
+            max_total_tokens,
+            used: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// The tokens used by the calls made so far
+    pub fn used(&self) -> u32 {
+        self.used.get()
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.max_total_tokens.saturating_sub(self.used.get())
+    }
+
+    /// Checks that the budget is not used up yet, before making a call
+    pub fn check(&self) -> Result<(), Error> {
+        if self.remaining() > 0 {
+            Ok(())
+        } else {
+            Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "Token budget of {} tokens is exhausted, {} tokens were used",
+                    self.max_total_tokens,
+                    self.used.get()
+                ),
+                provider_error_json: None,
+            })
+        }
+    }
+
+    /// Adds the tokens of a call's usage. Providers not reporting the total are counted by their
+    /// input and output tokens.
+    pub fn record(&self, usage: &Usage) {
+        let tokens = usage.total_tokens.unwrap_or_else(|| {
+            usage
+                .input_tokens
+                .unwrap_or(0)
+                .saturating_add(usage.output_tokens.unwrap_or(0))
+        });
+        self.used.set(self.used.get().saturating_add(tokens));
+    }
+
+    /// Makes a `send` or `continue` call if the budget is not used up, recording the usage of
+    /// the returned response. A returned `tool-request` has no usage and is not counted.
+    pub fn call(&self, call: impl FnOnce() -> ChatEvent) -> ChatEvent {
+        if let Err(error) = self.check() {
+            return ChatEvent::Error(error);
+        }
+
+        let event = call();
+        if let ChatEvent::Message(response) = &event {
+            if let Some(usage) = &response.metadata.usage {
+                self.record(usage);
+            }
+        }
+        event
+    }
+
+    /// Opens a stream if the budget is not used up. The usage of the stream is recorded when its
+    /// `finish` event is received.
+    pub fn stream<S: GuestChatStream>(
+        &self,
+        open: impl FnOnce() -> S,
+    ) -> Result<BudgetedChatStream<S>, Error> {
+        self.check()?;
+        Ok(BudgetedChatStream {
+            stream: open(),
+            budget: self.clone(),
+        })
+    }
+}
+
+/// A chat stream whose usage is recorded in a [`TokenBudget`]
+pub struct BudgetedChatStream<S: GuestChatStream> {
+    stream: S,
+    budget: TokenBudget,
+}
+
+impl<S: GuestChatStream> BudgetedChatStream<S> {
+    pub fn inner(&self) -> &S {
+        &self.stream
+    }
+
+    fn record(&self, events: &[StreamEvent]) {
+        for event in events {
+            if let StreamEvent::Finish(metadata) = event {
+                if let Some(usage) = &metadata.usage {
+                    self.budget.record(usage);
+                }
+            }
+        }
+    }
+}
+
+impl<S: GuestChatStream> GuestChatStream for BudgetedChatStream<S> {
+    fn get_next(&self) -> Option<Vec<StreamEvent>> {
+        let events = self.stream.get_next();
+        if let Some(events) = &events {
+            self.record(events);
+        }
+        events
+    }
+
+    fn blocking_get_next(&self) -> Vec<StreamEvent> {
+        let events = self.stream.blocking_get_next();
+        self.record(&events);
+        events
+    }
+
+    fn is_finished(&self) -> bool {
+        self.stream.is_finished()
+    }
+
+    fn last_finish_reason(&self) -> Option<FinishReason> {
+        self.stream.last_finish_reason()
+    }
+
+    fn cancel(&self) {
+        self.stream.cancel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::budget::TokenBudget;
+    use crate::error::is_retriable;
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, ContentPart, ErrorCode, FinishReason, GuestChatStream,
+        ResponseMetadata, StreamEvent, ToolCall, Usage,
+    };
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+
+    fn usage(input_tokens: u32, output_tokens: u32, total_tokens: Option<u32>) -> Usage {
+        Usage {
+            input_tokens: Some(input_tokens),
+            output_tokens: Some(output_tokens),
+            total_tokens,
+            reasoning_tokens: None,
+            cached_tokens: None,
+            audio_tokens: None,
+        }
+    }
+
+    fn metadata(usage: Option<Usage>) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason: Some(FinishReason::Stop),
+            usage,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        }
+    }
+
+    fn message(usage: Usage) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "response".to_string(),
+            content: vec![ContentPart::Text("Hello".to_string())],
+            tool_calls: vec![],
+            metadata: metadata(Some(usage)),
+        })
+    }
+
+    /// Stream returning the given batches of events
+    struct MockStream {
+        batches: RefCell<VecDeque<Vec<StreamEvent>>>,
+    }
+
+    impl GuestChatStream for MockStream {
+        fn get_next(&self) -> Option<Vec<StreamEvent>> {
+            self.batches.borrow_mut().pop_front()
+        }
+
+        fn blocking_get_next(&self) -> Vec<StreamEvent> {
+            self.batches.borrow_mut().pop_front().unwrap_or_default()
+        }
+
+        fn is_finished(&self) -> bool {
+            self.batches.borrow().is_empty()
+        }
+
+        fn last_finish_reason(&self) -> Option<FinishReason> {
+            None
+        }
+
+        fn cancel(&self) {
+            self.batches.borrow_mut().clear();
+        }
+    }
+
+    #[test]
+    fn usage_is_accumulated_across_calls() {
+        let budget = TokenBudget::new(1000);
+
+        budget.call(|| message(usage(100, 50, Some(150))));
+        budget.call(|| message(usage(200, 30, None)));
+
+        assert_eq!(budget.used(), 380);
+        assert_eq!(budget.remaining(), 620);
+    }
+
+    #[test]
+    fn calls_are_refused_once_the_budget_is_exhausted() {
+        let budget = TokenBudget::new(300);
+        let sent = Cell::new(0);
+        let send = || {
+            sent.set(sent.get() + 1);
+            message(usage(100, 100, Some(200)))
+        };
+
+        assert!(matches!(budget.call(send), ChatEvent::Message(_)));
+        assert!(matches!(budget.call(send), ChatEvent::Message(_)));
+        match budget.call(send) {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InvalidRequest);
+                assert!(!is_retriable(&error));
+                assert_eq!(
+                    error.message,
+                    "Token budget of 300 tokens is exhausted, 400 tokens were used"
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        assert_eq!(sent.get(), 2);
+    }
+
+    #[test]
+    fn stream_usage_is_recorded_on_finish() {
+        let budget = TokenBudget::new(100);
+        let stream = budget
+            .stream(|| MockStream {
+                batches: RefCell::new(VecDeque::from([vec![StreamEvent::Finish(metadata(Some(
+                    usage(80, 40, Some(120)),
+                )))]])),
+            })
+            .unwrap();
+
+        stream.blocking_get_next();
+
+        assert_eq!(budget.used(), 120);
+        let error = budget.stream(|| -> MockStream { unreachable!() }).err();
+        assert_eq!(
+            error.map(|error| error.code),
+            Some(ErrorCode::InvalidRequest)
+        );
+    }
+
+    #[test]
+    fn tool_requests_are_not_counted() {
+        let budget = TokenBudget::new(100);
+
+        budget.call(|| {
+            ChatEvent::ToolRequest(vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: "{}".to_string(),
+            }])
+        });
+
+        assert_eq!(budget.used(), 0);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::golem::llm::llm::{ContentPart, Error, ErrorCode, ImageDetail, ImageUrl, Message, Role};
+use serde::{Deserialize, Serialize};
+
+/// A message in the OpenAI chat messages format, which is understood by most tools working with
+/// conversation histories
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default)]
+    content: Option<ChatContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatContentPart>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ChatImageUrl },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatImageUrl {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+/// Exports a conversation as a JSON array in the OpenAI chat messages format, to be used with
+/// other systems.
+///
+/// Messages consisting of a single text part have a string `content`, the others a list of
+/// `text` and `image_url` parts.
+pub fn messages_to_chat_json(messages: &[Message]) -> String {
+    let messages = messages
+        .iter()
+        .map(|message| ChatMessage {
+            role: match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+                Role::System => "system",
+                Role::Tool => "tool",
+            }
+            .to_string(),
+            name: message.name.clone(),
+            content: Some(match message.content.as_slice() {
+                [ContentPart::Text(text)] => ChatContent::Text(text.clone()),
+                parts => ChatContent::Parts(
+                    parts
+                        .iter()
+                        .map(|part| match part {
+                            ContentPart::Text(text) => ChatContentPart::Text { text: text.clone() },
+                            ContentPart::Image(image) => ChatContentPart::ImageUrl {
+                                image_url: ChatImageUrl {
+                                    url: image.url.clone(),
+                                    detail: image.detail.map(|detail| {
+                                        match detail {
+                                            ImageDetail::Low => "low",
+                                            ImageDetail::High => "high",
+                                            ImageDetail::Auto => "auto",
+                                        }
+                                        .to_string()
+                                    }),
+                                },
+                            },
+                        })
+                        .collect(),
+                ),
+            }),
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string(&messages).unwrap()
+}
+
+/// Imports a conversation from a JSON array in the OpenAI chat messages format.
+///
+/// The `developer` role is imported as a system message. The other fields of the messages, such
+/// as the tool calls of assistant messages, are not part of a `Message` and are ignored.
+pub fn messages_from_chat_json(json: &str) -> Result<Vec<Message>, Error> {
+    let messages: Vec<ChatMessage> = serde_json::from_str(json).map_err(|err| Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!("Failed to parse chat messages: {err}"),
+        provider_error_json: None,
+    })?;
+
+    messages
+        .into_iter()
+        .map(|message| {
+            let role = match message.role.as_str() {
+                "user" => Role::User,
+                "assistant" => Role::Assistant,
+                "system" | "developer" => Role::System,
+                "tool" => Role::Tool,
+                other => {
+                    return Err(Error {
+                        code: ErrorCode::InvalidRequest,
+                        message: format!("Unknown chat message role: {other}"),
+                        provider_error_json: None,
+                    })
+                }
+            };
+            let content = match message.content {
+                None => vec![],
+                Some(ChatContent::Text(text)) => vec![ContentPart::Text(text)],
+                Some(ChatContent::Parts(parts)) => parts
+                    .into_iter()
+                    .map(|part| match part {
+                        ChatContentPart::Text { text } => ContentPart::Text(text),
+                        ChatContentPart::ImageUrl { image_url } => ContentPart::Image(ImageUrl {
+                            url: image_url.url,
+                            detail: image_url.detail.as_deref().and_then(|detail| match detail {
+                                "low" => Some(ImageDetail::Low),
+                                "high" => Some(ImageDetail::High),
+                                "auto" => Some(ImageDetail::Auto),
+                                _ => None,
+                            }),
+                        }),
+                    })
+                    .collect(),
+            };
+            Ok(Message {
+                role,
+                name: message.name,
+                content,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat_format::{messages_from_chat_json, messages_to_chat_json};
+    use crate::golem::llm::llm::{ContentPart, ErrorCode, ImageDetail, ImageUrl, Message, Role};
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message {
+                role: Role::System,
+                name: None,
+                content: vec![ContentPart::Text("You are a helpful assistant".to_string())],
+            },
+            Message {
+                role: Role::User,
+                name: Some("alice".to_string()),
+                content: vec![
+                    ContentPart::Text("What is in this image?".to_string()),
+                    ContentPart::Image(ImageUrl {
+                        url: "https://example.com/image.png".to_string(),
+                        detail: Some(ImageDetail::High),
+                    }),
+                ],
+            },
+            Message {
+                role: Role::Assistant,
+                name: None,
+                content: vec![ContentPart::Text("A cat".to_string())],
+            },
+            Message {
+                role: Role::Tool,
+                name: None,
+                content: vec![ContentPart::Text(r#"{"temperature":21}"#.to_string())],
+            },
+        ]
+    }
+
+    #[test]
+    fn conversation_is_exported_in_chat_format() {
+        let json: serde_json::Value =
+            serde_json::from_str(&messages_to_chat_json(&conversation())).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"role": "system", "content": "You are a helpful assistant"},
+                {"role": "user", "name": "alice", "content": [
+                    {"type": "text", "text": "What is in this image?"},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/image.png", "detail": "high"}}
+                ]},
+                {"role": "assistant", "content": "A cat"},
+                {"role": "tool", "content": "{\"temperature\":21}"}
+            ])
+        );
+    }
+
+    #[test]
+    fn conversation_survives_the_round_trip() {
+        let json = messages_to_chat_json(&conversation());
+
+        assert_eq!(messages_from_chat_json(&json).unwrap(), conversation());
+    }
+
+    #[test]
+    fn developer_role_and_null_content_are_imported() {
+        let messages = messages_from_chat_json(
+            r#"[{"role":"developer","content":"Be brief"},{"role":"assistant","content":null,"tool_calls":[]}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(messages[0].role, Role::System);
+        assert_eq!(messages[1].content, vec![]);
+    }
+
+    #[test]
+    fn unknown_role_is_rejected() {
+        let error = messages_from_chat_json(r#"[{"role":"robot","content":"Beep"}]"#).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(error.message, "Unknown chat message role: robot");
+    }
+}
@@ -1,8 +1,38 @@
+use crate::config::{bool_option, option};
 use crate::event_source::{Event, EventSource, MessageEvent};
-use crate::golem::llm::llm::{Error, ErrorCode, GuestChatStream, StreamEvent};
+use crate::golem::llm::llm::{
+    ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason,
+    GuestChatStream, StreamDelta, StreamEvent, ToolCall, Usage,
+};
+use crate::trace_id::{self, with_trace_id, TRACE_ID};
 use golem_rust::wasm_rpc::Pollable;
-use std::cell::{Ref, RefMut};
+use log::trace;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::task::Poll;
+use std::time::{Duration, Instant};
+
+/// Returns whether `GOLEM_LLM_STRICT_STREAM` is set to `1` or `true`, in which case stream events
+/// of unknown type are reported as errors instead of being ignored.
+pub fn strict_stream_mode() -> bool {
+    std::env::var("GOLEM_LLM_STRICT_STREAM")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// Decoding result for a stream event type that the provider's decoder does not handle
+pub fn unknown_event_type(typ: &str) -> Result<Option<StreamEvent>, String> {
+    decode_unknown_event_type(typ, strict_stream_mode())
+}
+
+fn decode_unknown_event_type(typ: &str, strict: bool) -> Result<Option<StreamEvent>, String> {
+    if strict {
+        Err(format!("Unknown stream event type: {typ}"))
+    } else {
+        trace!("{TRACE_ID}Ignoring stream event of unknown type: {typ}");
+        Ok(None)
+    }
+}
 
 pub trait LlmChatStreamState: 'static {
     fn failure(&self) -> &Option<Error>;
@@ -11,15 +41,573 @@ pub trait LlmChatStreamState: 'static {
     fn stream(&self) -> Ref<Option<EventSource>>;
     fn stream_mut(&self) -> RefMut<Option<EventSource>>;
     fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String>;
+
+    /// Decodes the data of a single server-sent event into the stream events to emit.
+    /// Implementations receiving messages that carry several events, like the deltas of multiple
+    /// candidates, override this instead of relying on [`Self::decode_message`].
+    fn decode_events(&self, raw: &str) -> Result<Vec<StreamEvent>, String> {
+        self.decode_message(raw)
+            .map(|stream_event| stream_event.into_iter().collect())
+    }
+
+    /// Called when the underlying stream ends before a `Finish` event was decoded, allowing
+    /// the implementation to emit a final event from the state it collected so far.
+    fn end_of_stream(&self) -> Option<StreamEvent> {
+        None
+    }
+
+    /// Called when the connection of the underlying stream drops before it finished. An
+    /// implementation able to resume the generation returns a new stream to continue with,
+    /// otherwise the transport error is emitted and the stream is finished. The dropped stream is
+    /// released right away, so no pollable subscribed to it may be alive while polling.
+    fn reconnect(&self) -> Option<EventSource> {
+        None
+    }
+}
+
+/// Raw provider payloads larger than this are not attached to the stream events
+pub const MAX_RAW_PAYLOAD_SIZE: usize = 16 * 1024;
+
+/// Returns whether the `attach_raw=true` provider option is set, requesting the raw provider
+/// payload to be attached to each `Delta` and `Finish` stream event.
+///
+/// Attaching the payloads roughly doubles the size of the stream events, which are also
+/// persisted in the oplog when durability is enabled.
+pub fn attach_raw_enabled(config: &Config) -> bool {
+    bool_option(config, "attach_raw")
+}
+
+/// Returns whether the `timing=true` provider option is set, requesting each `Delta` stream event
+/// to carry the milliseconds elapsed since the stream started in the `elapsed_ms` field of its
+/// metadata, see [`delta_elapsed_ms`]. This allows computing the inter-token latency and
+/// detecting stalls.
+pub fn timing_enabled(config: &Config) -> bool {
+    bool_option(config, "timing")
+}
+
+/// Returns whether the `stream_usage_updates=true` provider option is set, requesting deltas
+/// carrying the running token counts whenever the provider reports them during streaming, see
+/// [`delta_usage`]. Providers not reporting incremental usage ignore this option.
+pub fn stream_usage_updates_enabled(config: &Config) -> bool {
+    bool_option(config, "stream_usage_updates")
+}
+
+/// Returns whether the `emit_tool_start=true` provider option is set, requesting a marker as soon
+/// as the provider reports the id and name of a tool call, before its arguments are streamed.
+/// The markers are deltas listing the started tool calls in their metadata, see
+/// [`delta_started_tool_calls`], and the complete tool calls follow as usual.
+pub fn emit_tool_start_enabled(config: &Config) -> bool {
+    bool_option(config, "emit_tool_start")
+}
+
+/// Returns whether the `emit_heartbeat=true` provider option is set, requesting a heartbeat delta
+/// whenever the provider sends a keep-alive, such as Anthropic's `ping` events or server-sent
+/// event comments. Heartbeats carry no content, see [`is_heartbeat`], and let consumers waiting
+/// on a long generation tell a slow stream from a stalled one.
+pub fn emit_heartbeat_enabled(config: &Config) -> bool {
+    bool_option(config, "emit_heartbeat")
+}
+
+/// How long text is buffered by default with `flush_on_sentence` before it is emitted without a
+/// sentence boundary
+const DEFAULT_SENTENCE_FLUSH_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Returns the timeout of buffering text until a sentence boundary if the `flush_on_sentence=true`
+/// provider option is set, for example for text-to-speech pipelines which should not get partial
+/// words. The timeout is set in milliseconds with `flush_on_sentence_timeout_ms`, and defaults to
+/// one second.
+pub fn sentence_flush_timeout(config: &Config) -> Option<Duration> {
+    bool_option(config, "flush_on_sentence").then(|| {
+        option(config, "flush_on_sentence_timeout_ms")
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_SENTENCE_FLUSH_TIMEOUT)
+    })
+}
+
+/// Returns whether the `use_stream_internally=true` provider option is set, requesting `send` to
+/// use the provider's streaming endpoint and assemble the complete response from the stream.
+/// This helps with providers reporting usage or other data reliably only when streaming.
+pub fn use_stream_internally(config: &Config) -> bool {
+    bool_option(config, "use_stream_internally")
+}
+
+/// Consumes `stream` until it ends or fails, and assembles its events with [`collect_events`]
+pub fn collect_stream(stream: &impl GuestChatStream) -> ChatEvent {
+    collect_events(drain_stream(stream))
+}
+
+/// Consumes `stream` like [`collect_stream`], also keeping the log probabilities of the generated
+/// tokens
+pub fn collect_stream_with_logprobs(stream: &impl GuestChatStream) -> CollectedStream {
+    collect_events_with_logprobs(drain_stream(stream))
+}
+
+fn drain_stream(stream: &impl GuestChatStream) -> Vec<StreamEvent> {
+    let mut events = Vec::new();
+    loop {
+        let next = stream.blocking_get_next();
+        if next.is_empty() {
+            break;
+        }
+        let failed = next
+            .iter()
+            .any(|event| matches!(event, StreamEvent::Error(_)));
+        events.extend(next);
+        if failed {
+            break;
+        }
+    }
+    events
+}
+
+/// Assembles the events of a stream into the response a non-streaming request would return.
+///
+/// Consecutive text deltas are joined into a single text part, unless the provider reports them
+/// as parts of different content blocks. The first error event is returned as is, and a stream
+/// without a finish event is considered incomplete. Only the first candidate of a stream
+/// generating multiple candidates is collected.
+///
+/// When the deltas carry block indices, the order of the content parts and tool calls is
+/// reported under the `blocks` key of the `provider_metadata_json` of the response, as
+/// `{"type": "content", "content_index": n}` and `{"type": "tool_call", "id": ...}` entries,
+/// so interleaved text and tool calls can be reconstructed in order.
+pub fn collect_events(events: impl IntoIterator<Item = StreamEvent>) -> ChatEvent {
+    let mut content: Vec<ContentPart> = Vec::new();
+    let mut tool_calls: Vec<ToolCall> = Vec::new();
+    let mut usage = None;
+    let mut metadata = None;
+    // The block of the last content part, and the order of the blocks if the provider reports
+    // them
+    let mut content_block = None;
+    let mut blocks = Vec::new();
+
+    for event in events {
+        match event {
+            StreamEvent::Delta(delta) if delta_candidate_index(&delta) != 0 => {}
+            StreamEvent::Delta(delta) => {
+                if let Some(running_usage) = delta_usage(&delta) {
+                    usage = Some(running_usage);
+                }
+                let block_index = delta_block_index(&delta);
+                for part in delta.content.into_iter().flatten() {
+                    let same_block = block_index.is_none() || block_index == content_block;
+                    match (content.last_mut(), part) {
+                        (Some(ContentPart::Text(text)), ContentPart::Text(more)) if same_block => {
+                            text.push_str(&more)
+                        }
+                        (_, part) => {
+                            if block_index.is_some() {
+                                blocks.push(serde_json::json!({
+                                    "type": "content",
+                                    "content_index": content.len(),
+                                }));
+                            }
+                            content.push(part);
+                        }
+                    }
+                    content_block = block_index;
+                }
+                for tool_call in delta.tool_calls.into_iter().flatten() {
+                    if block_index.is_some() {
+                        blocks.push(serde_json::json!({"type": "tool_call", "id": tool_call.id}));
+                    }
+                    tool_calls.push(tool_call);
+                }
+            }
+            StreamEvent::Finish(finish) => metadata = Some(finish),
+            StreamEvent::Error(error) => return ChatEvent::Error(error),
+        }
+    }
+
+    let Some(mut metadata) = metadata else {
+        return ChatEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: "Stream ended without a finish event".to_string(),
+            provider_error_json: None,
+        });
+    };
+    if metadata.usage.is_none() {
+        metadata.usage = usage;
+    }
+    if !blocks.is_empty() {
+        let provider_metadata = match metadata.provider_metadata_json.as_deref() {
+            Some(json) => serde_json::from_str::<serde_json::Value>(json).ok(),
+            None => Some(serde_json::json!({})),
+        };
+        if let Some(serde_json::Value::Object(mut provider_metadata)) = provider_metadata {
+            provider_metadata.insert("blocks".to_string(), serde_json::Value::Array(blocks));
+            metadata.provider_metadata_json =
+                Some(serde_json::Value::Object(provider_metadata).to_string());
+        }
+    }
+
+    if content.is_empty() && !tool_calls.is_empty() {
+        ChatEvent::ToolRequest(tool_calls)
+    } else {
+        ChatEvent::Message(CompleteResponse {
+            id: metadata.provider_id.clone().unwrap_or_default(),
+            content,
+            tool_calls,
+            metadata,
+        })
+    }
+}
+
+/// The log probability of a generated token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+impl TokenLogprob {
+    /// The probability the model assigned to the token, between 0 and 1
+    pub fn confidence(&self) -> f64 {
+        self.logprob.exp()
+    }
+}
+
+/// A tool call whose arguments are not streamed yet
+#[derive(Debug, Serialize, Deserialize)]
+struct StartedToolCall {
+    id: String,
+    name: String,
+}
+
+/// The `provider_metadata_json` of stream deltas. Providers returning log probabilities put the
+/// ones of the delta's tokens in its `logprobs` field, and deltas of a stream generating multiple
+/// candidates carry the index of the candidate they belong to in `candidate_index`. With
+/// `emit_tool_start`, the tool calls started by a delta are listed in `started_tool_calls`, and
+/// with `timing` the time since the stream started is in `elapsed_ms`. Providers streaming the
+/// response as separate content blocks put the index of the delta's block in `block_index`.
+/// Heartbeats emitted with `emit_heartbeat` are marked by `heartbeat`, and the running usage
+/// reported with `stream_usage_updates` is in `usage`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DeltaMetadata {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    logprobs: Vec<TokenLogprob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    candidate_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    started_tool_calls: Vec<StartedToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    elapsed_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    block_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    heartbeat: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<UsageMetadata>,
+}
+
+/// The token counts of a [`Usage`], as carried in the metadata of a delta
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsageMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    total_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reasoning_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cached_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audio_tokens: Option<u32>,
+}
+
+impl From<Usage> for UsageMetadata {
+    fn from(usage: Usage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+            reasoning_tokens: usage.reasoning_tokens,
+            cached_tokens: usage.cached_tokens,
+            audio_tokens: usage.audio_tokens,
+        }
+    }
+}
+
+impl From<UsageMetadata> for Usage {
+    fn from(usage: UsageMetadata) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+            reasoning_tokens: usage.reasoning_tokens,
+            cached_tokens: usage.cached_tokens,
+            audio_tokens: usage.audio_tokens,
+        }
+    }
+}
+
+/// Creates the `provider_metadata_json` of a stream delta carrying the log probabilities of its
+/// tokens
+pub fn logprobs_metadata(logprobs: Vec<TokenLogprob>) -> String {
+    serde_json::to_string(&DeltaMetadata {
+        logprobs,
+        ..DeltaMetadata::default()
+    })
+    .unwrap()
+}
+
+/// Creates the `provider_metadata_json` of a stream delta starting the given tool calls, as
+/// (id, name) pairs
+pub fn started_tool_calls_metadata(started: Vec<(String, String)>) -> String {
+    serde_json::to_string(&DeltaMetadata {
+        started_tool_calls: started
+            .into_iter()
+            .map(|(id, name)| StartedToolCall { id, name })
+            .collect(),
+        ..DeltaMetadata::default()
+    })
+    .unwrap()
+}
+
+/// Gets the tool calls started by a stream delta, with empty arguments. The complete tool calls
+/// are reported by a later delta.
+pub fn delta_started_tool_calls(delta: &StreamDelta) -> Vec<ToolCall> {
+    delta_metadata(delta)
+        .started_tool_calls
+        .into_iter()
+        .map(|started| ToolCall {
+            id: started.id,
+            name: started.name,
+            arguments_json: String::new(),
+        })
+        .collect()
+}
+
+/// Creates the `provider_metadata_json` of a stream delta of the given candidate, if there is
+/// anything to attach. Deltas of the first candidate only get metadata when they carry log
+/// probabilities, so single candidate streams are unaffected.
+pub fn candidate_metadata(candidate_index: u32, logprobs: Vec<TokenLogprob>) -> Option<String> {
+    if candidate_index == 0 && logprobs.is_empty() {
+        None
+    } else {
+        Some(
+            serde_json::to_string(&DeltaMetadata {
+                logprobs,
+                candidate_index: Some(candidate_index),
+                ..DeltaMetadata::default()
+            })
+            .unwrap(),
+        )
+    }
+}
+
+fn delta_metadata(delta: &StreamDelta) -> DeltaMetadata {
+    delta
+        .provider_metadata_json
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<DeltaMetadata>(json).ok())
+        .unwrap_or_default()
+}
+
+/// Gets the index of the candidate a stream delta belongs to, `0` unless the stream generates
+/// multiple candidates
+pub fn delta_candidate_index(delta: &StreamDelta) -> u32 {
+    delta_metadata(delta).candidate_index.unwrap_or(0)
+}
+
+/// Gets the log probabilities of the tokens of a stream delta, if the provider returned them
+pub fn delta_logprobs(delta: &StreamDelta) -> Vec<TokenLogprob> {
+    delta_metadata(delta).logprobs
+}
+
+/// Gets the milliseconds elapsed between the start of the stream and a delta, if it was streamed
+/// with the `timing` option
+pub fn delta_elapsed_ms(delta: &StreamDelta) -> Option<f64> {
+    delta_metadata(delta).elapsed_ms
+}
+
+/// Marks a delta as part of the content block with the given index, keeping the metadata already
+/// set by the provider. This lets [`collect_events`] keep the text of separate blocks apart, for
+/// example the text before and after a tool call.
+pub fn with_block_index(mut delta: StreamDelta, block_index: u32) -> StreamDelta {
+    insert_delta_metadata(&mut delta, "block_index", serde_json::json!(block_index));
+    delta
+}
+
+/// Gets the index of the content block a stream delta belongs to, if the provider reports them
+pub fn delta_block_index(delta: &StreamDelta) -> Option<u32> {
+    delta_metadata(delta).block_index
+}
+
+/// Creates a heartbeat delta, emitted for a keep-alive of the provider when the stream was
+/// created with `emit_heartbeat`. It has no content and no tool calls.
+pub fn heartbeat_delta() -> StreamDelta {
+    StreamDelta {
+        content: None,
+        tool_calls: None,
+        provider_metadata_json: Some(
+            serde_json::to_string(&DeltaMetadata {
+                heartbeat: true,
+                ..DeltaMetadata::default()
+            })
+            .unwrap(),
+        ),
+        raw_json: None,
+    }
+}
+
+/// Creates a delta carrying the running usage of the stream, emitted with `stream_usage_updates`.
+/// It has no content and no tool calls.
+pub fn usage_delta(usage: Usage) -> StreamDelta {
+    StreamDelta {
+        content: None,
+        tool_calls: None,
+        provider_metadata_json: Some(
+            serde_json::to_string(&DeltaMetadata {
+                usage: Some(usage.into()),
+                ..DeltaMetadata::default()
+            })
+            .unwrap(),
+        ),
+        raw_json: None,
+    }
+}
+
+/// Gets the running usage carried by a stream delta, if it reports one
+pub fn delta_usage(delta: &StreamDelta) -> Option<Usage> {
+    delta_metadata(delta).usage.map(Usage::from)
+}
+
+/// Returns whether a stream event is a heartbeat, which only signals that the stream is alive
+pub fn is_heartbeat(event: &StreamEvent) -> bool {
+    matches!(event, StreamEvent::Delta(delta) if delta_metadata(delta).heartbeat)
+}
+
+/// A stream assembled by [`collect_events_with_logprobs`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedStream {
+    pub event: ChatEvent,
+    /// The log probabilities of all the generated tokens in order, empty if the provider did not
+    /// return them
+    pub logprobs: Vec<TokenLogprob>,
+}
+
+/// Assembles the events of a stream with [`collect_events`], also collecting the log
+/// probabilities carried by the deltas so low confidence parts of the response can be found.
+pub fn collect_events_with_logprobs(
+    events: impl IntoIterator<Item = StreamEvent>,
+) -> CollectedStream {
+    let events: Vec<StreamEvent> = events.into_iter().collect();
+    let logprobs = events
+        .iter()
+        .flat_map(|event| match event {
+            StreamEvent::Delta(delta) if delta_candidate_index(delta) == 0 => delta_logprobs(delta),
+            _ => Vec::new(),
+        })
+        .collect();
+    CollectedStream {
+        event: collect_events(events),
+        logprobs,
+    }
+}
+
+fn attach_raw(event: StreamEvent, raw: &str) -> StreamEvent {
+    if raw.len() > MAX_RAW_PAYLOAD_SIZE {
+        return event;
+    }
+    match event {
+        StreamEvent::Delta(mut delta) => {
+            delta.raw_json = Some(raw.to_string());
+            StreamEvent::Delta(delta)
+        }
+        StreamEvent::Finish(mut metadata) => {
+            metadata.raw_json = Some(raw.to_string());
+            StreamEvent::Finish(metadata)
+        }
+        other => other,
+    }
+}
+
+/// Adds `elapsed_ms` to the metadata of a delta, keeping the fields already set by the provider
+fn attach_timing(event: StreamEvent, started: Instant) -> StreamEvent {
+    match event {
+        StreamEvent::Delta(mut delta) => {
+            insert_delta_metadata(
+                &mut delta,
+                "elapsed_ms",
+                serde_json::json!(started.elapsed().as_secs_f64() * 1000.0),
+            );
+            StreamEvent::Delta(delta)
+        }
+        other => other,
+    }
+}
+
+/// Adds a field to the metadata of a delta. Deltas whose metadata is not a JSON object are left
+/// unchanged.
+fn insert_delta_metadata(delta: &mut StreamDelta, key: &str, value: serde_json::Value) {
+    let metadata = match delta.provider_metadata_json.as_deref() {
+        Some(json) => serde_json::from_str::<serde_json::Value>(json).ok(),
+        None => Some(serde_json::json!({})),
+    };
+    if let Some(serde_json::Value::Object(mut metadata)) = metadata {
+        metadata.insert(key.to_string(), value);
+        delta.provider_metadata_json = Some(serde_json::Value::Object(metadata).to_string());
+    }
 }
 
 pub struct LlmChatStream<T> {
     implementation: T,
+    raw_payloads: bool,
+    /// When the stream was created, if the deltas are annotated with the elapsed time
+    started: Option<Instant>,
+    /// Text buffered until a sentence boundary, if `flush_on_sentence` is enabled
+    sentences: Option<SentenceBuffer>,
+    /// Whether keep-alives of the provider are emitted as heartbeat deltas
+    heartbeat: bool,
+    /// The trace id of the request the stream was created for, prefixing the log lines of
+    /// decoding its events
+    trace_id: Option<String>,
+    finish_reason: Cell<Option<FinishReason>>,
 }
 
 impl<T: LlmChatStreamState> LlmChatStream<T> {
     pub fn new(implementation: T) -> Self {
-        Self { implementation }
+        Self {
+            implementation,
+            raw_payloads: false,
+            started: None,
+            sentences: None,
+            heartbeat: false,
+            trace_id: trace_id::current(),
+            finish_reason: Cell::new(None),
+        }
+    }
+
+    /// Enables attaching the raw provider payload to the emitted `Delta` and `Finish` events
+    pub fn with_raw_payloads(mut self, enabled: bool) -> Self {
+        self.raw_payloads = enabled;
+        self
+    }
+
+    /// Enables annotating the emitted `Delta` events with the time elapsed since now
+    pub fn with_timing(mut self, enabled: bool) -> Self {
+        self.started = enabled.then(Instant::now);
+        self
+    }
+
+    /// Enables buffering the text deltas until a sentence boundary, or until text was buffered for
+    /// the given timeout. The timeout is checked whenever the stream is polled, and
+    /// `blocking_get_next` wakes up when it elapses even if the provider sends nothing more.
+    pub fn with_sentence_flush(mut self, timeout: Option<Duration>) -> Self {
+        self.sentences = timeout.map(SentenceBuffer::new);
+        self
+    }
+
+    /// Enables emitting heartbeat deltas for the keep-alives of the provider, both the ones
+    /// decoded as [`heartbeat_delta`]s and the comments of the event stream. Otherwise they are
+    /// dropped.
+    pub fn with_heartbeat(mut self, enabled: bool) -> Self {
+        self.heartbeat = enabled;
+        self
     }
 
     pub fn subscribe(&self) -> Pollable {
@@ -29,10 +617,71 @@ impl<T: LlmChatStreamState> LlmChatStream<T> {
             golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(0)
         }
     }
-}
 
-impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
-    fn get_next(&self) -> Option<Vec<StreamEvent>> {
+    /// Decodes the data of a single server-sent event into the stream events to emit
+    fn process_message(&self, data: &str) -> Vec<StreamEvent> {
+        if data == "[DONE]" {
+            return vec![];
+        }
+
+        match self.implementation.decode_events(data) {
+            Ok(stream_events) => stream_events
+                .into_iter()
+                .filter(|stream_event| self.heartbeat || !is_heartbeat(stream_event))
+                .map(|stream_event| {
+                    self.observe(&stream_event);
+                    let stream_event = if self.raw_payloads {
+                        attach_raw(stream_event, data)
+                    } else {
+                        stream_event
+                    };
+                    match self.started {
+                        Some(started) => attach_timing(stream_event, started),
+                        None => stream_event,
+                    }
+                })
+                .collect(),
+            Err(error) => vec![StreamEvent::Error(Error {
+                code: ErrorCode::InternalError,
+                message: error,
+                provider_error_json: None,
+            })],
+        }
+    }
+
+    /// Tracks the final state of the stream from an event about to be emitted
+    fn observe(&self, stream_event: &StreamEvent) {
+        if let StreamEvent::Finish(metadata) = stream_event {
+            self.implementation.set_finished();
+            self.finish_reason.set(metadata.finish_reason);
+        }
+    }
+
+    /// Polls the next events with [`Self::next_events`], preceded by a heartbeat if the event
+    /// stream received keep-alive comments meanwhile and heartbeats are enabled
+    fn next_events_with_heartbeat(&self) -> Option<Vec<StreamEvent>> {
+        let events = self.next_events();
+        let comments = self
+            .implementation
+            .stream_mut()
+            .as_mut()
+            .map_or(0, EventSource::take_comments);
+        if !self.heartbeat || comments == 0 || events.as_ref().is_some_and(Vec::is_empty) {
+            return events;
+        }
+
+        let heartbeat = StreamEvent::Delta(heartbeat_delta());
+        let heartbeat = match self.started {
+            Some(started) => attach_timing(heartbeat, started),
+            None => heartbeat,
+        };
+        let mut events = events.unwrap_or_default();
+        events.insert(0, heartbeat);
+        Some(events)
+    }
+
+    /// Polls the underlying event stream for the next events to emit
+    fn next_events(&self) -> Option<Vec<StreamEvent>> {
         if self.implementation.is_finished() {
             return Some(vec![]);
         }
@@ -40,45 +689,50 @@ impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
         let mut stream = self.implementation.stream_mut();
         if let Some(stream) = stream.as_mut() {
             match stream.poll_next() {
-                Poll::Ready(None) => {
+                Poll::Ready(None)
+                | Poll::Ready(Some(Err(crate::event_source::error::Error::StreamEnded))) => {
                     self.implementation.set_finished();
-                    Some(vec![])
+                    let final_event = self.implementation.end_of_stream();
+                    if let Some(final_event) = &final_event {
+                        self.observe(final_event);
+                    }
+                    Some(final_event.into_iter().collect())
                 }
-                Poll::Ready(Some(Err(crate::event_source::error::Error::StreamEnded))) => {
-                    self.implementation.set_finished();
-                    Some(vec![])
+                Poll::Ready(Some(Err(error))) => {
+                    let dropped = matches!(
+                        error,
+                        crate::event_source::error::Error::Transport(_)
+                            | crate::event_source::error::Error::TransportStream(_)
+                    );
+                    let reconnected = if dropped {
+                        self.implementation.reconnect()
+                    } else {
+                        None
+                    };
+                    match reconnected {
+                        Some(reconnected) => {
+                            trace!("{TRACE_ID}Reconnected stream after connection drop: {error}");
+                            *stream = reconnected;
+                            None
+                        }
+                        None => {
+                            // The event source is closed after an error, so the stream ends with it
+                            self.implementation.set_finished();
+                            Some(vec![StreamEvent::Error(Error {
+                                code: ErrorCode::InternalError,
+                                message: error.to_string(),
+                                provider_error_json: None,
+                            })])
+                        }
+                    }
                 }
-                Poll::Ready(Some(Err(error))) => Some(vec![StreamEvent::Error(Error {
-                    code: ErrorCode::InternalError,
-                    message: error.to_string(),
-                    provider_error_json: None,
-                })]),
                 Poll::Ready(Some(Ok(event))) => {
                     let mut events = vec![];
 
                     match event {
                         Event::Open => {}
                         Event::Message(MessageEvent { data, .. }) => {
-                            if data != "[DONE]" {
-                                match self.implementation.decode_message(&data) {
-                                    Ok(Some(stream_event)) => {
-                                        if matches!(stream_event, StreamEvent::Finish(_)) {
-                                            self.implementation.set_finished();
-                                        }
-                                        events.push(stream_event);
-                                    }
-                                    Ok(None) => {
-                                        // Ignored event
-                                    }
-                                    Err(error) => {
-                                        events.push(StreamEvent::Error(Error {
-                                            code: ErrorCode::InternalError,
-                                            message: error,
-                                            provider_error_json: None,
-                                        }));
-                                    }
-                                }
-                            }
+                            events.extend(self.process_message(&data));
                         }
                     }
 
@@ -97,12 +751,33 @@ impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
             None
         }
     }
+}
+
+impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
+    fn get_next(&self) -> Option<Vec<StreamEvent>> {
+        with_trace_id(self.trace_id.clone(), || match &self.sentences {
+            Some(sentences) => sentences.process(self.next_events_with_heartbeat()),
+            None => self.next_events_with_heartbeat(),
+        })
+    }
 
     fn blocking_get_next(&self) -> Vec<StreamEvent> {
-        let pollable = self.subscribe();
         let mut result = Vec::new();
         loop {
-            pollable.block();
+            // The pollable is dropped before polling, as a reconnect replaces the event source it
+            // belongs to, and is created anew for every wait
+            let pollable = self.subscribe();
+            match self.sentences.as_ref().and_then(SentenceBuffer::remaining) {
+                Some(remaining) => {
+                    let timeout =
+                        golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(
+                            remaining.as_nanos() as u64,
+                        );
+                    golem_rust::bindings::wasi::io::poll::poll(&[&pollable, &timeout]);
+                }
+                None => pollable.block(),
+            }
+            drop(pollable);
             match self.get_next() {
                 Some(events) => {
                     result.extend(events);
@@ -112,4 +787,809 @@ impl<T: LlmChatStreamState> GuestChatStream for LlmChatStream<T> {
             }
         }
     }
+
+    fn is_finished(&self) -> bool {
+        self.implementation.is_finished()
+    }
+
+    fn last_finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason.get()
+    }
+
+    fn cancel(&self) {
+        if let Some(stream) = self.implementation.stream_mut().as_mut() {
+            stream.close();
+        }
+        self.implementation.set_finished();
+    }
+}
+
+/// Buffers the text deltas of a stream until a sentence boundary, see [`sentence_flush_timeout`]
+struct SentenceBuffer {
+    timeout: Duration,
+    /// The buffered text, and the last buffered delta, whose metadata the emitted delta carries
+    pending: RefCell<Option<(String, StreamDelta)>>,
+    /// When the buffered text started to wait for a sentence boundary
+    since: Cell<Option<Instant>>,
+}
+
+impl SentenceBuffer {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: RefCell::new(None),
+            since: Cell::new(None),
+        }
+    }
+
+    /// The time left until the buffered text is flushed, if any text is buffered
+    fn remaining(&self) -> Option<Duration> {
+        self.since
+            .get()
+            .map(|since| self.timeout.saturating_sub(since.elapsed()))
+    }
+
+    /// Buffers the text deltas among the next `events` of the stream, and returns the events to
+    /// emit instead: the text up to the last sentence boundary, or all of it on timeout, and the
+    /// other events, such as tool calls and the finish event, which flush the buffer immediately
+    fn process(&self, events: Option<Vec<StreamEvent>>) -> Option<Vec<StreamEvent>> {
+        let ended = events.as_ref().is_some_and(|events| events.is_empty());
+        let mut result = Vec::new();
+
+        for event in events.into_iter().flatten() {
+            match event {
+                StreamEvent::Delta(delta) if is_text_delta(&delta) => self.buffer(delta),
+                heartbeat if is_heartbeat(&heartbeat) => result.push(heartbeat),
+                other => {
+                    result.extend(self.flush(None));
+                    result.push(other);
+                }
+            }
+        }
+
+        let timed_out = self
+            .since
+            .get()
+            .is_some_and(|since| since.elapsed() >= self.timeout);
+        if ended || timed_out {
+            result.extend(self.flush(None));
+        } else {
+            let sentence_end = self
+                .pending
+                .borrow()
+                .as_ref()
+                .and_then(|(text, _)| sentence_end(text));
+            if let Some(sentence_end) = sentence_end {
+                result.extend(self.flush(Some(sentence_end)));
+            }
+        }
+
+        if result.is_empty() && !ended {
+            None
+        } else {
+            Some(result)
+        }
+    }
+
+    fn buffer(&self, delta: StreamDelta) {
+        let text = delta
+            .content
+            .iter()
+            .flatten()
+            .filter_map(|part| match part {
+                ContentPart::Text(text) => Some(text.as_str()),
+                ContentPart::Image(_) => None,
+            })
+            .collect::<String>();
+
+        let mut pending = self.pending.borrow_mut();
+        match pending.as_mut() {
+            Some((buffered, last)) => {
+                buffered.push_str(&text);
+                *last = delta;
+            }
+            None => {
+                *pending = Some((text, delta));
+                self.since.set(Some(Instant::now()));
+            }
+        }
+    }
+
+    /// Emits the buffered text up to `end`, or all of it if `end` is not set
+    fn flush(&self, end: Option<usize>) -> Option<StreamEvent> {
+        let mut pending = self.pending.borrow_mut();
+        let (text, last) = pending.take()?;
+        let end = end.unwrap_or(text.len());
+
+        let rest = &text[end..];
+        if rest.is_empty() {
+            self.since.set(None);
+        } else {
+            *pending = Some((rest.to_string(), last.clone()));
+            self.since.set(Some(Instant::now()));
+        }
+
+        (end > 0).then(|| {
+            StreamEvent::Delta(StreamDelta {
+                content: Some(vec![ContentPart::Text(text[..end].to_string())]),
+                ..last
+            })
+        })
+    }
+}
+
+/// Returns whether a delta only carries text, which can be buffered until a sentence boundary
+fn is_text_delta(delta: &StreamDelta) -> bool {
+    delta
+        .tool_calls
+        .as_ref()
+        .is_none_or(|tool_calls| tool_calls.is_empty())
+        && delta.content.as_ref().is_some_and(|content| {
+            !content.is_empty()
+                && content
+                    .iter()
+                    .all(|part| matches!(part, ContentPart::Text(_)))
+        })
+}
+
+/// Returns the position after the last sentence boundary in `text`: sentence ending punctuation
+/// followed by whitespace, which is included, or a line break
+fn sentence_end(text: &str) -> Option<usize> {
+    let mut end = None;
+    let mut previous = None;
+    for (index, c) in text.char_indices() {
+        let after_punctuation =
+            c.is_whitespace() && matches!(previous, Some('.' | '!' | '?' | '…'));
+        // Full-width punctuation is not followed by whitespace
+        if c == '\n' || after_punctuation || matches!(c, '。' | '！' | '？') {
+            end = Some(index + c.len_utf8());
+        }
+        previous = Some(c);
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat_stream::{
+        attach_raw, attach_raw_enabled, candidate_metadata, collect_events,
+        collect_events_with_logprobs, decode_unknown_event_type, delta_candidate_index,
+        delta_elapsed_ms, emit_heartbeat_enabled, heartbeat_delta, is_heartbeat, logprobs_metadata,
+        sentence_flush_timeout, usage_delta, LlmChatStream, LlmChatStreamState, SentenceBuffer,
+        TokenLogprob, MAX_RAW_PAYLOAD_SIZE,
+    };
+    use crate::event_source::EventSource;
+    use crate::golem::llm::llm::{
+        ChatEvent, ContentPart, Error, ErrorCode, FinishReason, GuestChatStream, ResponseMetadata,
+        StreamDelta, StreamEvent, ToolCall, Usage,
+    };
+    use crate::test_config::config;
+    use crate::transport::CannedStream;
+    use std::cell::{Ref, RefCell, RefMut};
+    use std::time::Duration;
+
+    /// Stream state decoding `finish` as a finish event, `ignored` as nothing, `invalid` as a
+    /// decoding failure, `ping` as a heartbeat and anything else as a text delta
+    #[derive(Default)]
+    struct TestStream {
+        stream: RefCell<Option<EventSource>>,
+        failure: Option<Error>,
+        finished: RefCell<bool>,
+    }
+
+    impl LlmChatStreamState for TestStream {
+        fn failure(&self) -> &Option<Error> {
+            &self.failure
+        }
+
+        fn is_finished(&self) -> bool {
+            *self.finished.borrow()
+        }
+
+        fn set_finished(&self) {
+            *self.finished.borrow_mut() = true;
+        }
+
+        fn stream(&self) -> Ref<Option<EventSource>> {
+            self.stream.borrow()
+        }
+
+        fn stream_mut(&self) -> RefMut<Option<EventSource>> {
+            self.stream.borrow_mut()
+        }
+
+        fn decode_message(&self, raw: &str) -> Result<Option<StreamEvent>, String> {
+            match raw {
+                "finish" => Ok(Some(StreamEvent::Finish(metadata(Some(
+                    FinishReason::Length,
+                ))))),
+                "ignored" => Ok(None),
+                "invalid" => Err("Failed to decode".to_string()),
+                "ping" => Ok(Some(StreamEvent::Delta(heartbeat_delta()))),
+                text => Ok(Some(StreamEvent::Delta(StreamDelta {
+                    content: Some(vec![ContentPart::Text(text.to_string())]),
+                    tool_calls: None,
+                    provider_metadata_json: None,
+                    raw_json: None,
+                }))),
+            }
+        }
+    }
+
+    fn metadata(finish_reason: Option<FinishReason>) -> ResponseMetadata {
+        ResponseMetadata {
+            finish_reason,
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        }
+    }
+
+    fn delta() -> StreamEvent {
+        StreamEvent::Delta(StreamDelta {
+            content: Some(vec![ContentPart::Text("Hi".to_string())]),
+            tool_calls: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        })
+    }
+
+    #[test]
+    fn raw_payload_is_attached_to_delta_and_finish() {
+        let raw = r#"{"type":"delta","extra":"field"}"#;
+
+        match attach_raw(delta(), raw) {
+            StreamEvent::Delta(delta) => assert_eq!(delta.raw_json, Some(raw.to_string())),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+
+        let finish = StreamEvent::Finish(ResponseMetadata {
+            finish_reason: None,
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        });
+        match attach_raw(finish, raw) {
+            StreamEvent::Finish(metadata) => assert_eq!(metadata.raw_json, Some(raw.to_string())),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn raw_payload_is_not_attached_to_errors_or_when_too_large() {
+        let error = StreamEvent::Error(Error {
+            code: ErrorCode::InternalError,
+            message: "failure".to_string(),
+            provider_error_json: None,
+        });
+        assert_eq!(attach_raw(error.clone(), "{}"), error);
+
+        let raw = format!(r#"{{"data":"{}"}}"#, "x".repeat(MAX_RAW_PAYLOAD_SIZE));
+        assert_eq!(attach_raw(delta(), &raw), delta());
+    }
+
+    #[test]
+    fn attach_raw_is_enabled_by_provider_option() {
+        assert!(!attach_raw_enabled(&config(&[])));
+        assert!(attach_raw_enabled(&config(&[("attach_raw", "true")])));
+    }
+
+    #[test]
+    fn unknown_event_type_is_ignored_by_default() {
+        assert_eq!(
+            decode_unknown_event_type("response.new_feature", false),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn unknown_event_type_fails_in_strict_mode() {
+        assert_eq!(
+            decode_unknown_event_type("response.new_feature", true),
+            Err("Unknown stream event type: response.new_feature".to_string())
+        );
+    }
+
+    #[test]
+    fn final_state_can_be_queried_after_draining() {
+        let stream = LlmChatStream::new(TestStream::default());
+        assert!(!stream.is_finished());
+        assert_eq!(stream.last_finish_reason(), None);
+
+        let events = ["Hello", "ignored", "finish", "[DONE]"]
+            .into_iter()
+            .flat_map(|data| stream.process_message(data))
+            .collect::<Vec<_>>();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1],
+            StreamEvent::Finish(metadata(Some(FinishReason::Length)))
+        );
+        assert!(stream.is_finished());
+        assert_eq!(stream.last_finish_reason(), Some(FinishReason::Length));
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    #[test]
+    fn deltas_carry_increasing_timestamps_with_timing() {
+        let stream = LlmChatStream::new(TestStream::default()).with_timing(true);
+
+        let mut elapsed = Vec::new();
+        for data in ["Hello", "world", "finish"] {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            for event in stream.process_message(data) {
+                match event {
+                    StreamEvent::Delta(delta) => elapsed.push(delta_elapsed_ms(&delta).unwrap()),
+                    StreamEvent::Finish(metadata) => {
+                        assert_eq!(metadata.provider_metadata_json, None)
+                    }
+                    other => panic!("Unexpected event: {other:?}"),
+                }
+            }
+        }
+
+        assert_eq!(elapsed.len(), 2);
+        assert!(elapsed[0] >= 2.0);
+        assert!(elapsed[1] >= elapsed[0] + 2.0);
+    }
+
+    #[test]
+    fn deltas_carry_no_timestamps_by_default() {
+        let stream = LlmChatStream::new(TestStream::default());
+
+        match &stream.process_message("Hello")[..] {
+            [StreamEvent::Delta(delta)] => assert_eq!(delta.provider_metadata_json, None),
+            other => panic!("Unexpected events: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decoding_errors_do_not_finish_the_stream() {
+        let stream = LlmChatStream::new(TestStream::default());
+
+        let events = stream.process_message("invalid");
+
+        assert!(matches!(events[..], [StreamEvent::Error(_)]));
+        assert!(!stream.is_finished());
+        assert_eq!(stream.last_finish_reason(), None);
+    }
+
+    #[test]
+    fn dropped_stream_is_finished_with_the_transport_error() {
+        let body = CannedStream::new(["data: Hello\n\n"]).dropping();
+        let stream = LlmChatStream::new(TestStream {
+            stream: RefCell::new(Some(EventSource::from_stream(body))),
+            ..TestStream::default()
+        });
+        assert_eq!(stream.get_next(), Some(vec![text_delta("Hello")]));
+
+        let events = stream.get_next().unwrap();
+
+        assert!(matches!(events[..], [StreamEvent::Error(_)]));
+        assert!(stream.is_finished());
+        assert_eq!(stream.last_finish_reason(), None);
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    #[test]
+    fn failed_stream_is_finished_without_finish_reason() {
+        let stream = LlmChatStream::new(TestStream {
+            failure: Some(Error {
+                code: ErrorCode::AuthenticationFailed,
+                message: "Invalid API key".to_string(),
+                provider_error_json: None,
+            }),
+            ..TestStream::default()
+        });
+
+        let events = stream.get_next().unwrap();
+
+        assert!(matches!(events[..], [StreamEvent::Error(_)]));
+        assert!(stream.is_finished());
+        assert_eq!(stream.last_finish_reason(), None);
+    }
+
+    #[test]
+    fn cancelled_stream_emits_no_more_events() {
+        let stream = LlmChatStream::new(TestStream::default());
+        assert_eq!(stream.process_message("Hello").len(), 1);
+
+        stream.cancel();
+
+        assert!(stream.is_finished());
+        assert_eq!(stream.last_finish_reason(), None);
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    #[test]
+    fn cancel_releases_the_stream() {
+        let body = CannedStream::new(["data: Hello\n\n", "data: World\n\n"]);
+        let released = body.released();
+        let stream = LlmChatStream::new(TestStream {
+            stream: RefCell::new(Some(EventSource::from_stream(body))),
+            ..TestStream::default()
+        });
+        assert_eq!(stream.get_next(), Some(vec![text_delta("Hello")]));
+
+        stream.cancel();
+
+        assert!(released.get());
+        assert_eq!(stream.get_next(), Some(vec![]));
+    }
+
+    #[test]
+    fn drop_releases_the_stream() {
+        let body = CannedStream::new(["data: Hello\n\n", "data: World\n\n"]);
+        let released = body.released();
+        let stream = LlmChatStream::new(TestStream {
+            stream: RefCell::new(Some(EventSource::from_stream(body))),
+            ..TestStream::default()
+        });
+        assert_eq!(stream.get_next(), Some(vec![text_delta("Hello")]));
+
+        drop(stream);
+
+        assert!(released.get());
+    }
+
+    fn text_delta(text: &str) -> StreamEvent {
+        StreamEvent::Delta(StreamDelta {
+            content: Some(vec![ContentPart::Text(text.to_string())]),
+            tool_calls: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        })
+    }
+
+    fn usage(output_tokens: u32) -> Usage {
+        Usage {
+            input_tokens: Some(10),
+            output_tokens: Some(output_tokens),
+            total_tokens: Some(10 + output_tokens),
+            reasoning_tokens: None,
+            cached_tokens: None,
+            audio_tokens: None,
+        }
+    }
+
+    #[test]
+    fn collected_response_matches_streamed_deltas() {
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+        };
+        let events = vec![
+            text_delta("Let me "),
+            text_delta("check "),
+            StreamEvent::Delta(usage_delta(usage(3))),
+            text_delta("the weather"),
+            StreamEvent::Delta(StreamDelta {
+                content: None,
+                tool_calls: Some(vec![tool_call.clone()]),
+                provider_metadata_json: None,
+                raw_json: None,
+            }),
+            StreamEvent::Finish(ResponseMetadata {
+                provider_id: Some("msg_1".to_string()),
+                ..metadata(Some(FinishReason::ToolCalls))
+            }),
+        ];
+
+        match collect_events(events) {
+            ChatEvent::Message(response) => {
+                assert_eq!(response.id, "msg_1");
+                assert_eq!(
+                    response.content,
+                    vec![ContentPart::Text("Let me check the weather".to_string())]
+                );
+                assert_eq!(response.tool_calls, vec![tool_call]);
+                assert_eq!(
+                    response.metadata.finish_reason,
+                    Some(FinishReason::ToolCalls)
+                );
+                assert_eq!(response.metadata.usage, Some(usage(3)));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn collected_tool_calls_without_text_are_a_tool_request() {
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: "{}".to_string(),
+        };
+        let events = vec![
+            StreamEvent::Delta(StreamDelta {
+                content: None,
+                tool_calls: Some(vec![tool_call.clone()]),
+                provider_metadata_json: None,
+                raw_json: None,
+            }),
+            StreamEvent::Finish(metadata(Some(FinishReason::ToolCalls))),
+        ];
+
+        assert_eq!(
+            collect_events(events),
+            ChatEvent::ToolRequest(vec![tool_call])
+        );
+    }
+
+    #[test]
+    fn collecting_fails_on_error_or_missing_finish() {
+        let error = Error {
+            code: ErrorCode::RateLimitExceeded,
+            message: "Slow down".to_string(),
+            provider_error_json: None,
+        };
+
+        assert_eq!(
+            collect_events(vec![text_delta("Hi"), StreamEvent::Error(error.clone())]),
+            ChatEvent::Error(error)
+        );
+        assert!(matches!(
+            collect_events(vec![text_delta("Hi")]),
+            ChatEvent::Error(Error {
+                code: ErrorCode::InternalError,
+                ..
+            })
+        ));
+    }
+
+    fn logprob_delta(tokens: &[(&str, f64)]) -> StreamEvent {
+        StreamEvent::Delta(StreamDelta {
+            content: Some(vec![ContentPart::Text(
+                tokens.iter().map(|(token, _)| *token).collect(),
+            )]),
+            tool_calls: None,
+            provider_metadata_json: Some(logprobs_metadata(
+                tokens
+                    .iter()
+                    .map(|(token, logprob)| TokenLogprob {
+                        token: token.to_string(),
+                        logprob: *logprob,
+                    })
+                    .collect(),
+            )),
+            raw_json: None,
+        })
+    }
+
+    #[test]
+    fn collected_logprobs_expose_token_confidence() {
+        let events = vec![
+            logprob_delta(&[("The", -0.01), (" capital", -0.05)]),
+            text_delta(" is"),
+            logprob_delta(&[(" Canberra", -2.3)]),
+            StreamEvent::Finish(metadata(Some(FinishReason::Stop))),
+        ];
+
+        let collected = collect_events_with_logprobs(events);
+
+        match &collected.event {
+            ChatEvent::Message(response) => assert_eq!(
+                response.content,
+                vec![ContentPart::Text("The capital is Canberra".to_string())]
+            ),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        let tokens: Vec<&str> = collected
+            .logprobs
+            .iter()
+            .map(|logprob| logprob.token.as_str())
+            .collect();
+        assert_eq!(tokens, vec!["The", " capital", " Canberra"]);
+        let low_confidence: Vec<&str> = collected
+            .logprobs
+            .iter()
+            .filter(|logprob| logprob.confidence() < 0.5)
+            .map(|logprob| logprob.token.as_str())
+            .collect();
+        assert_eq!(low_confidence, vec![" Canberra"]);
+        assert!((collected.logprobs[0].confidence() - 0.99).abs() < 0.001);
+    }
+
+    #[test]
+    fn stream_without_logprobs_collects_none() {
+        let collected = collect_events_with_logprobs(vec![
+            text_delta("Hi"),
+            StreamEvent::Finish(metadata(Some(FinishReason::Stop))),
+        ]);
+
+        assert!(matches!(collected.event, ChatEvent::Message(_)));
+        assert_eq!(collected.logprobs, vec![]);
+    }
+
+    fn candidate_delta(candidate_index: u32, text: &str) -> StreamEvent {
+        StreamEvent::Delta(StreamDelta {
+            content: Some(vec![ContentPart::Text(text.to_string())]),
+            tool_calls: None,
+            provider_metadata_json: candidate_metadata(candidate_index, vec![]),
+            raw_json: None,
+        })
+    }
+
+    #[test]
+    fn only_the_first_candidate_is_collected() {
+        let events = vec![
+            candidate_delta(0, "Hello"),
+            candidate_delta(1, "Hi"),
+            candidate_delta(1, " there"),
+            candidate_delta(0, " world"),
+            StreamEvent::Finish(metadata(Some(FinishReason::Stop))),
+        ];
+
+        match &events[2] {
+            StreamEvent::Delta(delta) => assert_eq!(delta_candidate_index(delta), 1),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        match &events[0] {
+            StreamEvent::Delta(delta) => {
+                assert_eq!(delta.provider_metadata_json, None);
+                assert_eq!(delta_candidate_index(delta), 0);
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        match collect_events(events) {
+            ChatEvent::Message(response) => assert_eq!(
+                response.content,
+                vec![ContentPart::Text("Hello world".to_string())]
+            ),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn text_is_flushed_at_sentence_boundaries() {
+        let buffer = SentenceBuffer::new(Duration::from_secs(60));
+
+        assert_eq!(buffer.process(Some(vec![text_delta("Hello")])), None);
+        assert_eq!(
+            buffer.process(Some(vec![text_delta(" world. How"), text_delta(" are")])),
+            Some(vec![text_delta("Hello world. ")])
+        );
+        assert_eq!(buffer.process(None), None);
+        assert_eq!(
+            buffer.process(Some(vec![text_delta(" you? Pi is 3.14")])),
+            Some(vec![text_delta("How are you? ")])
+        );
+
+        let finish = StreamEvent::Finish(ResponseMetadata {
+            finish_reason: Some(FinishReason::Stop),
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        });
+        assert_eq!(
+            buffer.process(Some(vec![finish.clone()])),
+            Some(vec![text_delta("Pi is 3.14"), finish])
+        );
+    }
+
+    #[test]
+    fn tool_calls_flush_the_buffered_text() {
+        let buffer = SentenceBuffer::new(Duration::from_secs(60));
+        let tool_call = StreamEvent::Delta(StreamDelta {
+            content: None,
+            tool_calls: Some(vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "get_weather".to_string(),
+                arguments_json: "{}".to_string(),
+            }]),
+            provider_metadata_json: None,
+            raw_json: None,
+        });
+
+        assert_eq!(
+            buffer.process(Some(vec![text_delta("Let me check"), tool_call.clone()])),
+            Some(vec![text_delta("Let me check"), tool_call])
+        );
+    }
+
+    #[test]
+    fn buffered_text_is_flushed_after_timeout() {
+        let buffer = SentenceBuffer::new(Duration::ZERO);
+
+        assert_eq!(
+            buffer.process(Some(vec![text_delta("No sentence end")])),
+            Some(vec![text_delta("No sentence end")])
+        );
+    }
+
+    #[test]
+    fn remaining_time_is_known_while_text_is_buffered() {
+        let timeout = Duration::from_secs(60);
+        let buffer = SentenceBuffer::new(timeout);
+        assert_eq!(buffer.remaining(), None);
+
+        buffer.process(Some(vec![text_delta("Hello")]));
+        assert!(buffer
+            .remaining()
+            .is_some_and(|remaining| remaining <= timeout));
+
+        buffer.process(Some(vec![text_delta(" world.")]));
+        assert_eq!(buffer.remaining(), None);
+    }
+
+    #[test]
+    fn sentence_flush_is_enabled_by_provider_option() {
+        assert_eq!(sentence_flush_timeout(&config(&[])), None);
+        assert_eq!(
+            sentence_flush_timeout(&config(&[("flush_on_sentence", "true")])),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            sentence_flush_timeout(&config(&[
+                ("flush_on_sentence", "true"),
+                ("flush_on_sentence_timeout_ms", "250")
+            ])),
+            Some(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn heartbeats_are_emitted_for_ping_frames_when_enabled() {
+        let stream = LlmChatStream::new(TestStream::default()).with_heartbeat(true);
+
+        let events = ["ping", "Hello", "ping", "finish"]
+            .into_iter()
+            .flat_map(|data| stream.process_message(data))
+            .collect::<Vec<_>>();
+
+        assert_eq!(events.len(), 4);
+        assert!(is_heartbeat(&events[0]));
+        assert!(!is_heartbeat(&events[1]));
+        assert!(is_heartbeat(&events[2]));
+        match collect_events(events) {
+            ChatEvent::Message(response) => {
+                assert_eq!(
+                    response.content,
+                    vec![ContentPart::Text("Hello".to_string())]
+                )
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn heartbeats_are_dropped_by_default() {
+        let stream = LlmChatStream::new(TestStream::default());
+
+        let events = ["ping", "Hello", "ping"]
+            .into_iter()
+            .flat_map(|data| stream.process_message(data))
+            .collect::<Vec<_>>();
+
+        assert_eq!(events, vec![text_delta("Hello")]);
+    }
+
+    #[test]
+    fn heartbeats_do_not_flush_the_buffered_text() {
+        let buffer = SentenceBuffer::new(Duration::from_secs(60));
+        let heartbeat = StreamEvent::Delta(heartbeat_delta());
+
+        let events = buffer.process(Some(vec![text_delta("Hello"), heartbeat.clone()]));
+        assert_eq!(events, Some(vec![heartbeat]));
+
+        let events = buffer.process(Some(vec![text_delta(" world. ")]));
+        assert_eq!(events, Some(vec![text_delta("Hello world. ")]));
+    }
+
+    #[test]
+    fn emit_heartbeat_is_enabled_by_provider_option() {
+        assert!(!emit_heartbeat_enabled(&config(&[])));
+        assert!(emit_heartbeat_enabled(&config(&[(
+            "emit_heartbeat",
+            "true"
+        )])));
+    }
 }
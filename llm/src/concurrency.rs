@@ -0,0 +1,360 @@
+use crate::config::{bool_option, option};
+use crate::durability::ExtendedGuest;
+use crate::golem::llm::llm::{
+    Config, Error, ErrorCode, FinishReason, GuestChatStream, Message, StreamEvent,
+};
+use crate::trace_id::{trace_id, with_trace_id};
+use golem_rust::wasm_rpc::Pollable;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Limit of simultaneous in-flight requests, enabled by the `max_concurrency` provider option.
+///
+/// Streams over the limit wait for a free slot, or with `max_concurrency_strict=true` fail
+/// immediately. Blocking calls are always strict, see [`ConcurrencyGate::acquire`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConcurrencyLimit {
+    pub max_concurrency: usize,
+    pub strict: bool,
+}
+
+impl ConcurrencyLimit {
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Error> {
+        let Some(max_concurrency) = option(config, "max_concurrency") else {
+            return Ok(None);
+        };
+        let max_concurrency = match max_concurrency.parse::<usize>() {
+            Ok(max_concurrency) if max_concurrency > 0 => max_concurrency,
+            _ => {
+                return Err(Error {
+                    code: ErrorCode::InvalidRequest,
+                    message: format!(
+                        "max_concurrency must be a positive integer, got {max_concurrency}"
+                    ),
+                    provider_error_json: None,
+                })
+            }
+        };
+
+        Ok(Some(Self {
+            max_concurrency,
+            strict: bool_option(config, "max_concurrency_strict"),
+        }))
+    }
+
+    /// The error of a request over the limit. This is raised by the worker itself rather than
+    /// the provider, so it is an `invalid-request`, which is neither retried nor failed over.
+    fn exceeded(&self) -> Error {
+        Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "The worker already has {} requests in flight, the limit set by the max_concurrency provider option",
+                self.max_concurrency
+            ),
+            provider_error_json: None,
+        }
+    }
+}
+
+/// A counting semaphore of the in-flight requests
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyGate {
+    in_flight: Rc<Cell<usize>>,
+}
+
+impl ConcurrencyGate {
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.get()
+    }
+
+    /// Takes a slot if less than `max_concurrency` requests are in flight. The slot is released
+    /// when the returned permit is dropped.
+    pub fn try_acquire(&self, max_concurrency: usize) -> Option<Permit> {
+        if self.in_flight.get() < max_concurrency {
+            self.in_flight.set(self.in_flight.get() + 1);
+            Some(Permit {
+                in_flight: self.in_flight.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Takes a slot for a blocking call.
+    ///
+    /// Blocking calls ignore `max_concurrency_strict` and always fail if the limit is reached:
+    /// the slots are only released by the worker's streams, and none of them makes progress
+    /// while the worker blocks, so waiting would never end.
+    pub fn acquire(&self, limit: Option<ConcurrencyLimit>) -> Result<Option<Permit>, Error> {
+        match limit {
+            Some(limit) => self
+                .try_acquire(limit.max_concurrency)
+                .map(Some)
+                .ok_or_else(|| limit.exceeded()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A slot of a [`ConcurrencyGate`], held while the request is in flight
+#[derive(Debug)]
+pub struct Permit {
+    in_flight: Rc<Cell<usize>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.in_flight.set(self.in_flight.get() - 1);
+    }
+}
+
+thread_local! {
+    /// The in-flight requests of the worker. Each provider is a separate component, so this is
+    /// tracked per provider.
+    static GATE: ConcurrencyGate = ConcurrencyGate::default();
+}
+
+/// Gets the worker's [`ConcurrencyGate`]
+pub fn gate() -> ConcurrencyGate {
+    GATE.with(|gate| gate.clone())
+}
+
+/// Takes a slot of the worker's gate for a blocking call, if `config` limits the concurrency
+pub fn acquire_permit(config: &Config) -> Result<Option<Permit>, Error> {
+    ConcurrencyLimit::from_config(config).and_then(|limit| gate().acquire(limit))
+}
+
+/// Sends the streaming request, logging with the request's trace id
+fn open_stream<Impl: ExtendedGuest>(messages: Vec<Message>, config: Config) -> Impl::ChatStream {
+    with_trace_id(trace_id(&config), || {
+        Impl::unwrapped_stream(messages, config)
+    })
+}
+
+/// How often a queued stream checks for a free slot
+const QUEUED_POLL_INTERVAL_NS: u64 = 50_000_000;
+
+/// A chat stream holding a slot of the worker's gate until it finishes.
+///
+/// If all slots are taken when it is created, the request is only sent by the first
+/// `get-next` call finding a free slot. Until then the stream is pending, and its pollable
+/// periodically wakes up to check again.
+pub struct GatedChatStream<Impl: ExtendedGuest> {
+    state: RefCell<GatedStreamState<Impl>>,
+}
+
+enum GatedStreamState<Impl: ExtendedGuest> {
+    Queued {
+        messages: Vec<Message>,
+        config: Config,
+        max_concurrency: usize,
+    },
+    Open {
+        stream: Impl::ChatStream,
+        permit: Option<Permit>,
+    },
+    Closed {
+        error: Option<Error>,
+    },
+}
+
+impl<Impl: ExtendedGuest> GatedChatStream<Impl> {
+    pub fn new(messages: Vec<Message>, config: Config) -> Self {
+        let limit = match ConcurrencyLimit::from_config(&config) {
+            Ok(Some(limit)) => limit,
+            Ok(None) => return Self::ungated(open_stream::<Impl>(messages, config)),
+            Err(error) => return Self::failed(error),
+        };
+
+        let state = match gate().try_acquire(limit.max_concurrency) {
+            Some(permit) => GatedStreamState::Open {
+                stream: open_stream::<Impl>(messages, config),
+                permit: Some(permit),
+            },
+            None if limit.strict => GatedStreamState::Closed {
+                error: Some(limit.exceeded()),
+            },
+            None => GatedStreamState::Queued {
+                messages,
+                config,
+                max_concurrency: limit.max_concurrency,
+            },
+        };
+        Self {
+            state: RefCell::new(state),
+        }
+    }
+
+    /// Wraps an already opened stream without taking a slot
+    pub fn ungated(stream: Impl::ChatStream) -> Self {
+        Self {
+            state: RefCell::new(GatedStreamState::Open {
+                stream,
+                permit: None,
+            }),
+        }
+    }
+
+    fn failed(error: Error) -> Self {
+        Self {
+            state: RefCell::new(GatedStreamState::Closed { error: Some(error) }),
+        }
+    }
+
+    pub fn subscribe(&self) -> Pollable {
+        match &*self.state.borrow() {
+            GatedStreamState::Queued { .. } => {
+                golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(
+                    QUEUED_POLL_INTERVAL_NS,
+                )
+            }
+            GatedStreamState::Open { stream, .. } => Impl::subscribe(stream),
+            GatedStreamState::Closed { .. } => {
+                golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(0)
+            }
+        }
+    }
+
+    /// Sends the queued request if a slot became free
+    fn try_open(&self) {
+        let mut state = self.state.borrow_mut();
+        if let GatedStreamState::Queued {
+            max_concurrency, ..
+        } = &*state
+        {
+            if let Some(permit) = gate().try_acquire(*max_concurrency) {
+                if let GatedStreamState::Queued {
+                    messages, config, ..
+                } = std::mem::replace(&mut *state, GatedStreamState::Closed { error: None })
+                {
+                    *state = GatedStreamState::Open {
+                        stream: open_stream::<Impl>(messages, config),
+                        permit: Some(permit),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl<Impl: ExtendedGuest> GuestChatStream for GatedChatStream<Impl> {
+    fn get_next(&self) -> Option<Vec<StreamEvent>> {
+        self.try_open();
+
+        match &mut *self.state.borrow_mut() {
+            GatedStreamState::Queued { .. } => None,
+            GatedStreamState::Open { stream, permit } => {
+                let result = stream.get_next();
+                if stream.is_finished() {
+                    permit.take();
+                }
+                result
+            }
+            GatedStreamState::Closed { error } => {
+                Some(error.take().map(StreamEvent::Error).into_iter().collect())
+            }
+        }
+    }
+
+    fn blocking_get_next(&self) -> Vec<StreamEvent> {
+        loop {
+            self.subscribe().block();
+            if let Some(events) = self.get_next() {
+                break events;
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        match &*self.state.borrow() {
+            GatedStreamState::Queued { .. } => false,
+            GatedStreamState::Open { stream, .. } => stream.is_finished(),
+            GatedStreamState::Closed { error } => error.is_none(),
+        }
+    }
+
+    fn last_finish_reason(&self) -> Option<FinishReason> {
+        match &*self.state.borrow() {
+            GatedStreamState::Open { stream, .. } => stream.last_finish_reason(),
+            GatedStreamState::Queued { .. } | GatedStreamState::Closed { .. } => None,
+        }
+    }
+
+    fn cancel(&self) {
+        let mut state = self.state.borrow_mut();
+        match &mut *state {
+            GatedStreamState::Open { stream, permit } => {
+                stream.cancel();
+                permit.take();
+            }
+            GatedStreamState::Queued { .. } => {
+                *state = GatedStreamState::Closed { error: None };
+            }
+            GatedStreamState::Closed { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::{ConcurrencyGate, ConcurrencyLimit};
+    use crate::golem::llm::llm::ErrorCode;
+    use crate::test_config::config;
+
+    #[test]
+    fn gate_serializes_beyond_the_limit() {
+        let gate = ConcurrencyGate::default();
+
+        let first = gate.try_acquire(2).unwrap();
+        let second = gate.try_acquire(2).unwrap();
+        assert!(gate.try_acquire(2).is_none());
+        assert_eq!(gate.in_flight(), 2);
+
+        drop(first);
+        let third = gate.try_acquire(2).unwrap();
+        assert!(gate.try_acquire(2).is_none());
+
+        drop(second);
+        drop(third);
+        assert_eq!(gate.in_flight(), 0);
+    }
+
+    #[test]
+    fn blocking_calls_fail_when_the_limit_is_reached() {
+        let gate = ConcurrencyGate::default();
+        let limit = ConcurrencyLimit::from_config(&config(&[("max_concurrency", "1")])).unwrap();
+
+        let permit = gate.acquire(limit).unwrap();
+        assert!(permit.is_some());
+
+        let error = gate.acquire(limit).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(!crate::error::is_retriable(&error));
+
+        drop(permit);
+        assert!(gate.acquire(limit).unwrap().is_some());
+        assert_eq!(gate.acquire(None).unwrap().map(|_| ()), None);
+    }
+
+    #[test]
+    fn limit_is_parsed_from_provider_options() {
+        assert_eq!(ConcurrencyLimit::from_config(&config(&[])).unwrap(), None);
+        assert_eq!(
+            ConcurrencyLimit::from_config(&config(&[
+                ("max_concurrency", "3"),
+                ("max_concurrency_strict", "true")
+            ]))
+            .unwrap(),
+            Some(ConcurrencyLimit {
+                max_concurrency: 3,
+                strict: true
+            })
+        );
+
+        for invalid in ["0", "many"] {
+            let error = ConcurrencyLimit::from_config(&config(&[("max_concurrency", invalid)]))
+                .unwrap_err();
+            assert_eq!(error.code, ErrorCode::InvalidRequest);
+        }
+    }
+}
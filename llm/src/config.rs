@@ -1,4 +1,5 @@
-use crate::golem::llm::llm::{Error, ErrorCode};
+use crate::golem::llm::llm::{Config, Error, ErrorCode, Kv};
+use std::collections::HashMap;
 use std::ffi::OsStr;
 
 /// Gets an expected configuration value from the environment, and fails if its is not found
@@ -8,16 +9,517 @@ pub fn with_config_key<R>(
     fail: impl FnOnce(Error) -> R,
     succeed: impl FnOnce(String) -> R,
 ) -> R {
-    let key_str = key.as_ref().to_string_lossy().to_string();
-    match std::env::var(key) {
+    match get_config_key(key) {
         Ok(value) => succeed(value),
-        Err(_) => {
-            let error = Error {
+        Err(error) => fail(error),
+    }
+}
+
+/// Gets a configuration value from the `key` environment variable. If it is not set, the value is
+/// read from the file the `{key}_FILE` environment variable points to, as commonly used for
+/// Docker and Kubernetes secrets.
+pub fn get_config_key(key: impl AsRef<OsStr>) -> Result<String, Error> {
+    let key = key.as_ref();
+    let key_str = key.to_string_lossy().to_string();
+    if let Ok(value) = std::env::var(key) {
+        return Ok(value);
+    }
+
+    match std::env::var(format!("{key_str}_FILE")) {
+        Ok(path) => std::fs::read_to_string(&path)
+            .map(|contents| contents.trim().to_string())
+            .map_err(|err| Error {
                 code: ErrorCode::InternalError,
-                message: format!("Missing config key: {key_str}"),
+                message: format!("Failed to read config key {key_str} from {path}: {err}"),
+                provider_error_json: None,
+            }),
+        Err(_) => Err(Error {
+            code: ErrorCode::InternalError,
+            message: format!("Missing config key: {key_str}"),
+            provider_error_json: None,
+        }),
+    }
+}
+
+/// Per-call overrides of a base [`Config`], returning a modified copy and leaving the original
+/// untouched
+pub trait ConfigExt {
+    fn with_model(&self, model: impl Into<String>) -> Config;
+
+    fn with_temperature(&self, temperature: f32) -> Config;
+}
+
+impl ConfigExt for Config {
+    fn with_model(&self, model: impl Into<String>) -> Config {
+        Config {
+            model: model.into(),
+            ..self.clone()
+        }
+    }
+
+    fn with_temperature(&self, temperature: f32) -> Config {
+        Config {
+            temperature: Some(temperature),
+            ..self.clone()
+        }
+    }
+}
+
+/// Gets the value of the `key` provider option, if it is set
+pub fn option<'a>(config: &'a Config, key: &str) -> Option<&'a str> {
+    config
+        .provider_options
+        .iter()
+        .find(|kv| kv.key == key)
+        .map(|kv| kv.value.as_str())
+}
+
+/// Returns whether the `key` provider option is set to `true`, enabling an opt-in feature
+pub fn bool_option(config: &Config, key: &str) -> bool {
+    option(config, key) == Some("true")
+}
+
+/// Gets the names of the provider built-in tools (such as `web_search` or `code_interpreter`)
+/// enabled by the comma separated `builtin_tools` provider option.
+///
+/// Built-in tools are executed by the provider itself, so unlike the tools in [`Config::tools`]
+/// they never result in tool call requests.
+pub fn builtin_tools(config: &Config) -> Vec<String> {
+    config
+        .provider_options
+        .iter()
+        .filter(|kv| kv.key == "builtin_tools")
+        .flat_map(|kv| kv.value.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// A format the output of the model is constrained to by the backend's constrained decoding
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputConstraint {
+    /// A grammar in the GBNF format of llama.cpp
+    Grammar(String),
+    Regex(String),
+}
+
+/// Gets the output constraint set with the `grammar` or `regex` provider option, guaranteeing
+/// that responses match a strict format so they can be parsed reliably. Only backends with
+/// constrained decoding support it, so the other providers reject it as unsupported.
+pub fn output_constraint(config: &Config) -> Result<Option<OutputConstraint>, Error> {
+    match (option(config, "grammar"), option(config, "regex")) {
+        (Some(_), Some(_)) => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: "Only one of the grammar and regex options can be set".to_string(),
+            provider_error_json: None,
+        }),
+        (Some(grammar), None) => Ok(Some(OutputConstraint::Grammar(grammar.to_string()))),
+        (None, Some(regex)) => Ok(Some(OutputConstraint::Regex(regex.to_string()))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Gets the string key-value tags of the `metadata` provider option, given as a JSON object, to be
+/// attached to the request for analytics and filtering in the provider's dashboard.
+pub fn request_metadata(config: &Config) -> Result<Option<HashMap<String, String>>, Error> {
+    option(config, "metadata")
+        .map(|metadata| {
+            serde_json::from_str(metadata).map_err(|err| Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!("metadata must be a JSON object of strings: {err}"),
                 provider_error_json: None,
-            };
-            fail(error)
+            })
+        })
+        .transpose()
+}
+
+/// Gets the maximum number of tokens to generate. `None` leaves the limit to the provider's
+/// default and omits it from the request, while zero is rejected as providers interpret it
+/// inconsistently.
+pub fn max_tokens(config: &Config) -> Result<Option<u32>, Error> {
+    match config.max_tokens {
+        Some(0) => Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: "max_tokens must be positive, leave it unset to use the provider's default"
+                .to_string(),
+            provider_error_json: None,
+        }),
+        max_tokens => Ok(max_tokens),
+    }
+}
+
+/// Logical model names resolved by `model_alias=builtin`, as (alias, provider, model) triples
+const BUILTIN_MODEL_ALIASES: &[(&str, &str, &str)] = &[
+    ("fast", "openai", "gpt-4o-mini"),
+    ("smart", "openai", "gpt-4o"),
+    ("fast", "anthropic", "claude-3-5-haiku-latest"),
+    ("smart", "anthropic", "claude-sonnet-4-0"),
+    ("fast", "grok", "grok-3-mini"),
+    ("smart", "grok", "grok-3"),
+    ("fast", "openrouter", "openai/gpt-4o-mini"),
+    ("smart", "openrouter", "anthropic/claude-sonnet-4"),
+];
+
+/// Gets the model id to send to `provider`, resolving logical model names such as `fast` or
+/// `smart` when aliasing is enabled with the `model_alias` provider option.
+///
+/// The option is either `builtin`, selecting a small built-in table, or a JSON object mapping
+/// each alias to the model id to use per provider, for example
+/// `{"fast": {"openai": "gpt-4o-mini", "anthropic": "claude-3-5-haiku-latest"}}`. Models without
+/// an alias for the given provider are used as they are.
+pub fn resolve_model(config: &Config, provider: &str) -> Result<String, Error> {
+    let Some(aliases) = option(config, "model_alias") else {
+        return Ok(config.model.clone());
+    };
+
+    let resolved = if aliases == "builtin" {
+        BUILTIN_MODEL_ALIASES
+            .iter()
+            .find(|(alias, alias_provider, _)| {
+                *alias == config.model && *alias_provider == provider
+            })
+            .map(|(_, _, model)| model.to_string())
+    } else {
+        let mut aliases: HashMap<String, HashMap<String, String>> = serde_json::from_str(aliases)
+            .map_err(|err| Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Failed to parse model_alias: {err}"),
+            provider_error_json: None,
+        })?;
+        aliases
+            .remove(&config.model)
+            .and_then(|mut models| models.remove(provider))
+    };
+
+    Ok(resolved.unwrap_or_else(|| config.model.clone()))
+}
+
+/// Sampling presets selected with the `preset` provider option, as (preset, temperature, top_p)
+/// triples
+const SAMPLING_PRESETS: &[(&str, f32, Option<f32>)] = &[
+    ("deterministic", 0.0, None),
+    ("balanced", 0.7, Some(0.9)),
+    ("creative", 1.0, Some(0.95)),
+];
+
+/// Expands the sampling preset selected with the `preset` provider option (`deterministic`,
+/// `balanced` or `creative`) into the `temperature` and the `top_p` provider option of the config
+/// sent to `provider`.
+///
+/// Explicitly set values take precedence over the preset. Anthropic recommends tuning only one of
+/// temperature and `top_p`, so its presets only set the temperature.
+pub fn apply_sampling_preset(config: Config, provider: &str) -> Result<Config, Error> {
+    let Some(preset) = option(&config, "preset") else {
+        return Ok(config);
+    };
+    let Some((_, temperature, top_p)) =
+        SAMPLING_PRESETS.iter().find(|(name, _, _)| *name == preset)
+    else {
+        return Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("preset must be deterministic, balanced or creative, got {preset}"),
+            provider_error_json: None,
+        });
+    };
+
+    let mut provider_options = config.provider_options.clone();
+    if let Some(top_p) = top_p.filter(|_| provider != "anthropic") {
+        if option(&config, "top_p").is_none() {
+            provider_options.push(Kv {
+                key: "top_p".to_string(),
+                value: top_p.to_string(),
+            });
+        }
+    }
+    Ok(Config {
+        temperature: config.temperature.or(Some(*temperature)),
+        provider_options,
+        ..config
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        apply_sampling_preset, bool_option, builtin_tools, get_config_key, max_tokens, option,
+        output_constraint, request_metadata, resolve_model, ConfigExt, OutputConstraint,
+    };
+    use crate::golem::llm::llm::{Config, ErrorCode};
+    use crate::test_config::config;
+
+    fn base_config() -> Config {
+        Config {
+            model: "gpt-4o".to_string(),
+            temperature: Some(0.2),
+            max_tokens: Some(100),
+            stop_sequences: Some(vec!["STOP".to_string()]),
+            tool_choice: Some("auto".to_string()),
+            ..config(&[("user", "alice")])
         }
     }
+
+    #[test]
+    fn model_override_preserves_other_fields() {
+        let base = base_config();
+
+        let overridden = base.with_model("gpt-4o-mini");
+
+        assert_eq!(overridden.model, "gpt-4o-mini");
+        assert_eq!(
+            Config {
+                model: base.model.clone(),
+                ..overridden
+            },
+            base
+        );
+        assert_eq!(base.model, "gpt-4o");
+    }
+
+    #[test]
+    fn temperature_override_preserves_other_fields() {
+        let base = base_config();
+
+        let overridden = base.with_model("gpt-4o-mini").with_temperature(0.9);
+
+        assert_eq!(overridden.temperature, Some(0.9));
+        assert_eq!(overridden.model, "gpt-4o-mini");
+        assert_eq!(overridden.max_tokens, base.max_tokens);
+        assert_eq!(overridden.stop_sequences, base.stop_sequences);
+        assert_eq!(overridden.tool_choice, base.tool_choice);
+        assert_eq!(overridden.provider_options, base.provider_options);
+        assert_eq!(base.temperature, Some(0.2));
+    }
+
+    #[test]
+    fn options_are_looked_up_by_key() {
+        let config = config(&[("attach_raw", "true"), ("timing", "yes")]);
+
+        assert_eq!(option(&config, "timing"), Some("yes"));
+        assert_eq!(option(&config, "missing"), None);
+        assert!(bool_option(&config, "attach_raw"));
+        assert!(!bool_option(&config, "timing"));
+        assert!(!bool_option(&config, "missing"));
+    }
+
+    #[test]
+    fn builtin_tools_are_parsed_from_provider_options() {
+        let config = config(&[("builtin_tools", "web_search, code_interpreter,")]);
+
+        assert_eq!(
+            builtin_tools(&config),
+            vec!["web_search".to_string(), "code_interpreter".to_string()]
+        );
+    }
+
+    #[test]
+    fn no_builtin_tools_by_default() {
+        assert!(builtin_tools(&config(&[])).is_empty());
+    }
+
+    #[test]
+    fn grammar_and_regex_are_exclusive() {
+        assert_eq!(
+            output_constraint(&config(&[("regex", "[0-9]+")])).unwrap(),
+            Some(OutputConstraint::Regex("[0-9]+".to_string()))
+        );
+        let error = output_constraint(&config(&[
+            ("grammar", r#"root ::= "yes" | "no""#),
+            ("regex", "[0-9]+"),
+        ]))
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    fn aliased(model: &str, model_alias: &str) -> Config {
+        Config {
+            model: model.to_string(),
+            ..config(&[("model_alias", model_alias)])
+        }
+    }
+
+    const ALIASES: &str =
+        r#"{"fast": {"openai": "gpt-4.1-nano", "anthropic": "claude-3-haiku-20240307"}}"#;
+
+    #[test]
+    fn alias_is_resolved_per_provider() {
+        let config = aliased("fast", ALIASES);
+
+        assert_eq!(resolve_model(&config, "openai").unwrap(), "gpt-4.1-nano");
+        assert_eq!(
+            resolve_model(&config, "anthropic").unwrap(),
+            "claude-3-haiku-20240307"
+        );
+    }
+
+    #[test]
+    fn builtin_aliases_are_resolved() {
+        let config = aliased("smart", "builtin");
+
+        assert_eq!(resolve_model(&config, "openai").unwrap(), "gpt-4o");
+        assert_eq!(resolve_model(&config, "grok").unwrap(), "grok-3");
+    }
+
+    #[test]
+    fn models_without_alias_are_unchanged() {
+        assert_eq!(
+            resolve_model(&aliased("fast", ALIASES), "grok").unwrap(),
+            "fast"
+        );
+        assert_eq!(
+            resolve_model(&aliased("gpt-4o", ALIASES), "openai").unwrap(),
+            "gpt-4o"
+        );
+    }
+
+    #[test]
+    fn aliasing_is_off_by_default() {
+        let config = Config {
+            model: "fast".to_string(),
+            ..config(&[])
+        };
+
+        assert_eq!(resolve_model(&config, "openai").unwrap(), "fast");
+    }
+
+    #[test]
+    fn invalid_alias_map_is_rejected() {
+        let error = resolve_model(&aliased("fast", "{not json"), "openai").unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn zero_max_tokens_is_rejected() {
+        let mut config = config(&[]);
+        assert_eq!(max_tokens(&config), Ok(None));
+
+        config.max_tokens = Some(100);
+        assert_eq!(max_tokens(&config), Ok(Some(100)));
+
+        config.max_tokens = Some(0);
+        assert_eq!(
+            max_tokens(&config).unwrap_err().code,
+            ErrorCode::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn request_metadata_is_parsed() {
+        let tagged = config(&[("metadata", r#"{"tenant": "acme", "feature": "chat"}"#)]);
+
+        let metadata = request_metadata(&tagged).unwrap().unwrap();
+
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["tenant"], "acme");
+        assert_eq!(request_metadata(&config(&[])).unwrap(), None);
+    }
+
+    #[test]
+    fn non_string_request_metadata_is_rejected() {
+        let config = config(&[("metadata", r#"{"attempt": 1}"#)]);
+
+        let error = request_metadata(&config).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    /// Writes a secret file for the given test, returning its path
+    fn secret_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("golem-llm-{name}"));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn config_key_is_read_from_file() {
+        let path = secret_file("file-key", "sk-from-file\n");
+        std::env::set_var("GOLEM_LLM_TEST_FILE_KEY_FILE", &path);
+
+        assert_eq!(
+            get_config_key("GOLEM_LLM_TEST_FILE_KEY").unwrap(),
+            "sk-from-file"
+        );
+    }
+
+    #[test]
+    fn direct_config_key_takes_precedence_over_file() {
+        let path = secret_file("direct-key", "sk-from-file");
+        std::env::set_var("GOLEM_LLM_TEST_DIRECT_KEY_FILE", &path);
+        std::env::set_var("GOLEM_LLM_TEST_DIRECT_KEY", "sk-direct");
+
+        assert_eq!(
+            get_config_key("GOLEM_LLM_TEST_DIRECT_KEY").unwrap(),
+            "sk-direct"
+        );
+    }
+
+    #[test]
+    fn missing_config_key_and_file_are_reported() {
+        assert_eq!(
+            get_config_key("GOLEM_LLM_TEST_MISSING_KEY")
+                .unwrap_err()
+                .message,
+            "Missing config key: GOLEM_LLM_TEST_MISSING_KEY"
+        );
+
+        std::env::set_var(
+            "GOLEM_LLM_TEST_UNREADABLE_KEY_FILE",
+            "/nonexistent/golem-llm-secret",
+        );
+        let error = get_config_key("GOLEM_LLM_TEST_UNREADABLE_KEY").unwrap_err();
+        assert!(error
+            .message
+            .starts_with("Failed to read config key GOLEM_LLM_TEST_UNREADABLE_KEY"));
+    }
+
+    fn top_p(config: &Config) -> Option<&str> {
+        option(config, "top_p")
+    }
+
+    #[test]
+    fn sampling_presets_expand_to_documented_values() {
+        for (name, temperature, expected_top_p) in [
+            ("deterministic", 0.0, None),
+            ("balanced", 0.7, Some("0.9")),
+            ("creative", 1.0, Some("0.95")),
+        ] {
+            let expanded = apply_sampling_preset(config(&[("preset", name)]), "openai").unwrap();
+            assert_eq!(expanded.temperature, Some(temperature));
+            assert_eq!(top_p(&expanded), expected_top_p);
+
+            let expanded = apply_sampling_preset(config(&[("preset", name)]), "anthropic").unwrap();
+            assert_eq!(expanded.temperature, Some(temperature));
+            assert_eq!(top_p(&expanded), None);
+        }
+    }
+
+    #[test]
+    fn explicit_sampling_values_override_the_preset() {
+        let mut explicit = config(&[("preset", "creative"), ("top_p", "0.5")]);
+        explicit.temperature = Some(0.3);
+
+        let expanded = apply_sampling_preset(explicit, "grok").unwrap();
+
+        assert_eq!(expanded.temperature, Some(0.3));
+        assert_eq!(top_p(&expanded), Some("0.5"));
+        assert_eq!(
+            expanded
+                .provider_options
+                .iter()
+                .filter(|kv| kv.key == "top_p")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn unknown_preset_is_rejected() {
+        let error = apply_sampling_preset(config(&[("preset", "wild")]), "openai").unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "preset must be deterministic, balanced or creative, got wild"
+        );
+    }
 }
@@ -0,0 +1,208 @@
+use crate::config::option;
+use crate::golem::llm::llm::{Config, Error, ErrorCode, Message, Usage};
+use crate::models::model_info;
+use crate::trace_id::TRACE_ID;
+use crate::truncation::{ApproximateTokenCounter, TokenCounter};
+use log::warn;
+
+/// The share of the context window above which a conversation is considered near the limit,
+/// unless set with the `context_warning_threshold` provider option
+pub const DEFAULT_WARNING_THRESHOLD: f32 = 0.8;
+
+/// How much of the model's context window a conversation takes up
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextBudget {
+    pub context_window: u32,
+    pub used: u32,
+    pub remaining: u32,
+    /// The used share of the context window, between 0 and 100
+    pub percent: f32,
+    /// Whether the used share reached the warning threshold, so the conversation should be
+    /// truncated or summarized before it hits the limit
+    pub near_limit: bool,
+}
+
+/// Estimates the remaining context budget of a conversation with the approximate token counter,
+/// see [`context_budget_with_counter`]
+pub fn context_budget(
+    messages: &[Message],
+    config: &Config,
+    usage: Option<&Usage>,
+) -> Result<Option<ContextBudget>, Error> {
+    context_budget_with_counter(messages, config, usage, &ApproximateTokenCounter)
+}
+
+/// Estimates the remaining context budget of the running conversation `messages` for the model
+/// of `config`, or returns `None` if its context window is not known.
+///
+/// `usage` is the usage of the latest response, whose input and output tokens are the exact size
+/// of the conversation up to and including that response. As messages added since then are not
+/// part of it, the larger of it and the counted tokens of `messages` is used. A warning is
+/// logged when the conversation is near the limit.
+pub fn context_budget_with_counter(
+    messages: &[Message],
+    config: &Config,
+    usage: Option<&Usage>,
+    counter: &impl TokenCounter,
+) -> Result<Option<ContextBudget>, Error> {
+    let threshold = warning_threshold(config)?;
+    let Some(info) = model_info(config, &config.model)? else {
+        return Ok(None);
+    };
+
+    let reported = usage
+        .map(|usage| {
+            usage
+                .input_tokens
+                .unwrap_or(0)
+                .saturating_add(usage.output_tokens.unwrap_or(0))
+        })
+        .unwrap_or(0);
+    let used = counter.count_tokens(messages).max(reported);
+    let percent = used as f32 * 100.0 / info.context_window as f32;
+    let near_limit = percent >= threshold * 100.0;
+    if near_limit {
+        warn!(
+            "{TRACE_ID}Conversation uses {used} of the {} tokens context window of {} ({percent:.1}%)",
+            info.context_window, config.model
+        );
+    }
+
+    Ok(Some(ContextBudget {
+        context_window: info.context_window,
+        used,
+        remaining: info.context_window.saturating_sub(used),
+        percent,
+        near_limit,
+    }))
+}
+
+fn warning_threshold(config: &Config) -> Result<f32, Error> {
+    match option(config, "context_warning_threshold") {
+        Some(value) => value
+            .parse::<f32>()
+            .ok()
+            .filter(|threshold| (0.0..=1.0).contains(threshold))
+            .ok_or_else(|| Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "context_warning_threshold must be a number between 0 and 1, got '{value}'"
+                ),
+                provider_error_json: None,
+            }),
+        None => Ok(DEFAULT_WARNING_THRESHOLD),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context_budget::{context_budget, context_budget_with_counter};
+    use crate::golem::llm::llm::{Config, ErrorCode, Message, Usage};
+    use crate::prompt::{assistant_message, user_message};
+    use crate::{test_config, test_logger};
+
+    fn config(model: &str, provider_options: &[(&str, &str)]) -> Config {
+        Config {
+            model: model.to_string(),
+            ..test_config::config(provider_options)
+        }
+    }
+
+    fn usage(input_tokens: u32, output_tokens: u32) -> Usage {
+        Usage {
+            input_tokens: Some(input_tokens),
+            output_tokens: Some(output_tokens),
+            total_tokens: Some(input_tokens + output_tokens),
+            reasoning_tokens: None,
+            cached_tokens: None,
+            audio_tokens: None,
+        }
+    }
+
+    /// Counts 10,000 tokens per message
+    fn count(messages: &[Message]) -> u32 {
+        messages.len() as u32 * 10_000
+    }
+
+    #[test]
+    fn warning_triggers_when_approaching_the_limit() {
+        test_logger::install();
+        let config = config("gpt-4o", &[]);
+        let mut messages = vec![user_message("Plan the trip")];
+
+        let mut warned_at = None;
+        for turn in 1..=5 {
+            messages.push(assistant_message("Next step"));
+            messages.push(user_message("Continue"));
+
+            let budget = context_budget_with_counter(&messages, &config, None, &count)
+                .unwrap()
+                .unwrap();
+            assert_eq!(budget.used, messages.len() as u32 * 10_000);
+            assert_eq!(budget.remaining, 128_000 - budget.used);
+            if budget.near_limit && warned_at.is_none() {
+                warned_at = Some((turn, budget.percent));
+            }
+        }
+
+        // 11 messages take up 110,000 of the 128,000 tokens, crossing 80% for the first time
+        let (turn, percent) = warned_at.unwrap();
+        assert_eq!(turn, 5);
+        assert!((percent - 85.9375).abs() < 0.001);
+        assert!(test_logger::messages("golem_llm::context_budget").contains(
+            &"WARN Conversation uses 110000 of the 128000 tokens context window of gpt-4o (85.9%)"
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn reported_usage_is_used_when_larger_than_the_estimate() {
+        let messages = vec![user_message("Hello"), assistant_message("Hi")];
+
+        let budget = context_budget(
+            &messages,
+            &config("claude-sonnet-4-0", &[]),
+            Some(&usage(150_000, 20_000)),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(budget.used, 170_000);
+        assert_eq!(budget.remaining, 30_000);
+        assert_eq!(budget.percent, 85.0);
+        assert!(budget.near_limit);
+    }
+
+    #[test]
+    fn threshold_is_configurable() {
+        let messages = vec![user_message("Hello")];
+        let budget = context_budget(
+            &messages,
+            &config("gpt-4o", &[("context_warning_threshold", "0.5")]),
+            Some(&usage(60_000, 4_000)),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(budget.near_limit);
+
+        let error = context_budget(
+            &messages,
+            &config("gpt-4o", &[("context_warning_threshold", "80")]),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn unknown_model_has_no_budget() {
+        let budget = context_budget(
+            &[user_message("Hello")],
+            &config("my-local-model", &[]),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(budget, None);
+    }
+}
@@ -1,4 +1,13 @@
-use crate::golem::llm::llm::{Config, ContentPart, Guest, Message, Role, StreamDelta};
+use crate::chat_stream::{collect_stream, use_stream_internally};
+use crate::concurrency::acquire_permit;
+use crate::empty_response::reject_empty_response;
+use crate::golem::llm::llm::{
+    ChatEvent, Config, ContentPart, Guest, Message, Role, StreamDelta, ToolCall, ToolResult,
+};
+use crate::stop_sequence::strip_stop_sequence;
+use crate::tool_args::reject_invalid_tool_args;
+use crate::tool_call_only::require_tool_call;
+use crate::trace_id::{trace_id, with_trace_id};
 use golem_rust::wasm_rpc::Pollable;
 use std::marker::PhantomData;
 
@@ -61,19 +70,64 @@ pub trait ExtendedGuest: Guest + 'static {
     fn subscribe(stream: &Self::ChatStream) -> Pollable;
 }
 
+/// Sends a non-streaming request, or assembles the response from the streaming endpoint if the
+/// `use_stream_internally` provider option is set
+fn send_request<Impl: ExtendedGuest>(messages: Vec<Message>, config: Config) -> ChatEvent {
+    let _permit = match acquire_permit(&config) {
+        Ok(permit) => permit,
+        Err(error) => return ChatEvent::Error(error),
+    };
+    with_trace_id(trace_id(&config), || {
+        if use_stream_internally(&config) {
+            collect_stream(&Impl::unwrapped_stream(messages, config))
+        } else {
+            Impl::send(messages, config)
+        }
+    })
+}
+
+/// Applies the response checks requested in the provider options to the result of `send` or
+/// `continue`
+fn check_response(config: &Config, event: ChatEvent) -> ChatEvent {
+    let event = strip_stop_sequence(config, event);
+    let event = reject_invalid_tool_args(config, require_tool_call(config, event));
+    reject_empty_response(config, event)
+}
+
+/// Continues the conversation with the results of the requested tool calls
+fn continue_request<Impl: ExtendedGuest>(
+    messages: Vec<Message>,
+    tool_results: Vec<(ToolCall, ToolResult)>,
+    config: Config,
+) -> ChatEvent {
+    let _permit = match acquire_permit(&config) {
+        Ok(permit) => permit,
+        Err(error) => return ChatEvent::Error(error),
+    };
+    with_trace_id(trace_id(&config), || {
+        Impl::continue_(messages, tool_results, config)
+    })
+}
+
 /// When the durability feature flag is off, wrapping with `DurableLLM` is just a passthrough
 #[cfg(not(feature = "durability"))]
 mod passthrough_impl {
-    use crate::durability::{DurableLLM, ExtendedGuest};
+    use crate::concurrency::GatedChatStream;
+    use crate::durability::{
+        check_response, continue_request, send_request, DurableLLM, ExtendedGuest,
+    };
     use crate::golem::llm::llm::{
-        ChatEvent, ChatStream, Config, Guest, Message, ToolCall, ToolResult,
+        ChatEvent, ChatStream, Config, Error, Guest, ImageGenConfig, ImageResult, Message,
+        ToolCall, ToolResult,
     };
+    use crate::json_schema::send_validated;
 
     impl<Impl: ExtendedGuest> Guest for DurableLLM<Impl> {
-        type ChatStream = Impl::ChatStream;
+        type ChatStream = GatedChatStream<Impl>;
 
         fn send(messages: Vec<Message>, config: Config) -> ChatEvent {
-            Impl::send(messages, config)
+            let event = send_validated(messages, config.clone(), send_request::<Impl>);
+            check_response(&config, event)
         }
 
         fn continue_(
@@ -81,15 +135,26 @@ mod passthrough_impl {
             tool_results: Vec<(ToolCall, ToolResult)>,
             config: Config,
         ) -> ChatEvent {
-            Impl::continue_(messages, tool_results, config)
+            let event = continue_request::<Impl>(messages, tool_results, config.clone());
+            check_response(&config, event)
         }
 
         fn stream(messages: Vec<Message>, config: Config) -> ChatStream {
-            Impl::stream(messages, config)
+            ChatStream::new(GatedChatStream::<Impl>::new(messages, config))
+        }
+
+        fn generate_image(
+            prompt: String,
+            config: ImageGenConfig,
+        ) -> Result<Vec<ImageResult>, Error> {
+            Impl::generate_image(prompt, config)
         }
     }
 }
 
+#[cfg(feature = "oplog-debug")]
+pub use durable_impl::{dump_continue_input, dump_send_input};
+
 /// When the durability feature flag is on, wrapping with `DurableLLM` adds custom durability
 /// on top of the provider-specific LLM implementation using Golem's special host functions and
 /// the `golem-rust` helper library.
@@ -100,13 +165,17 @@ mod passthrough_impl {
 /// which is implemented using the type classes and builder in the `golem-rust` library.
 #[cfg(feature = "durability")]
 mod durable_impl {
-    use crate::durability::{DurableLLM, ExtendedGuest};
+    use crate::concurrency::GatedChatStream;
+    use crate::durability::{
+        check_response, continue_request, send_request, DurableLLM, ExtendedGuest,
+    };
     use crate::golem::llm::llm::{
         ChatEvent, ChatStream, CompleteResponse, Config, ContentPart, Error, ErrorCode,
-        FinishReason, Guest, GuestChatStream, ImageDetail, ImageUrl, Kv, Message, ResponseMetadata,
-        Role, StreamDelta, StreamEvent, ToolCall, ToolDefinition, ToolFailure, ToolResult,
-        ToolSuccess, Usage,
+        FinishReason, Guest, GuestChatStream, ImageData, ImageDetail, ImageGenConfig, ImageResult,
+        ImageUrl, Kv, Message, ResponseMetadata, Role, StreamDelta, StreamEvent, ToolCall,
+        ToolDefinition, ToolFailure, ToolResult, ToolSuccess, Usage,
     };
+    use crate::json_schema::send_validated;
     use golem_rust::bindings::golem::durability::durability::{
         DurableFunctionType, LazyInitializedPollable,
     };
@@ -129,7 +198,9 @@ mod durable_impl {
             );
             if durability.is_live() {
                 let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
-                    Impl::send(messages.clone(), config.clone())
+                    let event =
+                        send_validated(messages.clone(), config.clone(), send_request::<Impl>);
+                    check_response(&config, event)
                 });
                 durability.persist_infallible(SendInput { messages, config }, result)
             } else {
@@ -149,7 +220,12 @@ mod durable_impl {
             );
             if durability.is_live() {
                 let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
-                    Impl::continue_(messages.clone(), tool_results.clone(), config.clone())
+                    let event = continue_request::<Impl>(
+                        messages.clone(),
+                        tool_results.clone(),
+                        config.clone(),
+                    );
+                    check_response(&config, event)
                 });
                 durability.persist_infallible(
                     ContinueInput {
@@ -172,7 +248,7 @@ mod durable_impl {
             );
             if durability.is_live() {
                 let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
-                    ChatStream::new(DurableChatStream::<Impl>::live(Impl::unwrapped_stream(
+                    ChatStream::new(DurableChatStream::<Impl>::live(GatedChatStream::new(
                         messages.clone(),
                         config.clone(),
                     )))
@@ -184,6 +260,25 @@ mod durable_impl {
                 ChatStream::new(DurableChatStream::<Impl>::replay(messages, config))
             }
         }
+
+        fn generate_image(
+            prompt: String,
+            config: ImageGenConfig,
+        ) -> Result<Vec<ImageResult>, Error> {
+            let durability = Durability::<Result<Vec<ImageResult>, Error>, UnusedError>::new(
+                "golem_llm",
+                "generate_image",
+                DurableFunctionType::WriteRemote,
+            );
+            if durability.is_live() {
+                let result = with_persistence_level(PersistenceLevel::PersistNothing, || {
+                    Impl::generate_image(prompt.clone(), config.clone())
+                });
+                durability.persist_infallible(GenerateImageInput { prompt, config }, result)
+            } else {
+                durability.replay_infallible()
+            }
+        }
     }
 
     /// Represents the durable chat stream's state
@@ -191,40 +286,72 @@ mod durable_impl {
     /// In live mode it directly calls the underlying LLM stream which is implemented on
     /// top of an SSE parser using the wasi-http response body stream.
     ///
-    /// In replay mode it buffers the replayed messages, and also tracks the created pollables
-    /// to be able to reattach them to the new live stream when the switch to live mode
-    /// happens.
+    /// In replay mode it buffers the replayed messages, and tracks the lazily initialized pollables
+    /// created for waiting on it. They are dropped when the switch to live mode happens, as the
+    /// pollables are created anew for every wait: the live stream may replace its underlying
+    /// response stream when reconnecting, which invalidates the pollables subscribed to it.
     ///
     /// When reaching the end of the replay mode, if the replayed stream was not finished yet,
     /// the replay prompt implemented in `ExtendedGuest` is used to create a new LLM response
-    /// stream and continue the response seamlessly.
+    /// stream and continue the response seamlessly. Cancelling the stream is recorded in the
+    /// oplog, so a cancelled stream is never continued this way.
     enum DurableChatStreamState<Impl: ExtendedGuest> {
         Live {
-            stream: Impl::ChatStream,
-            pollables: Vec<LazyInitializedPollable>,
+            stream: GatedChatStream<Impl>,
         },
         Replay {
             original_messages: Vec<Message>,
             config: Config,
             pollables: Vec<LazyInitializedPollable>,
-            partial_result: Vec<StreamDelta>,
-            finished: bool,
+            replayed: ReplayedStream,
         },
     }
 
+    /// What is known about a stream from the replayed oplog entries
+    #[derive(Debug, Default)]
+    struct ReplayedStream {
+        partial_result: Vec<StreamDelta>,
+        finished: bool,
+        finish_reason: Option<FinishReason>,
+    }
+
+    impl ReplayedStream {
+        fn record(&mut self, events: &[StreamEvent]) {
+            for event in events {
+                match event {
+                    StreamEvent::Delta(delta) => {
+                        self.partial_result.push(delta.clone());
+                    }
+                    StreamEvent::Finish(metadata) => {
+                        self.finished = true;
+                        self.finish_reason = metadata.finish_reason;
+                    }
+                    StreamEvent::Error(_) => {
+                        self.finished = true;
+                    }
+                }
+            }
+        }
+
+        /// Records a cancellation, after which the stream must not be continued
+        fn cancel(&mut self) {
+            self.finished = true;
+        }
+
+        /// Whether the response has to be continued with a new live stream when the replay ends
+        fn needs_continuation(&self) -> bool {
+            !self.finished
+        }
+    }
+
     pub struct DurableChatStream<Impl: ExtendedGuest> {
         state: RefCell<Option<DurableChatStreamState<Impl>>>,
-        subscription: RefCell<Option<Pollable>>,
     }
 
     impl<Impl: ExtendedGuest> DurableChatStream<Impl> {
-        fn live(stream: Impl::ChatStream) -> Self {
+        fn live(stream: GatedChatStream<Impl>) -> Self {
             Self {
-                state: RefCell::new(Some(DurableChatStreamState::Live {
-                    stream,
-                    pollables: Vec::new(),
-                })),
-                subscription: RefCell::new(None),
+                state: RefCell::new(Some(DurableChatStreamState::Live { stream })),
             }
         }
 
@@ -234,17 +361,15 @@ mod durable_impl {
                     original_messages,
                     config,
                     pollables: Vec::new(),
-                    partial_result: Vec::new(),
-                    finished: false,
+                    replayed: ReplayedStream::default(),
                 })),
-                subscription: RefCell::new(None),
             }
         }
 
         fn subscribe(&self) -> Pollable {
             let mut state = self.state.borrow_mut();
             match &mut *state {
-                Some(DurableChatStreamState::Live { stream, .. }) => Impl::subscribe(stream),
+                Some(DurableChatStreamState::Live { stream }) => stream.subscribe(),
                 Some(DurableChatStreamState::Replay { pollables, .. }) => {
                     let lazy_pollable = LazyInitializedPollable::new();
                     let pollable = lazy_pollable.subscribe();
@@ -256,18 +381,113 @@ mod durable_impl {
                 }
             }
         }
+
+        /// Polls the stream in live mode. When the replay ends, the response of a replayed stream
+        /// that did not finish is continued with a new live stream, which replaces the replayed
+        /// one.
+        fn live_next(&self) -> Option<Vec<StreamEvent>> {
+            let mut state = self.state.borrow_mut();
+            let (result, new_live_stream) = match &*state {
+                Some(DurableChatStreamState::Live { stream }) => (
+                    with_persistence_level(PersistenceLevel::PersistNothing, || stream.get_next()),
+                    None,
+                ),
+                Some(DurableChatStreamState::Replay {
+                    original_messages,
+                    config,
+                    replayed,
+                    ..
+                }) => {
+                    if !replayed.needs_continuation() {
+                        (None, None)
+                    } else {
+                        let extended_messages =
+                            Impl::retry_prompt(original_messages, &replayed.partial_result);
+
+                        let (stream, first_live_result) =
+                            with_persistence_level(PersistenceLevel::PersistNothing, || {
+                                let stream = with_trace_id(trace_id(config), || {
+                                    <Impl as ExtendedGuest>::unwrapped_stream(
+                                        extended_messages,
+                                        config.clone(),
+                                    )
+                                });
+                                let next = stream.get_next();
+                                (stream, next)
+                            });
+
+                        (first_live_result, Some(stream))
+                    }
+                }
+                None => {
+                    unreachable!()
+                }
+            };
+
+            if let Some(stream) = new_live_stream {
+                // The stream continuing a recovered response is not gated, it takes the place
+                // of a request that was already in flight
+                *state = Some(DurableChatStreamState::Live {
+                    stream: GatedChatStream::ungated(stream),
+                });
+            }
+
+            result
+        }
+
+        /// Rebuilds the state of the stream from a replayed result of `get_next`
+        fn replayed_next(&self, result: &Option<Vec<StreamEvent>>) {
+            match &mut *self.state.borrow_mut() {
+                Some(DurableChatStreamState::Live { .. }) => {
+                    unreachable!("Durable chat stream cannot be in live mode during replay")
+                }
+                Some(DurableChatStreamState::Replay { replayed, .. }) => {
+                    if let Some(result) = result {
+                        replayed.record(result);
+                    }
+                }
+                None => {
+                    unreachable!()
+                }
+            }
+        }
+
+        /// Cancels the stream in live mode
+        fn live_cancel(&self) {
+            match &mut *self.state.borrow_mut() {
+                Some(DurableChatStreamState::Live { stream }) => {
+                    with_persistence_level(PersistenceLevel::PersistNothing, || {
+                        stream.cancel();
+                    });
+                }
+                Some(DurableChatStreamState::Replay { replayed, .. }) => replayed.cancel(),
+                None => {
+                    unreachable!()
+                }
+            }
+        }
+
+        /// Rebuilds the state of the stream from a replayed cancellation. It is the stream's
+        /// terminal entry, so reaching the end of the replay does not open a new live stream to
+        /// continue the response.
+        fn replayed_cancel(&self) {
+            match &mut *self.state.borrow_mut() {
+                Some(DurableChatStreamState::Live { .. }) => {
+                    unreachable!("Durable chat stream cannot be in live mode during replay")
+                }
+                Some(DurableChatStreamState::Replay { replayed, .. }) => replayed.cancel(),
+                None => {
+                    unreachable!()
+                }
+            }
+        }
     }
 
     impl<Impl: ExtendedGuest> Drop for DurableChatStream<Impl> {
         fn drop(&mut self) {
-            let _ = self.subscription.take();
             match self.state.take() {
-                Some(DurableChatStreamState::Live {
-                    mut pollables,
-                    stream,
-                }) => {
+                Some(DurableChatStreamState::Live { stream }) => {
                     with_persistence_level(PersistenceLevel::PersistNothing, move || {
-                        pollables.clear();
                         drop(stream);
                     });
                 }
@@ -287,109 +507,21 @@ mod durable_impl {
                 DurableFunctionType::ReadRemote,
             );
             if durability.is_live() {
-                let mut state = self.state.borrow_mut();
-                let (result, new_live_stream) = match &*state {
-                    Some(DurableChatStreamState::Live { stream, .. }) => {
-                        let result =
-                            with_persistence_level(PersistenceLevel::PersistNothing, || {
-                                stream.get_next()
-                            });
-                        (durability.persist_infallible(NoInput, result.clone()), None)
-                    }
-                    Some(DurableChatStreamState::Replay {
-                        original_messages,
-                        config,
-                        pollables,
-                        partial_result,
-                        finished,
-                    }) => {
-                        if *finished {
-                            (None, None)
-                        } else {
-                            let extended_messages =
-                                Impl::retry_prompt(original_messages, partial_result);
-
-                            let (stream, first_live_result) =
-                                with_persistence_level(PersistenceLevel::PersistNothing, || {
-                                    let stream = <Impl as ExtendedGuest>::unwrapped_stream(
-                                        extended_messages,
-                                        config.clone(),
-                                    );
-
-                                    for lazy_initialized_pollable in pollables {
-                                        lazy_initialized_pollable.set(Impl::subscribe(&stream));
-                                    }
-
-                                    let next = stream.get_next();
-                                    (stream, next)
-                                });
-                            durability.persist_infallible(NoInput, first_live_result.clone());
-
-                            (first_live_result, Some(stream))
-                        }
-                    }
-                    None => {
-                        unreachable!()
-                    }
-                };
-
-                if let Some(stream) = new_live_stream {
-                    let pollables = match state.take() {
-                        Some(DurableChatStreamState::Live { pollables, .. }) => pollables,
-                        Some(DurableChatStreamState::Replay { pollables, .. }) => pollables,
-                        None => {
-                            unreachable!()
-                        }
-                    };
-                    *state = Some(DurableChatStreamState::Live { stream, pollables });
-                }
-
-                result
+                durability.persist_infallible(NoInput, self.live_next())
             } else {
                 let result: Option<Vec<StreamEvent>> = durability.replay_infallible();
-                let mut state = self.state.borrow_mut();
-                match &mut *state {
-                    Some(DurableChatStreamState::Live { .. }) => {
-                        unreachable!("Durable chat stream cannot be in live mode during replay")
-                    }
-                    Some(DurableChatStreamState::Replay {
-                        partial_result,
-                        finished,
-                        ..
-                    }) => {
-                        if let Some(result) = &result {
-                            for event in result {
-                                match event {
-                                    StreamEvent::Delta(delta) => {
-                                        partial_result.push(delta.clone());
-                                    }
-                                    StreamEvent::Finish(_) => {
-                                        *finished = true;
-                                    }
-                                    StreamEvent::Error(_) => {
-                                        *finished = true;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    None => {
-                        unreachable!()
-                    }
-                }
+                self.replayed_next(&result);
                 result
             }
         }
 
         fn blocking_get_next(&self) -> Vec<StreamEvent> {
-            let mut subscription = self.subscription.borrow_mut();
-            if subscription.is_none() {
-                *subscription = Some(self.subscribe());
-            }
-            let subscription = subscription.as_mut().unwrap();
             let mut result = Vec::new();
             loop {
-                subscription.block();
+                // Subscribing anew for every wait, and dropping the pollable before polling,
+                // because the live stream may replace its underlying response stream when it
+                // reconnects, and the pollables must be dropped before the stream they belong to
+                self.subscribe().block();
                 match self.get_next() {
                     Some(events) => {
                         result.extend(events);
@@ -399,6 +531,43 @@ mod durable_impl {
                 }
             }
         }
+
+        // Both queries are answered from the stream's state, which is rebuilt from the
+        // replayed events in replay mode, so they are not recorded in the oplog
+        fn is_finished(&self) -> bool {
+            match &*self.state.borrow() {
+                Some(DurableChatStreamState::Live { stream }) => stream.is_finished(),
+                Some(DurableChatStreamState::Replay { replayed, .. }) => replayed.finished,
+                None => {
+                    unreachable!()
+                }
+            }
+        }
+
+        fn last_finish_reason(&self) -> Option<FinishReason> {
+            match &*self.state.borrow() {
+                Some(DurableChatStreamState::Live { stream }) => stream.last_finish_reason(),
+                Some(DurableChatStreamState::Replay { replayed, .. }) => replayed.finish_reason,
+                None => {
+                    unreachable!()
+                }
+            }
+        }
+
+        fn cancel(&self) {
+            let durability = Durability::<NoOutput, UnusedError>::new(
+                "golem_llm",
+                "cancel",
+                DurableFunctionType::WriteRemote,
+            );
+            if durability.is_live() {
+                self.live_cancel();
+                let _ = durability.persist_infallible(NoInput, NoOutput);
+            } else {
+                let _: NoOutput = durability.replay_infallible();
+                self.replayed_cancel();
+            }
+        }
     }
 
     // variant stream-event {
@@ -455,12 +624,16 @@ mod durable_impl {
     // record stream-delta {
     //   content: option<list<content-part>>,
     //   tool-calls: option<list<tool-call>>,
+    //   provider-metadata-json: option<string>,
+    //   raw-json: option<string>,
     // }
     impl IntoValue for StreamDelta {
         fn add_to_builder<T: NodeBuilder>(self, builder: T) -> T::Result {
             let mut builder = builder.record();
             builder = self.content.add_to_builder(builder.item());
             builder = self.tool_calls.add_to_builder(builder.item());
+            builder = self.provider_metadata_json.add_to_builder(builder.item());
+            builder = self.raw_json.add_to_builder(builder.item());
             builder.finish()
         }
 
@@ -472,6 +645,9 @@ mod durable_impl {
             builder = TypeNodeBuilder::finish(
                 ToolCall::add_to_type_builder(builder.field("tool-calls").option().list()).finish(),
             );
+            builder =
+                TypeNodeBuilder::finish(builder.field("provider-metadata-json").option().string());
+            builder = TypeNodeBuilder::finish(builder.field("raw-json").option().string());
             builder.finish()
         }
     }
@@ -491,6 +667,16 @@ mod durable_impl {
                         .field(1)
                         .ok_or_else(|| "Missing tool-calls field".to_string())?,
                 )?,
+                provider_metadata_json: Option::<String>::from_extractor(
+                    &extractor
+                        .field(2)
+                        .ok_or_else(|| "Missing provider-metadata-json field".to_string())?,
+                )?,
+                raw_json: Option::<String>::from_extractor(
+                    &extractor
+                        .field(3)
+                        .ok_or_else(|| "Missing raw-json field".to_string())?,
+                )?,
             })
         }
     }
@@ -735,6 +921,7 @@ mod durable_impl {
     //     provider-id: option<string>,
     //     timestamp: option<string>,
     //     provider-metadata-json: option<string>,
+    //     raw-json: option<string>,
     //   }
     impl IntoValue for ResponseMetadata {
         fn add_to_builder<T: NodeBuilder>(self, builder: T) -> T::Result {
@@ -744,6 +931,7 @@ mod durable_impl {
             builder = self.provider_id.add_to_builder(builder.item());
             builder = self.timestamp.add_to_builder(builder.item());
             builder = self.provider_metadata_json.add_to_builder(builder.item());
+            builder = self.raw_json.add_to_builder(builder.item());
             builder.finish()
         }
 
@@ -755,6 +943,7 @@ mod durable_impl {
             builder = TypeNodeBuilder::finish(builder.field("timestamp").option().string());
             builder =
                 TypeNodeBuilder::finish(builder.field("provider-metadata-json").option().string());
+            builder = TypeNodeBuilder::finish(builder.field("raw-json").option().string());
             builder.finish()
         }
     }
@@ -789,6 +978,11 @@ mod durable_impl {
                         .field(4)
                         .ok_or_else(|| "Missing provider-metadata-json field".to_string())?,
                 )?,
+                raw_json: Option::<String>::from_extractor(
+                    &extractor
+                        .field(5)
+                        .ok_or_else(|| "Missing raw-json field".to_string())?,
+                )?,
             })
         }
     }
@@ -797,6 +991,9 @@ mod durable_impl {
     //     input-tokens: option<u32>,
     //     output-tokens: option<u32>,
     //     total-tokens: option<u32>,
+    //     reasoning-tokens: option<u32>,
+    //     cached-tokens: option<u32>,
+    //     audio-tokens: option<u32>,
     //   }
     impl IntoValue for Usage {
         fn add_to_builder<T: NodeBuilder>(self, builder: T) -> T::Result {
@@ -816,6 +1013,21 @@ mod durable_impl {
                 .option_fn(self.total_tokens.is_some(), |inner| {
                     inner.u32(self.total_tokens.unwrap())
                 });
+            builder = builder
+                .item()
+                .option_fn(self.reasoning_tokens.is_some(), |inner| {
+                    inner.u32(self.reasoning_tokens.unwrap())
+                });
+            builder = builder
+                .item()
+                .option_fn(self.cached_tokens.is_some(), |inner| {
+                    inner.u32(self.cached_tokens.unwrap())
+                });
+            builder = builder
+                .item()
+                .option_fn(self.audio_tokens.is_some(), |inner| {
+                    inner.u32(self.audio_tokens.unwrap())
+                });
             builder.finish()
         }
 
@@ -824,6 +1036,9 @@ mod durable_impl {
             builder = TypeNodeBuilder::finish(builder.field("input-tokens").option().u32());
             builder = TypeNodeBuilder::finish(builder.field("output-tokens").option().u32());
             builder = TypeNodeBuilder::finish(builder.field("total-tokens").option().u32());
+            builder = TypeNodeBuilder::finish(builder.field("reasoning-tokens").option().u32());
+            builder = TypeNodeBuilder::finish(builder.field("cached-tokens").option().u32());
+            builder = TypeNodeBuilder::finish(builder.field("audio-tokens").option().u32());
             builder.finish()
         }
     }
@@ -866,6 +1081,39 @@ mod durable_impl {
                             .ok_or_else(|| "total-tokens is not u32".to_string())
                     })
                     .transpose()?,
+                reasoning_tokens: extractor
+                    .field(3)
+                    .ok_or_else(|| "Missing reasoning-tokens field".to_string())?
+                    .option()
+                    .ok_or_else(|| "reasoning-tokens is not an option".to_string())?
+                    .map(|inner| {
+                        inner
+                            .u32()
+                            .ok_or_else(|| "reasoning-tokens is not u32".to_string())
+                    })
+                    .transpose()?,
+                cached_tokens: extractor
+                    .field(4)
+                    .ok_or_else(|| "Missing cached-tokens field".to_string())?
+                    .option()
+                    .ok_or_else(|| "cached-tokens is not an option".to_string())?
+                    .map(|inner| {
+                        inner
+                            .u32()
+                            .ok_or_else(|| "cached-tokens is not u32".to_string())
+                    })
+                    .transpose()?,
+                audio_tokens: extractor
+                    .field(5)
+                    .ok_or_else(|| "Missing audio-tokens field".to_string())?
+                    .option()
+                    .ok_or_else(|| "audio-tokens is not an option".to_string())?
+                    .map(|inner| {
+                        inner
+                            .u32()
+                            .ok_or_else(|| "audio-tokens is not u32".to_string())
+                    })
+                    .transpose()?,
             })
         }
     }
@@ -918,6 +1166,147 @@ mod durable_impl {
         }
     }
 
+    #[derive(Debug)]
+    struct GenerateImageInput {
+        prompt: String,
+        config: ImageGenConfig,
+    }
+
+    impl IntoValue for GenerateImageInput {
+        fn add_to_builder<T: NodeBuilder>(self, builder: T) -> T::Result {
+            let mut builder = builder.record();
+            builder = self.prompt.add_to_builder(builder.item());
+            builder = self.config.add_to_builder(builder.item());
+            builder.finish()
+        }
+
+        fn add_to_type_builder<T: TypeNodeBuilder>(builder: T) -> T::Result {
+            let mut builder = builder.record();
+            builder = builder.field("prompt").string();
+            builder = ImageGenConfig::add_to_type_builder(builder.field("config"));
+            builder.finish()
+        }
+    }
+
+    //   record image-gen-config {
+    //     model: string,
+    //     n: option<u32>,
+    //     size: option<string>,
+    //     quality: option<string>,
+    //     style: option<string>,
+    //     provider-options: list<kv>,
+    //   }
+    impl IntoValue for ImageGenConfig {
+        fn add_to_builder<T: NodeBuilder>(self, builder: T) -> T::Result {
+            let mut builder = builder.record();
+            builder = self.model.add_to_builder(builder.item());
+            builder = self.n.add_to_builder(builder.item());
+            builder = self.size.add_to_builder(builder.item());
+            builder = self.quality.add_to_builder(builder.item());
+            builder = self.style.add_to_builder(builder.item());
+            builder = self.provider_options.add_to_builder(builder.item());
+            builder.finish()
+        }
+
+        fn add_to_type_builder<T: TypeNodeBuilder>(builder: T) -> T::Result {
+            let mut builder = builder.record();
+            builder = builder.field("model").string();
+            builder = TypeNodeBuilder::finish(builder.field("n").option().u32());
+            builder = TypeNodeBuilder::finish(builder.field("size").option().string());
+            builder = TypeNodeBuilder::finish(builder.field("quality").option().string());
+            builder = TypeNodeBuilder::finish(builder.field("style").option().string());
+            builder = Vec::<Kv>::add_to_type_builder(builder.field("provider-options"));
+            builder.finish()
+        }
+    }
+
+    //   record image-result {
+    //     data: image-data,
+    //     size: option<string>,
+    //     revised-prompt: option<string>,
+    //   }
+    impl IntoValue for ImageResult {
+        fn add_to_builder<T: NodeBuilder>(self, builder: T) -> T::Result {
+            let mut builder = builder.record();
+            builder = self.data.add_to_builder(builder.item());
+            builder = self.size.add_to_builder(builder.item());
+            builder = self.revised_prompt.add_to_builder(builder.item());
+            builder.finish()
+        }
+
+        fn add_to_type_builder<T: TypeNodeBuilder>(builder: T) -> T::Result {
+            let mut builder = builder.record();
+            builder = ImageData::add_to_type_builder(builder.field("data"));
+            builder = TypeNodeBuilder::finish(builder.field("size").option().string());
+            builder = TypeNodeBuilder::finish(builder.field("revised-prompt").option().string());
+            builder.finish()
+        }
+    }
+
+    impl FromValueAndType for ImageResult {
+        fn from_extractor<'a, 'b>(
+            extractor: &'a impl WitValueExtractor<'a, 'b>,
+        ) -> Result<Self, String> {
+            Ok(Self {
+                data: ImageData::from_extractor(
+                    &extractor
+                        .field(0)
+                        .ok_or_else(|| "Missing data field".to_string())?,
+                )?,
+                size: Option::<String>::from_extractor(
+                    &extractor
+                        .field(1)
+                        .ok_or_else(|| "Missing size field".to_string())?,
+                )?,
+                revised_prompt: Option::<String>::from_extractor(
+                    &extractor
+                        .field(2)
+                        .ok_or_else(|| "Missing revised-prompt field".to_string())?,
+                )?,
+            })
+        }
+    }
+
+    //   variant image-data {
+    //     url(string),
+    //     base64(string),
+    //   }
+    impl IntoValue for ImageData {
+        fn add_to_builder<T: NodeBuilder>(self, builder: T) -> T::Result {
+            match self {
+                ImageData::Url(url) => builder.variant(0).string(&url).finish(),
+                ImageData::Base64(data) => builder.variant(1).string(&data).finish(),
+            }
+        }
+
+        fn add_to_type_builder<T: TypeNodeBuilder>(builder: T) -> T::Result {
+            let mut builder = builder.variant();
+            builder = builder.case("url").string();
+            builder = builder.case("base64").string();
+            builder.finish()
+        }
+    }
+
+    impl FromValueAndType for ImageData {
+        fn from_extractor<'a, 'b>(
+            extractor: &'a impl WitValueExtractor<'a, 'b>,
+        ) -> Result<Self, String> {
+            let (idx, inner) = extractor
+                .variant()
+                .ok_or_else(|| "ImageData should be variant".to_string())?;
+            let value = inner
+                .ok_or_else(|| "Missing image data".to_string())?
+                .string()
+                .ok_or_else(|| "ImageData should be string".to_string())?
+                .to_string();
+            match idx {
+                0 => Ok(ImageData::Url(value)),
+                1 => Ok(ImageData::Base64(value)),
+                _ => Err(format!("Invalid ImageData variant: {idx}")),
+            }
+        }
+    }
+
     #[derive(Debug, Clone, PartialEq)]
     struct SendInput {
         messages: Vec<Message>,
@@ -1307,6 +1696,124 @@ mod durable_impl {
         }
     }
 
+    /// Returns the input persisted in the oplog by `send` and `stream` for the given messages and
+    /// config, rendered as JSON, to help understanding and debugging what the durability layer
+    /// stores, for example whether sensitive data ends up in the oplog
+    #[cfg(feature = "oplog-debug")]
+    pub fn dump_send_input(messages: Vec<Message>, config: Config) -> String {
+        dump(SendInput { messages, config })
+    }
+
+    /// Returns the input persisted in the oplog by `continue` for the given messages, tool
+    /// results and config, rendered as JSON like [`dump_send_input`]
+    #[cfg(feature = "oplog-debug")]
+    pub fn dump_continue_input(
+        messages: Vec<Message>,
+        tool_results: Vec<(ToolCall, ToolResult)>,
+        config: Config,
+    ) -> String {
+        dump(ContinueInput {
+            messages,
+            tool_results,
+            config,
+        })
+    }
+
+    #[cfg(feature = "oplog-debug")]
+    fn dump(input: impl IntoValue) -> String {
+        use golem_rust::value_and_type::IntoValueAndType;
+
+        let vnt = input.into_value_and_type();
+        serde_json::to_string_pretty(&value_to_json(&vnt, 0, 0)).unwrap()
+    }
+
+    /// Converts the value node at `value_idx` of type node `type_idx` to JSON. Records become
+    /// objects, variants with a payload `{"case": payload}` objects and the other variant and enum
+    /// cases their names.
+    #[cfg(feature = "oplog-debug")]
+    fn value_to_json(
+        vnt: &golem_rust::wasm_rpc::ValueAndType,
+        value_idx: usize,
+        type_idx: usize,
+    ) -> serde_json::Value {
+        use golem_rust::wasm_rpc::{WitNode, WitTypeNode};
+        use serde_json::{json, Map, Value};
+
+        let child = |value: &i32, typ: &i32| value_to_json(vnt, *value as usize, *typ as usize);
+        let case = |name: &str, payload: Value| {
+            Value::Object(Map::from_iter([(name.to_string(), payload)]))
+        };
+
+        match (&vnt.value.nodes[value_idx], &vnt.typ.nodes[type_idx]) {
+            (WitNode::RecordValue(values), WitTypeNode::RecordType(fields)) => Value::Object(
+                values
+                    .iter()
+                    .zip(fields)
+                    .map(|(value, (name, typ))| (name.clone(), child(value, typ)))
+                    .collect(),
+            ),
+            (WitNode::VariantValue((idx, value)), WitTypeNode::VariantType(cases)) => {
+                let (name, typ) = &cases[*idx as usize];
+                match (value, typ) {
+                    (Some(value), Some(typ)) => case(name, child(value, typ)),
+                    _ => json!(name),
+                }
+            }
+            (WitNode::EnumValue(idx), WitTypeNode::EnumType(names)) => json!(names[*idx as usize]),
+            (WitNode::FlagsValue(flags), WitTypeNode::FlagsType(names)) => json!(names
+                .iter()
+                .zip(flags)
+                .filter(|(_, set)| **set)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()),
+            (WitNode::TupleValue(values), WitTypeNode::TupleType(types)) => Value::Array(
+                values
+                    .iter()
+                    .zip(types)
+                    .map(|(value, typ)| child(value, typ))
+                    .collect(),
+            ),
+            (WitNode::ListValue(values), WitTypeNode::ListType(typ)) => {
+                Value::Array(values.iter().map(|value| child(value, typ)).collect())
+            }
+            (WitNode::OptionValue(value), WitTypeNode::OptionType(typ)) => value
+                .as_ref()
+                .map(|value| child(value, typ))
+                .unwrap_or(Value::Null),
+            (WitNode::ResultValue(result), WitTypeNode::ResultType((ok, err))) => match result {
+                Ok(value) => case(
+                    "ok",
+                    value
+                        .zip(*ok)
+                        .map(|(value, typ)| child(&value, &typ))
+                        .unwrap_or(Value::Null),
+                ),
+                Err(value) => case(
+                    "err",
+                    value
+                        .zip(*err)
+                        .map(|(value, typ)| child(&value, &typ))
+                        .unwrap_or(Value::Null),
+                ),
+            },
+            (WitNode::PrimU8(value), _) => json!(value),
+            (WitNode::PrimU16(value), _) => json!(value),
+            (WitNode::PrimU32(value), _) => json!(value),
+            (WitNode::PrimU64(value), _) => json!(value),
+            (WitNode::PrimS8(value), _) => json!(value),
+            (WitNode::PrimS16(value), _) => json!(value),
+            (WitNode::PrimS32(value), _) => json!(value),
+            (WitNode::PrimS64(value), _) => json!(value),
+            (WitNode::PrimFloat32(value), _) => json!(value),
+            (WitNode::PrimFloat64(value), _) => json!(value),
+            (WitNode::PrimChar(value), _) => json!(value),
+            (WitNode::PrimBool(value), _) => json!(value),
+            (WitNode::PrimString(value), _) => json!(value),
+            // Resource handles are never part of the persisted inputs
+            _ => Value::Null,
+        }
+    }
+
     #[derive(Debug)]
     struct NoInput;
 
@@ -1384,13 +1891,20 @@ mod durable_impl {
 
     #[cfg(test)]
     mod tests {
-        use crate::durability::durable_impl::SendInput;
+        use crate::durability::durable_impl::{DurableChatStream, ReplayedStream, SendInput};
+        use crate::durability::ExtendedGuest;
+        use crate::error::unsupported;
         use crate::golem::llm::llm::{
-            ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, FinishReason,
-            ImageDetail, ImageUrl, Message, ResponseMetadata, Role, ToolCall, Usage,
+            ChatEvent, ChatStream, CompleteResponse, Config, ContentPart, Error, ErrorCode,
+            FinishReason, Guest, GuestChatStream, ImageData, ImageDetail, ImageGenConfig,
+            ImageResult, ImageUrl, Message, ResponseMetadata, Role, StreamDelta, StreamEvent,
+            ToolCall, ToolResult, Usage,
         };
+        use crate::prompt::user_message;
+        use crate::test_config::config;
         use golem_rust::value_and_type::{FromValueAndType, IntoValueAndType};
-        use golem_rust::wasm_rpc::WitTypeNode;
+        use golem_rust::wasm_rpc::{Pollable, WitTypeNode};
+        use std::cell::RefCell;
         use std::fmt::Debug;
 
         fn roundtrip_test<T: Debug + Clone + PartialEq + IntoValueAndType + FromValueAndType>(
@@ -1449,11 +1963,17 @@ mod durable_impl {
                 input_tokens: Some(100),
                 output_tokens: Some(200),
                 total_tokens: Some(300),
+                reasoning_tokens: Some(50),
+                cached_tokens: Some(20),
+                audio_tokens: Some(10),
             });
             roundtrip_test(Usage {
                 input_tokens: None,
                 output_tokens: None,
                 total_tokens: None,
+                reasoning_tokens: None,
+                cached_tokens: None,
+                audio_tokens: None,
             });
         }
 
@@ -1465,10 +1985,14 @@ mod durable_impl {
                     input_tokens: Some(100),
                     output_tokens: None,
                     total_tokens: Some(100),
+                    reasoning_tokens: None,
+                    cached_tokens: Some(40),
+                    audio_tokens: None,
                 }),
                 provider_id: Some("provider_id".to_string()),
                 timestamp: Some("2023-10-01T00:00:00Z".to_string()),
                 provider_metadata_json: Some("{\"key\": \"value\"}".to_string()),
+                raw_json: Some("{\"type\": \"message_stop\"}".to_string()),
             });
             roundtrip_test(ResponseMetadata {
                 finish_reason: None,
@@ -1476,6 +2000,7 @@ mod durable_impl {
                 provider_id: None,
                 timestamp: None,
                 provider_metadata_json: None,
+                raw_json: None,
             });
         }
 
@@ -1501,6 +2026,7 @@ mod durable_impl {
                     provider_id: None,
                     timestamp: None,
                     provider_metadata_json: None,
+                    raw_json: None,
                 },
             });
         }
@@ -1527,6 +2053,7 @@ mod durable_impl {
                     provider_id: None,
                     timestamp: None,
                     provider_metadata_json: None,
+                    raw_json: None,
                 },
             }));
             roundtrip_test(ChatEvent::ToolRequest(vec![ToolCall {
@@ -1541,6 +2068,32 @@ mod durable_impl {
             }));
         }
 
+        #[test]
+        fn stream_event_roundtrip() {
+            roundtrip_test(StreamEvent::Delta(StreamDelta {
+                content: Some(vec![ContentPart::Text("Hello".to_string())]),
+                tool_calls: None,
+                provider_metadata_json: Some(
+                    r#"{"logprobs":[{"token":"Hello","logprob":-0.1}]}"#.to_string(),
+                ),
+                raw_json: None,
+            }));
+        }
+
+        #[test]
+        fn image_result_roundtrip() {
+            roundtrip_test(ImageResult {
+                data: ImageData::Url("https://example.com/generated.png".to_string()),
+                size: Some("1024x1024".to_string()),
+                revised_prompt: Some("A cat sitting on a fence".to_string()),
+            });
+            roundtrip_test(ImageResult {
+                data: ImageData::Base64("aGVsbG8=".to_string()),
+                size: None,
+                revised_prompt: None,
+            });
+        }
+
         #[test]
         fn send_input_encoding() {
             let input = SendInput {
@@ -1579,5 +2132,181 @@ mod durable_impl {
                 }
             }
         }
+
+        #[cfg(feature = "oplog-debug")]
+        #[test]
+        fn send_input_is_dumped_as_json() {
+            let dumped = crate::durability::dump_send_input(
+                vec![Message {
+                    role: Role::User,
+                    name: Some("alice".to_string()),
+                    content: vec![ContentPart::Image(ImageUrl {
+                        url: "https://example.com/image.png".to_string(),
+                        detail: Some(ImageDetail::High),
+                    })],
+                }],
+                Config {
+                    model: "gpt-4o".to_string(),
+                    temperature: Some(0.5),
+                    ..crate::test_config::config(&[("user", "alice")])
+                },
+            );
+
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&dumped).unwrap(),
+                serde_json::json!({
+                    "messages": [{
+                        "role": "user",
+                        "name": "alice",
+                        "content": [{
+                            "image": {
+                                "url": "https://example.com/image.png",
+                                "detail": "high"
+                            }
+                        }]
+                    }],
+                    "config": {
+                        "model": "gpt-4o",
+                        "temperature": 0.5,
+                        "max-tokens": null,
+                        "stop-sequences": null,
+                        "tools": [],
+                        "tool-choice": null,
+                        "provider-options": [{"key": "user", "value": "alice"}]
+                    }
+                })
+            );
+        }
+
+        fn replayed_delta(text: &str) -> StreamEvent {
+            StreamEvent::Delta(StreamDelta {
+                content: Some(vec![ContentPart::Text(text.to_string())]),
+                tool_calls: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            })
+        }
+
+        #[test]
+        fn interrupted_replay_is_continued_with_live_stream() {
+            let mut replayed = ReplayedStream::default();
+
+            replayed.record(&[replayed_delta("Hello"), replayed_delta(" world")]);
+
+            assert!(replayed.needs_continuation());
+            assert_eq!(replayed.partial_result.len(), 2);
+        }
+
+        #[test]
+        fn cancelled_stream_is_not_continued_after_replay() {
+            // The oplog of a live stream cancelled after its first events: the replayed
+            // get-next results followed by the cancellation marker
+            let mut replayed = ReplayedStream::default();
+            replayed.record(&[replayed_delta("Hello")]);
+            replayed.record(&[]);
+            replayed.cancel();
+
+            assert!(!replayed.needs_continuation());
+            assert!(replayed.finished);
+            assert_eq!(replayed.finish_reason, None);
+        }
+
+        thread_local! {
+            /// The calls made to [`CountingGuest`]
+            static CALLS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+        }
+
+        /// LLM implementation recording the calls opening a stream to continue a response
+        struct CountingGuest;
+
+        /// Stream of [`CountingGuest`], finished right away
+        struct EndedStream;
+
+        impl GuestChatStream for EndedStream {
+            fn get_next(&self) -> Option<Vec<StreamEvent>> {
+                Some(vec![])
+            }
+
+            fn blocking_get_next(&self) -> Vec<StreamEvent> {
+                vec![]
+            }
+
+            fn is_finished(&self) -> bool {
+                true
+            }
+
+            fn last_finish_reason(&self) -> Option<FinishReason> {
+                None
+            }
+
+            fn cancel(&self) {}
+        }
+
+        impl Guest for CountingGuest {
+            type ChatStream = EndedStream;
+
+            fn send(_messages: Vec<Message>, _config: Config) -> ChatEvent {
+                ChatEvent::Error(unsupported("Sending"))
+            }
+
+            fn continue_(
+                _messages: Vec<Message>,
+                _tool_results: Vec<(ToolCall, ToolResult)>,
+                _config: Config,
+            ) -> ChatEvent {
+                ChatEvent::Error(unsupported("Continuing"))
+            }
+
+            fn stream(_messages: Vec<Message>, _config: Config) -> ChatStream {
+                unreachable!("The durable chat stream opens its streams with unwrapped_stream")
+            }
+
+            fn generate_image(
+                _prompt: String,
+                _config: ImageGenConfig,
+            ) -> Result<Vec<ImageResult>, Error> {
+                Err(unsupported("Image generation"))
+            }
+        }
+
+        impl ExtendedGuest for CountingGuest {
+            fn unwrapped_stream(_messages: Vec<Message>, _config: Config) -> EndedStream {
+                CALLS.with_borrow_mut(|calls| calls.push("unwrapped_stream"));
+                EndedStream
+            }
+
+            fn retry_prompt(
+                original_messages: &[Message],
+                _partial_result: &[StreamDelta],
+            ) -> Vec<Message> {
+                CALLS.with_borrow_mut(|calls| calls.push("retry_prompt"));
+                original_messages.to_vec()
+            }
+
+            fn subscribe(_stream: &EndedStream) -> Pollable {
+                golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(0)
+            }
+        }
+
+        #[test]
+        fn cancelled_durable_stream_is_not_continued_when_the_replay_ends() {
+            let stream = DurableChatStream::<CountingGuest>::replay(
+                vec![user_message("Hello")],
+                config(&[]),
+            );
+            // The oplog of a live stream cancelled after its first events: the get-next results
+            // followed by the cancellation
+            stream.replayed_next(&Some(vec![replayed_delta("Hello")]));
+            stream.replayed_next(&None);
+            stream.replayed_cancel();
+            assert!(stream.is_finished());
+
+            // The replay ended, so the next get-next is live
+            assert_eq!(stream.live_next(), None);
+
+            assert!(stream.is_finished());
+            assert_eq!(stream.last_finish_reason(), None);
+            assert_eq!(CALLS.with_borrow(Vec::clone), Vec::<&str>::new());
+        }
     }
 }
@@ -0,0 +1,101 @@
+use crate::config::bool_option;
+use crate::golem::llm::llm::{ChatEvent, Config, ContentPart, Error, ErrorCode};
+
+/// Returns whether the `error_on_empty=true` provider option is set, turning responses without
+/// any content or tool calls into errors.
+///
+/// By default an empty response, for example when the model stops immediately, is returned as a
+/// message with the real finish reason.
+pub fn error_on_empty_enabled(config: &Config) -> bool {
+    bool_option(config, "error_on_empty")
+}
+
+/// Checks a response when `error_on_empty` is enabled. A message whose text is empty or
+/// whitespace only, without images or tool calls, is turned into an `internal-error`.
+pub fn reject_empty_response(config: &Config, event: ChatEvent) -> ChatEvent {
+    if !error_on_empty_enabled(config) {
+        return event;
+    }
+
+    match event {
+        ChatEvent::Message(response)
+            if response.tool_calls.is_empty()
+                && response.content.iter().all(|part| match part {
+                    ContentPart::Text(text) => text.trim().is_empty(),
+                    ContentPart::Image(_) => false,
+                }) =>
+        {
+            let finish_reason = response
+                .metadata
+                .finish_reason
+                .map(|finish_reason| format!("{finish_reason:?}"))
+                .unwrap_or_else(|| "unknown".to_string());
+            ChatEvent::Error(Error {
+                code: ErrorCode::InternalError,
+                message: format!(
+                    "The model returned an empty response (finish reason: {finish_reason})"
+                ),
+                provider_error_json: None,
+            })
+        }
+        event => event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::empty_response::reject_empty_response;
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, ContentPart, ErrorCode, FinishReason, ResponseMetadata,
+    };
+    use crate::test_config::config;
+
+    fn message(content: Vec<ContentPart>) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "response".to_string(),
+            content,
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: Some(FinishReason::Stop),
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            },
+        })
+    }
+
+    #[test]
+    fn empty_response_is_returned_by_default() {
+        let empty = message(vec![ContentPart::Text(String::new())]);
+
+        assert_eq!(reject_empty_response(&config(&[]), empty.clone()), empty);
+    }
+
+    #[test]
+    fn empty_or_whitespace_response_is_an_error_when_enabled() {
+        for content in [vec![], vec![ContentPart::Text(" \n ".to_string())]] {
+            match reject_empty_response(&config(&[("error_on_empty", "true")]), message(content)) {
+                ChatEvent::Error(error) => {
+                    assert_eq!(error.code, ErrorCode::InternalError);
+                    assert_eq!(
+                        error.message,
+                        "The model returned an empty response (finish reason: Stop)"
+                    );
+                }
+                other => panic!("Unexpected event: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn non_empty_response_is_unchanged_when_enabled() {
+        let response = message(vec![ContentPart::Text("Hello".to_string())]);
+
+        assert_eq!(
+            reject_empty_response(&config(&[("error_on_empty", "true")]), response.clone()),
+            response
+        );
+    }
+}
@@ -1,6 +1,12 @@
 use crate::event_source;
-use crate::golem::llm::llm::{Error, ErrorCode};
+use crate::golem::llm::llm::{Error, ErrorCode, ToolCall, ToolResult, ToolSuccess};
+use crate::trace_id::TRACE_ID;
+use crate::transport::{deprecation_notice, HttpResponse};
+use log::{trace, warn};
 use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
 
 /// Creates an `Error` value representing that something is unsuported
 pub fn unsupported(what: impl AsRef<str>) -> Error {
@@ -11,7 +17,214 @@ pub fn unsupported(what: impl AsRef<str>) -> Error {
     }
 }
 
+/// Checks that the result of a successful tool call is valid JSON, so a malformed result is
+/// rejected before being sent to the provider
+pub fn check_tool_result_json(success: &ToolSuccess) -> Result<(), Error> {
+    serde_json::from_str::<serde::de::IgnoredAny>(&success.result_json)
+        .map(|_| ())
+        .map_err(|err| Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Result of tool call {} ({}) is not valid JSON: {err}",
+                success.id, success.name
+            ),
+            provider_error_json: None,
+        })
+}
+
+/// Parses the arguments of a tool call passed back to a provider expecting them as a JSON value,
+/// so malformed or truncated arguments are rejected instead of panicking
+pub fn parse_tool_call_arguments<T: DeserializeOwned>(tool_call: &ToolCall) -> Result<T, Error> {
+    serde_json::from_str(&tool_call.arguments_json).map_err(|err| Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!(
+            "Arguments of tool call {} ({}) are not valid JSON: {err}",
+            tool_call.id, tool_call.name
+        ),
+        provider_error_json: None,
+    })
+}
+
+/// Checks that a tool result answers the tool call it is paired with.
+///
+/// Providers correlate results with calls by the id of the `ToolCall` returned by `send` or
+/// emitted in a stream delta, so the result must carry exactly the same id.
+pub fn check_tool_result_id(tool_call: &ToolCall, tool_result: &ToolResult) -> Result<(), Error> {
+    let result_id = match tool_result {
+        ToolResult::Success(success) => &success.id,
+        ToolResult::Error(failure) => &failure.id,
+    };
+    if *result_id == tool_call.id {
+        Ok(())
+    } else {
+        Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Result with id {result_id} does not match the id of tool call {} ({})",
+                tool_call.id, tool_call.name
+            ),
+            provider_error_json: None,
+        })
+    }
+}
+
+/// The start of the message of errors for responses with a failure status
+const REQUEST_FAILED: &str = "Request failed with ";
+
+/// The start of the message of errors reported in the body of responses with a success status
+const ERROR_IN_SUCCESSFUL_RESPONSE: &str = "Provider returned an error in a successful response: ";
+
+/// Separates the details of errors for requests that timed out or lost their connection from the
+/// underlying error
+const CONNECTION_FAILED: &str = ": connection failed: ";
+
+/// Key of the marker of transient failures in the `provider_error_json` of an error, see
+/// [`is_transient`]
+const TRANSIENT: &str = "transient";
+
+/// Converts a failed request into an `Error`.
+///
+/// Connection level failures, such as an HTTP/2 GOAWAY sent by a provider under load, a reset
+/// connection or a broken pipe, are transient like a 503 response, so they are `internal-error`s
+/// and retriable. Requests that could not even be built are permanent `invalid-request` errors.
 pub fn from_reqwest_error(details: impl AsRef<str>, err: reqwest::Error) -> Error {
+    if err.is_builder() {
+        Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("{}: {err}", details.as_ref()),
+            provider_error_json: None,
+        }
+    } else if err.is_timeout() || is_connection_error(&err) {
+        transient(Error {
+            code: ErrorCode::InternalError,
+            message: format!("{}{CONNECTION_FAILED}{err}", details.as_ref()),
+            provider_error_json: None,
+        })
+    } else {
+        Error {
+            code: ErrorCode::InternalError,
+            message: format!("{}: {err}", details.as_ref()),
+            provider_error_json: None,
+        }
+    }
+}
+
+/// Returns whether an error, or any error it was caused by, means that the connection was
+/// refused, reset or closed by the server, for example with an HTTP/2 GOAWAY frame.
+///
+/// The error is classified by the kind of the HTTP client's errors and of the underlying I/O
+/// errors. Only if neither tells, and the HTTP client failed while sending the request, the
+/// messages are checked as a last resort, as a GOAWAY or a connection closed by the server is
+/// reported by the HTTP/2 implementation without any I/O error or kind to check.
+pub fn is_connection_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    if error_chain(err).any(|err| {
+        err.downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_connect)
+            || err.downcast_ref::<std::io::Error>().is_some_and(|err| {
+                matches!(
+                    err.kind(),
+                    std::io::ErrorKind::ConnectionRefused
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::NotConnected
+                        | std::io::ErrorKind::BrokenPipe
+                )
+            })
+    }) {
+        return true;
+    }
+
+    let sending_failed = err
+        .downcast_ref::<reqwest::Error>()
+        .is_none_or(reqwest::Error::is_request);
+    sending_failed
+        && error_chain(err).any(|err| {
+            let message = err.to_string().to_lowercase();
+            [
+                "goaway",
+                "connection closed",
+                "connection reset",
+                "broken pipe",
+            ]
+            .iter()
+            .any(|pattern| message.contains(pattern))
+        })
+}
+
+/// Iterates over an error and the errors it was caused by
+fn error_chain<'a>(
+    err: &'a (dyn std::error::Error + 'static),
+) -> impl Iterator<Item = &'a (dyn std::error::Error + 'static)> {
+    std::iter::successors(Some(err), |err| err.source())
+}
+
+/// Returns whether a failed request can be retried: rate limits and transient failures, like
+/// server errors, timeouts and connection errors, are retriable, while invalid requests,
+/// authentication failures and unsupported features are permanent
+pub fn is_retriable(error: &Error) -> bool {
+    matches!(
+        error.code,
+        ErrorCode::RateLimitExceeded | ErrorCode::InternalError
+    )
+}
+
+/// Returns whether an error is a transient failure of the provider: a rate limit, a response
+/// with a server error or timeout status, a timeout or a connection failure. Other
+/// `internal-error`s, such as a response that could not be decoded, are not transient, as the
+/// same request would likely fail the same way again.
+///
+/// Transient `internal-error`s are classified when they are created, and marked as such in their
+/// `provider_error_json`, so the classification survives being returned by a provider component.
+pub fn is_transient(error: &Error) -> bool {
+    match error.code {
+        ErrorCode::RateLimitExceeded => true,
+        ErrorCode::InternalError => error
+            .provider_error_json
+            .as_deref()
+            .and_then(|json| serde_json::from_str::<serde_json::Value>(json).ok())
+            .is_some_and(|body| body.get(TRANSIENT) == Some(&serde_json::Value::Bool(true))),
+        _ => false,
+    }
+}
+
+/// Marks an error as a transient failure of the provider, see [`is_transient`]. The marker is
+/// added to the provider's error body if it is a JSON object, any other body is kept as a string
+/// under `body`.
+fn transient(mut error: Error) -> Error {
+    let mut body = match error
+        .provider_error_json
+        .as_deref()
+        .map(serde_json::from_str::<serde_json::Value>)
+    {
+        Some(Ok(serde_json::Value::Object(body))) => body,
+        _ => {
+            let mut body = serde_json::Map::new();
+            if let Some(raw) = error.provider_error_json.take() {
+                body.insert("body".to_string(), serde_json::Value::String(raw));
+            }
+            body
+        }
+    };
+    body.insert(TRANSIENT.to_string(), serde_json::Value::Bool(true));
+    error.provider_error_json = Some(serde_json::Value::Object(body).to_string());
+    error
+}
+
+/// Marks the error of a failed request as transient if its status is a server error or timeout
+fn classified_by_status(status: StatusCode, error: Error) -> Error {
+    if is_transient_status(status) {
+        transient(error)
+    } else {
+        error
+    }
+}
+
+/// Returns whether the status means that the provider failed or timed out
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || is_timeout_status(status)
+}
+
+pub fn from_event_source_error(details: impl AsRef<str>, err: event_source::error::Error) -> Error {
     Error {
         code: ErrorCode::InternalError,
         message: format!("{}: {err}", details.as_ref()),
@@ -19,14 +232,306 @@ pub fn from_reqwest_error(details: impl AsRef<str>, err: reqwest::Error) -> Erro
     }
 }
 
-pub fn from_event_source_error(details: impl AsRef<str>, err: event_source::error::Error) -> Error {
+/// Creates the error for a failed request whose response body is not in the provider's error
+/// format, such as an HTML page returned by a gateway. The raw body is passed through in
+/// `provider_error_json` so the real cause is not masked by a decoding error, as a string under
+/// `body` if the failure is transient, see [`is_transient`].
+pub fn unparseable_error_response(status: StatusCode, body: String) -> Error {
+    classified_by_status(
+        status,
+        Error {
+            code: error_code_from_status(status),
+            message: format!("{REQUEST_FAILED}{status}"),
+            provider_error_json: Some(body),
+        },
+    )
+}
+
+/// The body of a provider's error responses
+pub trait ErrorBody: DeserializeOwned + Debug {
+    /// Converts the decoded error body of a response with the given status
+    fn into_error(self, status: StatusCode) -> Error;
+
+    /// Returns whether the error is a transient failure of the provider, such as an overloaded
+    /// server, by its type or code. It is only checked for errors reported in responses with a
+    /// success status, the other errors are classified by their status.
+    fn is_transient(&self) -> bool {
+        false
+    }
+}
+
+/// Error body of providers whose error responses are not decoded, their raw body is passed
+/// through by [`parse_response`] instead
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RawErrorBody {}
+
+impl ErrorBody for RawErrorBody {
+    fn into_error(self, _status: StatusCode) -> Error {
+        match self {}
+    }
+}
+
+/// Error body of the OpenAI API and the OpenAI compatible APIs, such as xAI's
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAiErrorBody {
+    pub error: OpenAiError,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAiError {
+    pub message: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub param: Option<String>,
+}
+
+impl ErrorBody for OpenAiErrorBody {
+    /// These APIs occasionally report errors in responses with a success status, in which case
+    /// the error is classified by its code or type, such as `rate_limit_exceeded` or
+    /// `invalid_request_error`, instead of by the status
+    fn into_error(self, status: StatusCode) -> Error {
+        let mut error = error_from_body(status, &self.error.message, &self);
+        if status.is_success() {
+            error.code = self.error.error_code();
+        }
+        error
+    }
+
+    fn is_transient(&self) -> bool {
+        match self.error.status() {
+            Some(status) => is_transient_status(status),
+            None => self
+                .error
+                .is(&["server_error", "overloaded", "timeout", "unavailable"]),
+        }
+    }
+}
+
+impl OpenAiError {
+    fn error_code(&self) -> ErrorCode {
+        if let Some(status) = self.status() {
+            return error_code_from_status(status);
+        }
+
+        if self.is(&["rate_limit", "insufficient_quota"]) {
+            ErrorCode::RateLimitExceeded
+        } else if self.is(&["invalid_api_key", "authentication", "permission"]) {
+            ErrorCode::AuthenticationFailed
+        } else if self.is(&["invalid_request", "not_found", "context_length"]) {
+            ErrorCode::InvalidRequest
+        } else {
+            ErrorCode::InternalError
+        }
+    }
+
+    /// The HTTP status given as the numeric code of the error, if any
+    fn status(&self) -> Option<StatusCode> {
+        self.code
+            .as_ref()
+            .and_then(serde_json::Value::as_u64)
+            .and_then(|code| u16::try_from(code).ok())
+            .and_then(|code| StatusCode::from_u16(code).ok())
+    }
+
+    /// Returns whether the code or the type of the error contains any of the patterns
+    fn is(&self, patterns: &[&str]) -> bool {
+        [
+            self.code.as_ref().and_then(serde_json::Value::as_str),
+            self.typ.as_deref(),
+        ]
+        .iter()
+        .flatten()
+        .any(|kind| patterns.iter().any(|pattern| kind.contains(pattern)))
+    }
+}
+
+/// Creates the error of a failed request from the message and the whole error body of the
+/// provider. An error reported in a response with a success status gets a message of its own, as
+/// the status does not tell what failed.
+pub fn error_from_body(status: StatusCode, message: &str, body: &impl Serialize) -> Error {
+    let message = if status.is_success() {
+        format!("{ERROR_IN_SUCCESSFUL_RESPONSE}{message}")
+    } else {
+        format!("{REQUEST_FAILED}{status}: {message}")
+    };
+    classified_by_status(
+        status,
+        Error {
+            code: error_code_from_status(status),
+            message,
+            provider_error_json: serde_json::to_string(body).ok(),
+        },
+    )
+}
+
+/// Decodes the body of a provider's response.
+///
+/// Failed requests are converted to an error with the provider's [`ErrorBody`], or with
+/// [`unparseable_error_response`] if their body is in a different format. A successful response
+/// that does not match `T` but is a valid error body is an error too, as some providers report
+/// failures with a success status. Such an error is transient if the body says so, see
+/// [`ErrorBody::is_transient`].
+///
+/// Deprecation notices in the response headers, see [`deprecation_notice`], are logged as
+/// warnings so operators learn about retiring models before they stop working.
+pub fn parse_response<T: DeserializeOwned + Debug, E: ErrorBody>(
+    api_name: &str,
+    response: HttpResponse,
+) -> Result<T, Error> {
+    if let Some(notice) = deprecation_notice(&response) {
+        warn!("{TRACE_ID}{api_name} reported a deprecation: {notice}");
+    }
+
+    let status = response.status;
+    if status.is_success() {
+        match response.json::<T>() {
+            Ok(body) => {
+                trace!("{TRACE_ID}Received response from {api_name}: {body:?}");
+                Ok(body)
+            }
+            Err(err) => match response.json::<E>() {
+                Ok(error_body) => {
+                    trace!("{TRACE_ID}Received error in successful response from {api_name}: {error_body:?}");
+                    let is_transient = error_body.is_transient();
+                    let error = error_body.into_error(status);
+                    Err(if is_transient {
+                        transient(error)
+                    } else {
+                        error
+                    })
+                }
+                Err(_) => Err(response_decoding_error(err, response.text())),
+            },
+        }
+    } else {
+        match response.json::<E>() {
+            Ok(error_body) => {
+                trace!("{TRACE_ID}Received {status} response from {api_name}: {error_body:?}");
+                Err(error_body.into_error(status))
+            }
+            Err(err) => {
+                trace!("{TRACE_ID}Received {status} response from {api_name} with unexpected body: {err}");
+                Err(unparseable_error_response(status, response.text()))
+            }
+        }
+    }
+}
+
+/// Creates the error for a successful response whose body could not be decoded.
+///
+/// If the body is valid JSON, the provider most likely changed its response format, so the error
+/// reports a schema mismatch along with the JSON path of the offending value. The raw body is
+/// passed through in `provider_error_json` in both cases.
+pub fn response_decoding_error(err: serde_json::Error, body: String) -> Error {
+    let message = if serde_json::from_str::<serde::de::IgnoredAny>(&body).is_ok() {
+        format!(
+            "Response body does not match the expected schema at {}: {err}",
+            json_path_at(
+                &body,
+                err.line(),
+                err.column(),
+                err.to_string().starts_with("missing field")
+            )
+        )
+    } else {
+        format!("Failed to decode response body: {err}")
+    };
     Error {
         code: ErrorCode::InternalError,
-        message: format!("{}: {err}", details.as_ref()),
-        provider_error_json: None,
+        message,
+        provider_error_json: Some(body),
     }
 }
 
+enum PathSegment {
+    Field(Option<String>),
+    Index(usize),
+}
+
+/// Gets the path of the JSON value the parser was at when it stopped at the given position of
+/// `json`, in the `$.field[index]` notation. With `at_object` the path of the innermost object is
+/// returned instead of its last field, as serde reports missing fields when reaching the end of the
+/// object.
+fn json_path_at(json: &str, line: usize, column: usize, at_object: bool) -> String {
+    let line_start: usize = json
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let position = line_start + column;
+
+    let mut segments = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string = String::new();
+    let mut last_string = None;
+
+    for (idx, c) in json.char_indices() {
+        if idx >= position {
+            break;
+        }
+        if in_string {
+            match c {
+                _ if escaped => {
+                    escaped = false;
+                    string.push(c);
+                }
+                '\\' => escaped = true,
+                '"' => {
+                    in_string = false;
+                    last_string = Some(std::mem::take(&mut string));
+                }
+                _ => string.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' => segments.push(PathSegment::Field(None)),
+            '[' => segments.push(PathSegment::Index(0)),
+            '}' | ']' => {
+                segments.pop();
+            }
+            ':' => {
+                if let Some(PathSegment::Field(field)) = segments.last_mut() {
+                    *field = last_string.take();
+                }
+            }
+            ',' => match segments.last_mut() {
+                Some(PathSegment::Field(field)) => *field = None,
+                Some(PathSegment::Index(index)) => *index += 1,
+                None => {}
+            },
+            _ => {}
+        }
+    }
+
+    if at_object {
+        if let Some(PathSegment::Field(field)) = segments.last_mut() {
+            *field = None;
+        }
+    }
+
+    let mut path = "$".to_string();
+    for segment in segments {
+        match segment {
+            PathSegment::Field(Some(field)) => path.push_str(&format!(".{field}")),
+            PathSegment::Field(None) => {}
+            PathSegment::Index(index) => path.push_str(&format!("[{index}]")),
+        }
+    }
+    path
+}
+
+/// Maps an HTTP status to an `ErrorCode`.
+///
+/// Request timeouts (408 and 504) are mapped to `InternalError` like other server side failures,
+/// so they are treated as retriable instead of as an invalid request. Use `is_timeout_status` to
+/// tell them apart from other failures.
 pub fn error_code_from_status(status: StatusCode) -> ErrorCode {
     if status == StatusCode::TOO_MANY_REQUESTS {
         ErrorCode::RateLimitExceeded
@@ -35,9 +540,459 @@ pub fn error_code_from_status(status: StatusCode) -> ErrorCode {
         || status == StatusCode::PAYMENT_REQUIRED
     {
         ErrorCode::AuthenticationFailed
+    } else if is_timeout_status(status) {
+        ErrorCode::InternalError
     } else if status.is_client_error() {
         ErrorCode::InvalidRequest
     } else {
         ErrorCode::InternalError
     }
 }
+
+/// Returns whether the status means that the request timed out
+pub fn is_timeout_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::GATEWAY_TIMEOUT
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{
+        check_tool_result_id, check_tool_result_json, error_code_from_status, error_from_body,
+        from_reqwest_error, is_connection_error, is_retriable, is_timeout_status, is_transient,
+        parse_response, response_decoding_error, unparseable_error_response, ErrorBody,
+        OpenAiErrorBody, RawErrorBody,
+    };
+    use crate::golem::llm::llm::{
+        Error, ErrorCode, ToolCall, ToolFailure, ToolResult, ToolSuccess,
+    };
+    use crate::test_logger;
+    use crate::transport::HttpResponse;
+    use reqwest::StatusCode;
+
+    fn tool_success(result_json: &str) -> ToolSuccess {
+        ToolSuccess {
+            id: "call_1".to_string(),
+            name: "sum".to_string(),
+            result_json: result_json.to_string(),
+            execution_time_ms: None,
+        }
+    }
+
+    #[test]
+    fn valid_tool_result_json_is_accepted() {
+        assert_eq!(
+            check_tool_result_json(&tool_success(r#"{"value":6}"#)),
+            Ok(())
+        );
+        assert_eq!(check_tool_result_json(&tool_success("6")), Ok(()));
+    }
+
+    #[test]
+    fn invalid_tool_result_json_is_rejected() {
+        let error = check_tool_result_json(&tool_success("{ value: 6 }")).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert!(error
+            .message
+            .starts_with("Result of tool call call_1 (sum) is not valid JSON"));
+    }
+
+    #[test]
+    fn tool_result_must_answer_its_call() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "sum".to_string(),
+            arguments_json: "{}".to_string(),
+        };
+
+        assert_eq!(
+            check_tool_result_id(&call, &ToolResult::Success(tool_success("6"))),
+            Ok(())
+        );
+
+        let error = check_tool_result_id(
+            &call,
+            &ToolResult::Error(ToolFailure {
+                id: "fc_1".to_string(),
+                name: "sum".to_string(),
+                error_message: "failed".to_string(),
+                error_code: None,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "Result with id fc_1 does not match the id of tool call call_1 (sum)"
+        );
+    }
+
+    /// The error of a response with the given status and a body in the provider's error format
+    fn timed_out(status: StatusCode) -> Error {
+        parse_response::<serde_json::Value, ProviderError>(
+            "Test API",
+            HttpResponse::new(status, r#"{"message":"Timed out"}"#),
+        )
+        .unwrap_err()
+    }
+
+    #[test]
+    fn request_timeout_is_retriable() {
+        let error = timed_out(StatusCode::REQUEST_TIMEOUT);
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert!(error.message.to_lowercase().contains("timeout"));
+        assert!(is_retriable(&error));
+        assert!(is_transient(&error));
+    }
+
+    #[test]
+    fn gateway_timeout_is_retriable() {
+        let error = timed_out(StatusCode::GATEWAY_TIMEOUT);
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert!(error.message.to_lowercase().contains("timeout"));
+        assert!(is_retriable(&error));
+        assert!(is_transient(&error));
+    }
+
+    /// An error of the HTTP client caused by another error, like the ones surfaced by reqwest
+    #[derive(Debug)]
+    struct ClientError {
+        message: &'static str,
+        source: Option<std::io::Error>,
+    }
+
+    impl std::fmt::Display for ClientError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.message)
+        }
+    }
+
+    impl std::error::Error for ClientError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.source
+                .as_ref()
+                .map(|err| err as &(dyn std::error::Error + 'static))
+        }
+    }
+
+    #[test]
+    fn connection_errors_are_retriable() {
+        let broken_pipe = ClientError {
+            message: "error sending request",
+            source: Some(std::io::Error::from(std::io::ErrorKind::BrokenPipe)),
+        };
+        let go_away = ClientError {
+            message: "http2 error: connection error received: GOAWAY (NO_ERROR)",
+            source: None,
+        };
+        let invalid_json = ClientError {
+            message: "error decoding response body",
+            source: Some(std::io::Error::from(std::io::ErrorKind::InvalidData)),
+        };
+
+        assert!(is_connection_error(&broken_pipe));
+        assert!(is_connection_error(&go_away));
+        assert!(!is_connection_error(&invalid_json));
+
+        // Connection errors are reported like server errors, so they are retried the same way
+        let unavailable = Error {
+            code: error_code_from_status(StatusCode::SERVICE_UNAVAILABLE),
+            message: "Service unavailable".to_string(),
+            provider_error_json: None,
+        };
+        assert!(is_retriable(&unavailable));
+        assert!(!is_retriable(&Error {
+            code: ErrorCode::InvalidRequest,
+            ..unavailable
+        }));
+    }
+
+    #[test]
+    fn refused_connection_is_a_transient_failure() {
+        // Nothing listens on the port of a closed listener, so connecting to it is refused
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{port}"))
+            .send()
+            .unwrap_err();
+
+        let error = from_reqwest_error("Request failed", err);
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert!(error
+            .message
+            .starts_with("Request failed: connection failed: "));
+        assert!(is_retriable(&error));
+        assert!(is_transient(&error));
+    }
+
+    #[test]
+    fn other_client_errors_are_invalid_requests() {
+        assert!(!is_timeout_status(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            error_code_from_status(StatusCode::BAD_REQUEST),
+            ErrorCode::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn unparseable_error_response_passes_the_body_through() {
+        let body = "<html><body><h1>502 Bad Gateway</h1></body></html>".to_string();
+
+        let error = unparseable_error_response(StatusCode::BAD_GATEWAY, body.clone());
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert_eq!(error.message, "Request failed with 502 Bad Gateway");
+        assert_eq!(
+            error.provider_error_json,
+            Some(serde_json::json!({ "body": body, "transient": true }).to_string())
+        );
+
+        let error = unparseable_error_response(StatusCode::NOT_FOUND, body.clone());
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(error.provider_error_json, Some(body));
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct ProviderError {
+        message: String,
+    }
+
+    impl ErrorBody for ProviderError {
+        fn into_error(self, status: StatusCode) -> Error {
+            error_from_body(status, &self.message, &self)
+        }
+    }
+
+    #[test]
+    fn successful_response_is_decoded() {
+        let response = HttpResponse::new(StatusCode::OK, r#"{"message":"Hello"}"#);
+
+        let body: ProviderError = parse_response::<_, RawErrorBody>("Test API", response).unwrap();
+
+        assert_eq!(body.message, "Hello");
+    }
+
+    #[test]
+    fn deprecation_notice_is_logged_as_warning() {
+        test_logger::install();
+        let response = HttpResponse::new(StatusCode::OK, r#"{"message":"Hello"}"#)
+            .with_header("Deprecation", "@1750000000")
+            .with_header("Sunset", "Wed, 01 Oct 2025 00:00:00 GMT");
+
+        parse_response::<ProviderError, RawErrorBody>("Test API", response).unwrap();
+
+        assert_eq!(
+            test_logger::messages("golem_llm::error"),
+            vec!["WARN Test API reported a deprecation: @1750000000 (sunset: Wed, 01 Oct 2025 00:00:00 GMT)"]
+        );
+    }
+
+    #[test]
+    fn error_body_is_converted_by_the_provider() {
+        let response =
+            HttpResponse::new(StatusCode::TOO_MANY_REQUESTS, r#"{"message":"Slow down"}"#);
+
+        let error =
+            parse_response::<serde_json::Value, ProviderError>("Test API", response).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::RateLimitExceeded);
+        assert_eq!(
+            error.message,
+            "Request failed with 429 Too Many Requests: Slow down"
+        );
+        assert_eq!(
+            error.provider_error_json,
+            Some(r#"{"message":"Slow down"}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn error_body_in_successful_response_is_an_error() {
+        let response = HttpResponse::new(StatusCode::OK, r#"{"message":"Overloaded"}"#);
+
+        let error = parse_response::<Vec<String>, ProviderError>("Test API", response).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert_eq!(
+            error.message,
+            "Provider returned an error in a successful response: Overloaded"
+        );
+        assert!(!is_transient(&error));
+    }
+
+    #[test]
+    fn openai_error_in_successful_response_is_classified_by_its_code() {
+        for (body, code) in [
+            (
+                r#"{"error":{"message":"Bad","type":"invalid_request_error","param":"model"}}"#,
+                ErrorCode::InvalidRequest,
+            ),
+            (
+                r#"{"error":{"message":"No key","code":"invalid_api_key"}}"#,
+                ErrorCode::AuthenticationFailed,
+            ),
+            (
+                r#"{"error":{"message":"Busy","code":429}}"#,
+                ErrorCode::RateLimitExceeded,
+            ),
+            (
+                r#"{"error":{"message":"Oops","type":"unknown_error"}}"#,
+                ErrorCode::InternalError,
+            ),
+        ] {
+            let error = parse_response::<Vec<String>, OpenAiErrorBody>(
+                "Test API",
+                HttpResponse::new(StatusCode::OK, body),
+            )
+            .unwrap_err();
+
+            assert_eq!(error.code, code, "{body}");
+            assert_eq!(error.provider_error_json, Some(body.to_string()));
+            assert_eq!(
+                is_transient(&error),
+                code == ErrorCode::RateLimitExceeded,
+                "{body}"
+            );
+        }
+    }
+
+    #[test]
+    fn openai_server_error_in_successful_response_is_transient() {
+        for body in [
+            r#"{"error":{"message":"Oops","type":"server_error"}}"#,
+            r#"{"error":{"code":503,"message":"Unavailable"}}"#,
+        ] {
+            let error = parse_response::<Vec<String>, OpenAiErrorBody>(
+                "Test API",
+                HttpResponse::new(StatusCode::OK, body),
+            )
+            .unwrap_err();
+
+            assert_eq!(error.code, ErrorCode::InternalError, "{body}");
+            assert!(error
+                .message
+                .starts_with("Provider returned an error in a successful response: "));
+            assert!(is_transient(&error), "{body}");
+        }
+    }
+
+    #[test]
+    fn unexpected_error_body_is_passed_through() {
+        let page = "<html><body><h1>503 Service Unavailable</h1></body></html>";
+
+        for error in [
+            parse_response::<serde_json::Value, ProviderError>(
+                "Test API",
+                HttpResponse::new(StatusCode::SERVICE_UNAVAILABLE, page),
+            )
+            .unwrap_err(),
+            parse_response::<serde_json::Value, RawErrorBody>(
+                "Test API",
+                HttpResponse::new(StatusCode::SERVICE_UNAVAILABLE, r#"{"message":"Down"}"#),
+            )
+            .unwrap_err(),
+        ] {
+            assert_eq!(error.code, ErrorCode::InternalError);
+            assert_eq!(error.message, "Request failed with 503 Service Unavailable");
+        }
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Response {
+        id: String,
+        output: Vec<Output>,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Output {
+        text: String,
+        tokens: u32,
+    }
+
+    fn decoding_error(body: &str) -> crate::golem::llm::llm::Error {
+        let err = serde_json::from_str::<Response>(body).unwrap_err();
+        response_decoding_error(err, body.to_string())
+    }
+
+    #[test]
+    fn schema_mismatch_reports_path_of_wrong_value() {
+        let body = r#"{"id": "resp_1", "output": [{"text": "Hi", "tokens": 2}, {"text": "there", "tokens": "3"}]}"#;
+
+        let error = decoding_error(body);
+
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert!(error.message.starts_with(
+            "Response body does not match the expected schema at $.output[1].tokens: invalid type"
+        ));
+        assert_eq!(error.provider_error_json, Some(body.to_string()));
+    }
+
+    #[test]
+    fn schema_mismatch_reports_path_of_object_missing_field() {
+        let body = "{\n  \"id\": \"resp_1\",\n  \"output\": [{\"text\": \"Hi\"}]\n}";
+
+        let error = decoding_error(body);
+
+        assert!(error
+            .message
+            .starts_with("Response body does not match the expected schema at $.output[0]: missing field `tokens`"));
+    }
+
+    #[test]
+    fn invalid_json_is_reported_as_decoding_failure() {
+        let error = decoding_error("<html>Oops</html>");
+
+        assert!(error.message.starts_with("Failed to decode response body"));
+        assert_eq!(
+            error.provider_error_json,
+            Some("<html>Oops</html>".to_string())
+        );
+    }
+
+    #[test]
+    fn only_failures_of_the_provider_are_transient() {
+        let failed = |status: StatusCode| match parse_response::<serde_json::Value, RawErrorBody>(
+            "API",
+            HttpResponse::new(status, "Oops"),
+        ) {
+            Err(error) => error,
+            Ok(body) => panic!("Unexpected body: {body}"),
+        };
+
+        assert!(is_transient(&failed(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_transient(&failed(StatusCode::GATEWAY_TIMEOUT)));
+        assert!(is_transient(&failed(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_transient(&failed(StatusCode::BAD_REQUEST)));
+
+        // The classification is carried by the error, not derived from its message
+        let overloaded = error_from_body(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Overloaded",
+            &serde_json::json!({ "error": { "type": "overloaded_error" } }),
+        );
+        assert!(is_transient(&overloaded));
+        assert_eq!(
+            overloaded.provider_error_json,
+            Some(r#"{"error":{"type":"overloaded_error"},"transient":true}"#.to_string())
+        );
+        assert!(!is_transient(&Error {
+            provider_error_json: None,
+            ..overloaded
+        }));
+
+        let schema_mismatch = decoding_error(r#"{"output": "text"}"#);
+        assert_eq!(schema_mismatch.code, ErrorCode::InternalError);
+        assert!(!is_transient(&schema_mismatch));
+        assert!(!is_transient(&decoding_error("<html>Oops</html>")));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::event_source::event_stream::EventStreamError;
+use crate::event_source::utf8_stream::ReadError;
 use core::fmt;
-use golem_rust::bindings::wasi::io::streams::StreamError;
 use nom::error::Error as NomError;
 use reqwest::header::HeaderValue;
 use reqwest::Error as ReqwestError;
@@ -61,16 +61,14 @@ impl From<EventStreamError<ReqwestError>> for Error {
     }
 }
 
-impl From<EventStreamError<StreamError>> for Error {
-    fn from(err: EventStreamError<StreamError>) -> Self {
+impl From<EventStreamError<ReadError>> for Error {
+    fn from(err: EventStreamError<ReadError>) -> Self {
         match err {
             EventStreamError::Utf8(err) => Self::Utf8(err),
             EventStreamError::Parser(err) => Self::Parser(err),
             EventStreamError::Transport(err) => match err {
-                StreamError::Closed => Self::StreamEnded,
-                StreamError::LastOperationFailed(err) => {
-                    Self::TransportStream(err.to_debug_string())
-                }
+                ReadError::Closed => Self::StreamEnded,
+                ReadError::Failed(err) => Self::TransportStream(err),
             },
         }
     }
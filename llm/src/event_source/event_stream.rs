@@ -1,9 +1,8 @@
 use crate::event_source::parser::{is_bom, is_lf, line, RawEventLine};
-use crate::event_source::utf8_stream::{Utf8Stream, Utf8StreamError};
+use crate::event_source::utf8_stream::{ByteStream, ReadError, Utf8Stream, Utf8StreamError};
 use crate::event_source::MessageEvent;
 use core::fmt;
 use core::time::Duration;
-use golem_rust::bindings::wasi::io::streams::{InputStream, StreamError};
 use golem_rust::wasm_rpc::Pollable;
 use log::trace;
 use nom::error::Error as NomError;
@@ -119,27 +118,94 @@ impl EventStreamState {
     fn is_terminated(self) -> bool {
         matches!(self, Self::Terminated)
     }
-    fn is_started(self) -> bool {
-        matches!(self, Self::Started)
+}
+
+/// Parses the text of an event stream, arriving in arbitrary chunks, into events
+#[derive(Default)]
+pub struct EventParser {
+    buffer: String,
+    builder: EventBuilder,
+    started: bool,
+    /// Length of the buffer's prefix already known not to complete a line
+    scanned: usize,
+    /// Comment lines parsed since the last [`EventParser::take_comments`]
+    comments: usize,
+    /// Bytes of the buffer looked at so far, to check that parsing is linear in the size of the
+    /// stream
+    #[cfg(test)]
+    examined: usize,
+}
+
+impl EventParser {
+    /// Appends the next chunk of the stream, dropping the byte order mark at its beginning
+    pub fn push(&mut self, chunk: &str) {
+        if chunk.is_empty() {
+            return;
+        }
+
+        let chunk = if self.started {
+            chunk
+        } else {
+            self.started = true;
+            chunk.strip_prefix(is_bom).unwrap_or(chunk)
+        };
+        self.buffer.push_str(chunk);
+    }
+
+    /// Returns the next complete event from the chunks pushed so far
+    pub fn next_event<E>(&mut self) -> Result<Option<MessageEvent>, EventStreamError<E>> {
+        // Only parse again if a line ending arrived, otherwise a long line split into many chunks
+        // would be parsed from its beginning for each of them
+        let line_end = self.buffer[self.scanned..].find(['\n', '\r']);
+        #[cfg(test)]
+        {
+            self.examined += line_end.map_or(self.buffer.len() - self.scanned, |idx| idx + 1);
+        }
+        if line_end.is_none() {
+            self.scanned = self.buffer.len();
+            return Ok(None);
+        }
+
+        #[cfg(test)]
+        let unparsed = self.buffer.len();
+        let result = parse_event(&mut self.buffer, &mut self.builder, &mut self.comments);
+        #[cfg(test)]
+        {
+            // The parsed lines, and the incomplete line left over if no event was completed
+            self.examined += match result {
+                Ok(None) => unparsed,
+                _ => unparsed - self.buffer.len(),
+            };
+        }
+        self.scanned = match result {
+            // A trailing CR may be the first half of a CRLF
+            Ok(None) => self.buffer.len() - usize::from(self.buffer.ends_with('\r')),
+            _ => 0,
+        };
+        result
+    }
+
+    /// Returns the number of comment lines parsed since the last call. Servers send comments as
+    /// keep-alives, so they are not dispatched as events but can be noticed this way.
+    pub fn take_comments(&mut self) -> usize {
+        std::mem::take(&mut self.comments)
     }
 }
 
 /// A Stream of events
 pub struct EventStream {
     stream: Utf8Stream,
-    buffer: String,
-    builder: EventBuilder,
+    parser: EventParser,
     state: EventStreamState,
     last_event_id: String,
 }
 
 impl EventStream {
     /// Initialize the EventStream with a Stream
-    pub fn new(stream: InputStream) -> Self {
+    pub fn new(stream: impl ByteStream + 'static) -> Self {
         Self {
             stream: Utf8Stream::new(stream),
-            buffer: String::new(),
-            builder: EventBuilder::default(),
+            parser: EventParser::default(),
             state: EventStreamState::NotStarted,
             last_event_id: String::new(),
         }
@@ -160,12 +226,15 @@ impl EventStream {
         self.stream.subscribe()
     }
 
-    pub fn poll_next(
-        &mut self,
-    ) -> Poll<Option<Result<MessageEvent, EventStreamError<StreamError>>>> {
+    /// Returns the number of keep-alive comments received since the last call
+    pub fn take_comments(&mut self) -> usize {
+        self.parser.take_comments()
+    }
+
+    pub fn poll_next(&mut self) -> Poll<Option<Result<MessageEvent, EventStreamError<ReadError>>>> {
         trace!("Polling for next event");
 
-        match parse_event(&mut self.buffer, &mut self.builder) {
+        match self.parser.next_event() {
             Ok(Some(event)) => {
                 self.last_event_id = event.id.clone();
                 return Poll::Ready(Some(Ok(event)));
@@ -185,19 +254,10 @@ impl EventStream {
                         continue;
                     }
 
-                    let slice = if self.state.is_started() {
-                        &string
-                    } else {
-                        self.state = EventStreamState::Started;
-                        if is_bom(string.chars().next().unwrap()) {
-                            &string[1..]
-                        } else {
-                            &string
-                        }
-                    };
-                    self.buffer.push_str(slice);
+                    self.state = EventStreamState::Started;
+                    self.parser.push(&string);
 
-                    match parse_event(&mut self.buffer, &mut self.builder) {
+                    match self.parser.next_event() {
                         Ok(Some(event)) => {
                             self.last_event_id = event.id.clone();
                             return Poll::Ready(Some(Ok(event)));
@@ -261,6 +321,7 @@ impl<E> std::error::Error for EventStreamError<E> where E: fmt::Display + fmt::D
 fn parse_event<E>(
     buffer: &mut String,
     builder: &mut EventBuilder,
+    comments: &mut usize,
 ) -> Result<Option<MessageEvent>, EventStreamError<E>> {
     if buffer.is_empty() {
         return Ok(None);
@@ -268,6 +329,9 @@ fn parse_event<E>(
     loop {
         match line(buffer.as_ref()) {
             Ok((rem, next_line)) => {
+                if matches!(next_line, RawEventLine::Comment(_)) {
+                    *comments += 1;
+                }
                 builder.add(next_line);
                 let consumed = buffer.len() - rem.len();
                 let rem = buffer.split_off(consumed);
@@ -283,3 +347,189 @@ fn parse_event<E>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::event_source::event_stream::EventParser;
+    use crate::event_source::utf8_stream::Utf8Decoder;
+    use crate::event_source::MessageEvent;
+    use std::time::Duration;
+
+    // Each byte is looked at when searching for a line ending, when parsing its line, and again
+    // if a CRLF is split between chunks. Handling the buffer in a quadratic way would look at
+    // the bytes of a long line once per chunk instead.
+    const MAX_EXAMINED_PER_BYTE: usize = 4;
+
+    /// Feeds `bytes` through the decoder and the parser in `chunk_size` chunks, the same way
+    /// `EventStream::poll_next` does with the chunks read from the response stream. Returns the
+    /// events and the number of bytes the parser looked at.
+    fn parse(bytes: &[u8], chunk_size: usize) -> (Vec<MessageEvent>, usize) {
+        let mut decoder = Utf8Decoder::default();
+        let mut parser = EventParser::default();
+        let mut events = Vec::new();
+
+        let mut drain = |parser: &mut EventParser| {
+            while let Some(event) = parser.next_event::<()>().unwrap() {
+                events.push(event);
+            }
+        };
+        for chunk in bytes.chunks(chunk_size) {
+            parser.push(&decoder.decode(chunk));
+            drain(&mut parser);
+        }
+        if let Some(rest) = decoder.finish() {
+            parser.push(&rest.unwrap());
+            drain(&mut parser);
+        }
+
+        (events, parser.examined)
+    }
+
+    fn assert_parsed(fixture: &str, expected: &[MessageEvent], chunk_sizes: &[usize]) {
+        for chunk_size in chunk_sizes {
+            let (events, examined) = parse(fixture.as_bytes(), *chunk_size);
+
+            assert_eq!(events.len(), expected.len());
+            assert!(
+                events == expected,
+                "events differ with {chunk_size} byte chunks"
+            );
+            assert!(
+                examined <= MAX_EXAMINED_PER_BYTE * fixture.len(),
+                "parser looked at {examined} bytes of {} with {chunk_size} byte chunks",
+                fixture.len()
+            );
+        }
+    }
+
+    fn event(data: &str) -> MessageEvent {
+        MessageEvent {
+            event: "message".to_string(),
+            data: data.to_string(),
+            id: String::new(),
+            retry: None,
+        }
+    }
+
+    fn fixture(events: &[MessageEvent], line_ending: &str) -> String {
+        events
+            .iter()
+            .map(|event| {
+                let data = event
+                    .data
+                    .split('\n')
+                    .map(|line| format!("data: {line}{line_ending}"))
+                    .collect::<String>();
+                format!("event: {}{line_ending}{data}{line_ending}", event.event)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn many_small_frames() {
+        let expected = (0..20_000)
+            .map(|idx| event(&format!(r#"{{"type":"delta","text":"token {idx}"}}"#)))
+            .collect::<Vec<_>>();
+
+        assert_parsed(&fixture(&expected, "\n"), &expected, &[1024, 17]);
+    }
+
+    #[test]
+    fn large_frames() {
+        let expected = (0..4)
+            .map(|idx| event(&idx.to_string().repeat(256 * 1024)))
+            .collect::<Vec<_>>();
+
+        assert_parsed(&fixture(&expected, "\n"), &expected, &[1024, 64 * 1024]);
+    }
+
+    #[test]
+    fn multibyte_content_split_between_chunks() {
+        let expected = (0..5_000)
+            .map(|idx| event(&format!("{idx}: árvíztűrő 🦀 голем 語言モデル")))
+            .collect::<Vec<_>>();
+
+        // Odd chunk sizes split the multibyte characters at every possible byte offset
+        assert_parsed(&fixture(&expected, "\n"), &expected, &[1024, 7, 3, 1]);
+    }
+
+    #[test]
+    fn multiline_data_and_crlf_line_endings() {
+        let expected = (0..5_000)
+            .map(|idx| event(&format!("line {idx}\nline {}\n{{}}", idx + 1)))
+            .collect::<Vec<_>>();
+
+        assert_parsed(&fixture(&expected, "\r\n"), &expected, &[1024, 2, 1]);
+    }
+
+    #[test]
+    fn comments_ids_and_byte_order_mark() {
+        let fixture = "\u{feff}: keep-alive\n\nid: 1\nevent: delta\ndata: first\n\n: ping\nretry: 500\ndata: second\n\n";
+        let expected = vec![
+            MessageEvent {
+                event: "delta".to_string(),
+                data: "first".to_string(),
+                id: "1".to_string(),
+                retry: None,
+            },
+            MessageEvent {
+                event: "message".to_string(),
+                data: "second".to_string(),
+                id: "1".to_string(),
+                retry: Some(Duration::from_millis(500)),
+            },
+        ];
+
+        assert_parsed(fixture, &expected, &[1024, 1]);
+    }
+
+    #[test]
+    fn comments_are_counted_without_dispatching_events() {
+        let mut parser = EventParser::default();
+        parser.push(": OPENROUTER PROCESSING\n\n: OPENROUTER PROCESSING\n\n");
+
+        assert_eq!(parser.next_event::<()>(), Ok(None));
+        assert_eq!(parser.take_comments(), 2);
+        assert_eq!(parser.take_comments(), 0);
+
+        parser.push(": ping\ndata: first\n\n");
+        assert_eq!(parser.next_event::<()>(), Ok(Some(event("first"))));
+        assert_eq!(parser.take_comments(), 1);
+    }
+
+    #[test]
+    fn invalid_utf8_at_the_end_of_the_stream_is_reported() {
+        let mut decoder = Utf8Decoder::default();
+
+        assert_eq!(decoder.decode(b"data: \xF0\x9F"), "data: ");
+        assert!(decoder.finish().unwrap().is_err());
+    }
+
+    #[test]
+    fn incomplete_frame_is_not_dispatched() {
+        let mut parser = EventParser::default();
+        parser.push("data: partial");
+
+        assert_eq!(parser.next_event::<()>(), Ok(None));
+
+        parser.push("\n\n");
+
+        assert_eq!(parser.next_event::<()>(), Ok(Some(event("partial"))));
+        assert_eq!(parser.next_event::<()>(), Ok(None));
+    }
+
+    #[test]
+    fn json_payload_split_across_data_lines() {
+        let fixture = "data: {\"type\":\"delta\",\ndata: \"text\":\"Hello\"}\n\n";
+        let expected = vec![event("{\"type\":\"delta\",\n\"text\":\"Hello\"}")];
+
+        assert_parsed(fixture, &expected, &[1024, 5, 1]);
+
+        let (events, _) = parse(fixture.as_bytes(), 1024);
+        let payload: serde_json::Value = serde_json::from_str(&events[0].data).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({"type": "delta", "text": "Hello"})
+        );
+    }
+}
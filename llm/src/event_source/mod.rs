@@ -9,11 +9,13 @@ mod utf8_stream;
 
 use crate::event_source::error::Error;
 use crate::event_source::event_stream::EventStream;
+use crate::event_source::utf8_stream::InputByteStream;
 use golem_rust::wasm_rpc::Pollable;
 pub use message_event::MessageEvent;
 use reqwest::header::HeaderValue;
 use reqwest::{Response, StatusCode};
 use std::task::Poll;
+pub use utf8_stream::{ByteStream, ReadError};
 
 /// The ready state of an [`EventSource`]
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
@@ -28,8 +30,9 @@ pub enum ReadyState {
 }
 
 pub struct EventSource {
-    stream: EventStream,
-    response: Response,
+    // The stream must be released before the response it reads from
+    stream: Option<EventStream>,
+    response: Option<Response>,
     is_closed: bool,
 }
 
@@ -43,10 +46,10 @@ impl EventSource {
                         golem_rust::bindings::wasi::io::streams::InputStream,
                     >(response.get_raw_input_stream())
                 };
-                let stream = EventStream::new(handle);
+                let stream = EventStream::new(InputByteStream::new(handle));
                 Ok(Self {
-                    response,
-                    stream,
+                    stream: Some(stream),
+                    response: Some(response),
                     is_closed: false,
                 })
             }
@@ -54,9 +57,23 @@ impl EventSource {
         }
     }
 
-    /// Close the EventSource stream and stop trying to reconnect
+    /// Reads the events from a stream not backed by an HTTP response, like a test double
+    pub fn from_stream(stream: impl ByteStream + 'static) -> Self {
+        Self {
+            stream: Some(EventStream::new(stream)),
+            response: None,
+            is_closed: false,
+        }
+    }
+
+    /// Close the EventSource stream and stop trying to reconnect.
+    ///
+    /// The underlying input stream and HTTP response are released immediately, closing the
+    /// connection. All pollables returned by [`EventSource::subscribe`] must be dropped before.
     pub fn close(&mut self) {
         self.is_closed = true;
+        self.stream = None;
+        self.response = None;
     }
 
     /// Get the current ready state
@@ -69,15 +86,25 @@ impl EventSource {
     }
 
     pub fn subscribe(&self) -> Pollable {
-        self.stream.subscribe()
+        match &self.stream {
+            Some(stream) => stream.subscribe(),
+            None => golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(0),
+        }
+    }
+
+    /// Returns the number of comments received since the last call. Servers send comments as
+    /// keep-alives, so they are not emitted as events.
+    pub fn take_comments(&mut self) -> usize {
+        self.stream.as_mut().map_or(0, EventStream::take_comments)
     }
 
     pub fn poll_next(&mut self) -> Poll<Option<Result<Event, Error>>> {
-        if self.is_closed {
-            return Poll::Ready(None);
-        }
+        let stream = match &mut self.stream {
+            Some(stream) if !self.is_closed => stream,
+            _ => return Poll::Ready(None),
+        };
 
-        match self.stream.poll_next() {
+        match stream.poll_next() {
             Poll::Ready(Some(Err(err))) => {
                 let err = err.into();
                 self.is_closed = true;
@@ -94,6 +121,12 @@ impl EventSource {
     }
 }
 
+impl Drop for EventSource {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
 fn check_response(response: Response) -> Result<Response, Error> {
     match response.status() {
         StatusCode::OK => {}
@@ -142,3 +175,57 @@ impl From<MessageEvent> for Event {
         Event::Message(event)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::event_source::{Event, EventSource, ReadyState};
+    use crate::transport::CannedStream;
+    use std::task::Poll;
+
+    #[test]
+    fn close_releases_the_stream() {
+        let stream = CannedStream::new(["data: Hello\n\n"]);
+        let released = stream.released();
+        let mut event_source = EventSource::from_stream(stream);
+        assert_eq!(event_source.ready_state(), ReadyState::Open);
+        assert!(event_source.stream.is_some());
+
+        event_source.close();
+
+        assert_eq!(event_source.ready_state(), ReadyState::Closed);
+        assert!(released.get());
+        assert!(event_source.stream.is_none());
+        assert!(matches!(event_source.poll_next(), Poll::Ready(None)));
+    }
+
+    #[test]
+    fn drop_releases_the_stream() {
+        let stream = CannedStream::new(["data: Hello\n\n"]);
+        let released = stream.released();
+        let event_source = EventSource::from_stream(stream);
+        assert!(!released.get());
+
+        drop(event_source);
+
+        assert!(released.get());
+    }
+
+    #[test]
+    fn closed_stream_stays_ended() {
+        let mut event_source =
+            EventSource::from_stream(CannedStream::new(["data: Hello\n\n", "data: World\n\n"]));
+        assert!(matches!(
+            event_source.poll_next(),
+            Poll::Ready(Some(Ok(Event::Message(message)))) if message.data == "Hello"
+        ));
+
+        event_source.close();
+        event_source.close();
+
+        for _ in 0..3 {
+            assert!(matches!(event_source.poll_next(), Poll::Ready(None)));
+        }
+        assert_eq!(event_source.ready_state(), ReadyState::Closed);
+        assert!(event_source.stream.is_none());
+    }
+}
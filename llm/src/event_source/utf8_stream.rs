@@ -4,22 +4,70 @@ use log::trace;
 use std::string::FromUtf8Error;
 use std::task::Poll;
 
-pub struct Utf8Stream {
+/// The bytes of a response body, read as they arrive. Implemented by the wasi-http body stream,
+/// and by test doubles.
+pub trait ByteStream {
+    /// Returns a pollable which is ready when bytes can be read or the stream ended
+    fn subscribe(&self) -> Pollable;
+
+    /// Reads up to `len` bytes if some are available, without blocking
+    fn poll_read(&mut self, len: u64) -> Poll<Result<Vec<u8>, ReadError>>;
+}
+
+/// Error reading a [`ByteStream`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadError {
+    /// The stream ended
+    Closed,
+    /// Reading failed, for example because the connection dropped
+    Failed(String),
+}
+
+/// The wasi-http response body stream
+pub struct InputByteStream {
+    // The pollable must be dropped before the stream it belongs to
     subscription: Pollable,
     stream: InputStream,
-    buffer: Vec<u8>,
+}
+
+impl InputByteStream {
+    pub fn new(stream: InputStream) -> Self {
+        Self {
+            subscription: stream.subscribe(),
+            stream,
+        }
+    }
+}
+
+impl ByteStream for InputByteStream {
+    fn subscribe(&self) -> Pollable {
+        self.stream.subscribe()
+    }
+
+    fn poll_read(&mut self, len: u64) -> Poll<Result<Vec<u8>, ReadError>> {
+        if !self.subscription.ready() {
+            return Poll::Pending;
+        }
+        Poll::Ready(self.stream.read(len).map_err(|err| match err {
+            StreamError::Closed => ReadError::Closed,
+            StreamError::LastOperationFailed(err) => ReadError::Failed(err.to_debug_string()),
+        }))
+    }
+}
+
+pub struct Utf8Stream {
+    stream: Box<dyn ByteStream>,
+    decoder: Utf8Decoder,
     terminated: bool,
 }
 
 impl Utf8Stream {
     const CHUNK_SIZE: u64 = 1024;
 
-    pub fn new(stream: InputStream) -> Self {
-        let subscription = stream.subscribe();
+    pub fn new(stream: impl ByteStream + 'static) -> Self {
         Self {
-            stream,
-            subscription,
-            buffer: Vec::new(),
+            stream: Box::new(stream),
+            decoder: Utf8Decoder::default(),
             terminated: false,
         }
     }
@@ -28,42 +76,61 @@ impl Utf8Stream {
         self.stream.subscribe()
     }
 
-    pub fn poll_next(&mut self) -> Poll<Option<Result<String, Utf8StreamError<StreamError>>>> {
-        if !self.terminated && self.subscription.ready() {
-            match self.stream.read(Self::CHUNK_SIZE) {
-                Ok(bytes) => {
-                    trace!("Read {} bytes from response stream", bytes.len());
-
-                    self.buffer.extend_from_slice(bytes.as_ref());
-                    let bytes = core::mem::take(&mut self.buffer);
-                    match String::from_utf8(bytes) {
-                        Ok(string) => Poll::Ready(Some(Ok(string))),
-                        Err(err) => {
-                            let valid_size = err.utf8_error().valid_up_to();
-                            let mut bytes = err.into_bytes();
-                            let rem = bytes.split_off(valid_size);
-                            self.buffer = rem;
-                            Poll::Ready(Some(Ok(unsafe { String::from_utf8_unchecked(bytes) })))
-                        }
-                    }
-                }
-                Err(StreamError::Closed) => {
-                    trace!("Response stream closed");
-
-                    self.terminated = true;
-                    if self.buffer.is_empty() {
-                        Poll::Ready(None)
-                    } else {
-                        Poll::Ready(Some(
-                            String::from_utf8(core::mem::take(&mut self.buffer))
-                                .map_err(Utf8StreamError::Utf8),
-                        ))
-                    }
-                }
-                Err(err) => Poll::Ready(Some(Err(Utf8StreamError::Transport(err)))),
+    pub fn poll_next(&mut self) -> Poll<Option<Result<String, Utf8StreamError<ReadError>>>> {
+        if self.terminated {
+            return Poll::Pending;
+        }
+        match self.stream.poll_read(Self::CHUNK_SIZE) {
+            Poll::Ready(Ok(bytes)) => {
+                trace!("Read {} bytes from response stream", bytes.len());
+
+                Poll::Ready(Some(Ok(self.decoder.decode(&bytes))))
+            }
+            Poll::Ready(Err(ReadError::Closed)) => {
+                trace!("Response stream closed");
+
+                self.terminated = true;
+                Poll::Ready(
+                    self.decoder
+                        .finish()
+                        .map(|result| result.map_err(Utf8StreamError::Utf8)),
+                )
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(Utf8StreamError::Transport(err)))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes a byte stream arriving in arbitrary chunks into strings, holding back multibyte
+/// characters split between chunks until the rest of their bytes arrive
+#[derive(Default)]
+pub struct Utf8Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    /// Returns the longest valid UTF-8 prefix of the pending and the new bytes
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        self.buffer.extend_from_slice(bytes);
+        let bytes = core::mem::take(&mut self.buffer);
+        match String::from_utf8(bytes) {
+            Ok(string) => string,
+            Err(err) => {
+                let valid_size = err.utf8_error().valid_up_to();
+                let mut bytes = err.into_bytes();
+                self.buffer = bytes.split_off(valid_size);
+                unsafe { String::from_utf8_unchecked(bytes) }
             }
+        }
+    }
+
+    /// Decodes the remaining bytes at the end of the stream, if there are any
+    pub fn finish(&mut self) -> Option<Result<String, FromUtf8Error>> {
+        if self.buffer.is_empty() {
+            None
         } else {
-            Poll::Pending
+            Some(String::from_utf8(core::mem::take(&mut self.buffer)))
         }
     }
 }
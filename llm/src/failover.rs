@@ -0,0 +1,324 @@
+use crate::error::is_transient;
+use crate::golem::llm::llm::{ChatEvent, Config, Error, ErrorCode, Message};
+use crate::models::{closest_model_to, model_info, ModelRequirements};
+use crate::trace_id::TRACE_ID;
+use log::warn;
+
+/// Which errors of a provider make a [`Failover`] try the next one
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailoverPolicy {
+    fail_over_on: Vec<ErrorCode>,
+    transient_only: bool,
+}
+
+impl FailoverPolicy {
+    /// Fails over on the given error codes. Invalid requests and authentication failures would
+    /// fail the same way with any provider, or mean that the configuration is wrong, so failing
+    /// over on them is refused.
+    pub fn on(fail_over_on: Vec<ErrorCode>) -> Result<Self, Error> {
+        if let Some(code) = fail_over_on.iter().find(|code| {
+            matches!(
+                code,
+                ErrorCode::InvalidRequest | ErrorCode::AuthenticationFailed
+            )
+        }) {
+            return Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!("Cannot fail over on {code:?} errors"),
+                provider_error_json: None,
+            });
+        }
+        Ok(Self {
+            fail_over_on,
+            transient_only: false,
+        })
+    }
+
+    pub fn fails_over_on(&self, error: &Error) -> bool {
+        self.fail_over_on.contains(&error.code) && (!self.transient_only || is_transient(error))
+    }
+}
+
+impl Default for FailoverPolicy {
+    /// Fails over on rate limits and on the `internal-error`s that are transient failures of the
+    /// provider, see [`is_transient`]: server errors, timeouts and connection errors. Responses
+    /// that could not be decoded would fail the same way with the next provider, so they do not
+    /// fail over.
+    fn default() -> Self {
+        Self {
+            fail_over_on: vec![ErrorCode::RateLimitExceeded, ErrorCode::InternalError],
+            transient_only: true,
+        }
+    }
+}
+
+/// A provider a [`Failover`] can send a request to, with the model to use with it
+pub struct FailoverTarget<'a> {
+    pub provider: String,
+    pub model: String,
+    send: Box<dyn Fn(Vec<Message>, Config) -> ChatEvent + 'a>,
+}
+
+impl<'a> FailoverTarget<'a> {
+    /// `send` calls the `send` (or `continue`) function of the provider's component
+    pub fn new(
+        provider: impl Into<String>,
+        model: impl Into<String>,
+        send: impl Fn(Vec<Message>, Config) -> ChatEvent + 'a,
+    ) -> Self {
+        Self {
+            provider: provider.into(),
+            model: model.into(),
+            send: Box::new(send),
+        }
+    }
+
+    /// A target using the model of `provider` equivalent to `model`, that is supporting vision
+    /// and tools if it does, having at least its context window and being the closest to it in
+    /// price, so a request fails over to a model of the same tier. Returns `None` if `model` is
+    /// unknown or the provider has no equivalent model.
+    pub fn equivalent(
+        config: &Config,
+        provider: impl Into<String>,
+        model: &str,
+        send: impl Fn(Vec<Message>, Config) -> ChatEvent + 'a,
+    ) -> Result<Option<Self>, Error> {
+        let Some(info) = model_info(config, model)? else {
+            return Ok(None);
+        };
+        let provider = provider.into();
+        let requirements = ModelRequirements {
+            needs_vision: info.supports_vision,
+            needs_tools: info.supports_tools,
+            min_context: Some(info.context_window),
+            max_price: None,
+        };
+        Ok(
+            closest_model_to(config, model, &requirements, &[provider.as_str()])?
+                .map(|choice| Self::new(provider, choice.model, send)),
+        )
+    }
+}
+
+/// Sends a request to a primary provider and, when it fails with an error of the policy, fails
+/// over to the next providers in order, transparently to the caller. The model of the config is
+/// replaced with the model of each target, the rest of the config is sent as it is.
+///
+/// The error of the last provider tried is returned if all of them fail.
+pub struct Failover<'a> {
+    policy: FailoverPolicy,
+    targets: Vec<FailoverTarget<'a>>,
+}
+
+impl<'a> Failover<'a> {
+    pub fn new(targets: Vec<FailoverTarget<'a>>) -> Self {
+        Self::with_policy(FailoverPolicy::default(), targets)
+    }
+
+    pub fn with_policy(policy: FailoverPolicy, targets: Vec<FailoverTarget<'a>>) -> Self {
+        Self { policy, targets }
+    }
+
+    pub fn send(&self, messages: Vec<Message>, config: Config) -> ChatEvent {
+        let mut last = ChatEvent::Error(Error {
+            code: ErrorCode::InvalidRequest,
+            message: "No providers to send the request to".to_string(),
+            provider_error_json: None,
+        });
+        for (idx, target) in self.targets.iter().enumerate() {
+            let config = Config {
+                model: target.model.clone(),
+                ..config.clone()
+            };
+            let event = (target.send)(messages.clone(), config);
+            match &event {
+                ChatEvent::Error(error) if self.policy.fails_over_on(error) => {
+                    if let Some(next) = self.targets.get(idx + 1) {
+                        warn!(
+                            "{TRACE_ID}{} failed with {:?}, failing over to {}: {}",
+                            target.provider, error.code, next.provider, error.message
+                        );
+                    }
+                    last = event;
+                }
+                _ => return event,
+            }
+        }
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{parse_response, response_decoding_error, RawErrorBody};
+    use crate::failover::{Failover, FailoverPolicy, FailoverTarget};
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, Message,
+        ResponseMetadata,
+    };
+    use crate::prompt::user_message;
+    use crate::test_config;
+    use crate::transport::HttpResponse;
+    use reqwest::StatusCode;
+    use std::cell::RefCell;
+
+    fn config() -> Config {
+        Config {
+            model: "gpt-4o".to_string(),
+            temperature: Some(0.2),
+            ..test_config::config(&[])
+        }
+    }
+
+    fn response(text: &str) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "id".to_string(),
+            content: vec![ContentPart::Text(text.to_string())],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            },
+        })
+    }
+
+    /// The error a provider returns for a response with the given status
+    fn failure(status: StatusCode) -> ChatEvent {
+        let response = HttpResponse::new(status, r#"{"error":{"message":"Try again later"}}"#);
+        match parse_response::<serde_json::Value, RawErrorBody>("API", response) {
+            Err(error) => ChatEvent::Error(error),
+            Ok(body) => panic!("Unexpected body: {body}"),
+        }
+    }
+
+    #[test]
+    fn rate_limited_primary_fails_over_to_the_secondary() {
+        let calls = RefCell::new(Vec::new());
+        let calls_ref = &calls;
+        let record = move |provider: &'static str, event: fn() -> ChatEvent| {
+            move |_: Vec<Message>, config: Config| {
+                calls_ref.borrow_mut().push((provider, config.model));
+                event()
+            }
+        };
+        let failover = Failover::new(vec![
+            FailoverTarget::new(
+                "openai",
+                "gpt-4o",
+                record("openai", || failure(StatusCode::TOO_MANY_REQUESTS)),
+            ),
+            FailoverTarget::new(
+                "anthropic",
+                "claude-sonnet-4-0",
+                record("anthropic", || response("Hello from the secondary")),
+            ),
+        ]);
+
+        let event = failover.send(vec![user_message("Hello")], config());
+
+        assert_eq!(event, response("Hello from the secondary"));
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                ("openai", "gpt-4o".to_string()),
+                ("anthropic", "claude-sonnet-4-0".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_requests_do_not_fail_over() {
+        let secondary_called = RefCell::new(false);
+        let failover = Failover::new(vec![
+            FailoverTarget::new("openai", "gpt-4o", |_, _| failure(StatusCode::BAD_REQUEST)),
+            FailoverTarget::new("anthropic", "claude-sonnet-4-0", |_, _| {
+                *secondary_called.borrow_mut() = true;
+                response("Hello")
+            }),
+        ]);
+
+        match failover.send(vec![user_message("Hello")], config()) {
+            ChatEvent::Error(error) => assert_eq!(error.code, ErrorCode::InvalidRequest),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        assert!(!*secondary_called.borrow());
+    }
+
+    #[test]
+    fn undecodable_responses_do_not_fail_over_by_default() {
+        let secondary_called = RefCell::new(false);
+        let failover = Failover::new(vec![
+            FailoverTarget::new("openai", "gpt-4o", |_, _| {
+                let err = serde_json::from_str::<Vec<String>>("{}").unwrap_err();
+                ChatEvent::Error(response_decoding_error(err, "{}".to_string()))
+            }),
+            FailoverTarget::new("anthropic", "claude-sonnet-4-0", |_, _| {
+                *secondary_called.borrow_mut() = true;
+                response("Hello")
+            }),
+        ]);
+
+        match failover.send(vec![user_message("Hello")], config()) {
+            ChatEvent::Error(error) => assert_eq!(error.code, ErrorCode::InternalError),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+        assert!(!*secondary_called.borrow());
+    }
+
+    #[test]
+    fn last_error_is_returned_when_all_providers_fail() {
+        let failover = Failover::new(vec![
+            FailoverTarget::new("openai", "gpt-4o", |_, _| {
+                failure(StatusCode::TOO_MANY_REQUESTS)
+            }),
+            FailoverTarget::new("anthropic", "claude-sonnet-4-0", |_, _| {
+                failure(StatusCode::SERVICE_UNAVAILABLE)
+            }),
+        ]);
+
+        match failover.send(vec![user_message("Hello")], config()) {
+            ChatEvent::Error(Error { code, .. }) => assert_eq!(code, ErrorCode::InternalError),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn policy_can_not_fail_over_on_permanent_errors() {
+        let error = FailoverPolicy::on(vec![
+            ErrorCode::RateLimitExceeded,
+            ErrorCode::AuthenticationFailed,
+        ])
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+
+        let policy = FailoverPolicy::on(vec![ErrorCode::RateLimitExceeded]).unwrap();
+        let failover = Failover::with_policy(
+            policy,
+            vec![
+                FailoverTarget::new("openai", "gpt-4o", |_, _| {
+                    failure(StatusCode::SERVICE_UNAVAILABLE)
+                }),
+                FailoverTarget::new("anthropic", "claude-sonnet-4-0", |_, _| response("Hello")),
+            ],
+        );
+        assert!(matches!(
+            failover.send(vec![user_message("Hello")], config()),
+            ChatEvent::Error(_)
+        ));
+    }
+
+    #[test]
+    fn equivalent_target_uses_a_model_with_the_same_capabilities() {
+        let target =
+            FailoverTarget::equivalent(&config(), "anthropic", "gpt-4o", |_, _| response("Hello"))
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(target.provider, "anthropic");
+        assert_eq!(target.model, "claude-sonnet-4-0");
+    }
+}
@@ -0,0 +1,141 @@
+use serde_json::Value;
+
+/// Extracts the first JSON object or array from a model's response text.
+///
+/// Models asked to respond with JSON often wrap it in markdown code fences or surround it with
+/// prose. The text is first tried as is, then the contents of each fenced code block, and
+/// finally the first balanced `{...}` or `[...]` section of the text that parses as JSON.
+pub fn extract_json(text: &str) -> Option<Value> {
+    if let Some(value) = parse_structured(text) {
+        return Some(value);
+    }
+
+    fenced_blocks(text)
+        .into_iter()
+        .find_map(parse_structured)
+        .or_else(|| first_balanced(text))
+}
+
+fn parse_structured(text: &str) -> Option<Value> {
+    match serde_json::from_str(text.trim()) {
+        Ok(value @ (Value::Object(_) | Value::Array(_))) => Some(value),
+        _ => None,
+    }
+}
+
+/// Returns the contents of the markdown code fences in `text`, without the language tag
+fn fenced_blocks(text: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("```") {
+        let after_fence = &rest[start + 3..];
+        let body_start = after_fence.find('\n').map(|idx| idx + 1).unwrap_or(0);
+        let body = &after_fence[body_start..];
+        match body.find("```") {
+            Some(end) => {
+                blocks.push(&body[..end]);
+                rest = &body[end + 3..];
+            }
+            None => break,
+        }
+    }
+    blocks
+}
+
+fn first_balanced(text: &str) -> Option<Value> {
+    text.char_indices()
+        .filter(|(_, c)| *c == '{' || *c == '[')
+        .find_map(|(start, _)| {
+            balanced_end(&text[start..])
+                .and_then(|end| serde_json::from_str(&text[start..start + end]).ok())
+        })
+}
+
+/// Finds the end of the object or array starting at the beginning of `text`, skipping over
+/// brackets inside string literals
+fn balanced_end(text: &str) -> Option<usize> {
+    let mut expected = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (idx, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' => expected.push('}'),
+            '[' => expected.push(']'),
+            '}' | ']' => {
+                if expected.pop() != Some(c) {
+                    return None;
+                }
+                if expected.is_empty() {
+                    return Some(idx + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json_extract::extract_json;
+    use serde_json::json;
+
+    #[test]
+    fn clean_json_is_parsed() {
+        assert_eq!(
+            extract_json(r#" {"name": "Golem", "tags": ["a", "b"]} "#),
+            Some(json!({"name": "Golem", "tags": ["a", "b"]}))
+        );
+        assert_eq!(extract_json("[1, 2, 3]"), Some(json!([1, 2, 3])));
+    }
+
+    #[test]
+    fn fenced_json_is_extracted() {
+        let text = "Here is the result:\n```json\n{\"name\": \"Golem\"}\n```\nLet me know if you need more.";
+
+        assert_eq!(extract_json(text), Some(json!({"name": "Golem"})));
+    }
+
+    #[test]
+    fn fence_without_language_tag_is_extracted() {
+        let text = "```\n[{\"id\": 1}]\n```";
+
+        assert_eq!(extract_json(text), Some(json!([{"id": 1}])));
+    }
+
+    #[test]
+    fn prose_wrapped_json_is_extracted() {
+        let text = r#"Sure! The answer is {"answer": "use {braces} and [brackets]", "nested": {"ok": true}} as requested."#;
+
+        assert_eq!(
+            extract_json(text),
+            Some(json!({"answer": "use {braces} and [brackets]", "nested": {"ok": true}}))
+        );
+    }
+
+    #[test]
+    fn invalid_candidates_are_skipped() {
+        let text = r#"Options are [a, b], the result is {"value": 6}"#;
+
+        assert_eq!(extract_json(text), Some(json!({"value": 6})));
+    }
+
+    #[test]
+    fn text_without_json_returns_none() {
+        assert_eq!(extract_json("No JSON here, just {unbalanced text"), None);
+        assert_eq!(extract_json("42"), None);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::config::{bool_option, option};
+use crate::golem::llm::llm::{ChatEvent, Config, ContentPart, Error, ErrorCode, Message, Role};
+use serde_json::Value;
+
+/// Client side validation of JSON responses, enabled by the `validate_json_schema=true` provider
+/// option. The schema to validate against is given in the `json_schema` provider option, and
+/// setting `json_schema_retry=true` allows one corrective retry with the validation error fed
+/// back to the model.
+pub struct JsonValidation {
+    schema: Value,
+    retry: bool,
+}
+
+impl JsonValidation {
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Error> {
+        if !bool_option(config, "validate_json_schema") {
+            return Ok(None);
+        }
+
+        let schema = option(config, "json_schema").ok_or_else(|| Error {
+            code: ErrorCode::InvalidRequest,
+            message: "validate_json_schema requires the json_schema provider option".to_string(),
+            provider_error_json: None,
+        })?;
+        let schema = serde_json::from_str(schema).map_err(|err| Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Failed to parse json_schema: {err}"),
+            provider_error_json: None,
+        })?;
+
+        Ok(Some(Self {
+            schema,
+            retry: bool_option(config, "json_schema_retry"),
+        }))
+    }
+
+    /// Checks the assembled text of a response message against the schema. Other events are
+    /// accepted as they are.
+    pub fn check(&self, event: &ChatEvent) -> Result<(), String> {
+        if let ChatEvent::Message(response) = event {
+            let text = response_text(&response.content);
+            let value: Value = serde_json::from_str(&text)
+                .map_err(|err| format!("Response is not valid JSON: {err}"))?;
+            validate(&value, &self.schema)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Sends `messages` with `send`, validating the response if [`JsonValidation`] is enabled in
+/// `config`.
+pub fn send_validated(
+    messages: Vec<Message>,
+    config: Config,
+    send: impl Fn(Vec<Message>, Config) -> ChatEvent,
+) -> ChatEvent {
+    let validation = match JsonValidation::from_config(&config) {
+        Ok(Some(validation)) => validation,
+        Ok(None) => return send(messages, config),
+        Err(error) => return ChatEvent::Error(error),
+    };
+
+    let event = send(messages.clone(), config.clone());
+    let problem = match validation.check(&event) {
+        Ok(()) => return event,
+        Err(problem) => problem,
+    };
+
+    let (event, problem) = match event {
+        ChatEvent::Message(response) if validation.retry => {
+            let mut messages = messages;
+            messages.push(Message {
+                role: Role::Assistant,
+                name: None,
+                content: response.content,
+            });
+            messages.push(Message {
+                role: Role::User,
+                name: None,
+                content: vec![ContentPart::Text(format!(
+                    "The previous response was rejected: {problem}. Respond again with only JSON matching the requested schema."
+                ))],
+            });
+
+            let event = send(messages, config);
+            match validation.check(&event) {
+                Ok(()) => return event,
+                Err(problem) => (event, problem),
+            }
+        }
+        event => (event, problem),
+    };
+
+    ChatEvent::Error(Error {
+        code: ErrorCode::InternalError,
+        message: format!("Response does not match the requested JSON schema: {problem}"),
+        provider_error_json: match event {
+            ChatEvent::Message(response) => Some(response_text(&response.content)),
+            _ => None,
+        },
+    })
+}
+
+fn response_text(content: &[ContentPart]) -> String {
+    content
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text(text) => Some(text.as_str()),
+            ContentPart::Image(_) => None,
+        })
+        .collect()
+}
+
+/// Validates `value` against a JSON schema.
+///
+/// Supports the commonly used subset of the specification: `type`, `enum`, `const`,
+/// `properties`, `required`, `additionalProperties`, `items`, `minItems`, `maxItems`,
+/// `minLength`, `maxLength`, `minimum` and `maximum`. Other keywords are ignored.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), String> {
+    validate_at("$", value, schema)
+}
+
+fn validate_at(path: &str, value: &Value, schema: &Value) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{path} is not allowed")),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(typ) = schema.get("type") {
+        let allowed: Vec<&str> = match typ {
+            Value::String(typ) => vec![typ.as_str()],
+            Value::Array(types) => types.iter().filter_map(|typ| typ.as_str()).collect(),
+            _ => vec![],
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|typ| has_type(value, typ)) {
+            return Err(format!("{path} should be of type {}", allowed.join(" or ")));
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            return Err(format!(
+                "{path} should be one of {}",
+                Value::Array(options.clone())
+            ));
+        }
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return Err(format!("{path} should be {expected}"));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(|name| name.as_str()) {
+                    if !object.contains_key(name) {
+                        return Err(format!("{path} is missing required property '{name}'"));
+                    }
+                }
+            }
+
+            let properties = schema.get("properties").and_then(|p| p.as_object());
+            for (name, field) in object {
+                let field_path = format!("{path}.{name}");
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(field_schema) => validate_at(&field_path, field, field_schema)?,
+                    None => {
+                        if let Some(additional) = schema.get("additionalProperties") {
+                            validate_at(&field_path, field, additional)?;
+                        }
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            check_length(path, items.len(), schema, "minItems", "maxItems", "items")?;
+            if let Some(item_schema) = schema.get("items") {
+                for (idx, item) in items.iter().enumerate() {
+                    validate_at(&format!("{path}[{idx}]"), item, item_schema)?;
+                }
+            }
+        }
+        Value::String(string) => {
+            let length = string.chars().count();
+            check_length(path, length, schema, "minLength", "maxLength", "characters")?;
+        }
+        Value::Number(number) => {
+            if let Some(number) = number.as_f64() {
+                if let Some(minimum) = schema.get("minimum").and_then(|min| min.as_f64()) {
+                    if number < minimum {
+                        return Err(format!("{path} should be at least {minimum}"));
+                    }
+                }
+                if let Some(maximum) = schema.get("maximum").and_then(|max| max.as_f64()) {
+                    if number > maximum {
+                        return Err(format!("{path} should be at most {maximum}"));
+                    }
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+
+    Ok(())
+}
+
+fn has_type(value: &Value, typ: &str) -> bool {
+    match typ {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn check_length(
+    path: &str,
+    length: usize,
+    schema: &serde_json::Map<String, Value>,
+    min_key: &str,
+    max_key: &str,
+    unit: &str,
+) -> Result<(), String> {
+    let length = length as u64;
+    if let Some(min) = schema.get(min_key).and_then(|min| min.as_u64()) {
+        if length < min {
+            return Err(format!("{path} should have at least {min} {unit}"));
+        }
+    }
+    if let Some(max) = schema.get(max_key).and_then(|max| max.as_u64()) {
+        if length > max {
+            return Err(format!("{path} should have at most {max} {unit}"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, ErrorCode, Message, ResponseMetadata,
+        Role,
+    };
+    use crate::json_schema::{send_validated, validate};
+    use crate::test_config;
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    const SCHEMA: &str = r#"{"type":"object","properties":{"name":{"type":"string"},"age":{"type":"integer","minimum":0}},"required":["name","age"],"additionalProperties":false}"#;
+
+    fn config(retry: bool) -> Config {
+        let mut provider_options = vec![("validate_json_schema", "true"), ("json_schema", SCHEMA)];
+        if retry {
+            provider_options.push(("json_schema_retry", "true"));
+        }
+        test_config::config(&provider_options)
+    }
+
+    fn response(text: &str) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "id".to_string(),
+            content: vec![ContentPart::Text(text.to_string())],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            },
+        })
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("Who are you?".to_string())],
+        }]
+    }
+
+    #[test]
+    fn valid_response_is_returned() {
+        let event = send_validated(messages(), config(false), |_, _| {
+            response(r#"{"name":"Golem","age":3}"#)
+        });
+
+        assert_eq!(event, response(r#"{"name":"Golem","age":3}"#));
+    }
+
+    #[test]
+    fn invalid_json_is_rejected() {
+        let event = send_validated(messages(), config(false), |_, _| response("not json"));
+
+        match event {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InternalError);
+                assert!(error.message.contains("Response is not valid JSON"));
+                assert_eq!(error.provider_error_json, Some("not json".to_string()));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn schema_mismatch_is_rejected() {
+        let event = send_validated(messages(), config(false), |_, _| {
+            response(r#"{"name":"Golem"}"#)
+        });
+
+        match event {
+            ChatEvent::Error(error) => {
+                assert!(error
+                    .message
+                    .ends_with("$ is missing required property 'age'"));
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn mismatch_is_retried_with_validation_error() {
+        let calls = RefCell::new(Vec::new());
+        let event = send_validated(messages(), config(true), |messages, _| {
+            calls.borrow_mut().push(messages);
+            if calls.borrow().len() == 1 {
+                response(r#"{"name":"Golem","age":-1}"#)
+            } else {
+                response(r#"{"name":"Golem","age":1}"#)
+            }
+        });
+
+        assert_eq!(event, response(r#"{"name":"Golem","age":1}"#));
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].len(), 3);
+        assert_eq!(calls[1][1].role, Role::Assistant);
+        match &calls[1][2].content[0] {
+            ContentPart::Text(text) => assert!(text.contains("$.age should be at least 0")),
+            other => panic!("Unexpected content: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn validation_is_disabled_by_default() {
+        let event = send_validated(messages(), test_config::config(&[]), |_, _| {
+            response("not json")
+        });
+
+        assert_eq!(event, response("not json"));
+    }
+
+    #[test]
+    fn validate_reports_nested_paths() {
+        let schema = json!({"type": "array", "items": {"type": "object", "properties": {"tags": {"type": "array", "items": {"enum": ["a", "b"]}}}}});
+
+        assert_eq!(validate(&json!([{"tags": ["a", "b"]}]), &schema), Ok(()));
+        assert_eq!(
+            validate(&json!([{"tags": ["a"]}, {"tags": ["c"]}]), &schema),
+            Err(r#"$[1].tags[0] should be one of ["a","b"]"#.to_string())
+        );
+    }
+}
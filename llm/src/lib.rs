@@ -1,10 +1,36 @@
+pub mod budget;
+pub mod chat_format;
 pub mod chat_stream;
+pub mod concurrency;
 pub mod config;
+pub mod context_budget;
 pub mod durability;
+pub mod empty_response;
 pub mod error;
+pub mod failover;
+pub mod json_extract;
+pub mod json_schema;
+pub mod models;
+pub mod prompt;
+pub mod response_id;
+pub mod select;
+pub mod stop_sequence;
+pub mod tool_args;
+pub mod tool_call_only;
+pub mod tool_examples;
+pub mod tool_loop;
+pub mod trace_id;
+pub mod transport;
+pub mod truncation;
+pub mod validation;
 
 #[allow(dead_code)]
 pub mod event_source;
+#[cfg(any(test, feature = "test-support"))]
+#[doc(hidden)]
+pub mod test_config;
+#[cfg(test)]
+mod test_logger;
 
 wit_bindgen::generate!({
     path: "../wit",
@@ -22,25 +48,93 @@ use std::str::FromStr;
 
 pub struct LoggingState {
     logging_initialized: bool,
+    logger_installed: bool,
+    level: Option<log::LevelFilter>,
 }
 
 impl LoggingState {
     /// Initializes WASI logging based on the `GOLEM_LLM_LOG` environment variable.
+    ///
+    /// A logger installed with [`Self::install_logger`] is kept instead of the WASI one, and a
+    /// level set with [`Self::set_level`] takes precedence over the environment variable.
     pub fn init(&mut self) {
         if !self.logging_initialized {
-            let _ = wasi_logger::Logger::install();
-            let max_level: log::LevelFilter =
-                log::LevelFilter::from_str(&std::env::var("GOLEM_LLM_LOG").unwrap_or_default())
-                    .unwrap_or(log::LevelFilter::Info);
-            log::set_max_level(max_level);
+            if !self.logger_installed {
+                let _ = wasi_logger::Logger::install();
+            }
+            log::set_max_level(self.max_level());
             self.logging_initialized = true;
         }
     }
+
+    /// The maximum log level to initialize the logging with
+    fn max_level(&self) -> log::LevelFilter {
+        self.level.unwrap_or_else(|| {
+            log::LevelFilter::from_str(&std::env::var("GOLEM_LLM_LOG").unwrap_or_default())
+                .unwrap_or(log::LevelFilter::Info)
+        })
+    }
+
+    /// Sets the maximum log level, bypassing the `GOLEM_LLM_LOG` environment variable. It can be
+    /// called before or after [`Self::init`].
+    pub fn set_level(&mut self, level: log::LevelFilter) {
+        self.level = Some(level);
+        if self.logging_initialized {
+            log::set_max_level(level);
+        }
+    }
+
+    /// Installs a custom log target instead of the WASI logger, for tests or embedding hosts.
+    /// It has to be called before [`Self::init`], as the logger can only be installed once.
+    pub fn install_logger(
+        &mut self,
+        logger: &'static dyn log::Log,
+    ) -> Result<(), log::SetLoggerError> {
+        log::set_logger(logger)?;
+        self.logger_installed = true;
+        Ok(())
+    }
 }
 
 thread_local! {
     /// This holds the state of our application.
     pub static LOGGING_STATE: RefCell<LoggingState> = const { RefCell::new(LoggingState {
         logging_initialized: false,
+        logger_installed: false,
+        level: None,
     }) };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_logger, LoggingState};
+    use log::LevelFilter;
+
+    #[test]
+    fn custom_logger_is_used() {
+        test_logger::install();
+
+        log::info!("captured");
+
+        assert_eq!(
+            test_logger::messages(module_path!()),
+            vec!["INFO captured".to_string()]
+        );
+    }
+
+    #[test]
+    fn set_level_takes_precedence_over_the_environment() {
+        // Not initialized, so the global maximum log level shared with the other tests is kept
+        let mut state = LoggingState {
+            logging_initialized: false,
+            logger_installed: false,
+            level: None,
+        };
+
+        state.set_level(LevelFilter::Warn);
+        assert_eq!(state.max_level(), LevelFilter::Warn);
+
+        state.set_level(LevelFilter::Trace);
+        assert_eq!(state.max_level(), LevelFilter::Trace);
+    }
+}
@@ -0,0 +1,476 @@
+use crate::config::option;
+use crate::golem::llm::llm::{Config, Error, ErrorCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What is known about a model, for features budgeting the context window, the output length
+/// or the cost of a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// The maximum number of tokens of the input and the output together
+    pub context_window: u32,
+    /// The maximum number of tokens the model can generate in a single response
+    pub max_output: u32,
+    /// The price of a million input tokens in USD
+    pub input_price: f64,
+    /// The price of a million output tokens in USD
+    pub output_price: f64,
+    pub supports_vision: bool,
+    pub supports_tools: bool,
+    /// The provider serving the model, for [`best_model_for`]. Only needed in overrides of
+    /// models the provider of which cannot be told from the model id, see [`MODEL_PROVIDERS`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+}
+
+/// The environment variable holding model info overrides, in the same format as the
+/// `model_info` provider option
+pub const MODEL_INFO_ENV_VAR: &str = "GOLEM_LLM_MODEL_INFO";
+
+const fn info(
+    context_window: u32,
+    max_output: u32,
+    input_price: f64,
+    output_price: f64,
+    supports_vision: bool,
+) -> ModelInfo {
+    ModelInfo {
+        context_window,
+        max_output,
+        input_price,
+        output_price,
+        supports_vision,
+        supports_tools: true,
+        provider: None,
+    }
+}
+
+/// Built-in info of commonly used models, by model id
+const BUILTIN_MODELS: &[(&str, ModelInfo)] = &[
+    ("gpt-4o", info(128_000, 16_384, 2.5, 10.0, true)),
+    ("gpt-4o-mini", info(128_000, 16_384, 0.15, 0.6, true)),
+    ("gpt-4.1", info(1_047_576, 32_768, 2.0, 8.0, true)),
+    ("gpt-4.1-mini", info(1_047_576, 32_768, 0.4, 1.6, true)),
+    ("gpt-4.1-nano", info(1_047_576, 32_768, 0.1, 0.4, true)),
+    ("o3", info(200_000, 100_000, 2.0, 8.0, true)),
+    ("o4-mini", info(200_000, 100_000, 1.1, 4.4, true)),
+    ("claude-opus-4-0", info(200_000, 32_000, 15.0, 75.0, true)),
+    (
+        "claude-opus-4-20250514",
+        info(200_000, 32_000, 15.0, 75.0, true),
+    ),
+    ("claude-sonnet-4-0", info(200_000, 64_000, 3.0, 15.0, true)),
+    (
+        "claude-sonnet-4-20250514",
+        info(200_000, 64_000, 3.0, 15.0, true),
+    ),
+    (
+        "claude-3-7-sonnet-latest",
+        info(200_000, 64_000, 3.0, 15.0, true),
+    ),
+    (
+        "claude-3-5-haiku-latest",
+        info(200_000, 8_192, 0.8, 4.0, false),
+    ),
+    (
+        "claude-3-haiku-20240307",
+        info(200_000, 4_096, 0.25, 1.25, true),
+    ),
+    ("grok-3", info(131_072, 131_072, 3.0, 15.0, false)),
+    ("grok-3-mini", info(131_072, 131_072, 0.3, 0.5, false)),
+    ("grok-2-vision-1212", info(32_768, 32_768, 2.0, 10.0, true)),
+];
+
+/// Gets the built-in info of a model. OpenRouter style model ids prefixed with the vendor, such
+/// as `openai/gpt-4o`, are looked up without the prefix.
+pub fn builtin_model_info(model: &str) -> Option<ModelInfo> {
+    let find = |model: &str| {
+        BUILTIN_MODELS
+            .iter()
+            .find(|(id, _)| *id == model)
+            .map(|(_, info)| info.clone())
+    };
+    find(model).or_else(|| {
+        model
+            .split_once('/')
+            .and_then(|(_, unprefixed)| find(unprefixed))
+    })
+}
+
+fn parse_overrides(source: &str, json: &str) -> Result<BTreeMap<String, ModelInfo>, Error> {
+    serde_json::from_str(json).map_err(|err| Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!("Failed to parse {source}: {err}"),
+        provider_error_json: None,
+    })
+}
+
+/// The overrides of the built-in table, from the `GOLEM_LLM_MODEL_INFO` environment variable
+/// and the `model_info` provider option, the latter taking precedence
+fn overrides(config: &Config) -> Result<BTreeMap<String, ModelInfo>, Error> {
+    let mut overrides = match std::env::var(MODEL_INFO_ENV_VAR) {
+        Ok(json) => parse_overrides(MODEL_INFO_ENV_VAR, &json)?,
+        Err(_) => BTreeMap::new(),
+    };
+    if let Some(json) = option(config, "model_info") {
+        overrides.extend(parse_overrides("model_info", json)?);
+    }
+    Ok(overrides)
+}
+
+/// Gets the info of `model`, or `None` if nothing is known about it.
+///
+/// The built-in table can be extended or overridden with a JSON object mapping model ids to
+/// their full info, for example
+/// `{"my-model": {"context_window": 32768, "max_output": 4096, "input_price": 0.5,
+/// "output_price": 1.5, "supports_vision": false, "supports_tools": true}}`, given either in the
+/// `model_info` provider option or in the `GOLEM_LLM_MODEL_INFO` environment variable. The
+/// provider option takes precedence over the environment variable.
+pub fn model_info(config: &Config, model: &str) -> Result<Option<ModelInfo>, Error> {
+    Ok(overrides(config)?
+        .remove(model)
+        .or_else(|| builtin_model_info(model)))
+}
+
+/// The providers serving the models, by model id prefix. OpenRouter model ids are prefixed with
+/// the vendor, such as `openai/gpt-4o`, so ids containing a `/` are served by `openrouter`.
+/// Ollama models have no common prefix, their overrides have to set the `provider` field.
+const MODEL_PROVIDERS: &[(&str, &str)] = &[
+    ("gpt-", "openai"),
+    ("o3", "openai"),
+    ("o4-", "openai"),
+    ("claude-", "anthropic"),
+    ("grok-", "grok"),
+];
+
+/// The provider serving `model`, or `None` if it cannot be told
+fn model_provider<'a>(model: &str, info: &'a ModelInfo) -> Option<&'a str> {
+    if let Some(provider) = &info.provider {
+        return Some(provider);
+    }
+    if model.contains('/') {
+        return Some("openrouter");
+    }
+    MODEL_PROVIDERS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, provider)| *provider)
+}
+
+/// What a model has to support to be chosen by [`best_model_for`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelRequirements {
+    pub needs_vision: bool,
+    pub needs_tools: bool,
+    /// The minimum context window in tokens
+    pub min_context: Option<u32>,
+    /// The maximum price of a million input tokens plus a million output tokens in USD
+    pub max_price: Option<f64>,
+}
+
+impl ModelRequirements {
+    fn are_met_by(&self, info: &ModelInfo) -> bool {
+        (!self.needs_vision || info.supports_vision)
+            && (!self.needs_tools || info.supports_tools)
+            && self
+                .min_context
+                .is_none_or(|min_context| info.context_window >= min_context)
+            && self
+                .max_price
+                .is_none_or(|max_price| price(info) <= max_price)
+    }
+}
+
+/// A model chosen by [`best_model_for`], together with the provider serving it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelChoice {
+    pub provider: String,
+    pub model: String,
+    pub info: ModelInfo,
+}
+
+fn price(info: &ModelInfo) -> f64 {
+    info.input_price + info.output_price
+}
+
+/// The models known for `config`: the built-in table merged with the overrides of
+/// [`model_info`]
+fn known_models(config: &Config) -> Result<Vec<(String, ModelInfo)>, Error> {
+    let overrides = overrides(config)?;
+    let mut models: Vec<_> = BUILTIN_MODELS
+        .iter()
+        .filter(|(model, _)| !overrides.contains_key(*model))
+        .map(|(model, info)| (model.to_string(), info.clone()))
+        .collect();
+    models.extend(overrides);
+    Ok(models)
+}
+
+/// The models meeting the requirements among the ones served by the given configured
+/// providers, with the provider serving them
+fn candidates(
+    config: &Config,
+    requirements: &ModelRequirements,
+    providers: &[&str],
+) -> Result<Vec<ModelChoice>, Error> {
+    Ok(known_models(config)?
+        .into_iter()
+        .filter_map(|(model, info)| {
+            let provider = model_provider(&model, &info)?.to_string();
+            let served = providers.contains(&provider.as_str());
+            (served && requirements.are_met_by(&info)).then_some(ModelChoice {
+                provider,
+                model,
+                info,
+            })
+        })
+        .collect())
+}
+
+/// Chooses a model meeting the requirements, among the ones served by the given configured
+/// providers, or `None` if there is no such model.
+///
+/// The candidates are the models of the built-in table, which covers `openai`, `anthropic` and
+/// `grok`, and the models added by the overrides of [`model_info`]. OpenRouter and Ollama
+/// models are only chosen once added by an override.
+///
+/// The cheapest model is chosen, and of equally priced ones the one with the largest context
+/// window. This allows selecting models by policy instead of hard-coding a model id per use case.
+pub fn best_model_for(
+    config: &Config,
+    requirements: &ModelRequirements,
+    providers: &[&str],
+) -> Result<Option<ModelChoice>, Error> {
+    Ok(candidates(config, requirements, providers)?
+        .into_iter()
+        .min_by(|a, b| {
+            price(&a.info)
+                .total_cmp(&price(&b.info))
+                .then(b.info.context_window.cmp(&a.info.context_window))
+        }))
+}
+
+/// Chooses the model meeting the requirements whose price is the closest to the price of
+/// `model`, among the ones served by the given configured providers, or `None` if `model` is
+/// unknown or there is no such model. Of equally close ones, the one with the largest context
+/// window is chosen.
+///
+/// This finds the counterpart of a model with another provider, in the same tier rather than
+/// the cheapest one.
+pub fn closest_model_to(
+    config: &Config,
+    model: &str,
+    requirements: &ModelRequirements,
+    providers: &[&str],
+) -> Result<Option<ModelChoice>, Error> {
+    let Some(info) = model_info(config, model)? else {
+        return Ok(None);
+    };
+    let distance = |choice: &ModelChoice| (price(&choice.info) - price(&info)).abs();
+    Ok(candidates(config, requirements, providers)?
+        .into_iter()
+        .min_by(|a, b| {
+            distance(a)
+                .total_cmp(&distance(b))
+                .then(b.info.context_window.cmp(&a.info.context_window))
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::ErrorCode;
+    use crate::models::{
+        best_model_for, builtin_model_info, closest_model_to, model_info, ModelInfo,
+        ModelRequirements,
+    };
+    use crate::test_config::config;
+
+    const OVERRIDES: &str = r#"{
+        "gpt-4o": {"context_window": 64000, "max_output": 4096, "input_price": 1.0,
+                   "output_price": 2.0, "supports_vision": false, "supports_tools": true},
+        "my-model": {"context_window": 8192, "max_output": 1024, "input_price": 0.0,
+                     "output_price": 0.0, "supports_vision": false, "supports_tools": false}
+    }"#;
+
+    #[test]
+    fn builtin_models_are_looked_up() {
+        let info = builtin_model_info("gpt-4o").unwrap();
+        assert_eq!(info.context_window, 128_000);
+        assert!(info.supports_vision);
+
+        assert_eq!(
+            builtin_model_info("anthropic/claude-sonnet-4-0"),
+            builtin_model_info("claude-sonnet-4-0")
+        );
+        assert_eq!(builtin_model_info("unknown-model"), None);
+        assert_eq!(model_info(&config(&[]), "unknown-model").unwrap(), None);
+    }
+
+    #[test]
+    fn provider_option_overrides_and_extends_the_builtin_table() {
+        let config = config(&[("model_info", OVERRIDES)]);
+
+        let overridden = model_info(&config, "gpt-4o").unwrap().unwrap();
+        assert_eq!(overridden.context_window, 64_000);
+        assert!(!overridden.supports_vision);
+
+        assert_eq!(
+            model_info(&config, "my-model").unwrap(),
+            Some(ModelInfo {
+                context_window: 8192,
+                max_output: 1024,
+                input_price: 0.0,
+                output_price: 0.0,
+                supports_vision: false,
+                supports_tools: false,
+                provider: None,
+            })
+        );
+        assert_eq!(
+            model_info(&config, "gpt-4o-mini").unwrap(),
+            builtin_model_info("gpt-4o-mini")
+        );
+    }
+
+    #[test]
+    fn invalid_overrides_are_rejected() {
+        let error = model_info(
+            &config(&[("model_info", r#"{"my-model": {}}"#)]),
+            "my-model",
+        )
+        .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    /// Chooses a model without overrides, returning its provider and id
+    fn choose(requirements: ModelRequirements, providers: &[&str]) -> Option<(String, String)> {
+        best_model_for(&config(&[]), &requirements, providers)
+            .unwrap()
+            .map(|choice| (choice.provider, choice.model))
+    }
+
+    #[test]
+    fn cheapest_model_of_the_configured_providers_is_chosen() {
+        assert_eq!(
+            choose(
+                ModelRequirements::default(),
+                &["openai", "anthropic", "grok"]
+            ),
+            Some(("openai".to_string(), "gpt-4.1-nano".to_string()))
+        );
+        assert_eq!(
+            choose(ModelRequirements::default(), &["anthropic", "grok"]),
+            Some(("grok".to_string(), "grok-3-mini".to_string()))
+        );
+    }
+
+    #[test]
+    fn models_not_meeting_the_requirements_are_skipped() {
+        let requirements = ModelRequirements {
+            needs_vision: true,
+            needs_tools: true,
+            min_context: Some(150_000),
+            max_price: None,
+        };
+        assert_eq!(
+            choose(requirements.clone(), &["anthropic", "grok"]),
+            Some((
+                "anthropic".to_string(),
+                "claude-3-haiku-20240307".to_string()
+            ))
+        );
+        assert_eq!(
+            choose(requirements, &["grok"]),
+            None,
+            "no Grok model with vision has a large enough context"
+        );
+
+        let requirements = ModelRequirements {
+            needs_vision: true,
+            max_price: Some(12.0),
+            ..ModelRequirements::default()
+        };
+        assert_eq!(
+            choose(requirements, &["grok"]),
+            Some(("grok".to_string(), "grok-2-vision-1212".to_string()))
+        );
+    }
+
+    #[test]
+    fn nothing_is_chosen_within_a_too_low_price() {
+        let requirements = ModelRequirements {
+            max_price: Some(0.1),
+            ..ModelRequirements::default()
+        };
+
+        assert_eq!(choose(requirements, &["openai", "anthropic", "grok"]), None);
+        assert_eq!(choose(ModelRequirements::default(), &["ollama"]), None);
+    }
+
+    #[test]
+    fn overridden_models_are_chosen() {
+        let config = config(&[(
+            "model_info",
+            r#"{
+                "gpt-4.1-nano": {"context_window": 1047576, "max_output": 32768,
+                                 "input_price": 5.0, "output_price": 20.0,
+                                 "supports_vision": true, "supports_tools": true},
+                "llama3.2": {"context_window": 131072, "max_output": 2048, "input_price": 0.0,
+                             "output_price": 0.0, "supports_vision": false,
+                             "supports_tools": true, "provider": "ollama"},
+                "meta-llama/llama-3.3-70b-instruct": {"context_window": 131072,
+                                                      "max_output": 16384,
+                                                      "input_price": 0.1, "output_price": 0.3,
+                                                      "supports_vision": false,
+                                                      "supports_tools": true}
+            }"#,
+        )]);
+        let choose = |providers: &[&str]| {
+            best_model_for(&config, &ModelRequirements::default(), providers)
+                .unwrap()
+                .map(|choice| (choice.provider, choice.model))
+        };
+
+        assert_eq!(
+            choose(&["ollama", "openai"]),
+            Some(("ollama".to_string(), "llama3.2".to_string()))
+        );
+        assert_eq!(
+            choose(&["openrouter"]),
+            Some((
+                "openrouter".to_string(),
+                "meta-llama/llama-3.3-70b-instruct".to_string()
+            ))
+        );
+        assert_eq!(
+            choose(&["openai"]),
+            Some(("openai".to_string(), "gpt-4o-mini".to_string())),
+            "the overridden price of gpt-4.1-nano is used"
+        );
+    }
+
+    #[test]
+    fn model_closest_in_price_is_chosen() {
+        let closest = |model: &str, providers: &[&str]| {
+            closest_model_to(
+                &config(&[]),
+                model,
+                &ModelRequirements::default(),
+                providers,
+            )
+            .unwrap()
+            .map(|choice| choice.model)
+        };
+
+        assert_eq!(
+            closest("gpt-4o", &["anthropic"]),
+            Some("claude-sonnet-4-0".to_string())
+        );
+        assert_eq!(
+            closest("claude-3-haiku-20240307", &["openai"]),
+            Some("gpt-4.1-mini".to_string())
+        );
+        assert_eq!(closest("unknown-model", &["openai"]), None);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::golem::llm::llm::{ChatEvent, Config, ContentPart, Message, Role};
+
+/// Creates a user message consisting of a single text part
+pub fn user_message(text: impl Into<String>) -> Message {
+    Message {
+        role: Role::User,
+        name: None,
+        content: vec![ContentPart::Text(text.into())],
+    }
+}
+
+/// Creates an assistant message consisting of a single text part
+pub fn assistant_message(text: impl Into<String>) -> Message {
+    Message {
+        role: Role::Assistant,
+        name: None,
+        content: vec![ContentPart::Text(text.into())],
+    }
+}
+
+/// A few-shot example: an input and the answer expected from the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Example {
+    pub input: String,
+    pub output: String,
+}
+
+impl Example {
+    pub fn new(input: impl Into<String>, output: impl Into<String>) -> Self {
+        Self {
+            input: input.into(),
+            output: output.into(),
+        }
+    }
+}
+
+/// Puts few-shot examples in front of a conversation, as alternating user and assistant
+/// messages.
+///
+/// The examples are inserted after the leading system messages of `messages` and before the rest
+/// of the conversation, so each example input is answered by its output and the real query
+/// follows the last example answer, keeping the strict role alternation Anthropic requires.
+pub fn with_examples(examples: &[Example], messages: Vec<Message>) -> Vec<Message> {
+    let system_count = messages
+        .iter()
+        .take_while(|message| message.role == Role::System)
+        .count();
+    let mut messages = messages.into_iter();
+
+    let mut result = Vec::with_capacity(examples.len() * 2 + messages.len());
+    result.extend(messages.by_ref().take(system_count));
+    for example in examples {
+        result.push(user_message(example.input.clone()));
+        result.push(assistant_message(example.output.clone()));
+    }
+    result.extend(messages);
+    result
+}
+
+/// Sends a one-shot text prompt with `send`, wrapping it in a single user message.
+pub fn send_text(
+    prompt: &str,
+    config: Config,
+    send: impl FnOnce(Vec<Message>, Config) -> ChatEvent,
+) -> ChatEvent {
+    send(vec![user_message(prompt)], config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, Message, ResponseMetadata, Role,
+    };
+    use crate::prompt::{send_text, with_examples, Example};
+    use crate::test_config;
+    use std::cell::RefCell;
+
+    fn config() -> Config {
+        Config {
+            temperature: Some(0.2),
+            ..test_config::config(&[])
+        }
+    }
+
+    fn response() -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "id".to_string(),
+            content: vec![ContentPart::Text("Golem".to_string())],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            },
+        })
+    }
+
+    #[test]
+    fn prompt_is_sent_as_a_single_user_message() {
+        let sent = RefCell::new(None);
+
+        let event = send_text("What is Golem?", config(), |messages, config| {
+            *sent.borrow_mut() = Some((messages, config));
+            response()
+        });
+
+        assert_eq!(event, response());
+        assert_eq!(
+            sent.into_inner(),
+            Some((
+                vec![Message {
+                    role: Role::User,
+                    name: None,
+                    content: vec![ContentPart::Text("What is Golem?".to_string())],
+                }],
+                config()
+            ))
+        );
+    }
+
+    fn text_message(role: Role, text: &str) -> Message {
+        Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        }
+    }
+
+    #[test]
+    fn examples_alternate_before_the_query() {
+        let examples = [
+            Example::new("I loved it", "positive"),
+            Example::new("Waste of time", "negative"),
+        ];
+
+        let messages = with_examples(
+            &examples,
+            vec![
+                text_message(Role::System, "Classify the sentiment"),
+                text_message(Role::User, "Not bad at all"),
+            ],
+        );
+
+        assert_eq!(
+            messages,
+            vec![
+                text_message(Role::System, "Classify the sentiment"),
+                text_message(Role::User, "I loved it"),
+                text_message(Role::Assistant, "positive"),
+                text_message(Role::User, "Waste of time"),
+                text_message(Role::Assistant, "negative"),
+                text_message(Role::User, "Not bad at all"),
+            ]
+        );
+    }
+
+    #[test]
+    fn conversation_without_examples_is_unchanged() {
+        let conversation = vec![
+            text_message(Role::User, "Hi"),
+            text_message(Role::Assistant, "Hello"),
+            text_message(Role::User, "How are you?"),
+        ];
+
+        assert_eq!(with_examples(&[], conversation.clone()), conversation);
+    }
+
+    #[test]
+    fn roles_alternate_without_system_prompt() {
+        let messages = with_examples(
+            &[Example::new("2 + 2", "4")],
+            vec![text_message(Role::User, "3 + 3")],
+        );
+
+        let roles = messages
+            .iter()
+            .map(|message| message.role)
+            .collect::<Vec<_>>();
+        assert_eq!(roles, vec![Role::User, Role::Assistant, Role::User]);
+    }
+}
@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+/// Returns the id of a complete response: the provider's own id, so that the response can be
+/// referenced later, for example in audit trails.
+///
+/// Some providers or proxies return an empty id, which breaks correlating the responses. In that
+/// case a stable id is synthesized from a hash of the whole response, including the model and the
+/// system fingerprint when the provider reports them, so the same response always gets the same
+/// id, even when it is replayed.
+pub fn response_id(provider_id: &str, response: &impl Serialize) -> String {
+    if !provider_id.trim().is_empty() {
+        return provider_id.to_string();
+    }
+
+    // FNV-1a, as the hash must not change between builds, unlike the standard library's hasher
+    let json = serde_json::to_string(response).unwrap_or_default();
+    let hash = json.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("golem-{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::response_id::response_id;
+    use serde_json::json;
+
+    #[test]
+    fn provider_id_is_kept() {
+        assert_eq!(response_id("msg_1", &json!({"model": "m"})), "msg_1");
+    }
+
+    #[test]
+    fn missing_id_is_synthesized_from_the_response() {
+        let response = json!({"id": "", "model": "m", "content": "Hello"});
+        let id = response_id("", &response);
+
+        assert!(id.starts_with("golem-"));
+        assert_eq!(id.len(), "golem-".len() + 16);
+        assert_eq!(response_id(" ", &response), id);
+        assert_ne!(
+            response_id("", &json!({"id": "", "model": "m", "content": "Hi"})),
+            id
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::chat_stream::{LlmChatStream, LlmChatStreamState};
+use crate::concurrency::GatedChatStream;
+use crate::durability::ExtendedGuest;
+use crate::golem::llm::llm::{GuestChatStream, StreamEvent};
+use golem_rust::wasm_rpc::Pollable;
+
+/// A chat stream that can be waited on with a pollable
+pub trait SubscribableStream: GuestChatStream {
+    fn subscribe(&self) -> Pollable;
+}
+
+impl<T: LlmChatStreamState> SubscribableStream for LlmChatStream<T> {
+    fn subscribe(&self) -> Pollable {
+        LlmChatStream::subscribe(self)
+    }
+}
+
+impl<Impl: ExtendedGuest> SubscribableStream for GatedChatStream<Impl> {
+    fn subscribe(&self) -> Pollable {
+        GatedChatStream::subscribe(self)
+    }
+}
+
+/// A set of active chat streams, for example of the conversations of many users served by one
+/// worker, returning the events of whichever stream is ready.
+///
+/// Streams are identified by a key given when adding them. They are checked in round-robin order,
+/// starting after the one that returned events last, so a fast stream cannot starve the others.
+/// Finished streams are removed from the set after their last events were returned.
+pub struct StreamSet<K, S> {
+    streams: Vec<(K, S)>,
+    /// The position to start checking the streams from
+    next: usize,
+}
+
+impl<K: Clone, S: SubscribableStream> StreamSet<K, S> {
+    pub fn new() -> Self {
+        Self {
+            streams: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub fn insert(&mut self, key: K, stream: S) {
+        self.streams.push((key, stream));
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Returns the next events of a ready stream without blocking, or `None` if none of them has
+    /// events yet. An empty list of events means the stream ended without any more events.
+    pub fn try_next(&mut self) -> Option<(K, Vec<StreamEvent>)> {
+        for offset in 0..self.streams.len() {
+            let index = (self.next + offset) % self.streams.len();
+            let (key, stream) = &self.streams[index];
+            let Some(events) = stream.get_next() else {
+                continue;
+            };
+            let key = key.clone();
+
+            if stream.is_finished() {
+                self.streams.remove(index);
+                self.next = index;
+            } else if events.is_empty() {
+                continue;
+            } else {
+                self.next = index + 1;
+            }
+            return Some((key, events));
+        }
+        None
+    }
+
+    /// Waits until one of the streams has events and returns them like [`Self::try_next`], or
+    /// `None` when the set is empty
+    pub fn next(&mut self) -> Option<(K, Vec<StreamEvent>)> {
+        loop {
+            if let Some(next) = self.try_next() {
+                return Some(next);
+            }
+            if self.streams.is_empty() {
+                return None;
+            }
+
+            let pollables = self
+                .streams
+                .iter()
+                .map(|(_, stream)| stream.subscribe())
+                .collect::<Vec<_>>();
+            golem_rust::bindings::wasi::io::poll::poll(&pollables.iter().collect::<Vec<_>>());
+        }
+    }
+
+    /// Cancels all the streams and removes them from the set
+    pub fn cancel_all(&mut self) {
+        for (_, stream) in self.streams.drain(..) {
+            stream.cancel();
+        }
+        self.next = 0;
+    }
+}
+
+impl<K: Clone, S: SubscribableStream> Default for StreamSet<K, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ContentPart, FinishReason, GuestChatStream, ResponseMetadata, StreamDelta, StreamEvent,
+    };
+    use crate::select::{StreamSet, SubscribableStream};
+    use golem_rust::wasm_rpc::Pollable;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Stream returning the given batches of events, where `None` means not ready yet
+    struct MockStream {
+        batches: RefCell<VecDeque<Option<Vec<StreamEvent>>>>,
+    }
+
+    impl MockStream {
+        fn new(batches: Vec<Option<Vec<StreamEvent>>>) -> Self {
+            Self {
+                batches: RefCell::new(batches.into()),
+            }
+        }
+    }
+
+    impl GuestChatStream for MockStream {
+        fn get_next(&self) -> Option<Vec<StreamEvent>> {
+            self.batches
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or(Some(vec![]))
+        }
+
+        fn blocking_get_next(&self) -> Vec<StreamEvent> {
+            loop {
+                if let Some(events) = self.get_next() {
+                    break events;
+                }
+            }
+        }
+
+        fn is_finished(&self) -> bool {
+            self.batches.borrow().is_empty()
+        }
+
+        fn last_finish_reason(&self) -> Option<FinishReason> {
+            None
+        }
+
+        fn cancel(&self) {
+            self.batches.borrow_mut().clear();
+        }
+    }
+
+    impl SubscribableStream for MockStream {
+        fn subscribe(&self) -> Pollable {
+            golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(0)
+        }
+    }
+
+    fn text(text: &str) -> Option<Vec<StreamEvent>> {
+        Some(vec![StreamEvent::Delta(StreamDelta {
+            content: Some(vec![ContentPart::Text(text.to_string())]),
+            tool_calls: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        })])
+    }
+
+    fn finish() -> Option<Vec<StreamEvent>> {
+        Some(vec![StreamEvent::Finish(ResponseMetadata {
+            finish_reason: Some(FinishReason::Stop),
+            usage: None,
+            provider_id: None,
+            timestamp: None,
+            provider_metadata_json: None,
+            raw_json: None,
+        })])
+    }
+
+    fn label(events: &[StreamEvent]) -> String {
+        match events {
+            [StreamEvent::Delta(delta)] => match delta.content.as_deref() {
+                Some([ContentPart::Text(text)]) => text.clone(),
+                _ => panic!("Unexpected delta: {delta:?}"),
+            },
+            [StreamEvent::Finish(_)] => "finish".to_string(),
+            other => panic!("Unexpected events: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn ready_streams_are_interleaved_fairly() {
+        let mut set = StreamSet::new();
+        set.insert(
+            "alice",
+            MockStream::new(vec![text("a1"), text("a2"), text("a3"), finish()]),
+        );
+        set.insert(
+            "bob",
+            MockStream::new(vec![text("b1"), None, text("b2"), finish()]),
+        );
+
+        let mut received = Vec::new();
+        while let Some((key, events)) = set.try_next() {
+            received.push(format!("{key}:{}", label(&events)));
+        }
+
+        assert_eq!(
+            received,
+            vec![
+                "alice:a1",
+                "bob:b1",
+                "alice:a2",
+                "alice:a3",
+                "bob:b2",
+                "alice:finish",
+                "bob:finish",
+            ]
+        );
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn nothing_is_returned_until_a_stream_is_ready() {
+        let mut set = StreamSet::new();
+        set.insert(1, MockStream::new(vec![None, text("hello"), finish()]));
+
+        assert_eq!(set.try_next(), None);
+        assert_eq!(set.try_next().map(|(key, _)| key), Some(1));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn next_returns_the_events_of_ready_streams_until_the_set_is_empty() {
+        let mut set = StreamSet::new();
+        set.insert("alice", MockStream::new(vec![text("a1"), finish()]));
+        set.insert("bob", MockStream::new(vec![text("b1"), finish()]));
+
+        let mut received = Vec::new();
+        while let Some((key, events)) = set.next() {
+            received.push(format!("{key}:{}", label(&events)));
+        }
+
+        assert_eq!(
+            received,
+            vec!["alice:a1", "bob:b1", "alice:finish", "bob:finish"]
+        );
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn next_of_an_empty_set_is_none() {
+        let mut set = StreamSet::<&str, MockStream>::new();
+
+        assert_eq!(set.next(), None);
+    }
+}
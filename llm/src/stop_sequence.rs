@@ -0,0 +1,99 @@
+use crate::config::bool_option;
+use crate::golem::llm::llm::{ChatEvent, Config, ContentPart};
+
+/// Returns whether the `strip_stop_sequence=true` provider option is set.
+///
+/// Providers differ in whether the matched stop sequence is part of the returned text: OpenAI,
+/// Anthropic and Grok leave it out, while with Ollama and OpenRouter it depends on the model and
+/// the backend serving it. With this option a trailing stop sequence is removed from the responses
+/// of every provider, so they behave the same.
+pub fn strip_stop_sequence_enabled(config: &Config) -> bool {
+    bool_option(config, "strip_stop_sequence")
+}
+
+/// Removes a trailing stop sequence of `config` from the text of a message, when
+/// `strip_stop_sequence` is enabled
+pub fn strip_stop_sequence(config: &Config, event: ChatEvent) -> ChatEvent {
+    if !strip_stop_sequence_enabled(config) {
+        return event;
+    }
+    let Some(stop_sequences) = &config.stop_sequences else {
+        return event;
+    };
+
+    match event {
+        ChatEvent::Message(mut response) => {
+            if let Some(ContentPart::Text(text)) = response
+                .content
+                .iter_mut()
+                .rfind(|part| matches!(part, ContentPart::Text(_)))
+            {
+                if let Some(stripped) = stop_sequences
+                    .iter()
+                    .filter(|stop| !stop.is_empty())
+                    .find_map(|stop| text.strip_suffix(stop.as_str()))
+                {
+                    *text = stripped.to_string();
+                }
+            }
+            ChatEvent::Message(response)
+        }
+        event => event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, FinishReason, ResponseMetadata,
+    };
+    use crate::stop_sequence::strip_stop_sequence;
+    use crate::test_config;
+
+    fn config(strip_stop_sequence: bool) -> Config {
+        Config {
+            stop_sequences: Some(vec!["END".to_string(), "\n\n".to_string()]),
+            ..test_config::config(&[("strip_stop_sequence", &strip_stop_sequence.to_string())])
+        }
+    }
+
+    fn message(text: &str) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "response".to_string(),
+            content: vec![ContentPart::Text(text.to_string())],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: Some(FinishReason::Stop),
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            },
+        })
+    }
+
+    #[test]
+    fn trailing_stop_sequence_is_stripped_when_enabled() {
+        assert_eq!(
+            strip_stop_sequence(&config(true), message("Hello END")),
+            message("Hello ")
+        );
+        assert_eq!(
+            strip_stop_sequence(&config(true), message("Hello\n\n")),
+            message("Hello")
+        );
+    }
+
+    #[test]
+    fn text_is_unchanged_by_default_or_without_trailing_stop_sequence() {
+        assert_eq!(
+            strip_stop_sequence(&config(false), message("Hello END")),
+            message("Hello END")
+        );
+        assert_eq!(
+            strip_stop_sequence(&config(true), message("The END is near")),
+            message("The END is near")
+        );
+    }
+}
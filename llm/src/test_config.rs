@@ -0,0 +1,21 @@
+use crate::golem::llm::llm::{Config, Kv};
+
+/// A config for the `model` model with the given provider options and nothing else set. Tests
+/// needing other settings override them with the struct update syntax.
+pub fn config(provider_options: &[(&str, &str)]) -> Config {
+    Config {
+        model: "model".to_string(),
+        temperature: None,
+        max_tokens: None,
+        stop_sequences: None,
+        tools: vec![],
+        tool_choice: None,
+        provider_options: provider_options
+            .iter()
+            .map(|(key, value)| Kv {
+                key: key.to_string(),
+                value: value.to_string(),
+            })
+            .collect(),
+    }
+}
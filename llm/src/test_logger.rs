@@ -0,0 +1,54 @@
+use crate::LOGGING_STATE;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::{Mutex, Once};
+
+/// Logger capturing all the log messages of the tests, by target. As a logger can only be
+/// installed once per process, tests running in parallel share it and only look at the messages
+/// of their own module.
+struct CapturingLogger {
+    messages: Mutex<Vec<(String, String)>>,
+}
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.messages.lock().unwrap().push((
+            record.target().to_string(),
+            format!("{} {}", record.level(), record.args()),
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    messages: Mutex::new(Vec::new()),
+};
+
+static INSTALL: Once = Once::new();
+
+/// Installs the capturing logger, logging at `info` level, if it is not installed yet
+pub fn install() {
+    INSTALL.call_once(|| {
+        LOGGING_STATE.with_borrow_mut(|state| {
+            state.install_logger(&LOGGER).unwrap();
+            state.set_level(LevelFilter::Info);
+            state.init();
+        })
+    });
+}
+
+/// Gets the messages captured from the given target, formatted as `LEVEL message`
+pub fn messages(target: &str) -> Vec<String> {
+    LOGGER
+        .messages
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(message_target, _)| message_target == target)
+        .map(|(_, message)| message.clone())
+        .collect()
+}
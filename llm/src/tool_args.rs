@@ -0,0 +1,105 @@
+use crate::config::bool_option;
+use crate::golem::llm::llm::{ChatEvent, Config, Error, ErrorCode, ToolCall};
+
+/// Returns whether the `validate_tool_args=true` provider option is set, checking that the
+/// arguments of the tool calls in a response are valid JSON.
+///
+/// Some models occasionally return truncated or otherwise malformed arguments, which would
+/// otherwise only fail when the tool is executed.
+pub fn validate_tool_args_enabled(config: &Config) -> bool {
+    bool_option(config, "validate_tool_args")
+}
+
+/// Checks the tool call arguments of a response when `validate_tool_args` is enabled. A response
+/// with a tool call whose arguments are not valid JSON is turned into an `internal-error`, which
+/// is retriable, carrying the malformed arguments in `provider_error_json`. Empty arguments of
+/// tools without parameters are accepted.
+pub fn reject_invalid_tool_args(config: &Config, event: ChatEvent) -> ChatEvent {
+    if !validate_tool_args_enabled(config) {
+        return event;
+    }
+
+    let invalid = match &event {
+        ChatEvent::Message(response) => response.tool_calls.iter().find_map(invalid_arguments),
+        ChatEvent::ToolRequest(tool_calls) => tool_calls.iter().find_map(invalid_arguments),
+        ChatEvent::Error(_) => None,
+    };
+    match invalid {
+        Some(error) => ChatEvent::Error(error),
+        None => event,
+    }
+}
+
+fn invalid_arguments(tool_call: &ToolCall) -> Option<Error> {
+    if tool_call.arguments_json.trim().is_empty() {
+        return None;
+    }
+    serde_json::from_str::<serde::de::IgnoredAny>(&tool_call.arguments_json)
+        .err()
+        .map(|err| Error {
+            code: ErrorCode::InternalError,
+            message: format!(
+                "Arguments of tool call {} ({}) are not valid JSON: {err}",
+                tool_call.id, tool_call.name
+            ),
+            provider_error_json: Some(tool_call.arguments_json.clone()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{ChatEvent, Config, ErrorCode, ToolCall};
+    use crate::test_config;
+    use crate::tool_args::reject_invalid_tool_args;
+
+    fn config(validate_tool_args: bool) -> Config {
+        test_config::config(&[("validate_tool_args", &validate_tool_args.to_string())])
+    }
+
+    fn tool_request(arguments_json: &str) -> ChatEvent {
+        ChatEvent::ToolRequest(vec![ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: arguments_json.to_string(),
+        }])
+    }
+
+    #[test]
+    fn truncated_arguments_are_an_error_when_enabled() {
+        match reject_invalid_tool_args(&config(true), tool_request(r#"{"city":"Ber"#)) {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InternalError);
+                assert!(error
+                    .message
+                    .starts_with("Arguments of tool call call_1 (get_weather) are not valid JSON"));
+                assert_eq!(
+                    error.provider_error_json,
+                    Some(r#"{"city":"Ber"#.to_string())
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn arguments_are_not_checked_by_default() {
+        let request = tool_request(r#"{"city":"Ber"#);
+
+        assert_eq!(
+            reject_invalid_tool_args(&config(false), request.clone()),
+            request
+        );
+    }
+
+    #[test]
+    fn valid_and_empty_arguments_are_accepted() {
+        for arguments_json in [r#"{"city":"Berlin"}"#, ""] {
+            let request = tool_request(arguments_json);
+
+            assert_eq!(
+                reject_invalid_tool_args(&config(true), request.clone()),
+                request
+            );
+        }
+    }
+}
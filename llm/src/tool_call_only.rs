@@ -0,0 +1,172 @@
+use crate::config::bool_option;
+use crate::golem::llm::llm::{ChatEvent, Config, ContentPart, Error, ErrorCode};
+
+/// Returns whether the `tool_call_only=true` provider option is set, requiring the response to
+/// be a tool call.
+///
+/// The providers force the model to call one of the tools with their own mechanism, see
+/// [`forced_tool_choice`], and `send` and `continue` fail with an `invalid-request` error if the
+/// model still responds with text only.
+pub fn tool_call_only_enabled(config: &Config) -> bool {
+    bool_option(config, "tool_call_only")
+}
+
+/// Gets the tool choice to send to the provider. In tool call only mode, a missing tool choice is
+/// replaced with `required`, the provider's name of the choice forcing the model to call any of
+/// the tools, while a specific tool given in the config is kept.
+pub fn forced_tool_choice(config: &Config, required: &str) -> Result<Option<String>, Error> {
+    if !tool_call_only_enabled(config) {
+        return Ok(config.tool_choice.clone());
+    }
+
+    let invalid = |message: &str| Error {
+        code: ErrorCode::InvalidRequest,
+        message: message.to_string(),
+        provider_error_json: None,
+    };
+    if config.tools.is_empty() {
+        return Err(invalid("tool_call_only requires at least one tool"));
+    }
+    match config.tool_choice.as_deref() {
+        None | Some("auto") => Ok(Some(required.to_string())),
+        Some("none") => Err(invalid(
+            "tool_call_only cannot be used with the none tool choice",
+        )),
+        Some(_) => Ok(config.tool_choice.clone()),
+    }
+}
+
+/// Checks a response in tool call only mode. A message carrying tool calls is turned into a
+/// tool request, dropping its text, while a message without any is an error.
+pub fn require_tool_call(config: &Config, event: ChatEvent) -> ChatEvent {
+    if !tool_call_only_enabled(config) {
+        return event;
+    }
+
+    match event {
+        ChatEvent::Message(response) if !response.tool_calls.is_empty() => {
+            ChatEvent::ToolRequest(response.tool_calls)
+        }
+        ChatEvent::Message(response) => ChatEvent::Error(Error {
+            code: ErrorCode::InvalidRequest,
+            message: "The model responded with text instead of a tool call".to_string(),
+            provider_error_json: Some(
+                response
+                    .content
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text(text) => Some(text.as_str()),
+                        ContentPart::Image(_) => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }),
+        event => event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, ErrorCode, ResponseMetadata, ToolCall,
+        ToolDefinition,
+    };
+    use crate::test_config;
+    use crate::tool_call_only::{forced_tool_choice, require_tool_call};
+
+    fn config(tool_choice: Option<&str>) -> Config {
+        Config {
+            tools: vec![ToolDefinition {
+                name: "get_weather".to_string(),
+                description: None,
+                parameters_schema: r#"{"type":"object"}"#.to_string(),
+            }],
+            tool_choice: tool_choice.map(str::to_string),
+            ..test_config::config(&[("tool_call_only", "true")])
+        }
+    }
+
+    fn message(tool_calls: Vec<ToolCall>) -> ChatEvent {
+        ChatEvent::Message(CompleteResponse {
+            id: "response".to_string(),
+            content: vec![ContentPart::Text("It is sunny in Berlin".to_string())],
+            tool_calls,
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            },
+        })
+    }
+
+    fn tool_call() -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments_json: r#"{"city":"Berlin"}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn text_response_is_rejected() {
+        match require_tool_call(&config(None), message(vec![])) {
+            ChatEvent::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InvalidRequest);
+                assert_eq!(
+                    error.provider_error_json,
+                    Some("It is sunny in Berlin".to_string())
+                );
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn tool_calls_are_returned_as_tool_request() {
+        assert_eq!(
+            require_tool_call(&config(None), message(vec![tool_call()])),
+            ChatEvent::ToolRequest(vec![tool_call()])
+        );
+        assert_eq!(
+            require_tool_call(&config(None), ChatEvent::ToolRequest(vec![tool_call()])),
+            ChatEvent::ToolRequest(vec![tool_call()])
+        );
+    }
+
+    #[test]
+    fn responses_are_unchanged_when_disabled() {
+        let mut config = config(None);
+        config.provider_options.clear();
+
+        assert_eq!(require_tool_call(&config, message(vec![])), message(vec![]));
+        assert_eq!(forced_tool_choice(&config, "required"), Ok(None));
+    }
+
+    #[test]
+    fn tool_choice_is_forced() {
+        assert_eq!(
+            forced_tool_choice(&config(None), "any"),
+            Ok(Some("any".to_string()))
+        );
+        assert_eq!(
+            forced_tool_choice(&config(Some("auto")), "required"),
+            Ok(Some("required".to_string()))
+        );
+        assert_eq!(
+            forced_tool_choice(&config(Some("get_weather")), "required"),
+            Ok(Some("get_weather".to_string()))
+        );
+
+        let error = forced_tool_choice(&config(Some("none")), "required").unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+
+        let mut without_tools = config(None);
+        without_tools.tools.clear();
+        let error = forced_tool_choice(&without_tools, "required").unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::golem::llm::llm::{Error, ErrorCode, ToolDefinition};
+use crate::json_schema::validate;
+use serde_json::Value;
+
+/// Embeds example invocations of a tool in its `parameters_schema`, as the standard JSON Schema
+/// `examples` keyword of the root schema. Models call tools more reliably when they see how the
+/// arguments are meant to look, and all providers pass the schema on as it is.
+///
+/// Each example is the arguments object of one call, and is validated against the schema when
+/// the tool is defined so a wrong example can not mislead the model. Examples already in the
+/// schema are kept, the new ones are added after them.
+pub fn with_tool_examples(
+    tool: ToolDefinition,
+    examples: &[Value],
+) -> Result<ToolDefinition, Error> {
+    let mut schema: Value = serde_json::from_str(&tool.parameters_schema).map_err(|err| Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!("Failed to parse tool parameters for {}: {err}", tool.name),
+        provider_error_json: None,
+    })?;
+
+    for (idx, example) in examples.iter().enumerate() {
+        validate(example, &schema).map_err(|problem| Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Example {idx} of tool {} does not match its parameters schema: {problem}",
+                tool.name
+            ),
+            provider_error_json: Some(example.to_string()),
+        })?;
+    }
+
+    let Some(object) = schema.as_object_mut() else {
+        return Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Parameters schema of tool {} is not an object", tool.name),
+            provider_error_json: None,
+        });
+    };
+    match object
+        .entry("examples")
+        .or_insert_with(|| Value::Array(vec![]))
+    {
+        Value::Array(existing) => existing.extend(examples.iter().cloned()),
+        _ => {
+            return Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!("The examples of tool {} are not an array", tool.name),
+                provider_error_json: None,
+            })
+        }
+    }
+
+    Ok(ToolDefinition {
+        parameters_schema: schema.to_string(),
+        ..tool
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{ErrorCode, ToolDefinition};
+    use crate::tool_examples::with_tool_examples;
+    use serde_json::json;
+
+    fn weather_tool() -> ToolDefinition {
+        ToolDefinition {
+            name: "get_weather".to_string(),
+            description: Some("Gets the current weather of a city".to_string()),
+            parameters_schema: r#"{"type":"object","properties":{"city":{"type":"string"},"unit":{"enum":["celsius","fahrenheit"]}},"required":["city"]}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn examples_are_embedded_in_the_schema() {
+        let tool = with_tool_examples(
+            weather_tool(),
+            &[
+                json!({"city": "Berlin"}),
+                json!({"city": "Boston", "unit": "fahrenheit"}),
+            ],
+        )
+        .unwrap();
+
+        let schema: serde_json::Value = serde_json::from_str(&tool.parameters_schema).unwrap();
+        assert_eq!(
+            schema["examples"],
+            json!([{"city": "Berlin"}, {"city": "Boston", "unit": "fahrenheit"}])
+        );
+        assert_eq!(schema["required"], json!(["city"]));
+        assert_eq!(tool.name, "get_weather");
+    }
+
+    #[test]
+    fn examples_are_added_to_existing_ones() {
+        let tool = with_tool_examples(weather_tool(), &[json!({"city": "Berlin"})]).unwrap();
+        let tool = with_tool_examples(tool, &[json!({"city": "Paris"})]).unwrap();
+
+        let schema: serde_json::Value = serde_json::from_str(&tool.parameters_schema).unwrap();
+        assert_eq!(
+            schema["examples"],
+            json!([{"city": "Berlin"}, {"city": "Paris"}])
+        );
+    }
+
+    #[test]
+    fn example_not_matching_the_schema_is_rejected() {
+        let error =
+            with_tool_examples(weather_tool(), &[json!({"city": "Rome", "unit": "kelvin"})])
+                .unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            r#"Example 0 of tool get_weather does not match its parameters schema: $.unit should be one of ["celsius","fahrenheit"]"#
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use crate::golem::llm::llm::{
+    ChatEvent, CompleteResponse, Config, Error, ErrorCode, Message, ToolCall, ToolFailure,
+    ToolResult, ToolSuccess,
+};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Executes the tool calls requested by the model
+pub trait ToolHandlers {
+    /// Runs the tool with the call's JSON arguments, returning its JSON result or an error
+    /// message. Returns `None` if there is no handler for the requested tool.
+    fn execute(&self, call: &ToolCall) -> Option<Result<String, String>>;
+}
+
+impl<F: Fn(&str) -> Result<String, String>> ToolHandlers for HashMap<String, F> {
+    fn execute(&self, call: &ToolCall) -> Option<Result<String, String>> {
+        self.get(&call.name)
+            .map(|handler| handler(&call.arguments_json))
+    }
+}
+
+/// Runs the send → tool request → execute → continue cycle until the model returns a complete
+/// response.
+///
+/// Each round executes all the tool calls of a `ToolRequest` with `handlers` and continues the
+/// conversation with the results of every round so far. Calls to tools without a handler are
+/// answered with a tool failure, letting the model recover. If the model still requests tools
+/// after `max_rounds` rounds, an `invalid-request` error is returned instead of continuing
+/// forever, as retrying the same conversation would not end differently.
+pub fn run_tool_loop(
+    messages: Vec<Message>,
+    send: impl Fn(Vec<Message>, Config) -> ChatEvent,
+    continue_: impl Fn(Vec<Message>, Vec<(ToolCall, ToolResult)>, Config) -> ChatEvent,
+    handlers: &impl ToolHandlers,
+    config: Config,
+    max_rounds: u32,
+) -> Result<CompleteResponse, Error> {
+    let mut tool_results = Vec::new();
+    let mut rounds = 0;
+    let mut event = send(messages.clone(), config.clone());
+
+    loop {
+        match event {
+            ChatEvent::Message(response) => return Ok(response),
+            ChatEvent::Error(error) => return Err(error),
+            ChatEvent::ToolRequest(tool_calls) => {
+                if rounds >= max_rounds {
+                    return Err(Error {
+                        code: ErrorCode::InvalidRequest,
+                        message: format!(
+                            "The model kept requesting tool calls after {max_rounds} rounds"
+                        ),
+                        provider_error_json: None,
+                    });
+                }
+                rounds += 1;
+
+                for tool_call in tool_calls {
+                    let result = execute(handlers, &tool_call);
+                    tool_results.push((tool_call, result));
+                }
+                event = continue_(messages.clone(), tool_results.clone(), config.clone());
+            }
+        }
+    }
+}
+
+fn execute(handlers: &impl ToolHandlers, tool_call: &ToolCall) -> ToolResult {
+    let start = Instant::now();
+    match handlers.execute(tool_call) {
+        Some(Ok(result_json)) => ToolResult::Success(ToolSuccess {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            result_json,
+            execution_time_ms: Some(start.elapsed().as_millis() as u32),
+        }),
+        Some(Err(error_message)) => ToolResult::Error(ToolFailure {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            error_message,
+            error_code: None,
+        }),
+        None => ToolResult::Error(ToolFailure {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            error_message: format!("Unknown tool: {}", tool_call.name),
+            error_code: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, ContentPart, ErrorCode, Message, ResponseMetadata, Role,
+        ToolCall, ToolResult,
+    };
+    use crate::test_config::config;
+    use crate::tool_loop::run_tool_loop;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            name: None,
+            content: vec![ContentPart::Text("What is 1 + 2 + 3 + 4?".to_string())],
+        }]
+    }
+
+    fn tool_request(round: usize) -> ChatEvent {
+        ChatEvent::ToolRequest(vec![ToolCall {
+            id: format!("call_{round}"),
+            name: "add".to_string(),
+            arguments_json: format!(r#"{{"a":{round},"b":1}}"#),
+        }])
+    }
+
+    fn response(text: &str) -> CompleteResponse {
+        CompleteResponse {
+            id: "response".to_string(),
+            content: vec![ContentPart::Text(text.to_string())],
+            tool_calls: vec![],
+            metadata: ResponseMetadata {
+                finish_reason: None,
+                usage: None,
+                provider_id: None,
+                timestamp: None,
+                provider_metadata_json: None,
+                raw_json: None,
+            },
+        }
+    }
+
+    fn handlers() -> HashMap<String, fn(&str) -> Result<String, String>> {
+        let mut handlers: HashMap<String, fn(&str) -> Result<String, String>> = HashMap::new();
+        handlers.insert("add".to_string(), |arguments| {
+            let arguments: serde_json::Value =
+                serde_json::from_str(arguments).map_err(|err| err.to_string())?;
+            let sum = arguments["a"].as_i64().unwrap_or(0) + arguments["b"].as_i64().unwrap_or(0);
+            Ok(sum.to_string())
+        });
+        handlers
+    }
+
+    #[test]
+    fn loop_runs_until_complete_response() {
+        let continued = RefCell::new(Vec::new());
+        let result = run_tool_loop(
+            messages(),
+            |_, _| tool_request(1),
+            |_, tool_results: Vec<(ToolCall, ToolResult)>, _| {
+                continued.borrow_mut().push(tool_results.len());
+                match tool_results.len() {
+                    1 => tool_request(2),
+                    2 => tool_request(3),
+                    _ => ChatEvent::Message(response("10")),
+                }
+            },
+            &handlers(),
+            config(&[]),
+            3,
+        );
+
+        assert_eq!(result, Ok(response("10")));
+        assert_eq!(*continued.borrow(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn tool_results_are_passed_to_continue() {
+        let last_results = RefCell::new(Vec::new());
+        let _ = run_tool_loop(
+            messages(),
+            |_, _| {
+                ChatEvent::ToolRequest(vec![
+                    ToolCall {
+                        id: "call_1".to_string(),
+                        name: "add".to_string(),
+                        arguments_json: r#"{"a":1,"b":2}"#.to_string(),
+                    },
+                    ToolCall {
+                        id: "call_2".to_string(),
+                        name: "multiply".to_string(),
+                        arguments_json: r#"{"a":1,"b":2}"#.to_string(),
+                    },
+                ])
+            },
+            |_, tool_results, _| {
+                *last_results.borrow_mut() = tool_results;
+                ChatEvent::Message(response("done"))
+            },
+            &handlers(),
+            config(&[]),
+            1,
+        );
+
+        let last_results = last_results.borrow();
+        match &last_results[0].1 {
+            ToolResult::Success(success) => assert_eq!(success.result_json, "3"),
+            other => panic!("Unexpected result: {other:?}"),
+        }
+        match &last_results[1].1 {
+            ToolResult::Error(failure) => {
+                assert_eq!(failure.error_message, "Unknown tool: multiply")
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn loop_fails_when_max_rounds_is_exceeded() {
+        let rounds = RefCell::new(0);
+        let result = run_tool_loop(
+            messages(),
+            |_, _| tool_request(1),
+            |_, _, _| {
+                *rounds.borrow_mut() += 1;
+                tool_request(*rounds.borrow() + 1)
+            },
+            &handlers(),
+            config(&[]),
+            2,
+        );
+
+        let error = result.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(
+            error.message,
+            "The model kept requesting tool calls after 2 rounds"
+        );
+        assert_eq!(*rounds.borrow(), 2);
+    }
+}
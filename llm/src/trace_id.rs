@@ -0,0 +1,94 @@
+use crate::config::option;
+use crate::golem::llm::llm::Config;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+
+thread_local! {
+    /// The trace id of the request being processed
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Gets the caller provided correlation id of a request, set with the `trace_id` provider option
+pub fn trace_id(config: &Config) -> Option<String> {
+    option(config, "trace_id")
+        .filter(|trace_id| !trace_id.is_empty())
+        .map(str::to_string)
+}
+
+/// Gets the trace id of the request being processed, if it has one
+pub fn current() -> Option<String> {
+    CURRENT.with_borrow(|current| current.clone())
+}
+
+/// Runs `f` while processing the request with the given trace id, so the log lines it emits are
+/// prefixed with it. The previous trace id is restored afterwards, even if `f` panics.
+pub fn with_trace_id<T>(trace_id: Option<String>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<String>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.set(self.0.take());
+        }
+    }
+
+    let _restore = Restore(CURRENT.replace(trace_id));
+    f()
+}
+
+/// Formats as the `[trace_id=...] ` prefix of the log lines of the request being processed, or as
+/// nothing outside of requests with a trace id. Used as `trace!("{TRACE_ID}Sending request")`, so
+/// all the log lines of one request can be grepped together.
+pub struct TraceIdPrefix;
+
+pub const TRACE_ID: TraceIdPrefix = TraceIdPrefix;
+
+impl Display for TraceIdPrefix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        CURRENT.with_borrow(|current| match current {
+            Some(trace_id) => write!(f, "[trace_id={trace_id}] "),
+            None => Ok(()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::Config;
+    use crate::trace_id::{current, trace_id, with_trace_id, TRACE_ID};
+    use crate::{test_config, test_logger};
+
+    fn config(trace_id: &str) -> Config {
+        test_config::config(&[("trace_id", trace_id)])
+    }
+
+    #[test]
+    fn trace_id_is_read_from_the_provider_options() {
+        assert_eq!(trace_id(&config("req-42")), Some("req-42".to_string()));
+        assert_eq!(trace_id(&config("")), None);
+    }
+
+    #[test]
+    fn trace_id_prefixes_the_log_lines_of_the_request() {
+        test_logger::install();
+
+        with_trace_id(trace_id(&config("req-42")), || {
+            log::info!("{TRACE_ID}Sending request");
+            with_trace_id(Some("nested".to_string()), || {
+                log::info!("{TRACE_ID}Nested request");
+            });
+            log::info!("{TRACE_ID}Received response");
+        });
+        log::info!("{TRACE_ID}Outside of a request");
+
+        assert_eq!(
+            test_logger::messages(module_path!()),
+            vec![
+                "INFO [trace_id=req-42] Sending request",
+                "INFO [trace_id=nested] Nested request",
+                "INFO [trace_id=req-42] Received response",
+                "INFO Outside of a request",
+            ]
+        );
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,780 @@
+use crate::error::{from_event_source_error, from_reqwest_error};
+use crate::event_source::{ByteStream, EventSource, ReadError};
+use crate::golem::llm::llm::{Error, ErrorCode, Kv};
+use flate2::read::GzDecoder;
+use log::warn;
+use reqwest::{Client, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::Read;
+use std::rc::Rc;
+use std::task::Poll;
+use std::time::Duration;
+
+/// A provider independent description of an HTTP request to be sent by a [`HttpTransport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+    pub timeout: Option<Duration>,
+}
+
+impl HttpRequest {
+    pub fn new(method: Method, url: impl Into<String>) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            headers: Vec::new(),
+            body: None,
+            timeout: None,
+        }
+    }
+
+    pub fn get(url: impl Into<String>) -> Self {
+        Self::new(Method::GET, url)
+    }
+
+    pub fn post(url: impl Into<String>) -> Self {
+        Self::new(Method::POST, url)
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn bearer_auth(self, token: impl AsRef<str>) -> Self {
+        let value = format!("Bearer {}", token.as_ref());
+        self.header(reqwest::header::AUTHORIZATION.as_str(), value)
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the body of the request to the JSON serialized form of `body`
+    pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Result<Self, Error> {
+        let bytes = serde_json::to_vec(body).map_err(|err| Error {
+            code: ErrorCode::InternalError,
+            message: format!("Failed to serialize request body: {err}"),
+            provider_error_json: None,
+        })?;
+        self.body = Some(bytes);
+        Ok(self.header(reqwest::header::CONTENT_TYPE.as_str(), "application/json"))
+    }
+}
+
+/// A fully received HTTP response returned by a [`HttpTransport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: StatusCode, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Gets the first value of a header, comparing the names case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.body)
+    }
+
+    /// Decodes a gzip compressed body, as the wasi-http based `reqwest` client does not
+    /// decompress responses by itself. Bodies without a `Content-Encoding`, or with an `identity`
+    /// or empty one, are returned as is.
+    pub fn decompressed(mut self) -> Result<Self, Error> {
+        let Some(encoding) = self.header(reqwest::header::CONTENT_ENCODING.as_str()) else {
+            return Ok(self);
+        };
+        let encoding = encoding.trim();
+        if encoding.is_empty() || encoding.eq_ignore_ascii_case("identity") {
+            return Ok(self);
+        }
+        if !encoding.eq_ignore_ascii_case("gzip") {
+            return Err(Error {
+                code: ErrorCode::InternalError,
+                message: format!("Unsupported response content encoding: {encoding}"),
+                provider_error_json: None,
+            });
+        }
+
+        let mut body = Vec::new();
+        GzDecoder::new(self.body.as_slice())
+            .read_to_end(&mut body)
+            .map_err(|err| Error {
+                code: ErrorCode::InternalError,
+                message: format!("Failed to decompress response body: {err}"),
+                provider_error_json: None,
+            })?;
+        self.body = body;
+        self.headers.retain(|(name, _)| {
+            !name.eq_ignore_ascii_case(reqwest::header::CONTENT_ENCODING.as_str())
+        });
+        Ok(self)
+    }
+}
+
+/// Infers the MIME type of an image from the file extension of its URL, for when it is not known
+/// from a `Content-Type` header or a data URL
+pub fn mime_type_from_url_extension(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let (_, extension) = file_name.rsplit_once('.')?;
+    match extension.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "bmp" => Some("image/bmp"),
+        "svg" => Some("image/svg+xml"),
+        "tif" | "tiff" => Some("image/tiff"),
+        "heic" => Some("image/heic"),
+        _ => None,
+    }
+}
+
+/// Gets the deprecation notice of a provider's response, reported in the OpenAI style
+/// `openai-deprecation` or the standard `deprecation` header, along with the date of the `sunset`
+/// header when present. Returns `None` if the response carries no deprecation notice.
+pub fn deprecation_notice(response: &HttpResponse) -> Option<String> {
+    let notice = response
+        .header("openai-deprecation")
+        .or_else(|| response.header("deprecation"));
+    match (notice, response.header("sunset")) {
+        (Some(notice), Some(sunset)) => Some(format!("{notice} (sunset: {sunset})")),
+        (Some(notice), None) => Some(notice.to_string()),
+        (None, Some(sunset)) => Some(format!("Sunset: {sunset}")),
+        (None, None) => None,
+    }
+}
+
+/// The remaining rate limit quotas reported in the headers of a provider's response, so callers
+/// can throttle before hitting the limits.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratelimit_remaining_requests: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratelimit_remaining_tokens: Option<u64>,
+}
+
+impl RateLimitStatus {
+    /// Reads the OpenAI style `x-ratelimit-remaining-*` and the Anthropic style
+    /// `anthropic-ratelimit-*-remaining` headers. Returns `None` if neither is present.
+    pub fn from_headers(response: &HttpResponse) -> Option<Self> {
+        let remaining = |kind: &str| {
+            response
+                .header(&format!("x-ratelimit-remaining-{kind}"))
+                .or_else(|| response.header(&format!("anthropic-ratelimit-{kind}-remaining")))
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        };
+        let status = Self {
+            ratelimit_remaining_requests: remaining("requests"),
+            ratelimit_remaining_tokens: remaining("tokens"),
+        };
+        if status == Self::default() {
+            None
+        } else {
+            Some(status)
+        }
+    }
+
+    /// Adds the remaining quotas to a provider metadata object
+    pub fn add_to_metadata(
+        &self,
+        provider_metadata: &mut serde_json::Map<String, serde_json::Value>,
+    ) {
+        if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(self) {
+            provider_metadata.extend(fields);
+        }
+    }
+}
+
+/// The HTTP layer used by the provider clients.
+///
+/// The default implementation is [`ReqwestTransport`]; unit tests can inject a
+/// [`CannedTransport`] instead to exercise response parsing and error mapping without network
+/// access.
+pub trait HttpTransport {
+    /// Sends a request and receives the whole response body
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error>;
+
+    /// Sends a batch of independent requests, returning the responses in the order of the
+    /// requests.
+    ///
+    /// The default implementation sends the requests one by one; transports capable of
+    /// non-blocking requests can override it to overlap them.
+    fn send_all(&self, requests: Vec<HttpRequest>) -> Vec<Result<HttpResponse, Error>> {
+        requests
+            .into_iter()
+            .map(|request| self.send(request))
+            .collect()
+    }
+
+    /// Sends a request and opens a server-sent events stream on the response body
+    fn stream(&self, request: HttpRequest) -> Result<EventSource, Error>;
+}
+
+/// [`HttpTransport`] implementation using the wasi-http based `reqwest` client
+pub struct ReqwestTransport {
+    client: Client,
+    /// Headers added to every request which does not set them itself, see [`default_headers`]
+    headers: Vec<(String, String)>,
+    /// The egress proxy configured in the environment, see [`ProxyConfig`]
+    proxy: Option<ProxyConfig>,
+    /// Whether the requests that should go through the proxy were already warned about
+    proxy_warned: Cell<bool>,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        let client = Client::builder()
+            .build()
+            .expect("Failed to initialize HTTP client");
+        Self {
+            client,
+            headers: DEFAULT_HEADERS.with(|headers| headers.clone()),
+            proxy: ProxyConfig::from_env(),
+            proxy_warned: Cell::new(false),
+        }
+    }
+
+    /// Adds the headers of a single call, given in the `header:<name>` provider options (see
+    /// [`header_options`]), to every request. They take precedence over the default headers.
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = merge_headers(&self.headers, headers);
+        self
+    }
+
+    fn execute(&self, request: HttpRequest) -> Result<reqwest::Response, Error> {
+        let request = with_missing_headers(request, &self.headers);
+        if let Some(proxy) = &self.proxy {
+            if proxy.applies_to(&request.url) && !self.proxy_warned.replace(true) {
+                warn!(
+                    "Proxy {} is configured, but requests are sent through the host's WASI HTTP \
+                     implementation, which routes them according to its own proxy settings",
+                    proxy.url
+                );
+            }
+        }
+
+        let mut builder = self.client.request(request.method, request.url);
+        for (name, value) in request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(timeout) = request.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+        builder
+            .send()
+            .map_err(|err| from_reqwest_error("Request failed", err))
+    }
+}
+
+/// Egress proxy settings, read from the standard `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY`
+/// environment variables (or their lowercase variants).
+///
+/// Under WASI preview 2 the component cannot open connections itself: requests are handed to the
+/// host's `wasi:http` implementation, which decides how to route them. The settings are read so
+/// that misconfigurations are visible, and requests which should use the proxy log a warning, but
+/// the proxy itself has to be configured for the host, for example the Golem worker executor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyConfig {
+    pub url: String,
+    /// Hosts, domain suffixes (like `.internal`) or `*` for which the proxy is not used
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Reads the proxy settings with `var` looking up environment variables
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let lookup = |name: &str| {
+            var(name)
+                .or_else(|| var(&name.to_lowercase()))
+                .filter(|value| !value.trim().is_empty())
+        };
+        let url = lookup("HTTPS_PROXY").or_else(|| lookup("HTTP_PROXY"))?;
+        let no_proxy = lookup("NO_PROXY")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|host| host.trim().to_lowercase())
+                    .filter(|host| !host.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self { url, no_proxy })
+    }
+
+    /// Returns whether a request to `url` should be sent through the proxy
+    pub fn applies_to(&self, url: &str) -> bool {
+        let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let authority = authority.split(['/', '?', '#']).next().unwrap_or_default();
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host)
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        !self.no_proxy.iter().any(|pattern| {
+            pattern == "*"
+                || host == pattern.trim_start_matches('.')
+                || host.ends_with(&format!(".{}", pattern.trim_start_matches('.')))
+        })
+    }
+}
+
+/// The environment variable holding the headers sent with every request of all providers, as a
+/// JSON object of header names and values, for example
+/// `{"OpenAI-Organization": "org-123", "X-Attribution": "my-app"}`
+pub const DEFAULT_HEADERS_ENV_VAR: &str = "GOLEM_LLM_DEFAULT_HEADERS";
+
+thread_local! {
+    /// The default headers, parsed once from the environment
+    static DEFAULT_HEADERS: Vec<(String, String)> = default_headers();
+}
+
+/// Reads the default headers from the `GOLEM_LLM_DEFAULT_HEADERS` environment variable. An
+/// invalid value is logged and ignored, as it can not fail the requests of the component.
+fn default_headers() -> Vec<(String, String)> {
+    match std::env::var(DEFAULT_HEADERS_ENV_VAR) {
+        Ok(value) => parse_default_headers(&value).unwrap_or_else(|err| {
+            warn!("Ignoring {DEFAULT_HEADERS_ENV_VAR}: {err}");
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Parses a JSON object of header names and string values
+pub fn parse_default_headers(json: &str) -> Result<Vec<(String, String)>, String> {
+    let headers = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(json)
+        .map_err(|err| format!("expected a JSON object of headers: {err}"))?;
+    headers
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => Ok((name, value)),
+            other => Err(format!(
+                "the value of header {name} is not a string: {other}"
+            )),
+        })
+        .collect()
+}
+
+/// Gets the headers of a single call from the `header:<name>` provider options, for example
+/// `header:OpenAI-Project=proj_123`
+pub fn header_options(provider_options: &[Kv]) -> Vec<(String, String)> {
+    provider_options
+        .iter()
+        .filter_map(|kv| {
+            kv.key
+                .strip_prefix("header:")
+                .filter(|name| !name.is_empty())
+                .map(|name| (name.to_string(), kv.value.clone()))
+        })
+        .collect()
+}
+
+/// Creates the transport of a single call, sending the default headers and the headers of the
+/// call's `header:<name>` provider options with every request.
+///
+/// The API clients of all providers are created with this transport, so their requests carry
+/// the headers of [`DEFAULT_HEADERS_ENV_VAR`], overridden by the ones of the call.
+pub fn transport_for(provider_options: &[Kv]) -> Box<dyn HttpTransport> {
+    Box::new(ReqwestTransport::new().with_headers(header_options(provider_options)))
+}
+
+/// Merges the headers of a call into the default headers, replacing the defaults of the same
+/// name, compared case-insensitively
+fn merge_headers(
+    defaults: &[(String, String)],
+    call: Vec<(String, String)>,
+) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = defaults
+        .iter()
+        .filter(|(name, _)| {
+            !call
+                .iter()
+                .any(|(other, _)| other.eq_ignore_ascii_case(name))
+        })
+        .cloned()
+        .collect();
+    merged.extend(call);
+    merged
+}
+
+/// Adds the headers a request does not set itself, so the headers the provider clients need,
+/// like the authorization and the content type, are never replaced
+fn with_missing_headers(mut request: HttpRequest, headers: &[(String, String)]) -> HttpRequest {
+    for (name, value) in headers {
+        if !request
+            .headers
+            .iter()
+            .any(|(other, _)| other.eq_ignore_ascii_case(name))
+        {
+            request.headers.push((name.clone(), value.clone()));
+        }
+    }
+    request
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    /// Requests a gzip compressed response. Streams are left uncompressed, so that events are
+    /// not held back by the decompression.
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error> {
+        let accept_encoding = reqwest::header::ACCEPT_ENCODING.as_str();
+        let request = if request
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case(accept_encoding))
+        {
+            request
+        } else {
+            request.header(accept_encoding, "gzip")
+        };
+        let response = self.execute(request)?;
+
+        let status = response.status();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.as_str().to_string(), value.to_string()))
+            })
+            .collect();
+        let body = response
+            .bytes()
+            .map_err(|err| from_reqwest_error("Failed to receive response body", err))?
+            .to_vec();
+
+        HttpResponse {
+            status,
+            headers,
+            body,
+        }
+        .decompressed()
+    }
+
+    fn stream(&self, request: HttpRequest) -> Result<EventSource, Error> {
+        let response = self.execute(request)?;
+        EventSource::new(response)
+            .map_err(|err| from_event_source_error("Failed to create SSE stream", err))
+    }
+}
+
+/// Test double for [`HttpTransport`] which answers requests with a queue of canned responses
+/// and records every request it receives. Streaming requests are answered with a separate queue
+/// of canned streams.
+#[derive(Default)]
+pub struct CannedTransport {
+    responses: RefCell<VecDeque<Result<HttpResponse, Error>>>,
+    streams: RefCell<VecDeque<CannedStream>>,
+    requests: RefCell<Vec<HttpRequest>>,
+}
+
+impl CannedTransport {
+    pub fn new(responses: impl IntoIterator<Item = Result<HttpResponse, Error>>) -> Self {
+        Self {
+            responses: RefCell::new(responses.into_iter().collect()),
+            streams: RefCell::new(VecDeque::new()),
+            requests: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Answers the streaming requests with the given streams, in order
+    pub fn with_streams(self, streams: impl IntoIterator<Item = CannedStream>) -> Self {
+        self.streams.borrow_mut().extend(streams);
+        self
+    }
+
+    /// Creates a transport answering the first request with `status` and a JSON `body`
+    pub fn json(status: StatusCode, body: impl Into<String>) -> Self {
+        Self::new([Ok(
+            HttpResponse::new(status, body.into()).with_header("content-type", "application/json")
+        )])
+    }
+
+    /// Returns the requests received so far
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.borrow().clone()
+    }
+}
+
+impl HttpTransport for CannedTransport {
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error> {
+        self.requests.borrow_mut().push(request);
+        self.responses.borrow_mut().pop_front().unwrap_or_else(|| {
+            Err(Error {
+                code: ErrorCode::InternalError,
+                message: "No more canned responses".to_string(),
+                provider_error_json: None,
+            })
+        })
+    }
+
+    fn stream(&self, request: HttpRequest) -> Result<EventSource, Error> {
+        self.requests.borrow_mut().push(request);
+        match self.streams.borrow_mut().pop_front() {
+            Some(stream) => Ok(EventSource::from_stream(stream)),
+            None => Err(Error {
+                code: ErrorCode::Unsupported,
+                message: "No more canned streams".to_string(),
+                provider_error_json: None,
+            }),
+        }
+    }
+}
+
+/// Test double for a response body stream, returning canned chunks of bytes one read at a time.
+/// After the last chunk the stream ends, or fails as if the connection dropped.
+pub struct CannedStream {
+    chunks: VecDeque<Vec<u8>>,
+    drops: bool,
+    released: Rc<Cell<bool>>,
+}
+
+impl CannedStream {
+    pub fn new(chunks: impl IntoIterator<Item = impl Into<Vec<u8>>>) -> Self {
+        Self {
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            drops: false,
+            released: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Makes the connection drop after the last chunk instead of the stream ending
+    pub fn dropping(mut self) -> Self {
+        self.drops = true;
+        self
+    }
+
+    /// Returns a flag which is set when the stream is released
+    pub fn released(&self) -> Rc<Cell<bool>> {
+        self.released.clone()
+    }
+}
+
+impl ByteStream for CannedStream {
+    fn subscribe(&self) -> golem_rust::wasm_rpc::Pollable {
+        golem_rust::bindings::wasi::clocks::monotonic_clock::subscribe_duration(0)
+    }
+
+    fn poll_read(&mut self, _len: u64) -> Poll<Result<Vec<u8>, ReadError>> {
+        Poll::Ready(match self.chunks.pop_front() {
+            Some(chunk) => Ok(chunk),
+            None if self.drops => Err(ReadError::Failed("connection reset".to_string())),
+            None => Err(ReadError::Closed),
+        })
+    }
+}
+
+impl Drop for CannedStream {
+    fn drop(&mut self) {
+        self.released.set(true);
+    }
+}
+
+impl<T: HttpTransport + ?Sized> HttpTransport for std::rc::Rc<T> {
+    fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error> {
+        (**self).send(request)
+    }
+
+    fn send_all(&self, requests: Vec<HttpRequest>) -> Vec<Result<HttpResponse, Error>> {
+        (**self).send_all(requests)
+    }
+
+    fn stream(&self, request: HttpRequest) -> Result<EventSource, Error> {
+        (**self).stream(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::Kv;
+    use crate::transport::{
+        header_options, merge_headers, parse_default_headers, with_missing_headers, HttpRequest,
+        HttpResponse, ProxyConfig,
+    };
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use reqwest::StatusCode;
+    use std::io::Write;
+
+    #[test]
+    fn gzip_compressed_body_is_decoded() {
+        let body = r#"{"id":"resp_1","output":"Hello"}"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+        let response = HttpResponse::new(StatusCode::OK, encoder.finish().unwrap())
+            .with_header("Content-Encoding", "gzip");
+
+        let response = response.decompressed().unwrap();
+
+        assert_eq!(response.text(), body);
+        assert_eq!(
+            response.json::<serde_json::Value>().unwrap()["output"],
+            "Hello"
+        );
+        assert_eq!(response.header("content-encoding"), None);
+    }
+
+    #[test]
+    fn uncompressed_body_is_unchanged() {
+        let response = HttpResponse::new(StatusCode::OK, "Hello");
+
+        assert_eq!(response.clone().decompressed().unwrap(), response);
+    }
+
+    #[test]
+    fn identity_and_empty_encodings_are_uncompressed() {
+        for encoding in ["identity", "Identity", ""] {
+            let response = HttpResponse::new(StatusCode::OK, "Hello")
+                .with_header("Content-Encoding", encoding);
+
+            assert_eq!(response.clone().decompressed().unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn corrupt_gzip_body_is_an_error() {
+        let response =
+            HttpResponse::new(StatusCode::OK, "Hello").with_header("content-encoding", "gzip");
+
+        let error = response.decompressed().unwrap_err();
+
+        assert!(error
+            .message
+            .starts_with("Failed to decompress response body"));
+    }
+
+    #[test]
+    fn proxy_settings_are_read_from_the_environment() {
+        let vars = |name: &str| match name {
+            "https_proxy" => Some("http://proxy.corp:3128".to_string()),
+            "NO_PROXY" => Some("localhost, .internal".to_string()),
+            _ => None,
+        };
+
+        let proxy = ProxyConfig::from_vars(vars).unwrap();
+
+        assert_eq!(
+            proxy,
+            ProxyConfig {
+                url: "http://proxy.corp:3128".to_string(),
+                no_proxy: vec!["localhost".to_string(), ".internal".to_string()],
+            }
+        );
+        assert!(proxy.applies_to("https://api.openai.com/v1/responses"));
+        assert!(!proxy.applies_to("http://localhost:11434/v1/chat/completions"));
+        assert!(!proxy.applies_to("https://llm.corp.internal/v1"));
+        assert_eq!(ProxyConfig::from_vars(|_| None), None);
+    }
+
+    #[test]
+    fn default_headers_are_applied_and_overridable_per_call() {
+        let defaults = parse_default_headers(
+            r#"{"OpenAI-Organization": "org-123", "X-Attribution": "my-app"}"#,
+        )
+        .unwrap();
+        let call = header_options(&[
+            Kv {
+                key: "header:x-attribution".to_string(),
+                value: "my-feature".to_string(),
+            },
+            Kv {
+                key: "header:OpenAI-Project".to_string(),
+                value: "proj_456".to_string(),
+            },
+            Kv {
+                key: "temperature".to_string(),
+                value: "0.5".to_string(),
+            },
+        ]);
+        let headers = merge_headers(&defaults, call);
+
+        let request = with_missing_headers(
+            HttpRequest::post("https://api.openai.com/v1/responses").bearer_auth("sk-test"),
+            &headers,
+        );
+
+        assert_eq!(
+            request.headers,
+            vec![
+                ("authorization".to_string(), "Bearer sk-test".to_string()),
+                ("OpenAI-Organization".to_string(), "org-123".to_string()),
+                ("x-attribution".to_string(), "my-feature".to_string()),
+                ("OpenAI-Project".to_string(), "proj_456".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn default_headers_do_not_replace_the_headers_of_the_client() {
+        let headers = parse_default_headers(r#"{"Authorization": "Bearer other"}"#).unwrap();
+
+        let request = with_missing_headers(
+            HttpRequest::post("https://api.openai.com/v1/responses").bearer_auth("sk-test"),
+            &headers,
+        );
+
+        assert_eq!(
+            request.headers,
+            vec![("authorization".to_string(), "Bearer sk-test".to_string())]
+        );
+    }
+
+    #[test]
+    fn invalid_default_headers_are_rejected() {
+        assert!(parse_default_headers("[]").is_err());
+        assert_eq!(
+            parse_default_headers(r#"{"X-Retries": 3}"#),
+            Err("the value of header X-Retries is not a string: 3".to_string())
+        );
+    }
+}
@@ -0,0 +1,404 @@
+use crate::golem::llm::llm::{ChatEvent, Config, ContentPart, Error, ErrorCode, Message, Role};
+
+/// Counts the number of tokens a list of messages takes up in the model's context window
+pub trait TokenCounter {
+    fn count_tokens(&self, messages: &[Message]) -> u32;
+}
+
+impl<F: Fn(&[Message]) -> u32> TokenCounter for F {
+    fn count_tokens(&self, messages: &[Message]) -> u32 {
+        self(messages)
+    }
+}
+
+/// A provider independent token count estimation, assuming roughly four characters per token
+/// plus a small fixed overhead for each message and image.
+pub struct ApproximateTokenCounter;
+
+impl ApproximateTokenCounter {
+    const CHARS_PER_TOKEN: u32 = 4;
+    const MESSAGE_OVERHEAD: u32 = 4;
+    const IMAGE_TOKENS: u32 = 85;
+}
+
+impl TokenCounter for ApproximateTokenCounter {
+    fn count_tokens(&self, messages: &[Message]) -> u32 {
+        messages
+            .iter()
+            .map(|message| {
+                let content: u32 = message
+                    .content
+                    .iter()
+                    .map(|part| match part {
+                        ContentPart::Text(text) => {
+                            (text.chars().count() as u32).div_ceil(Self::CHARS_PER_TOKEN)
+                        }
+                        ContentPart::Image(_) => Self::IMAGE_TOKENS,
+                    })
+                    .sum();
+                content + Self::MESSAGE_OVERHEAD
+            })
+            .sum()
+    }
+}
+
+/// A conversation split into the parts a [`TruncationStrategy`] is allowed to touch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    /// All system messages of the conversation, always preserved
+    pub system: Vec<Message>,
+    /// Non-system messages older than the latest turns, these are the candidates for reduction
+    pub older: Vec<Message>,
+    /// The latest non-system messages, always preserved
+    pub latest: Vec<Message>,
+}
+
+impl History {
+    /// Splits `messages` keeping the last `keep_latest` non-system messages in `latest`.
+    ///
+    /// System messages are moved in front of the conversation, keeping their relative order.
+    pub fn split(messages: Vec<Message>, keep_latest: usize) -> Self {
+        let (system, rest): (Vec<_>, Vec<_>) = messages
+            .into_iter()
+            .partition(|message| message.role == Role::System);
+        let split_at = rest.len().saturating_sub(keep_latest);
+        let mut older = rest;
+        let latest = older.split_off(split_at);
+        Self {
+            system,
+            older,
+            latest,
+        }
+    }
+
+    pub fn to_messages(&self) -> Vec<Message> {
+        let mut messages = self.system.clone();
+        messages.extend(self.older.iter().cloned());
+        messages.extend(self.latest.iter().cloned());
+        messages
+    }
+
+    pub fn into_messages(self) -> Vec<Message> {
+        let mut messages = self.system;
+        messages.extend(self.older);
+        messages.extend(self.latest);
+        messages
+    }
+}
+
+/// Decides how the older part of a [`History`] gets reduced when the conversation does not fit
+/// into the token budget.
+pub trait TruncationStrategy {
+    fn reduce(
+        &self,
+        history: History,
+        max_tokens: u32,
+        counter: &dyn TokenCounter,
+    ) -> Result<History, Error>;
+}
+
+/// Drops the oldest non-system messages one by one until the conversation fits the budget.
+pub struct TruncateOldest;
+
+impl TruncationStrategy for TruncateOldest {
+    fn reduce(
+        &self,
+        mut history: History,
+        max_tokens: u32,
+        counter: &dyn TokenCounter,
+    ) -> Result<History, Error> {
+        while !history.older.is_empty() && counter.count_tokens(&history.to_messages()) > max_tokens
+        {
+            history.older.remove(0);
+        }
+        Ok(history)
+    }
+}
+
+/// Replaces all the older non-system messages with a single system message containing their
+/// summary, produced by `summarize`.
+pub struct Summarize<F> {
+    summarize: F,
+}
+
+impl<F: Fn(Vec<Message>) -> Result<String, Error>> Summarize<F> {
+    pub fn new(summarize: F) -> Self {
+        Self { summarize }
+    }
+}
+
+impl<S: Fn(Vec<Message>, Config) -> ChatEvent> Summarize<LlmSummarizer<S>> {
+    /// Uses an LLM through the given `send` function to summarize the older messages
+    pub fn with_llm(send: S, config: Config) -> Self {
+        Self {
+            summarize: LlmSummarizer { send, config },
+        }
+    }
+}
+
+impl<F: Summarizer> TruncationStrategy for Summarize<F> {
+    fn reduce(
+        &self,
+        mut history: History,
+        _max_tokens: u32,
+        _counter: &dyn TokenCounter,
+    ) -> Result<History, Error> {
+        if history.older.is_empty() {
+            return Ok(history);
+        }
+
+        let older = std::mem::take(&mut history.older);
+        let summary = self.summarize.summarize(older)?;
+        history.system.push(Message {
+            role: Role::System,
+            name: None,
+            content: vec![ContentPart::Text(format!(
+                "Summary of the earlier conversation: {summary}"
+            ))],
+        });
+        Ok(history)
+    }
+}
+
+/// Produces a textual summary of a list of messages
+pub trait Summarizer {
+    fn summarize(&self, messages: Vec<Message>) -> Result<String, Error>;
+}
+
+impl<F: Fn(Vec<Message>) -> Result<String, Error>> Summarizer for F {
+    fn summarize(&self, messages: Vec<Message>) -> Result<String, Error> {
+        self(messages)
+    }
+}
+
+/// [`Summarizer`] asking an LLM to summarize the messages
+pub struct LlmSummarizer<S> {
+    send: S,
+    config: Config,
+}
+
+impl<S: Fn(Vec<Message>, Config) -> ChatEvent> Summarizer for LlmSummarizer<S> {
+    fn summarize(&self, messages: Vec<Message>) -> Result<String, Error> {
+        let mut request = vec![Message {
+            role: Role::System,
+            name: None,
+            content: vec![ContentPart::Text(
+                "Summarize the following conversation in a few sentences, keeping all facts that may be needed to continue it.".to_string(),
+            )],
+        }];
+        request.extend(messages);
+
+        match (self.send)(request, self.config.clone()) {
+            ChatEvent::Message(response) => Ok(response
+                .content
+                .into_iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text(text) => Some(text),
+                    ContentPart::Image(_) => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ChatEvent::ToolRequest(_) => Err(Error {
+                code: ErrorCode::InternalError,
+                message: "Expected a summary but the LLM requested a tool call".to_string(),
+                provider_error_json: None,
+            }),
+            ChatEvent::Error(error) => Err(error),
+        }
+    }
+}
+
+/// Keeps a conversation within the model's context window before sending it.
+pub struct ConversationWindow<C, S> {
+    max_tokens: u32,
+    keep_latest: usize,
+    counter: C,
+    strategy: S,
+}
+
+impl<S: TruncationStrategy> ConversationWindow<ApproximateTokenCounter, S> {
+    pub fn new(max_tokens: u32, keep_latest: usize, strategy: S) -> Self {
+        Self::with_counter(max_tokens, keep_latest, ApproximateTokenCounter, strategy)
+    }
+}
+
+impl<C: TokenCounter, S: TruncationStrategy> ConversationWindow<C, S> {
+    pub fn with_counter(max_tokens: u32, keep_latest: usize, counter: C, strategy: S) -> Self {
+        Self {
+            max_tokens,
+            keep_latest,
+            counter,
+            strategy,
+        }
+    }
+
+    /// Returns `messages` unchanged if they fit the budget, otherwise reduces them with the
+    /// configured strategy. System messages and the latest turns are always preserved, so the
+    /// result may still exceed the budget.
+    pub fn fit(&self, messages: Vec<Message>) -> Result<Vec<Message>, Error> {
+        if self.counter.count_tokens(&messages) <= self.max_tokens {
+            return Ok(messages);
+        }
+
+        let history = History::split(messages, self.keep_latest);
+        let reduced = self
+            .strategy
+            .reduce(history, self.max_tokens, &self.counter)?;
+        Ok(reduced.into_messages())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{
+        ChatEvent, CompleteResponse, Config, ContentPart, Error, ErrorCode, Message,
+        ResponseMetadata, Role,
+    };
+    use crate::test_config;
+    use crate::truncation::{ConversationWindow, Summarize, TruncateOldest};
+    use std::cell::RefCell;
+
+    fn message(role: Role, text: &str) -> Message {
+        Message {
+            role,
+            name: None,
+            content: vec![ContentPart::Text(text.to_string())],
+        }
+    }
+
+    fn texts(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .flat_map(|message| message.content.iter())
+            .filter_map(|part| match part {
+                ContentPart::Text(text) => Some(text.clone()),
+                ContentPart::Image(_) => None,
+            })
+            .collect()
+    }
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            message(Role::System, "system"),
+            message(Role::User, "first"),
+            message(Role::Assistant, "second"),
+            message(Role::User, "third"),
+            message(Role::Assistant, "fourth"),
+        ]
+    }
+
+    // Every message counts as a single token
+    fn count_messages(messages: &[Message]) -> u32 {
+        messages.len() as u32
+    }
+
+    fn config() -> Config {
+        Config {
+            model: "summarizer".to_string(),
+            ..test_config::config(&[])
+        }
+    }
+
+    #[test]
+    fn fitting_conversation_is_unchanged() {
+        let window = ConversationWindow::with_counter(5, 1, count_messages, TruncateOldest);
+
+        let result = window.fit(conversation()).unwrap();
+
+        assert_eq!(result, conversation());
+    }
+
+    #[test]
+    fn truncate_oldest_keeps_system_and_latest_messages() {
+        let window = ConversationWindow::with_counter(3, 1, count_messages, TruncateOldest);
+
+        let result = window.fit(conversation()).unwrap();
+
+        assert_eq!(texts(&result), vec!["system", "third", "fourth"]);
+    }
+
+    #[test]
+    fn truncate_oldest_never_drops_latest_messages() {
+        let window = ConversationWindow::with_counter(1, 2, count_messages, TruncateOldest);
+
+        let result = window.fit(conversation()).unwrap();
+
+        assert_eq!(texts(&result), vec!["system", "third", "fourth"]);
+    }
+
+    #[test]
+    fn summarize_replaces_older_messages_with_llm_summary() {
+        let received = RefCell::new(Vec::new());
+        let send = |messages: Vec<Message>, config: Config| {
+            *received.borrow_mut() = texts(&messages);
+            assert_eq!(config.model, "summarizer");
+            ChatEvent::Message(CompleteResponse {
+                id: "summary".to_string(),
+                content: vec![ContentPart::Text("they talked".to_string())],
+                tool_calls: vec![],
+                metadata: ResponseMetadata {
+                    finish_reason: None,
+                    usage: None,
+                    provider_id: None,
+                    timestamp: None,
+                    provider_metadata_json: None,
+                    raw_json: None,
+                },
+            })
+        };
+        let window = ConversationWindow::with_counter(
+            3,
+            2,
+            count_messages,
+            Summarize::with_llm(send, config()),
+        );
+
+        let result = window.fit(conversation()).unwrap();
+
+        assert_eq!(
+            texts(&result),
+            vec![
+                "system",
+                "Summary of the earlier conversation: they talked",
+                "third",
+                "fourth"
+            ]
+        );
+        assert_eq!(result[1].role, Role::System);
+        assert_eq!(&received.borrow()[1..], &["first", "second"]);
+    }
+
+    #[test]
+    fn summarize_propagates_llm_errors() {
+        let send = |_: Vec<Message>, _: Config| {
+            ChatEvent::Error(Error {
+                code: ErrorCode::RateLimitExceeded,
+                message: "Slow down".to_string(),
+                provider_error_json: None,
+            })
+        };
+        let window = ConversationWindow::with_counter(
+            3,
+            2,
+            count_messages,
+            Summarize::with_llm(send, config()),
+        );
+
+        let error = window.fit(conversation()).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::RateLimitExceeded);
+    }
+
+    #[test]
+    fn summarize_with_custom_summarizer() {
+        let strategy = Summarize::new(|messages: Vec<Message>| Ok(messages.len().to_string()));
+        let window = ConversationWindow::with_counter(3, 1, count_messages, strategy);
+
+        let result = window.fit(conversation()).unwrap();
+
+        assert_eq!(
+            texts(&result),
+            vec!["system", "Summary of the earlier conversation: 3", "fourth"]
+        );
+    }
+}
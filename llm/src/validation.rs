@@ -0,0 +1,270 @@
+use crate::concurrency::ConcurrencyLimit;
+use crate::config::{apply_sampling_preset, max_tokens, option, request_metadata, resolve_model};
+use crate::golem::llm::llm::{Config, Error, ErrorCode};
+use crate::json_schema::JsonValidation;
+use crate::models::model_info;
+use crate::tool_call_only::forced_tool_choice;
+use std::collections::HashSet;
+
+/// Tool choices selecting a mode instead of a specific tool, across all providers
+const TOOL_CHOICE_MODES: &[&str] = &["auto", "none", "required", "any"];
+
+/// Checks `config` before sending a request, without any network access, so that mistakes are
+/// found in CI or at startup instead of by the first request.
+///
+/// Checks that the model is set, the sampling parameters (`temperature`, and the `top_p`,
+/// `frequency_penalty` and `presence_penalty` provider options) are in range, the tools have
+/// unique names of at most 64 letters, digits, `_` or `-` and schemas that are JSON objects, and
+/// the tool choice is a mode or one of the tools. The provider options validated by the
+/// individual features, such as `metadata`, `model_alias` or `max_concurrency`, are checked as
+/// well. All the problems found are reported together in a single `invalid-request` error.
+pub fn validate_config(config: &Config) -> Result<(), Error> {
+    let mut problems = Vec::new();
+
+    if config.model.trim().is_empty() {
+        problems.push("model must not be empty".to_string());
+    }
+
+    if let Some(temperature) = config.temperature {
+        if !(0.0..=2.0).contains(&temperature) {
+            problems.push(format!(
+                "temperature must be between 0 and 2, got {temperature}"
+            ));
+        }
+    }
+    for (key, min, max) in [
+        ("top_p", 0.0, 1.0),
+        ("frequency_penalty", -2.0, 2.0),
+        ("presence_penalty", -2.0, 2.0),
+    ] {
+        if let Some(value) = option(config, key) {
+            match value.parse::<f32>() {
+                Ok(parsed) if (min..=max).contains(&parsed) => {}
+                _ => problems.push(format!(
+                    "{key} must be a number between {min} and {max}, got {value}"
+                )),
+            }
+        }
+    }
+
+    let mut names = HashSet::new();
+    for tool in &config.tools {
+        if tool.name.is_empty()
+            || tool.name.len() > 64
+            || !tool
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            problems.push(format!(
+                "Tool name {:?} must be 1 to 64 letters, digits, _ or -",
+                tool.name
+            ));
+        }
+        if !names.insert(tool.name.as_str()) {
+            problems.push(format!("Tool name {} is not unique", tool.name));
+        }
+        match serde_json::from_str::<serde_json::Value>(&tool.parameters_schema) {
+            Ok(serde_json::Value::Object(_)) => {}
+            Ok(_) => problems.push(format!(
+                "Parameters schema of tool {} must be a JSON object",
+                tool.name
+            )),
+            Err(err) => problems.push(format!(
+                "Failed to parse parameters schema of tool {}: {err}",
+                tool.name
+            )),
+        }
+    }
+
+    if let Some(tool_choice) = &config.tool_choice {
+        if !TOOL_CHOICE_MODES.contains(&tool_choice.as_str())
+            && !names.contains(tool_choice.as_str())
+        {
+            problems.push(format!("tool_choice {tool_choice} is not one of the tools"));
+        }
+    }
+
+    let checks = [
+        max_tokens(config).map(|_| ()),
+        request_metadata(config).map(|_| ()),
+        resolve_model(config, "").map(|_| ()),
+        apply_sampling_preset(config.clone(), "").map(|_| ()),
+        model_info(config, &config.model).map(|_| ()),
+        forced_tool_choice(config, "required").map(|_| ()),
+        ConcurrencyLimit::from_config(config).map(|_| ()),
+        JsonValidation::from_config(config).map(|_| ()),
+    ];
+    problems.extend(
+        checks
+            .into_iter()
+            .filter_map(|check| check.err())
+            .map(|error| error.message),
+    );
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error {
+            code: ErrorCode::InvalidRequest,
+            message: format!("Invalid config: {}", problems.join("; ")),
+            provider_error_json: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::golem::llm::llm::{Config, ErrorCode, ToolDefinition};
+    use crate::test_config;
+    use crate::validation::validate_config;
+
+    fn tool(name: &str, parameters_schema: &str) -> ToolDefinition {
+        ToolDefinition {
+            name: name.to_string(),
+            description: None,
+            parameters_schema: parameters_schema.to_string(),
+        }
+    }
+
+    fn config_with(provider_options: &[(&str, &str)]) -> Config {
+        Config {
+            model: "gpt-4o".to_string(),
+            temperature: Some(0.7),
+            max_tokens: Some(100),
+            tools: vec![tool("get_weather", r#"{"type":"object"}"#)],
+            tool_choice: Some("get_weather".to_string()),
+            ..test_config::config(provider_options)
+        }
+    }
+
+    fn config() -> Config {
+        config_with(&[("top_p", "0.9"), ("presence_penalty", "-1")])
+    }
+
+    /// Validates `config`, returning the problems found
+    fn problems(config: Config) -> Vec<String> {
+        match validate_config(&config) {
+            Ok(()) => vec![],
+            Err(error) => {
+                assert_eq!(error.code, ErrorCode::InvalidRequest);
+                error
+                    .message
+                    .strip_prefix("Invalid config: ")
+                    .unwrap()
+                    .split("; ")
+                    .map(str::to_string)
+                    .collect()
+            }
+        }
+    }
+
+    #[test]
+    fn valid_config_is_accepted() {
+        assert_eq!(problems(config()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn empty_model_is_rejected() {
+        let config = Config {
+            model: " ".to_string(),
+            ..config()
+        };
+
+        assert_eq!(problems(config), vec!["model must not be empty"]);
+    }
+
+    #[test]
+    fn out_of_range_sampling_parameters_are_rejected() {
+        let config = Config {
+            temperature: Some(2.5),
+            ..config_with(&[("top_p", "1.5"), ("frequency_penalty", "high")])
+        };
+
+        assert_eq!(
+            problems(config),
+            vec![
+                "temperature must be between 0 and 2, got 2.5",
+                "top_p must be a number between 0 and 1, got 1.5",
+                "frequency_penalty must be a number between -2 and 2, got high",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_tool_schemas_are_rejected() {
+        let config = Config {
+            tools: vec![
+                tool("get_weather", "{not json"),
+                tool("get_time", r#""object""#),
+            ],
+            ..config()
+        };
+
+        let problems = problems(config);
+
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("Failed to parse parameters schema of tool get_weather"));
+        assert_eq!(
+            problems[1],
+            "Parameters schema of tool get_time must be a JSON object"
+        );
+    }
+
+    #[test]
+    fn invalid_and_duplicate_tool_names_are_rejected() {
+        let config = Config {
+            tools: vec![
+                tool("get_weather", "{}"),
+                tool("get weather", "{}"),
+                tool("get_weather", "{}"),
+            ],
+            ..config()
+        };
+
+        assert_eq!(
+            problems(config),
+            vec![
+                r#"Tool name "get weather" must be 1 to 64 letters, digits, _ or -"#,
+                "Tool name get_weather is not unique",
+            ]
+        );
+    }
+
+    #[test]
+    fn tool_choice_must_reference_a_tool() {
+        let config = Config {
+            tool_choice: Some("get_time".to_string()),
+            ..config()
+        };
+        assert_eq!(
+            problems(config.clone()),
+            vec!["tool_choice get_time is not one of the tools"]
+        );
+
+        for mode in ["auto", "none", "required", "any"] {
+            let config = Config {
+                tool_choice: Some(mode.to_string()),
+                ..config.clone()
+            };
+            assert_eq!(problems(config), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn feature_options_are_validated() {
+        let mut config =
+            config_with(&[("metadata", r#"{"attempt": 1}"#), ("max_concurrency", "0")]);
+        config.max_tokens = Some(0);
+
+        let problems = problems(config);
+
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("max_tokens must be positive"));
+        assert!(problems[1].starts_with("metadata must be a JSON object of strings"));
+        assert_eq!(
+            problems[2],
+            "max_concurrency must be a positive integer, got 0"
+        );
+    }
+}
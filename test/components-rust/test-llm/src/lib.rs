@@ -160,7 +160,7 @@ impl Guest for Component {
                     llm::ToolResult::Success(llm::ToolSuccess {
                         id: call.id,
                         name: call.name,
-                        result_json: r#"{ value: 6 }"#.to_string(),
+                        result_json: r#"{"value":6}"#.to_string(),
                         execution_time_ms: None,
                     }),
                 ));
@@ -252,6 +252,12 @@ impl Guest for Component {
             }
         }
 
+        result.push_str(&format!(
+            "FINISHED: {} {:?}\n",
+            stream.is_finished(),
+            stream.last_finish_reason()
+        ));
+
         result
     }
 